@@ -110,23 +110,42 @@ io.on("connection", (socket) => {
     console.log("Unlocked all clients");
   });
 
+  // Input pairing: the client shows a code, the admin types it in, and the
+  // client answers with the token that every remote-* event must carry
+  socket.on("request-input-pairing", ({ clientId }) => {
+    if (clients.has(clientId)) {
+      io.to(clientId).emit("request-input-pairing", { adminId: socket.id });
+    }
+  });
+
+  socket.on("submit-pairing-code", ({ clientId, code }) => {
+    if (clients.has(clientId)) {
+      io.to(clientId).emit("submit-pairing-code", { adminId: socket.id, code });
+    }
+  });
+
+  socket.on("input-pairing-result", ({ adminId, token, error }) => {
+    console.log(`Pairing with ${socket.id}: ${error ? error : "ok"}`);
+    io.to(adminId).emit("input-pairing-result", { clientId: socket.id, token, error });
+  });
+
   // Remote control events (RustDesk style)
-  socket.on("remote-mouse-move", ({ clientId, x, y }) => {
-    io.to(clientId).emit("remote-mouse-move", { x, y });
+  socket.on("remote-mouse-move", ({ clientId, x, y, token }) => {
+    io.to(clientId).emit("remote-mouse-move", { x, y, token });
   });
 
-  socket.on("remote-mouse-click", ({ clientId, button }) => {
+  socket.on("remote-mouse-click", ({ clientId, button, token }) => {
     console.log("Remote click:", clientId, button);
-    io.to(clientId).emit("remote-mouse-click", { button });
+    io.to(clientId).emit("remote-mouse-click", { button, token });
   });
 
-  socket.on("remote-mouse-scroll", ({ clientId, deltaX, deltaY }) => {
-    io.to(clientId).emit("remote-mouse-scroll", { deltaX, deltaY });
+  socket.on("remote-mouse-scroll", ({ clientId, deltaX, deltaY, token }) => {
+    io.to(clientId).emit("remote-mouse-scroll", { deltaX, deltaY, token });
   });
 
-  socket.on("remote-key-press", ({ clientId, key, code, ctrl, alt, shift, meta }) => {
+  socket.on("remote-key-press", ({ clientId, key, code, ctrl, alt, shift, meta, token }) => {
     console.log("Remote key:", clientId, key, code);
-    io.to(clientId).emit("remote-key-press", { key, code, ctrl, alt, shift, meta });
+    io.to(clientId).emit("remote-key-press", { key, code, ctrl, alt, shift, meta, token });
   });
 
   // Screen size request/response
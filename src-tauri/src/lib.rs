@@ -1,28 +1,79 @@
 use base64::{engine::general_purpose, Engine};
-use openh264::encoder::{Encoder, EncoderConfig, BitRate, FrameRate};
+use openh264::encoder::{Encoder, EncoderConfig, BitRate, FrameRate, Complexity, IntraFramePeriod, QpRange, RateControlMode, UsageType};
 use openh264::formats::YUVBuffer;
 use parking_lot::Mutex;
 use rdev::{simulate, Button, EventType, Key, SimulateError};
 use scrap::{Capturer, Display};
 use std::io::ErrorKind::WouldBlock;
 use std::net::{UdpSocket, IpAddr};
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use std::thread;
 use tauri::{Emitter, Manager};
 
+mod protocol;
+use protocol::FrameKind;
+mod watermark;
+use watermark::WatermarkPosition;
+
+#[cfg(target_os = "windows")]
+mod windows_capture_handler;
+
 // ============== Constants ==============
 const STREAM_WIDTH: usize = 640;
 const STREAM_HEIGHT: usize = 360;
+const MAX_STREAM_WIDTH: usize = 3840;
+const MAX_STREAM_HEIGHT: usize = 2160;
+const MAX_STREAM_FPS: u32 = 60;
+const DEFAULT_STREAM_FPS: u32 = 15;
+const MAX_GOP_SECS: u32 = 60;
 
 // ============== Global State ==============
 lazy_static::lazy_static! {
     static ref CAPTURING: AtomicBool = AtomicBool::new(false);
-    static ref STREAMING: AtomicBool = AtomicBool::new(false);
     static ref UDP_RECEIVER_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref UDP_RECEIVER_PAUSED: AtomicBool = AtomicBool::new(false);
+    static ref UDP_RECEIVER_THREAD: Mutex<Option<thread::JoinHandle<()>>> = Mutex::new(None);
+    static ref RECEIVER_STARTED: Mutex<Option<Instant>> = Mutex::new(None);
     static ref FRAME_COUNT: AtomicU32 = AtomicU32::new(0);
+    // Letterbox instead of stretching when the source aspect differs from the stream's
+    static ref PRESERVE_ASPECT: AtomicBool = AtomicBool::new(false);
+    // start_stream fps when the call omits it
+    static ref STREAM_FPS: AtomicU32 = AtomicU32::new(DEFAULT_STREAM_FPS);
+    // Seconds between periodic keyframes of every H.264 encode; 0 = encoder default
+    static ref STREAM_GOP_SECS: AtomicU32 = AtomicU32::new(0);
+    // Interactive remote control: favour latency over picture quality in the encoder
+    // (start_stream / start_stream_auto / display streams)
+    static ref SENDER_LOW_LATENCY: AtomicBool = AtomicBool::new(false);
+    // Prefer a hardware H.264 encoder; ACTIVE_ENCODER is what the stream actually got
+    static ref HW_ACCEL: AtomicBool = AtomicBool::new(false);
+    // Encoder the running stream actually got ("none" if it failed to start)
+    static ref ACTIVE_ENCODER: Mutex<&'static str> = Mutex::new("none");
+    // Rate control of the main stream's encoder (start_stream quality_mode)
+    static ref QUALITY_MODE: Mutex<QualityMode> = Mutex::new(QualityMode::Bitrate(DEFAULT_BITRATE_BPS));
+    // What the running stream was started with; differs from QUALITY_MODE under start_stream_auto
+    static ref STREAM_QUALITY: Mutex<QualityMode> = Mutex::new(QualityMode::Bitrate(DEFAULT_BITRATE_BPS));
+    // RGB -> YUV coefficients for every H.264 encode (start_stream color_range / matrix)
+    static ref COLOR_SPACE: Mutex<ColorSpace> = Mutex::new(ColorSpace::default());
+    // Layout tagged for the viewer's decoder output
+    static ref PIXEL_FORMAT: Mutex<PixelFormat> = Mutex::new(PixelFormat::default());
+    // Emit h264-frame as [{ type, data }] per NAL unit instead of one Annex-B blob
+    static ref RECEIVER_SPLIT_NALS: AtomicBool = AtomicBool::new(false);
+    // Emit every completed frame right away instead of throttling (start_frame_receiver)
+    static ref RECEIVER_LOW_LATENCY: AtomicBool = AtomicBool::new(false);
     static ref LAST_H264_FRAME: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+    // Bumped (under the LAST_H264_FRAME lock) per frame, so pollers can spot gaps
+    static ref H264_FRAME_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    // Latest SPS/PPS of the main stream, so a late viewer can set up its decoder early
+    static ref PARAMETER_SETS: Mutex<Option<ParameterSets>> = Mutex::new(None);
     static ref LAST_JPEG_FRAME: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+    static ref CURSOR_POS: Mutex<Option<(f64, f64)>> = Mutex::new(None);
+    static ref CURSOR_TRACKER_STARTED: AtomicBool = AtomicBool::new(false);
+    static ref CURSOR_HIGHLIGHT: Mutex<CursorHighlight> = Mutex::new(CursorHighlight {
+        enabled: false,
+        radius: 24,
+        color: [255, 220, 0, 96],
+    });
 }
 
 // ============== Screen Capture ==============
@@ -35,6 +86,20 @@ struct ScreenCapturer {
 impl ScreenCapturer {
     fn new() -> Result<Self, String> {
         let display = Display::primary().map_err(|e| format!("No display: {}", e))?;
+        Self::from_display(display)
+    }
+    
+    // index into Display::all()
+    fn for_display(index: usize) -> Result<Self, String> {
+        let display = Display::all()
+            .map_err(|e| format!("No display: {}", e))?
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| format!("Display {} not found", index))?;
+        Self::from_display(display)
+    }
+    
+    fn from_display(display: Display) -> Result<Self, String> {
         let width = display.width();
         let height = display.height();
         let capturer = Capturer::new(display).map_err(|e| format!("Capturer error: {}", e))?;
@@ -51,43 +116,186 @@ impl ScreenCapturer {
 }
 
 // ============== H.264 Encoder ==============
+// openh264 accepts QP 0 (best) to 51 (worst)
+const QP_MAX: u8 = 51;
+const DEFAULT_QP: u8 = 26;
+const DEFAULT_BITRATE_BPS: u32 = 500_000;
+const MIN_BITRATE_KBPS: u32 = 50;
+const MAX_BITRATE_KBPS: u32 = 50_000;
+
+// Bitrate: fixed target (bps), quality varies with content.
+// ConstantQp: fixed quality, bitrate floats (sharper text on busy screens).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum QualityMode {
+    Bitrate(u32),
+    ConstantQp(u8),
+}
+
+impl QualityMode {
+    fn parse(mode: Option<&str>, qp: Option<u8>) -> Result<Self, String> {
+        match mode.unwrap_or("bitrate").trim().to_ascii_lowercase().as_str() {
+            "bitrate" => Ok(QualityMode::Bitrate(DEFAULT_BITRATE_BPS)),
+            "qp" | "constant_qp" => {
+                let qp = qp.unwrap_or(DEFAULT_QP);
+                if qp > QP_MAX {
+                    return Err(format!("QP must be 0-{} (got {})", QP_MAX, qp));
+                }
+                Ok(QualityMode::ConstantQp(qp))
+            }
+            other => Err(format!("Unknown quality mode: {} (expected bitrate or qp)", other)),
+        }
+    }
+    
+    // Target bitrate for bitrate mode (None keeps the default)
+    fn with_bitrate_kbps(self, kbps: Option<u32>) -> Result<Self, String> {
+        match (self, kbps) {
+            (_, None) => Ok(self),
+            (QualityMode::Bitrate(_), Some(kbps)) => {
+                if !(MIN_BITRATE_KBPS..=MAX_BITRATE_KBPS).contains(&kbps) {
+                    return Err(format!("bitrate_kbps must be {}-{} (got {})", MIN_BITRATE_KBPS, MAX_BITRATE_KBPS, kbps));
+                }
+                Ok(QualityMode::Bitrate(kbps * 1000))
+            }
+            (QualityMode::ConstantQp(_), Some(_)) => Err("bitrate_kbps only applies to bitrate mode".to_string()),
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            QualityMode::Bitrate(_) => "bitrate",
+            QualityMode::ConstantQp(_) => "qp",
+        }
+    }
+    
+    fn qp(self) -> Option<u8> {
+        match self {
+            QualityMode::Bitrate(_) => None,
+            QualityMode::ConstantQp(qp) => Some(qp),
+        }
+    }
+    
+    fn bitrate_bps(self) -> Option<u32> {
+        match self {
+            QualityMode::Bitrate(bps) => Some(bps),
+            QualityMode::ConstantQp(_) => None,
+        }
+    }
+}
+
+enum EncoderBackend {
+    OpenH264(Encoder),
+    Hardware(HardwareEncoder),
+}
+
 struct H264Encoder {
-    encoder: Encoder,
+    backend: EncoderBackend,
     width: usize,
     height: usize,
+    low_latency: bool,
+    quality: QualityMode,
+    // Rate control's frame rate; callers send at the same fps
+    fps: u32,
+    // Try a hardware encoder first, here and on every restart
+    hw_accel: bool,
     frame_count: u32,
+    // Encode calls that errored in a row (empty output from skip_frames doesn't count)
+    consecutive_errors: u32,
 }
 
 impl H264Encoder {
-    fn new(width: usize, height: usize) -> Result<Self, String> {
-        let config = EncoderConfig::new()
-            .bitrate(BitRate::from_bps(500_000)) // 500 kbps
-            .max_frame_rate(FrameRate::from_hz(30.0));
-        
-        let encoder = Encoder::with_api_config(
-            openh264::OpenH264API::from_source(),
-            config
-        ).map_err(|e| format!("H264 encoder error: {:?}", e))?;
+    // low_latency: real-time screen usage, low complexity, no long-term references,
+    // and bitrate RC that skips frames instead of queueing them. openh264 never
+    // emits B-frames, so every frame is decodable as soon as it arrives.
+    // fps must be the rate frames are actually fed in, or bitrate RC budgets per frame wrongly
+    fn new(width: usize, height: usize, fps: u32, low_latency: bool) -> Result<Self, String> {
+        Self::with_quality(width, height, fps, low_latency, QualityMode::Bitrate(DEFAULT_BITRATE_BPS))
+    }
+    
+    fn with_quality(width: usize, height: usize, fps: u32, low_latency: bool, quality: QualityMode) -> Result<Self, String> {
+        Self::with_backend(width, height, fps, low_latency, quality, false)
+    }
+    
+    // hw_accel: the first working backend of hardware_encoder(), else openh264;
+    // backend_name() tells which one this got
+    fn with_backend(width: usize, height: usize, fps: u32, low_latency: bool, quality: QualityMode, hw_accel: bool) -> Result<Self, String> {
+        if fps == 0 || fps > MAX_STREAM_FPS {
+            return Err(format!("Encoder fps must be 1-{} (got {})", MAX_STREAM_FPS, fps));
+        }
+        let hardware = if hw_accel { hardware_encoder() } else { None };
+        let backend = match hardware.map(|hw| HardwareEncoder::spawn(hw, width, height, fps, low_latency, quality)) {
+            Some(Ok(encoder)) => EncoderBackend::Hardware(encoder),
+            Some(Err(e)) => {
+                println!("Hardware encoder unavailable ({}), falling back to openh264", e);
+                EncoderBackend::OpenH264(openh264_encoder(fps, low_latency, quality)?)
+            }
+            None => EncoderBackend::OpenH264(openh264_encoder(fps, low_latency, quality)?),
+        };
         
         Ok(Self {
-            encoder,
+            backend,
             width,
             height,
+            low_latency,
+            quality,
+            fps,
+            hw_accel,
             frame_count: 0,
+            consecutive_errors: 0,
         })
     }
+    
+    fn backend_name(&self) -> &'static str {
+        match &self.backend {
+            EncoderBackend::OpenH264(_) => "openh264",
+            EncoderBackend::Hardware(hw) => hw.backend.encoder,
+        }
+    }
+    
+    // Fresh encoder with the same settings; its first frame is an IDR
+    fn restart(&mut self) -> Result<(), String> {
+        self.restart_with_quality(self.quality)
+    }
+    
+    // On failure the running encoder, and the quality it reports, stay as they were
+    fn restart_with_quality(&mut self, quality: QualityMode) -> Result<(), String> {
+        self.reconfigure(self.width, self.height, self.fps, quality)
+    }
+    
+    // Same, with a new output size and frame rate too (auto-quality tier changes).
+    // A hardware encoder that errored is not tried again for this stream.
+    fn reconfigure(&mut self, width: usize, height: usize, fps: u32, quality: QualityMode) -> Result<(), String> {
+        let hw_failed = matches!(self.backend, EncoderBackend::Hardware(_)) && self.consecutive_errors > 0;
+        *self = Self::with_backend(width, height, fps, self.low_latency, quality, self.hw_accel && !hw_failed)?;
+        self.force_keyframe();
+        Ok(())
+    }
 
     fn encode(&mut self, bgra: &[u8], src_width: usize, src_height: usize) -> Option<Vec<u8>> {
         // Resize and convert BGRA to YUV420
-        let yuv = bgra_to_yuv420_resized(bgra, src_width, src_height, self.width, self.height)?;
-        
-        let yuv_buf = YUVBuffer::from_vec(yuv, self.width, self.height);
-        
-        // Encode to H.264
-        let bitstream = self.encoder.encode(&yuv_buf).ok()?;
+        let preserve_aspect = PRESERVE_ASPECT.load(Ordering::Relaxed);
+        let color = *COLOR_SPACE.lock();
+        // openh264 only takes planar input; GPU encoders want NV12 (see HardwareEncoder::spawn)
+        let format = match self.backend {
+            EncoderBackend::OpenH264(_) => PixelFormat::I420,
+            EncoderBackend::Hardware(_) => PixelFormat::Nv12,
+        };
+        let yuv = bgra_to_yuv420_resized(bgra, src_width, src_height, self.width, self.height, preserve_aspect, color, format)?;
         
-        // Get raw H.264 data
-        let h264_data = bitstream.to_vec();
+        let encoded = match &mut self.backend {
+            EncoderBackend::OpenH264(encoder) => {
+                let yuv_buf = YUVBuffer::from_vec(yuv, self.width, self.height);
+                encoder.encode(&yuv_buf).map(|bitstream| bitstream.to_vec()).map_err(|e| format!("{:?}", e))
+            }
+            EncoderBackend::Hardware(encoder) => encoder.encode(&yuv),
+        };
+        let h264_data = match encoded {
+            Ok(data) => data,
+            Err(_) => {
+                self.consecutive_errors += 1;
+                return None;
+            }
+        };
+        self.consecutive_errors = 0;
         
         self.frame_count += 1;
         
@@ -97,32 +305,597 @@ impl H264Encoder {
             None
         }
     }
+    
+    // Make the next encoded frame an IDR
+    fn force_keyframe(&mut self) {
+        match &mut self.backend {
+            EncoderBackend::OpenH264(encoder) => encoder.force_intra_frame(),
+            EncoderBackend::Hardware(encoder) => encoder.keyframe_pending = true,
+        }
+    }
+}
+
+fn openh264_encoder(fps: u32, low_latency: bool, quality: QualityMode) -> Result<Encoder, String> {
+    let mut config = EncoderConfig::new()
+        .bitrate(BitRate::from_bps(quality.bitrate_bps().unwrap_or(DEFAULT_BITRATE_BPS)))
+        .max_frame_rate(FrameRate::from_hz(fps as f32));
+    
+    if low_latency {
+        config = config
+            .usage_type(UsageType::ScreenContentRealTime)
+            .complexity(Complexity::Low)
+            .rate_control_mode(RateControlMode::Bitrate)
+            .skip_frames(true)
+            .long_term_reference(false)
+            .background_detection(false);
+    }
+    
+    // Rate control off + a one-value QP range = every frame at that QP
+    if let QualityMode::ConstantQp(qp) = quality {
+        config = config
+            .rate_control_mode(RateControlMode::Off)
+            .qp(QpRange::new(qp, qp))
+            .skip_frames(false);
+    }
+    
+    let gop_secs = STREAM_GOP_SECS.load(Ordering::Relaxed);
+    if gop_secs > 0 {
+        config = config.intra_frame_period(IntraFramePeriod::from_num_frames(fps * gop_secs));
+    }
+    
+    Encoder::with_api_config(
+        openh264::OpenH264API::from_source(),
+        config
+    ).map_err(|e| format!("H264 encoder error: {:?}", e))
+}
+
+// ============== Hardware H.264 Encoder ==============
+// GPU encoders are driven through an ffmpeg child process: raw frames in on stdin,
+// Annex-B out on stdout. FFMPEG_PATH overrides the binary (default: ffmpeg on PATH).
+// A forced keyframe respawns the process, whose first frame is an IDR, at most once
+// per HARDWARE_KEYFRAME_MIN_INTERVAL; between those the GOP is STREAM_GOP_SECS
+// (two seconds when that is left to the encoder).
+const HARDWARE_KEYFRAME_MIN_INTERVAL: Duration = Duration::from_secs(1);
+const HARDWARE_GOP_SECS: u32 = 2;
+
+struct HardwareBackend {
+    // ffmpeg encoder name, also what get_stream_stats reports
+    encoder: &'static str,
+    // Before -i, e.g. the VAAPI render node
+    device_args: &'static [&'static str],
+    // Upload filter for encoders that take GPU surfaces
+    filter: Option<&'static str>,
+    low_latency_args: &'static [&'static str],
+    // Followed by the QP; None = no constant-QP mode, openh264 is used instead
+    qp_args: Option<&'static [&'static str]>,
+}
+
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+const NVENC: HardwareBackend = HardwareBackend {
+    encoder: "h264_nvenc",
+    device_args: &[],
+    filter: None,
+    low_latency_args: &["-preset", "p1", "-tune", "ll", "-zerolatency", "1", "-delay", "0"],
+    qp_args: Some(&["-rc", "constqp", "-qp"]),
+};
+
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+const QSV: HardwareBackend = HardwareBackend {
+    encoder: "h264_qsv",
+    device_args: &[],
+    filter: None,
+    low_latency_args: &["-preset", "veryfast", "-async_depth", "1"],
+    qp_args: Some(&["-q"]),
+};
+
+// Probed in this order; the first that encodes a test clip wins
+#[cfg(target_os = "windows")]
+const HARDWARE_BACKENDS: &[HardwareBackend] = &[
+    NVENC,
+    QSV,
+    HardwareBackend {
+        encoder: "h264_amf",
+        device_args: &[],
+        filter: None,
+        low_latency_args: &["-usage", "ultralowlatency"],
+        qp_args: Some(&["-rc", "cqp", "-qp_p"]),
+    },
+    HardwareBackend {
+        encoder: "h264_mf",
+        device_args: &[],
+        filter: None,
+        low_latency_args: &["-scenario", "display_remoting"],
+        qp_args: None,
+    },
+];
+
+#[cfg(target_os = "macos")]
+const HARDWARE_BACKENDS: &[HardwareBackend] = &[HardwareBackend {
+    encoder: "h264_videotoolbox",
+    device_args: &[],
+    filter: None,
+    low_latency_args: &["-realtime", "1"],
+    qp_args: None,
+}];
+
+#[cfg(target_os = "linux")]
+const HARDWARE_BACKENDS: &[HardwareBackend] = &[
+    NVENC,
+    HardwareBackend {
+        encoder: "h264_vaapi",
+        device_args: &["-vaapi_device", "/dev/dri/renderD128"],
+        filter: Some("format=nv12,hwupload"),
+        low_latency_args: &[],
+        qp_args: Some(&["-rc_mode", "CQP", "-qp"]),
+    },
+    QSV,
+];
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+const HARDWARE_BACKENDS: &[HardwareBackend] = &[];
+
+fn ffmpeg_command() -> std::process::Command {
+    let program = std::env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+    let mut command = std::process::Command::new(program);
+    command.args(["-hide_banner", "-loglevel", "error", "-nostats"]);
+    command
+}
+
+impl HardwareBackend {
+    // Everything after the input: filter, encoder, rate control and the Annex-B muxer.
+    // Each access unit starts with an AUD so the output can be cut into frames, and
+    // keyframes repeat SPS/PPS like openh264's do.
+    fn output_args(&self, fps: u32, low_latency: bool, quality: QualityMode) -> Result<Vec<String>, String> {
+        let mut args: Vec<String> = Vec::new();
+        if let Some(filter) = self.filter {
+            args.extend(["-vf".to_string(), filter.to_string()]);
+        }
+        args.extend(["-c:v", self.encoder, "-bf", "0", "-g"].iter().map(|s| s.to_string()));
+        let gop_secs = match STREAM_GOP_SECS.load(Ordering::Relaxed) {
+            0 => HARDWARE_GOP_SECS,
+            secs => secs,
+        };
+        args.push((fps * gop_secs).to_string());
+        if low_latency {
+            args.extend(self.low_latency_args.iter().map(|s| s.to_string()));
+        }
+        match quality {
+            QualityMode::Bitrate(bps) => args.extend(["-b:v".to_string(), bps.to_string()]),
+            QualityMode::ConstantQp(qp) => {
+                let qp_args = self.qp_args.ok_or_else(|| format!("{} has no constant-QP mode", self.encoder))?;
+                args.extend(qp_args.iter().map(|s| s.to_string()));
+                args.push(qp.to_string());
+            }
+        }
+        args.extend(["-bsf:v", "dump_extra=freq=keyframe,h264_metadata=aud=insert", "-f", "h264", "-"].iter().map(|s| s.to_string()));
+        Ok(args)
+    }
+    
+    // Encodes a few frames of a generated clip: the encoder is compiled in and the
+    // GPU/driver behind it actually works
+    fn probe(&self) -> bool {
+        let args = match self.output_args(30, true, QualityMode::Bitrate(DEFAULT_BITRATE_BPS)) {
+            Ok(args) => args,
+            Err(_) => return false,
+        };
+        ffmpeg_command()
+            .args(self.device_args)
+            .args(["-f", "lavfi", "-i", "color=black:s=640x360:r=30", "-frames:v", "3"])
+            .args(&args[..args.len() - 1])
+            .arg("-y")
+            .arg(if cfg!(target_os = "windows") { "NUL" } else { "/dev/null" })
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+lazy_static::lazy_static! {
+    // Probed on first hw_accel use: Some(None) = probed, nothing works
+    static ref HARDWARE_ENCODER: Mutex<Option<Option<&'static HardwareBackend>>> = Mutex::new(None);
+}
+
+fn hardware_encoder() -> Option<&'static HardwareBackend> {
+    let mut probed = HARDWARE_ENCODER.lock();
+    *probed.get_or_insert_with(|| {
+        let found = HARDWARE_BACKENDS.iter().find(|backend| backend.probe());
+        match found {
+            Some(backend) => println!("Hardware H.264 encoder: {}", backend.encoder),
+            None => println!("No hardware H.264 encoder found (is ffmpeg installed?)"),
+        }
+        found
+    })
+}
+
+struct HardwareEncoder {
+    backend: &'static HardwareBackend,
+    args: Vec<String>,
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    // Access units cut from stdout by the reader thread
+    units: std::sync::mpsc::Receiver<Vec<u8>>,
+    started: Instant,
+    keyframe_pending: bool,
+}
+
+impl HardwareEncoder {
+    fn spawn(backend: &'static HardwareBackend, width: usize, height: usize, fps: u32, low_latency: bool, quality: QualityMode) -> Result<Self, String> {
+        let mut args: Vec<String> = backend.device_args.iter().map(|s| s.to_string()).collect();
+        args.extend(["-f", "rawvideo", "-pix_fmt", "nv12"].iter().map(|s| s.to_string()));
+        args.extend(["-s".to_string(), format!("{}x{}", width, height), "-r".to_string(), fps.to_string()]);
+        args.extend(["-i".to_string(), "-".to_string()]);
+        args.extend(backend.output_args(fps, low_latency, quality)?);
+        Self::spawn_with_args(backend, args)
+    }
+    
+    fn spawn_with_args(backend: &'static HardwareBackend, args: Vec<String>) -> Result<Self, String> {
+        let mut child = ffmpeg_command()
+            .args(&args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("Cannot start ffmpeg: {}", e))?;
+        let stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+        let mut stdout = child.stdout.take().ok_or("ffmpeg has no stdout")?;
+        
+        let (tx, units) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            use std::io::Read;
+            let mut pending = Vec::new();
+            let mut chunk = vec![0u8; 64 * 1024];
+            loop {
+                match stdout.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        pending.extend_from_slice(&chunk[..n]);
+                        for unit in take_access_units(&mut pending) {
+                            if tx.send(unit).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            // At EOF the last access unit is complete too
+            pending.extend_from_slice(&[0, 0, 1, 9, 0xF0]);
+            for unit in take_access_units(&mut pending) {
+                let _ = tx.send(unit);
+            }
+        });
+        
+        Ok(Self { backend, args, child, stdin, units, started: Instant::now(), keyframe_pending: false })
+    }
+    
+    // One input frame in, at most one access unit out: the encoder runs a few frames
+    // behind, so the first calls return nothing and later ones drain in order
+    fn encode(&mut self, yuv: &[u8]) -> Result<Vec<u8>, String> {
+        use std::io::Write;
+        if self.keyframe_pending && self.started.elapsed() >= HARDWARE_KEYFRAME_MIN_INTERVAL {
+            *self = Self::spawn_with_args(self.backend, self.args.clone())?;
+        }
+        self.stdin.write_all(yuv).map_err(|e| format!("{} stopped: {}", self.backend.encoder, e))?;
+        match self.units.try_recv() {
+            Ok(unit) => Ok(unit),
+            Err(std::sync::mpsc::TryRecvError::Empty) => Ok(Vec::new()),
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => Err(format!("{} exited", self.backend.encoder)),
+        }
+    }
+}
+
+impl Drop for HardwareEncoder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Start of each AUD (00 00 01 09) in an Annex-B stream, including the leading zero
+// of a 4-byte start code
+fn aud_positions(data: &[u8]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 && data[i + 3] & 0x1F == 9 {
+            positions.push(if i > 0 && data[i - 1] == 0 { i - 1 } else { i });
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+    positions
+}
+
+// Complete access units at the front of an AUD-delimited stream, AUDs stripped.
+// The unit after the last AUD may still be arriving, so it stays in data.
+fn take_access_units(data: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let positions = aud_positions(data);
+    let mut units = Vec::new();
+    for pair in positions.windows(2) {
+        let unit = &data[pair[0]..pair[1]];
+        // Skip the AUD itself: start code, NAL header and its one-byte payload
+        let aud_len = if unit[0] == 0 && unit[1] == 0 && unit[2] == 0 { 6 } else { 5 };
+        if unit.len() > aud_len {
+            units.push(unit[aud_len..].to_vec());
+        }
+    }
+    if let Some(&last) = positions.last() {
+        data.drain(..last);
+    }
+    units
+}
+
+// NAL units in an Annex-B buffer (00 00 01 / 00 00 00 01 start codes), start codes stripped
+fn split_h264_nals(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    
+    let mut nals = Vec::with_capacity(starts.len());
+    for (n, &start) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map_or(data.len(), |&next| next - 3);
+        // A NAL never ends in 0x00, so trailing zeros are the next 4-byte start code
+        while end > start && data[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > start {
+            nals.push(&data[start..end]);
+        }
+    }
+    
+    nals
+}
+
+fn h264_nal_types(data: &[u8]) -> Vec<u8> {
+    split_h264_nals(data).iter().map(|nal| nal[0] & 0x1F).collect()
+}
+
+fn is_h264_keyframe(data: &[u8]) -> bool {
+    h264_nal_types(data).contains(&5) // 5 = IDR slice
+}
+
+// SPS/PPS NAL units without start codes
+#[derive(Clone, PartialEq)]
+struct ParameterSets {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+}
+
+impl ParameterSets {
+    // Sent on keyframes with set_parameter_set_hash, so a viewer notices new sets
+    fn hash(&self) -> u32 {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        hasher.update(&self.sps);
+        hasher.update(&self.pps);
+        hasher.digest() as u32
+    }
+}
+
+fn extract_parameter_sets(data: &[u8]) -> Option<ParameterSets> {
+    let nals = split_h264_nals(data);
+    let find = |nal_type: u8| nals.iter().find(|nal| nal[0] & 0x1F == nal_type).map(|nal| nal.to_vec());
+    
+    Some(ParameterSets {
+        sps: find(7)?,
+        pps: find(8)?,
+    })
+}
+
+// Where each destination pixel samples from. With preserve_aspect the source is
+// fitted inside the destination and centered; pixels outside that rect are borders.
+struct ResizeMap {
+    off_x: usize,
+    off_y: usize,
+    out_w: usize,
+    out_h: usize,
+    scale_x: f32,
+    scale_y: f32,
+}
+
+impl ResizeMap {
+    fn new(src_w: usize, src_h: usize, dst_w: usize, dst_h: usize, preserve_aspect: bool) -> Self {
+        let (out_w, out_h) = if preserve_aspect {
+            let scale = (dst_w as f32 / src_w as f32).min(dst_h as f32 / src_h as f32);
+            // Even sizes/offsets keep the bars aligned with the 2x2 chroma blocks
+            let out_w = (((src_w as f32 * scale) as usize) & !1).clamp(2, dst_w);
+            let out_h = (((src_h as f32 * scale) as usize) & !1).clamp(2, dst_h);
+            (out_w, out_h)
+        } else {
+            (dst_w, dst_h)
+        };
+        
+        Self {
+            off_x: ((dst_w - out_w) / 2) & !1,
+            off_y: ((dst_h - out_h) / 2) & !1,
+            out_w,
+            out_h,
+            scale_x: src_w as f32 / out_w as f32,
+            scale_y: src_h as f32 / out_h as f32,
+        }
+    }
+    
+    // Source (x, y) for a destination pixel, None inside the letterbox bars
+    fn source(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if x < self.off_x || y < self.off_y || x >= self.off_x + self.out_w || y >= self.off_y + self.out_h {
+            return None;
+        }
+        Some((
+            ((x - self.off_x) as f32 * self.scale_x) as usize,
+            ((y - self.off_y) as f32 * self.scale_y) as usize,
+        ))
+    }
+}
+
+// Limited range: Y 16-235, UV 16-240 (what H.264 assumes unless told otherwise).
+// Full range: 0-255 for both. BT.709 is the HD matrix; BT.601 the SD one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ColorMatrix {
+    #[default]
+    Bt601,
+    Bt709,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct ColorSpace {
+    matrix: ColorMatrix,
+    full_range: bool,
+}
+
+// 8.8 fixed-point rows for R, G, B
+struct YuvCoefficients {
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+    y_offset: i32,
+}
+
+impl ColorSpace {
+    fn parse(color_range: Option<&str>, matrix: Option<&str>) -> Result<Self, String> {
+        let full_range = match color_range.map(|r| r.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("limited") => false,
+            Some("full") => true,
+            Some(other) => return Err(format!("Unknown color range: {} (expected limited or full)", other)),
+        };
+        let matrix = match matrix.map(|m| m.trim().to_ascii_lowercase().replace(['.', '_', '-'], "")).as_deref() {
+            None | Some("bt601") => ColorMatrix::Bt601,
+            Some("bt709") => ColorMatrix::Bt709,
+            Some(other) => return Err(format!("Unknown color matrix: {} (expected bt601 or bt709)", other)),
+        };
+        Ok(Self { matrix, full_range })
+    }
+    
+    fn coefficients(self) -> YuvCoefficients {
+        // Rows sum to 0 for U/V so greys stay neutral
+        match (self.matrix, self.full_range) {
+            (ColorMatrix::Bt601, false) => YuvCoefficients {
+                y: [66, 129, 25], u: [-38, -74, 112], v: [112, -94, -18], y_offset: 16,
+            },
+            (ColorMatrix::Bt601, true) => YuvCoefficients {
+                y: [77, 150, 29], u: [-43, -85, 128], v: [128, -107, -21], y_offset: 0,
+            },
+            (ColorMatrix::Bt709, false) => YuvCoefficients {
+                y: [47, 157, 16], u: [-26, -86, 112], v: [112, -102, -10], y_offset: 16,
+            },
+            (ColorMatrix::Bt709, true) => YuvCoefficients {
+                y: [54, 183, 19], u: [-29, -99, 128], v: [128, -116, -12], y_offset: 0,
+            },
+        }
+    }
+    
+    // protocol::FLAG_* bits for the H.264 header
+    fn header_flags(self) -> u8 {
+        let mut flags = 0;
+        if self.full_range {
+            flags |= protocol::FLAG_FULL_RANGE;
+        }
+        if self.matrix == ColorMatrix::Bt709 {
+            flags |= protocol::FLAG_BT709;
+        }
+        flags
+    }
+    
+    fn from_header_flags(flags: u8) -> Self {
+        Self {
+            matrix: if flags & protocol::FLAG_BT709 != 0 { ColorMatrix::Bt709 } else { ColorMatrix::Bt601 },
+            full_range: flags & protocol::FLAG_FULL_RANGE != 0,
+        }
+    }
+    
+    fn matrix_name(self) -> &'static str {
+        match self.matrix {
+            ColorMatrix::Bt601 => "bt601",
+            ColorMatrix::Bt709 => "bt709",
+        }
+    }
+    
+    fn range_name(self) -> &'static str {
+        if self.full_range { "full" } else { "limited" }
+    }
+}
+
+// 4:2:0 chroma layout. I420: full U plane then full V plane. NV12: one plane
+// of interleaved U,V pairs, which many hardware encoders and decoders want.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum PixelFormat {
+    #[default]
+    I420,
+    Nv12,
+}
+
+impl PixelFormat {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "i420" | "yuv420p" => Ok(Self::I420),
+            "nv12" => Ok(Self::Nv12),
+            other => Err(format!("Unknown pixel format: {} (expected i420 or nv12)", other)),
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            Self::I420 => "i420",
+            Self::Nv12 => "nv12",
+        }
+    }
+    
+    fn header_flags(self) -> u8 {
+        if self == Self::Nv12 { protocol::FLAG_NV12 } else { 0 }
+    }
+    
+    fn from_header_flags(flags: u8) -> Self {
+        if flags & protocol::FLAG_NV12 != 0 { Self::Nv12 } else { Self::I420 }
+    }
+    
+    // Offsets of the U and V samples for chroma index i, given the Y plane size
+    fn chroma_offsets(self, y_size: usize, uv_size: usize, i: usize) -> (usize, usize) {
+        match self {
+            Self::I420 => (y_size + i, y_size + uv_size + i),
+            Self::Nv12 => (y_size + 2 * i, y_size + 2 * i + 1),
+        }
+    }
 }
 
 // BGRA to YUV420 with resize
+#[allow(clippy::too_many_arguments)]
 fn bgra_to_yuv420_resized(
     bgra: &[u8], 
     src_w: usize, 
     src_h: usize, 
     dst_w: usize, 
-    dst_h: usize
+    dst_h: usize,
+    preserve_aspect: bool,
+    color: ColorSpace,
+    format: PixelFormat
 ) -> Option<Vec<u8>> {
     let stride = bgra.len() / src_h;
-    let scale_x = src_w as f32 / dst_w as f32;
-    let scale_y = src_h as f32 / dst_h as f32;
+    let map = ResizeMap::new(src_w, src_h, dst_w, dst_h, preserve_aspect);
+    let k = color.coefficients();
     
     let y_size = dst_w * dst_h;
     let uv_size = (dst_w / 2) * (dst_h / 2);
-    let mut yuv = vec![0u8; y_size + uv_size * 2];
+    // Start out black (U=V=128) so letterbox bars need no extra pass
+    let mut yuv = vec![k.y_offset as u8; y_size];
+    yuv.resize(y_size + uv_size * 2, 128);
     
-    let (y_plane, uv_planes) = yuv.split_at_mut(y_size);
-    let (u_plane, v_plane) = uv_planes.split_at_mut(uv_size);
+    let y_plane = &mut yuv[..y_size];
     
     // Convert to Y plane
     for y in 0..dst_h {
-        let src_y = (y as f32 * scale_y) as usize;
         for x in 0..dst_w {
-            let src_x = (x as f32 * scale_x) as usize;
+            let (src_x, src_y) = match map.source(x, y) {
+                Some(p) => p,
+                None => continue,
+            };
             let i = src_y * stride + src_x * 4;
             
             if i + 2 < bgra.len() {
@@ -131,7 +904,7 @@ fn bgra_to_yuv420_resized(
                 let r = bgra[i + 2] as i32;
                 
                 // RGB to Y
-                let y_val = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+                let y_val = ((k.y[0] * r + k.y[1] * g + k.y[2] * b + 128) >> 8) + k.y_offset;
                 y_plane[y * dst_w + x] = y_val.clamp(0, 255) as u8;
             }
         }
@@ -139,9 +912,11 @@ fn bgra_to_yuv420_resized(
     
     // Convert to U and V planes (subsampled 2x2)
     for y in 0..(dst_h / 2) {
-        let src_y = ((y * 2) as f32 * scale_y) as usize;
         for x in 0..(dst_w / 2) {
-            let src_x = ((x * 2) as f32 * scale_x) as usize;
+            let (src_x, src_y) = match map.source(x * 2, y * 2) {
+                Some(p) => p,
+                None => continue,
+            };
             let i = src_y * stride + src_x * 4;
             
             if i + 2 < bgra.len() {
@@ -150,12 +925,12 @@ fn bgra_to_yuv420_resized(
                 let r = bgra[i + 2] as i32;
                 
                 // RGB to U, V
-                let u_val = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
-                let v_val = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+                let u_val = ((k.u[0] * r + k.u[1] * g + k.u[2] * b + 128) >> 8) + 128;
+                let v_val = ((k.v[0] * r + k.v[1] * g + k.v[2] * b + 128) >> 8) + 128;
                 
-                let uv_idx = y * (dst_w / 2) + x;
-                u_plane[uv_idx] = u_val.clamp(0, 255) as u8;
-                v_plane[uv_idx] = v_val.clamp(0, 255) as u8;
+                let (u_idx, v_idx) = format.chroma_offsets(y_size, uv_size, y * (dst_w / 2) + x);
+                yuv[u_idx] = u_val.clamp(0, 255) as u8;
+                yuv[v_idx] = v_val.clamp(0, 255) as u8;
             }
         }
     }
@@ -163,739 +938,7969 @@ fn bgra_to_yuv420_resized(
     Some(yuv)
 }
 
-// JPEG encoding for fallback/preview
-fn encode_jpeg(bgra: &[u8], src_w: usize, src_h: usize, quality: u8) -> Option<Vec<u8>> {
+// ============== Cursor Highlight ==============
+const CURSOR_HIGHLIGHT_MIN_RADIUS: u32 = 4;
+const CURSOR_HIGHLIGHT_MAX_RADIUS: u32 = 128;
+
+#[derive(Clone, Copy)]
+struct CursorHighlight {
+    enabled: bool,
+    radius: u32,    // in stream pixels
+    color: [u8; 4], // RGBA, alpha = opacity
+}
+
+// Track the real cursor position (local user + injected input) via rdev's global hook
+fn start_cursor_tracker() {
+    if CURSOR_TRACKER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(|| {
+        let result = rdev::listen(|event| {
+            if let EventType::MouseMove { x, y } = event.event_type {
+                *CURSOR_POS.lock() = Some((x, y));
+            }
+        });
+
+        if let Err(e) = result {
+            eprintln!("Cursor tracker error: {:?}", e);
+            CURSOR_TRACKER_STARTED.store(false, Ordering::SeqCst);
+        }
+    });
+}
+
+// Composite a translucent circle at the cursor position into the BGRA frame,
+// so both the H.264 and JPEG encoders see it
+fn draw_cursor_highlight(bgra: &mut [u8], src_w: usize, src_h: usize) {
+    let highlight = *CURSOR_HIGHLIGHT.lock();
+    if !highlight.enabled || src_w == 0 || src_h == 0 {
+        return;
+    }
+
+    let (cursor_x, cursor_y) = match *CURSOR_POS.lock() {
+        Some(pos) => pos,
+        None => return,
+    };
+
+    // rdev reports logical coordinates; the capture is in physical pixels (HiDPI)
+    let (scale_x, scale_y) = match rdev::display_size() {
+        Ok((w, h)) if w > 0 && h > 0 => (src_w as f64 / w as f64, src_h as f64 / h as f64),
+        _ => (1.0, 1.0),
+    };
+
+    let cx = (cursor_x * scale_x) as i64;
+    let cy = (cursor_y * scale_y) as i64;
+
+    // Radius is given in stream pixels, scale it up to source pixels
+    let radius = (highlight.radius as usize * src_w / capped_stream_size().0).max(1) as i64;
+
+    // Cursor entirely off the captured region
+    if cx + radius < 0 || cy + radius < 0 || cx - radius >= src_w as i64 || cy - radius >= src_h as i64 {
+        return;
+    }
+
     let stride = bgra.len() / src_h;
-    let dst_w = STREAM_WIDTH;
-    let dst_h = STREAM_HEIGHT;
-    let scale_x = src_w as f32 / dst_w as f32;
-    let scale_y = src_h as f32 / dst_h as f32;
-    
-    let mut rgb = Vec::with_capacity(dst_w * dst_h * 3);
-    
-    for y in 0..dst_h {
-        let src_y = (y as f32 * scale_y) as usize;
-        for x in 0..dst_w {
-            let src_x = (x as f32 * scale_x) as usize;
-            let i = src_y * stride + src_x * 4;
+    let [r, g, b, a] = highlight.color;
+    let alpha = a as u32;
+    let inv_alpha = 255 - alpha;
+
+    let x0 = (cx - radius).max(0) as usize;
+    let x1 = (cx + radius).min(src_w as i64 - 1) as usize;
+    let y0 = (cy - radius).max(0) as usize;
+    let y1 = (cy + radius).min(src_h as i64 - 1) as usize;
+    let radius_sq = radius * radius;
+
+    for y in y0..=y1 {
+        let dy = y as i64 - cy;
+        for x in x0..=x1 {
+            let dx = x as i64 - cx;
+            if dx * dx + dy * dy > radius_sq {
+                continue;
+            }
+
+            let i = y * stride + x * 4;
             if i + 2 < bgra.len() {
-                rgb.push(bgra[i + 2]); // R
-                rgb.push(bgra[i + 1]); // G
-                rgb.push(bgra[i]);     // B
-            } else {
-                rgb.extend_from_slice(&[0, 0, 0]);
+                bgra[i] = ((bgra[i] as u32 * inv_alpha + b as u32 * alpha) / 255) as u8;
+                bgra[i + 1] = ((bgra[i + 1] as u32 * inv_alpha + g as u32 * alpha) / 255) as u8;
+                bgra[i + 2] = ((bgra[i + 2] as u32 * inv_alpha + r as u32 * alpha) / 255) as u8;
             }
         }
     }
-    
-    let img = image::RgbImage::from_raw(dst_w as u32, dst_h as u32, rgb)?;
-    let mut buffer = std::io::Cursor::new(Vec::with_capacity(50000));
-    img.write_to(&mut buffer, image::ImageOutputFormat::Jpeg(quality)).ok()?;
-    Some(buffer.into_inner())
 }
 
+// ============== Stream Watermark ==============
+// Compliance stamp burned into every streamed frame; "{timestamp}" in the text
+// is replaced with the current UTC time on each frame
+struct StreamWatermark {
+    text: String,
+    position: WatermarkPosition,
+    opacity: f32,
+}
 
-// ============== H.264 UDP Streaming ==============
-fn start_h264_streaming(server_addr: String, fps: u32) -> Result<(), String> {
-    if STREAMING.swap(true, Ordering::SeqCst) {
-        return Err("Already streaming".to_string());
-    }
-    
-    thread::spawn(move || {
-        let socket = match UdpSocket::bind("0.0.0.0:0") {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("UDP bind error: {}", e);
-                STREAMING.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-        
-        let mut capturer = match ScreenCapturer::new() {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Capturer error: {}", e);
-                STREAMING.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-        
-        let mut encoder = match H264Encoder::new(STREAM_WIDTH, STREAM_HEIGHT) {
-            Ok(e) => e,
-            Err(e) => {
-                eprintln!("H264 encoder error: {}", e);
-                STREAMING.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-        
-        let frame_interval = Duration::from_micros(1_000_000 / fps as u64);
-        let mut sequence: u32 = 0;
-        let mut last_frame_time = Instant::now();
-        
-        println!("H.264 UDP streaming started to {} at {} FPS ({}x{})", 
-                 server_addr, fps, STREAM_WIDTH, STREAM_HEIGHT);
-        
-        let mut encode_errors = 0u32;
-        
-        while STREAMING.load(Ordering::SeqCst) {
-            let now = Instant::now();
-            
-            if let Some(bgra) = capturer.capture() {
-                // Encode to H.264
-                if let Some(h264_data) = encoder.encode(&bgra, capturer.width, capturer.height) {
-                    // Send via UDP with H264 magic header
-                    if send_h264_udp(&socket, &server_addr, &h264_data, sequence).is_ok() {
-                        sequence = sequence.wrapping_add(1);
-                        FRAME_COUNT.fetch_add(1, Ordering::Relaxed);
-                        if sequence % 30 == 0 {
-                            println!("Sent {} H.264 frames ({} bytes)", sequence, h264_data.len());
-                        }
-                    }
-                    
-                    *LAST_H264_FRAME.lock() = Some(h264_data);
-                } else {
-                    encode_errors += 1;
-                    if encode_errors % 30 == 1 {
-                        println!("H.264 encode failed (errors: {})", encode_errors);
-                    }
-                }
-                
-                // Also encode JPEG for preview/fallback
-                if let Some(jpeg) = encode_jpeg(&bgra, capturer.width, capturer.height, 60) {
-                    *LAST_JPEG_FRAME.lock() = Some(jpeg);
-                }
-                
-                let elapsed = now.elapsed();
-                if elapsed < frame_interval {
-                    thread::sleep(frame_interval - elapsed);
-                }
-                last_frame_time = Instant::now();
-            } else {
-                thread::sleep(Duration::from_millis(1));
-                
-                if last_frame_time.elapsed() > Duration::from_secs(2) {
-                    if let Ok(new_capturer) = ScreenCapturer::new() {
-                        capturer = new_capturer;
-                        last_frame_time = Instant::now();
-                    }
-                }
-            }
-        }
-        
-        println!("H.264 streaming stopped");
-    });
+lazy_static::lazy_static! {
+    static ref STREAM_WATERMARK: Mutex<Option<StreamWatermark>> = Mutex::new(None);
+}
+
+fn apply_stream_watermark(bgra: &mut [u8], src_w: usize, src_h: usize) {
+    let watermark = STREAM_WATERMARK.lock();
+    let Some(watermark) = watermark.as_ref() else {
+        return;
+    };
     
+    let text = if watermark.text.contains("{timestamp}") {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        watermark.text.replace("{timestamp}", &watermark::format_utc_timestamp(now))
+    } else {
+        watermark.text.clone()
+    };
+    watermark::draw_watermark(bgra, src_w, src_h, &text, watermark.position, watermark.opacity);
+}
+
+// text: e.g. "Session recorded - alice {timestamp}"; None or empty removes the stamp
+// position: top-left | top-right | bottom-left | bottom-right (default) | center
+// opacity: 0.0-1.0 (default 0.8)
+#[tauri::command]
+fn set_stream_watermark(
+    text: Option<String>,
+    position: Option<String>,
+    opacity: Option<f32>
+) -> Result<(), String> {
+    *STREAM_WATERMARK.lock() = parse_stream_watermark(text, position.as_deref(), opacity)?;
     Ok(())
 }
 
-fn send_h264_udp(socket: &UdpSocket, addr: &str, data: &[u8], sequence: u32) -> Result<(), String> {
-    const MAX_PAYLOAD: usize = 1400;
-    const HEADER_SIZE: usize = 12;
-    
-    let chunk_size = MAX_PAYLOAD - HEADER_SIZE;
-    let total_chunks = (data.len() + chunk_size - 1) / chunk_size;
+fn parse_stream_watermark(text: Option<String>, position: Option<&str>, opacity: Option<f32>) -> Result<Option<StreamWatermark>, String> {
+    let text = text.unwrap_or_default();
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
     
-    for (i, chunk) in data.chunks(chunk_size).enumerate() {
-        let mut packet = Vec::with_capacity(HEADER_SIZE + chunk.len());
-        
-        // Header: magic(2) + type(1) + flags(1) + seq(4) + idx(2) + total(2)
-        packet.extend_from_slice(b"H4");  // H.264 magic
-        packet.push(if i == 0 { 0x01 } else { 0x00 }); // type: 1=keyframe start
-        packet.push(0x00); // flags reserved
-        packet.extend_from_slice(&sequence.to_le_bytes());
-        packet.extend_from_slice(&(i as u16).to_le_bytes());
-        packet.extend_from_slice(&(total_chunks as u16).to_le_bytes());
-        packet.extend_from_slice(chunk);
-        
-        if socket.send_to(&packet, addr).is_err() {
-            return Err("Send failed".to_string());
-        }
+    let position = match position {
+        Some(name) => WatermarkPosition::parse(name)?,
+        None => WatermarkPosition::BottomRight,
+    };
+    let opacity = opacity.unwrap_or(0.8);
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(format!("Opacity must be between 0 and 1, got {}", opacity));
     }
     
-    Ok(())
+    Ok(Some(StreamWatermark { text, position, opacity }))
 }
 
-
-// ============== H.264 UDP Receiver ==============
-fn start_h264_receiver(app: tauri::AppHandle, port: u16) -> Result<(), String> {
-    if UDP_RECEIVER_RUNNING.swap(true, Ordering::SeqCst) {
-        return Err("Already running".to_string());
-    }
+// Nearest-neighbour resize of BGRA into packed RGB
+fn bgra_to_rgb_resized(
+    bgra: &[u8],
+    src_w: usize,
+    src_h: usize,
+    dst_w: usize,
+    dst_h: usize,
+    preserve_aspect: bool
+) -> Vec<u8> {
+    let stride = bgra.len() / src_h;
+    let map = ResizeMap::new(src_w, src_h, dst_w, dst_h, preserve_aspect);
     
-    thread::spawn(move || {
-        let socket = match UdpSocket::bind(format!("0.0.0.0:{}", port)) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("UDP bind error on port {}: {}", port, e);
-                UDP_RECEIVER_RUNNING.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-        
-        let _ = socket.set_read_timeout(Some(Duration::from_millis(100)));
-        
-        let mut frame_buffer = H264FrameAssembler::new();
-        let mut buf = [0u8; 1500];
-        let mut last_emit = Instant::now();
-        let emit_interval = Duration::from_millis(33);
-        
-        println!("H.264 UDP receiver started on port {}", port);
-        
-        while UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
-            match socket.recv_from(&mut buf) {
-                Ok((len, addr)) => {
-                    if len < 12 {
-                        continue;
-                    }
-                    
-                    // Check magic header
-                    if &buf[0..2] == b"H4" {
-                        // H.264 frame
-                        let _frame_type = buf[2];
-                        let seq = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
-                        let idx = u16::from_le_bytes([buf[8], buf[9]]) as usize;
-                        let total = u16::from_le_bytes([buf[10], buf[11]]) as usize;
-                        let payload = &buf[12..len];
-                        
-                        if let Some(h264_frame) = frame_buffer.add_chunk(seq, idx, total, payload) {
-                            if last_emit.elapsed() >= emit_interval {
-                                let base64_str = general_purpose::STANDARD.encode(&h264_frame);
-                                let _ = app.emit("h264-frame", (&addr.ip().to_string(), base64_str));
-                                last_emit = Instant::now();
-                            }
-                        }
-                    } else if &buf[0..2] == b"SF" {
-                        // Legacy JPEG frame (backward compatible)
-                        let seq = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
-                        let idx = u16::from_le_bytes([buf[6], buf[7]]) as usize;
-                        let total = u16::from_le_bytes([buf[8], buf[9]]) as usize;
-                        let payload = &buf[10..len];
-                        
-                        if let Some(jpeg_frame) = frame_buffer.add_chunk(seq, idx, total, payload) {
-                            if last_emit.elapsed() >= emit_interval {
-                                let base64_str = general_purpose::STANDARD.encode(&jpeg_frame);
-                                let data_url = format!("data:image/jpeg;base64,{}", base64_str);
-                                let _ = app.emit("udp-frame", (&addr.ip().to_string(), data_url));
-                                last_emit = Instant::now();
-                            }
-                        }
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || 
-                             e.kind() == std::io::ErrorKind::TimedOut => {
-                    continue;
-                }
-                Err(_) => {
-                    thread::sleep(Duration::from_millis(10));
+    let mut rgb = Vec::with_capacity(dst_w * dst_h * 3);
+    
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            // Letterbox bars and out-of-range reads are black
+            match map.source(x, y).map(|(src_x, src_y)| src_y * stride + src_x * 4) {
+                Some(i) if i + 2 < bgra.len() => {
+                    rgb.push(bgra[i + 2]); // R
+                    rgb.push(bgra[i + 1]); // G
+                    rgb.push(bgra[i]);     // B
                 }
+                _ => rgb.extend_from_slice(&[0, 0, 0]),
             }
         }
-        
-        println!("H.264 receiver stopped");
-    });
+    }
     
-    Ok(())
+    rgb
 }
 
-struct H264FrameAssembler {
-    current_seq: u32,
-    chunks: Vec<Option<Vec<u8>>>,
-    total: usize,
-    received: usize,
+fn encode_rgb_jpeg(rgb: Vec<u8>, width: usize, height: usize, quality: u8) -> Option<Vec<u8>> {
+    let img = image::RgbImage::from_raw(width as u32, height as u32, rgb)?;
+    let mut buffer = std::io::Cursor::new(Vec::with_capacity(50000));
+    img.write_to(&mut buffer, image::ImageOutputFormat::Jpeg(quality)).ok()?;
+    Some(buffer.into_inner())
 }
 
-impl H264FrameAssembler {
-    fn new() -> Self {
-        Self {
-            current_seq: u32::MAX,
-            chunks: Vec::new(),
-            total: 0,
-            received: 0,
+// JPEG encoding for fallback/preview
+fn encode_jpeg(bgra: &[u8], src_w: usize, src_h: usize, quality: u8) -> Option<Vec<u8>> {
+    let preserve_aspect = PRESERVE_ASPECT.load(Ordering::Relaxed);
+    let (width, height) = capped_stream_size();
+    let rgb = bgra_to_rgb_resized(bgra, src_w, src_h, width, height, preserve_aspect);
+    encode_rgb_jpeg(rgb, width, height, quality)
+}
+
+// Full-resolution RGBA PNG, no scaling (lossless capture)
+fn encode_png(bgra: &[u8], width: usize, height: usize) -> Option<Vec<u8>> {
+    if height == 0 {
+        return None;
+    }
+    let stride = bgra.len() / height;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in bgra.chunks(stride).take(height) {
+        for px in row.get(..width * 4)?.chunks_exact(4) {
+            rgba.extend_from_slice(&[px[2], px[1], px[0], 255]);
         }
     }
     
-    fn add_chunk(&mut self, seq: u32, idx: usize, total: usize, data: &[u8]) -> Option<Vec<u8>> {
-        if seq != self.current_seq {
-            self.current_seq = seq;
-            self.chunks = vec![None; total];
-            self.total = total;
-            self.received = 0;
-        }
-        
-        if idx < self.total && self.chunks[idx].is_none() {
-            self.chunks[idx] = Some(data.to_vec());
-            self.received += 1;
-        }
-        
-        if self.received == self.total {
-            let mut result = Vec::with_capacity(self.total * 1400);
-            for chunk in &self.chunks {
-                if let Some(data) = chunk {
-                    result.extend_from_slice(data);
-                }
-            }
-            
-            self.current_seq = u32::MAX;
-            self.chunks.clear();
-            self.received = 0;
-            
-            return Some(result);
+    let img = image::RgbaImage::from_raw(width as u32, height as u32, rgba)?;
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buffer, image::ImageOutputFormat::Png).ok()?;
+    Some(buffer.into_inner())
+}
+
+// Step quality down from `quality` until the JPEG fits in max_bytes.
+// Returns the smallest attempt (and its quality) even if nothing fits.
+fn encode_jpeg_within(bgra: &[u8], src_w: usize, src_h: usize, quality: u8, max_bytes: usize) -> Option<(Vec<u8>, u8)> {
+    const MIN_QUALITY: u8 = 10;
+    const QUALITY_STEP: u8 = 15;
+    
+    let preserve_aspect = PRESERVE_ASPECT.load(Ordering::Relaxed);
+    let (width, height) = capped_stream_size();
+    let rgb = bgra_to_rgb_resized(bgra, src_w, src_h, width, height, preserve_aspect);
+    let mut quality = quality.max(MIN_QUALITY);
+    
+    loop {
+        let jpeg = encode_rgb_jpeg(rgb.clone(), width, height, quality)?;
+        if jpeg.len() <= max_bytes || quality == MIN_QUALITY {
+            return Some((jpeg, quality));
         }
-        
-        None
+        quality = quality.saturating_sub(QUALITY_STEP).max(MIN_QUALITY);
     }
 }
 
 
-// ============== Input Simulation ==============
-fn send_event(event_type: &EventType) -> Result<(), String> {
-    match simulate(event_type) {
-        Ok(()) => {
-            thread::sleep(Duration::from_millis(5));
-            Ok(())
-        }
-        Err(SimulateError) => Err(format!("Failed: {:?}", event_type)),
-    }
+// ============== Encode Latency Histogram ==============
+// Bucket upper bounds in microseconds; the last bucket catches everything slower
+const LATENCY_BUCKETS_US: [u64; 11] = [
+    1_000, 2_000, 4_000, 8_000, 16_000, 33_000, 50_000, 100_000, 200_000, 500_000, u64::MAX,
+];
+
+lazy_static::lazy_static! {
+    static ref ENCODE_LATENCY_COUNTS: [AtomicU64; LATENCY_BUCKETS_US.len()] = Default::default();
+    static ref ENCODE_LATENCY_TOTAL_US: AtomicU64 = AtomicU64::new(0);
 }
 
-fn js_key_to_rdev(key: &str, code: &str) -> Option<Key> {
-    match code {
-        "KeyA" => Some(Key::KeyA), "KeyB" => Some(Key::KeyB), "KeyC" => Some(Key::KeyC),
-        "KeyD" => Some(Key::KeyD), "KeyE" => Some(Key::KeyE), "KeyF" => Some(Key::KeyF),
-        "KeyG" => Some(Key::KeyG), "KeyH" => Some(Key::KeyH), "KeyI" => Some(Key::KeyI),
-        "KeyJ" => Some(Key::KeyJ), "KeyK" => Some(Key::KeyK), "KeyL" => Some(Key::KeyL),
-        "KeyM" => Some(Key::KeyM), "KeyN" => Some(Key::KeyN), "KeyO" => Some(Key::KeyO),
-        "KeyP" => Some(Key::KeyP), "KeyQ" => Some(Key::KeyQ), "KeyR" => Some(Key::KeyR),
-        "KeyS" => Some(Key::KeyS), "KeyT" => Some(Key::KeyT), "KeyU" => Some(Key::KeyU),
-        "KeyV" => Some(Key::KeyV), "KeyW" => Some(Key::KeyW), "KeyX" => Some(Key::KeyX),
-        "KeyY" => Some(Key::KeyY), "KeyZ" => Some(Key::KeyZ),
-        "Digit0" => Some(Key::Num0), "Digit1" => Some(Key::Num1), "Digit2" => Some(Key::Num2),
-        "Digit3" => Some(Key::Num3), "Digit4" => Some(Key::Num4), "Digit5" => Some(Key::Num5),
-        "Digit6" => Some(Key::Num6), "Digit7" => Some(Key::Num7), "Digit8" => Some(Key::Num8),
-        "Digit9" => Some(Key::Num9),
-        "F1" => Some(Key::F1), "F2" => Some(Key::F2), "F3" => Some(Key::F3), "F4" => Some(Key::F4),
-        "F5" => Some(Key::F5), "F6" => Some(Key::F6), "F7" => Some(Key::F7), "F8" => Some(Key::F8),
-        "F9" => Some(Key::F9), "F10" => Some(Key::F10), "F11" => Some(Key::F11), "F12" => Some(Key::F12),
-        "Enter" | "NumpadEnter" => Some(Key::Return),
-        "Escape" => Some(Key::Escape), "Backspace" => Some(Key::Backspace),
-        "Tab" => Some(Key::Tab), "Space" => Some(Key::Space),
-        "Delete" => Some(Key::Delete), "Insert" => Some(Key::Insert),
-        "Home" => Some(Key::Home), "End" => Some(Key::End),
-        "PageUp" => Some(Key::PageUp), "PageDown" => Some(Key::PageDown),
-        "ArrowUp" => Some(Key::UpArrow), "ArrowDown" => Some(Key::DownArrow),
-        "ArrowLeft" => Some(Key::LeftArrow), "ArrowRight" => Some(Key::RightArrow),
-        "ShiftLeft" | "ShiftRight" => Some(Key::ShiftLeft),
-        "ControlLeft" | "ControlRight" => Some(Key::ControlLeft),
-        "AltLeft" | "AltRight" => Some(Key::Alt),
-        "MetaLeft" | "MetaRight" => Some(Key::MetaLeft),
-        _ => if key == " " { Some(Key::Space) } else { None }
+fn record_encode_latency(elapsed: Duration) {
+    let us = elapsed.as_micros() as u64;
+    let bucket = LATENCY_BUCKETS_US.iter().position(|&le| us <= le).unwrap_or(LATENCY_BUCKETS_US.len() - 1);
+    ENCODE_LATENCY_COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+    ENCODE_LATENCY_TOTAL_US.fetch_add(us, Ordering::Relaxed);
+}
+
+fn reset_encode_latency() {
+    for count in ENCODE_LATENCY_COUNTS.iter() {
+        count.store(0, Ordering::Relaxed);
     }
+    ENCODE_LATENCY_TOTAL_US.store(0, Ordering::Relaxed);
 }
 
-// ============== Tauri Commands ==============
-#[tauri::command]
-fn capture_screen() -> Result<String, String> {
-    if let Some(jpeg) = LAST_JPEG_FRAME.lock().clone() {
-        let base64_str = general_purpose::STANDARD.encode(&jpeg);
-        return Ok(format!("data:image/jpeg;base64,{}", base64_str));
+// Upper bound (ms) of the bucket holding the given percentile. Frames in the
+// overflow bucket report the last finite bound, i.e. "at least this slow".
+fn latency_percentile_ms(counts: &[u64], total: u64, percentile: f64) -> f64 {
+    if total == 0 {
+        return 0.0;
     }
     
-    let mut capturer = ScreenCapturer::new()?;
+    let target = (total as f64 * percentile).ceil() as u64;
+    let mut cumulative = 0u64;
     
-    for _ in 0..30 {
-        if let Some(bgra) = capturer.capture() {
-            if let Some(jpeg) = encode_jpeg(&bgra, capturer.width, capturer.height, 60) {
-                let base64_str = general_purpose::STANDARD.encode(&jpeg);
-                return Ok(format!("data:image/jpeg;base64,{}", base64_str));
-            }
+    for (i, count) in counts.iter().enumerate() {
+        cumulative += count;
+        if cumulative >= target {
+            let bound_us = LATENCY_BUCKETS_US[i.min(LATENCY_BUCKETS_US.len() - 2)];
+            return bound_us as f64 / 1000.0;
         }
-        thread::sleep(Duration::from_millis(10));
     }
     
-    Err("Capture timeout".to_string())
+    LATENCY_BUCKETS_US[LATENCY_BUCKETS_US.len() - 2] as f64 / 1000.0
+}
+
+// ============== Socket Tuning ==============
+// Tradeoffs:
+// - nodelay disables Nagle on the TCP paths. Small latency-sensitive writes go out
+//   immediately instead of being coalesced; bulk transfers write full buffers, so
+//   for them it barely matters. UDP has no Nagle.
+// - Larger kernel send/recv buffers absorb bursts (keyframes, high-RTT links) and
+//   reduce drops, at the cost of memory and extra queueing delay when the link
+//   can't keep up. 0 keeps the OS default.
+// Changes apply to sockets created after the call.
+const SOCKET_BUFFER_MIN: usize = 4 * 1024;
+const SOCKET_BUFFER_MAX: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+struct SocketTuning {
+    nodelay: bool,
+    send_buffer_size: usize,
+    recv_buffer_size: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref SOCKET_TUNING: Mutex<SocketTuning> = Mutex::new(SocketTuning {
+        nodelay: true,
+        send_buffer_size: 0,
+        recv_buffer_size: 0,
+    });
+}
+
+fn apply_socket_buffers(socket: socket2::SockRef) {
+    let tuning = *SOCKET_TUNING.lock();
+    
+    if tuning.send_buffer_size > 0 {
+        if let Err(e) = socket.set_send_buffer_size(tuning.send_buffer_size) {
+            eprintln!("Cannot set send buffer size: {}", e);
+        }
+    }
+    if tuning.recv_buffer_size > 0 {
+        if let Err(e) = socket.set_recv_buffer_size(tuning.recv_buffer_size) {
+            eprintln!("Cannot set recv buffer size: {}", e);
+        }
+    }
+}
+
+fn apply_tcp_tuning(stream: &TcpStream) {
+    let _ = stream.set_nodelay(SOCKET_TUNING.lock().nodelay);
+    apply_socket_buffers(socket2::SockRef::from(stream));
+}
+
+fn validate_socket_buffer(name: &str, size: usize) -> Result<usize, String> {
+    if size != 0 && !(SOCKET_BUFFER_MIN..=SOCKET_BUFFER_MAX).contains(&size) {
+        return Err(format!(
+            "{} must be 0 (OS default) or between {} and {} bytes",
+            name, SOCKET_BUFFER_MIN, SOCKET_BUFFER_MAX
+        ));
+    }
+    Ok(size)
+}
+
+// ============== Stream Targets (fan-out) ==============
+// Every encoded frame is sent to each target. A target whose sends keep failing
+// is degraded to keyframes only, so one bad link doesn't hold up the others.
+const TARGET_DEGRADE_AFTER_FAILURES: u32 = 10;
+// Frames in a row where every send hit a socket error (not just a full buffer)
+// before the socket is rebound and targets re-resolved, e.g. after a NIC reset
+const SOCKET_REBIND_AFTER_ERRORS: u32 = 10;
+const SOCKET_REBIND_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const SOCKET_REBIND_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEGRADED_KEYFRAME_INTERVAL: Duration = Duration::from_secs(2);
+// The stream socket is non-blocking; a frame that hits a full send buffer waits up
+// to this long for room before the rest of it is given up
+const SEND_STALL_BUDGET: Duration = Duration::from_millis(10);
+// UDP sends succeed even with nobody listening, so receivers ack with a
+// heartbeat; a target silent for this long is reported unreachable (0 = off)
+const DEFAULT_UNREACHABLE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Clone, serde::Serialize)]
+struct StreamTarget {
+    addr: String,
+    frames_sent: u64,
+    frames_dropped: u64,
+    consecutive_failures: u32,
+    degraded: bool,
+    unreachable: bool,
+    // A frame to this target went out incomplete; P-frames are dropped until the next keyframe
+    awaiting_keyframe: bool,
+    #[serde(skip)]
+    resolved: Option<SocketAddr>,
+    #[serde(skip)]
+    last_ack: Instant, // starts at creation so a new target gets a full timeout
+}
+
+fn resolve_target(addr: &str) -> Option<SocketAddr> {
+    use std::net::ToSocketAddrs;
+    
+    addr.to_socket_addrs().ok().and_then(|mut a| a.next())
+}
+
+impl StreamTarget {
+    fn new(addr: String) -> Self {
+        Self {
+            resolved: resolve_target(&addr),
+            addr,
+            frames_sent: 0,
+            frames_dropped: 0,
+            consecutive_failures: 0,
+            degraded: false,
+            unreachable: false,
+            awaiting_keyframe: false,
+            last_ack: Instant::now(),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    // Shared with the main stream's registry entry
+    static ref STREAM_TARGETS: std::sync::Arc<Mutex<Vec<StreamTarget>>> = std::sync::Arc::new(Mutex::new(Vec::new()));
+    static ref UNREACHABLE_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_UNREACHABLE_TIMEOUT_SECS);
+    // Stop streaming once every target is unreachable
+    static ref AUTO_STOP_UNREACHABLE: AtomicBool = AtomicBool::new(false);
+    static ref STREAM_NETWORK_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+    // None = OS defaults: 64ish for unicast, 1 for multicast (stays on the local subnet)
+    static ref STREAM_TTL: Mutex<Option<u32>> = Mutex::new(None);
+}
+
+fn validate_ttl(ttl: u32) -> Result<u32, String> {
+    if !(1..=255).contains(&ttl) {
+        return Err(format!("ttl must be 1-255 (got {})", ttl));
+    }
+    Ok(ttl)
+}
+
+fn bind_stream_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    // Both TTLs, so a multicast target added mid-stream gets the same hop limit
+    if let Some(ttl) = *STREAM_TTL.lock() {
+        socket.set_ttl(ttl)?;
+        socket.set_multicast_ttl_v4(ttl)?;
+    }
+    apply_socket_buffers(socket2::SockRef::from(&socket));
+    // A full send buffer for one slow target must not stall the loop
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+// Fresh socket plus fresh DNS for every target; None keeps the old socket
+fn reconnect_stream_socket(targets: &mut [StreamTarget], error: &std::io::Error) -> Option<UdpSocket> {
+    let socket = match bind_stream_socket() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Stream socket rebind failed: {}", e);
+            return None;
+        }
+    };
+    for target in targets.iter_mut() {
+        target.resolved = resolve_target(&target.addr);
+        target.consecutive_failures = 0;
+    }
+    println!("Stream socket rebound after send errors ({})", error);
+    Some(socket)
+}
+
+// While the interface is down a rebind "works" (0.0.0.0:0 always binds) and the
+// next sends fail again, so rebinds back off, and stream-network-reconnected
+// only goes out once a frame actually got through on the new socket.
+struct SocketRebinder {
+    errors: u32,
+    backoff: Duration,
+    next_attempt: Instant,
+    // Error that caused the last rebind, until a send confirms it
+    unconfirmed: Option<String>,
+}
+
+impl SocketRebinder {
+    fn new() -> Self {
+        Self {
+            errors: 0,
+            backoff: SOCKET_REBIND_MIN_BACKOFF,
+            next_attempt: Instant::now(),
+            unconfirmed: None,
+        }
+    }
+    
+    fn on_send_ok(&mut self, app: Option<&tauri::AppHandle>, delivered: bool) {
+        self.errors = 0;
+        if !delivered {
+            return;
+        }
+        self.backoff = SOCKET_REBIND_MIN_BACKOFF;
+        if let Some(error) = self.unconfirmed.take() {
+            let reconnects = STREAM_NETWORK_RECONNECTS.fetch_add(1, Ordering::Relaxed) + 1;
+            println!("Stream network reconnected (#{})", reconnects);
+            if let Some(app) = app {
+                let _ = app.emit("stream-network-reconnected", serde_json::json!({
+                    "reconnects": reconnects,
+                    "error": error
+                }));
+            }
+        }
+    }
+    
+    // Some(new socket) when it is time to rebind and the bind worked
+    fn on_send_error(&mut self, targets: &mut [StreamTarget], error: &std::io::Error) -> Option<UdpSocket> {
+        self.errors += 1;
+        if self.errors < SOCKET_REBIND_AFTER_ERRORS || Instant::now() < self.next_attempt {
+            return None;
+        }
+        self.errors = 0;
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(SOCKET_REBIND_MAX_BACKOFF);
+        
+        let socket = reconnect_stream_socket(targets, error)?;
+        self.unconfirmed = Some(error.to_string());
+        Some(socket)
+    }
+}
+
+// Drain heartbeat acks that came back on the (non-blocking) streaming socket;
+// probe replies go to link, if the stream is measuring one.
+// Returns true if a receiver also asked for a keyframe (reset_receiver_decoder).
+fn poll_heartbeat_acks(socket: &UdpSocket, targets: &mut [StreamTarget], mut link: Option<&mut LinkMonitor>) -> bool {
+    let mut buf = [0u8; 64];
+    let mut keyframe_requested = false;
+    
+    while let Ok((len, from)) = socket.recv_from(&mut buf) {
+        if protocol::parse_keyframe_request(&buf[..len]).is_some() {
+            keyframe_requested = true;
+            continue;
+        }
+        if let Some(seq) = protocol::parse_probe_reply(&buf[..len]) {
+            if let Some(link) = link.as_deref_mut() {
+                link.on_reply(seq);
+            }
+            continue;
+        }
+        if protocol::parse_heartbeat_ack(&buf[..len]).is_none() {
+            continue;
+        }
+        for target in targets.iter_mut() {
+            if target.resolved == Some(from) {
+                target.last_ack = Instant::now();
+                if target.unreachable {
+                    println!("Stream target {} is reachable again", target.addr);
+                    target.unreachable = false;
+                }
+            }
+        }
+    }
+    keyframe_requested
+}
+
+// Emit "stream-target-unreachable" once per target that stopped acking.
+// Returns true when auto-stop is on and no target is left reachable.
+fn check_unreachable_targets(app: Option<&tauri::AppHandle>, targets: &mut [StreamTarget]) -> bool {
+    let timeout_secs = UNREACHABLE_TIMEOUT_SECS.load(Ordering::Relaxed);
+    if timeout_secs == 0 {
+        return false;
+    }
+    let timeout = Duration::from_secs(timeout_secs);
+    
+    for target in targets.iter_mut() {
+        let silent = target.last_ack.elapsed();
+        if !target.unreachable && silent >= timeout {
+            target.unreachable = true;
+            println!("Stream target {} unreachable (no heartbeat for {:?})", target.addr, silent);
+            if let Some(app) = app {
+                let _ = app.emit("stream-target-unreachable", serde_json::json!({
+                    "addr": target.addr,
+                    "silent_secs": silent.as_secs()
+                }));
+            }
+        }
+    }
+    
+    AUTO_STOP_UNREACHABLE.load(Ordering::Relaxed) && !targets.is_empty() && targets.iter().all(|t| t.unreachable)
+}
+
+// Ok(true) if the frame reached at least one target. Err when every send failed
+// on the socket itself; WouldBlock only means a full buffer and doesn't count.
+// A target whose frame was cut off by WouldBlock gets awaiting_keyframe; the caller
+// forces a keyframe while any target has it.
+fn send_to_stream_targets(
+    socket: &UdpSocket,
+    targets: &mut [StreamTarget],
+    data: &[u8],
+    sequence: u32,
+    keyframe: bool
+) -> std::io::Result<bool> {
+    let mut delivered = false;
+    let mut socket_error = None;
+    let mut attempted = 0;
+    let mut socket_failures = 0;
+    
+    let tagged = if keyframe && PARAM_SET_HASH.load(Ordering::Relaxed) {
+        extract_parameter_sets(data).map(|sets| protocol::prepend_param_hash(sets.hash(), data))
+    } else {
+        None
+    };
+    let (data, extra_flags) = match &tagged {
+        Some(tagged) => (tagged.as_slice(), protocol::FLAG_PARAM_HASH),
+        None => (data, 0),
+    };
+    
+    for target in targets.iter_mut() {
+        // Deltas against a frame the receiver never fully got would only show garbage
+        if (target.degraded || target.awaiting_keyframe) && !keyframe {
+            target.frames_dropped += 1;
+            continue;
+        }
+        
+        attempted += 1;
+        let sent = match target.resolved {
+            Some(addr) => send_h264_udp(socket, addr, data, sequence, extra_flags),
+            None => send_h264_udp(socket, target.addr.as_str(), data, sequence, extra_flags),
+        };
+        if let Err(e) = sent {
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                target.awaiting_keyframe = true;
+            } else {
+                socket_failures += 1;
+                socket_error = Some(e);
+            }
+            target.frames_dropped += 1;
+            target.consecutive_failures += 1;
+            if !target.degraded && target.consecutive_failures >= TARGET_DEGRADE_AFTER_FAILURES {
+                println!("Stream target {} is failing, sending keyframes only", target.addr);
+                target.degraded = true;
+            }
+        } else {
+            target.frames_sent += 1;
+            target.consecutive_failures = 0;
+            target.awaiting_keyframe = false;
+            if target.degraded {
+                println!("Stream target {} recovered", target.addr);
+                target.degraded = false;
+            }
+            delivered = true;
+        }
+    }
+    
+    match socket_error {
+        Some(e) if socket_failures == attempted => Err(e),
+        _ => Ok(delivered),
+    }
+}
+
+fn validate_stream_target(addr: &str) -> Result<(), String> {
+    use std::net::ToSocketAddrs;
+    
+    addr.to_socket_addrs()
+        .map_err(|e| format!("Invalid target {}: {}", addr, e))?
+        .next()
+        .map(|_| ())
+        .ok_or_else(|| format!("Cannot resolve target: {}", addr))
+}
+
+// ============== H.264 UDP Streaming ==============
+lazy_static::lazy_static! {
+    // Display the main stream captures (None = primary); a pending switch is picked up by the loop
+    static ref STREAM_DISPLAY: Mutex<Option<usize>> = Mutex::new(None);
+    static ref STREAM_DISPLAY_SWITCH: Mutex<Option<usize>> = Mutex::new(None);
+}
+
+fn stream_capturer(display: Option<usize>) -> Result<ScreenCapturer, String> {
+    match display {
+        Some(index) => ScreenCapturer::for_display(index),
+        None => ScreenCapturer::new(),
+    }
+}
+
+// When a frame takes longer than its interval, skip up to this many captures to catch up
+const MAX_PRESSURE_SKIP: u32 = 4;
+
+// Consecutive encode errors before the encoder is recreated
+const ENCODER_RESTART_THRESHOLD: u32 = 10;
+
+// Main stream rate while nobody is watching (set_viewer_active(false)); every frame is a keyframe
+const INACTIVE_VIEWER_FPS: u32 = 2;
+
+lazy_static::lazy_static! {
+    static ref CPU_PRESSURE_DROPS: AtomicU64 = AtomicU64::new(0);
+    static ref ENCODER_RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+    static ref ENCODER_RESTARTS: AtomicU32 = AtomicU32::new(0);
+    // Frame rate the main stream's encoder was configured with (0 = not started)
+    static ref ENCODER_MAX_FPS: AtomicU32 = AtomicU32::new(0);
+    // Frontend focus hint; the main loop drops to INACTIVE_VIEWER_FPS while false
+    static ref VIEWER_ACTIVE: AtomicBool = AtomicBool::new(true);
+    // Rate the main loop is currently sending at (0 = not streaming)
+    static ref EFFECTIVE_STREAM_FPS: AtomicU32 = AtomicU32::new(0);
+    // For the average bitrate in get_stream_stats
+    static ref STREAM_BYTES_ENCODED: AtomicU64 = AtomicU64::new(0);
+    static ref STREAM_STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+    // JPEG of the last frame that actually went out as H.264 (snapshot_current_stream_frame)
+    static ref STREAMED_FRAME: Mutex<Option<StreamedFrame>> = Mutex::new(None);
+}
+
+struct StreamedFrame {
+    jpeg: Vec<u8>,
+    sequence: u32,
+    captured_at: Instant,
+}
+
+fn stream_avg_bitrate_kbps() -> f64 {
+    let elapsed = STREAM_STARTED.lock().map(|t| t.elapsed().as_secs_f64()).unwrap_or(0.0);
+    if elapsed <= 0.0 {
+        return 0.0;
+    }
+    STREAM_BYTES_ENCODED.load(Ordering::Relaxed) as f64 * 8.0 / elapsed / 1000.0
+}
+
+// ============== Performance Cap ==============
+// Machine-wide ceiling for kiosk/shared deployments: every stream and capture started
+// after set_performance_cap is clamped to it, whatever its caller asked for. Running
+// streams keep their settings until restarted. 0 = no limit for that field.
+const PERFORMANCE_CAP_MIN_WIDTH: u32 = 64;
+const PERFORMANCE_CAP_MIN_HEIGHT: u32 = 36;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PerformanceCap {
+    max_width: u32,
+    max_height: u32,
+    max_fps: u32,
+}
+
+impl PerformanceCap {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_width != 0 && self.max_width < PERFORMANCE_CAP_MIN_WIDTH {
+            return Err(format!("max_width must be 0 or at least {} (got {})", PERFORMANCE_CAP_MIN_WIDTH, self.max_width));
+        }
+        if self.max_height != 0 && self.max_height < PERFORMANCE_CAP_MIN_HEIGHT {
+            return Err(format!("max_height must be 0 or at least {} (got {})", PERFORMANCE_CAP_MIN_HEIGHT, self.max_height));
+        }
+        if self.max_fps > MAX_STREAM_FPS {
+            return Err(format!("max_fps must be 0-{} (got {})", MAX_STREAM_FPS, self.max_fps));
+        }
+        Ok(())
+    }
+    
+    fn fps(&self, fps: u32) -> u32 {
+        if self.max_fps > 0 { fps.min(self.max_fps) } else { fps }
+    }
+    
+    // Largest even size within the cap with the same aspect ratio as width x height
+    fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        let (mut w, mut h) = (width, height);
+        if self.max_width > 0 && w > self.max_width as usize {
+            h = h * self.max_width as usize / w;
+            w = self.max_width as usize;
+        }
+        if self.max_height > 0 && h > self.max_height as usize {
+            w = w * self.max_height as usize / h;
+            h = self.max_height as usize;
+        }
+        // YUV 4:2:0 needs even dimensions
+        ((w & !1).max(2), (h & !1).max(2))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PERFORMANCE_CAP: Mutex<PerformanceCap> = Mutex::new(PerformanceCap::default());
+    // Encode size asked for by start_stream / profiles, before the cap
+    static ref STREAM_SIZE: Mutex<(usize, usize)> = Mutex::new((STREAM_WIDTH, STREAM_HEIGHT));
+    // Output size of the main stream's encoder (STREAM_SIZE unless capped)
+    static ref STREAM_RESOLUTION: Mutex<(usize, usize)> = Mutex::new((STREAM_WIDTH, STREAM_HEIGHT));
+}
+
+fn validate_stream_size(width: u32, height: u32) -> Result<(usize, usize), String> {
+    let (width, height) = (width as usize, height as usize);
+    if !(PERFORMANCE_CAP_MIN_WIDTH as usize..=MAX_STREAM_WIDTH).contains(&width)
+        || !(PERFORMANCE_CAP_MIN_HEIGHT as usize..=MAX_STREAM_HEIGHT).contains(&height)
+    {
+        return Err(format!(
+            "Stream size must be between {}x{} and {}x{} (got {}x{})",
+            PERFORMANCE_CAP_MIN_WIDTH, PERFORMANCE_CAP_MIN_HEIGHT, MAX_STREAM_WIDTH, MAX_STREAM_HEIGHT, width, height
+        ));
+    }
+    // YUV 4:2:0 needs even dimensions
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(format!("Stream size must be even (got {}x{})", width, height));
+    }
+    Ok((width, height))
+}
+
+fn validate_gop_secs(secs: u32) -> Result<u32, String> {
+    if secs > MAX_GOP_SECS {
+        return Err(format!("gop_secs must be 0-{} (got {})", MAX_GOP_SECS, secs));
+    }
+    Ok(secs)
+}
+
+fn capped_fps(fps: u32) -> u32 {
+    PERFORMANCE_CAP.lock().fps(fps)
+}
+
+fn capped_stream_size() -> (usize, usize) {
+    let (width, height) = *STREAM_SIZE.lock();
+    PERFORMANCE_CAP.lock().size(width, height)
+}
+
+fn stream_resolution_label() -> String {
+    let (width, height) = *STREAM_RESOLUTION.lock();
+    format!("{}x{}", width, height)
+}
+
+fn capped_interval(interval: Duration) -> Duration {
+    match PERFORMANCE_CAP.lock().max_fps {
+        0 => interval,
+        max_fps => interval.max(Duration::from_micros(1_000_000 / max_fps as u64)),
+    }
+}
+
+// Omitted fields keep their current value; 0 removes that limit
+#[tauri::command]
+fn set_performance_cap(
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    max_fps: Option<u32>
+) -> Result<PerformanceCap, String> {
+    let mut cap = *PERFORMANCE_CAP.lock();
+    if let Some(v) = max_width {
+        cap.max_width = v;
+    }
+    if let Some(v) = max_height {
+        cap.max_height = v;
+    }
+    if let Some(v) = max_fps {
+        cap.max_fps = v;
+    }
+    cap.validate()?;
+    
+    *PERFORMANCE_CAP.lock() = cap;
+    println!("Performance cap: {:?}", cap);
+    Ok(cap)
+}
+
+#[tauri::command]
+fn get_performance_cap() -> PerformanceCap {
+    *PERFORMANCE_CAP.lock()
+}
+
+// What start_stream / start_frame_receiver / start_tcp_file_server return when an
+// instance is already up: its state, so the frontend can reuse it or stop and retry
+fn already_running(details: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({ "status": "already_running", "running": details })
+}
+
+fn uptime_secs(started: Option<Instant>) -> Option<u64> {
+    started.map(|t| t.elapsed().as_secs())
+}
+
+// ============== Scene Change Detection ==============
+// Forces a keyframe when consecutive frames differ a lot (window switch, slide
+// change) instead of waiting for the next periodic IDR. The difference is the mean
+// absolute luma difference of a small grid thumbnail, so it costs ~2k samples per frame.
+const SCENE_GRID_W: usize = 64;
+const SCENE_GRID_H: usize = 36;
+// Samples averaged per grid cell, per axis
+const SCENE_CELL_SAMPLES: usize = 4;
+// Mean luma difference (0-255) that counts as a scene change
+const SCENE_CHANGE_DEFAULT_THRESHOLD: f64 = 30.0;
+// Video playback can cross the threshold every frame; cap the keyframe rate
+const SCENE_CHANGE_MIN_INTERVAL: Duration = Duration::from_millis(250);
+
+lazy_static::lazy_static! {
+    static ref SCENE_CHANGE_ENABLED: AtomicBool = AtomicBool::new(false);
+    static ref SCENE_CHANGE_THRESHOLD: Mutex<f64> = Mutex::new(SCENE_CHANGE_DEFAULT_THRESHOLD);
+    static ref SCENE_CHANGE_KEYFRAMES: AtomicU64 = AtomicU64::new(0);
+}
+
+// SCENE_GRID_W x SCENE_GRID_H luma thumbnail, each cell the average of a few samples
+fn luma_signature(bgra: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut signature = Vec::with_capacity(SCENE_GRID_W * SCENE_GRID_H);
+    if width == 0 || height == 0 {
+        return signature;
+    }
+    let stride = bgra.len() / height;
+    
+    for gy in 0..SCENE_GRID_H {
+        for gx in 0..SCENE_GRID_W {
+            let mut sum = 0u32;
+            for sy in 0..SCENE_CELL_SAMPLES {
+                let y = ((gy * SCENE_CELL_SAMPLES + sy) * 2 + 1) * height / (SCENE_GRID_H * SCENE_CELL_SAMPLES * 2);
+                for sx in 0..SCENE_CELL_SAMPLES {
+                    let x = ((gx * SCENE_CELL_SAMPLES + sx) * 2 + 1) * width / (SCENE_GRID_W * SCENE_CELL_SAMPLES * 2);
+                    let i = y * stride + x * 4;
+                    if let Some(px) = bgra.get(i..i + 3) {
+                        // BT.601 weights in 8-bit fixed point
+                        sum += (29 * px[0] as u32 + 150 * px[1] as u32 + 77 * px[2] as u32) >> 8;
+                    }
+                }
+            }
+            signature.push((sum / (SCENE_CELL_SAMPLES * SCENE_CELL_SAMPLES) as u32) as u8);
+        }
+    }
+    signature
+}
+
+// Mean absolute difference of two signatures; mismatched ones count as a full change
+fn scene_difference(a: &[u8], b: &[u8]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 255.0;
+    }
+    let sad: u64 = a.iter().zip(b).map(|(&x, &y)| x.abs_diff(y) as u64).sum();
+    sad as f64 / a.len() as f64
+}
+
+fn scene_change_threshold() -> Option<f64> {
+    if SCENE_CHANGE_ENABLED.load(Ordering::Relaxed) {
+        Some(*SCENE_CHANGE_THRESHOLD.lock())
+    } else {
+        None
+    }
+}
+
+// Per-stream state for scene-change keyframes
+#[derive(Default)]
+struct SceneDetector {
+    signature: Option<Vec<u8>>,
+    last_keyframe: Option<Instant>,
+}
+
+impl SceneDetector {
+    // True when this frame should be forced to a keyframe
+    fn check(&mut self, bgra: &[u8], width: usize, height: usize) -> bool {
+        let Some(threshold) = scene_change_threshold() else {
+            self.signature = None;
+            return false;
+        };
+        
+        let signature = luma_signature(bgra, width, height);
+        let changed = self.signature.as_ref().is_some_and(|previous| scene_difference(previous, &signature) >= threshold)
+            && self.last_keyframe.is_none_or(|t| t.elapsed() >= SCENE_CHANGE_MIN_INTERVAL);
+        self.signature = Some(signature);
+        
+        if changed {
+            SCENE_CHANGE_KEYFRAMES.fetch_add(1, Ordering::Relaxed);
+            self.last_keyframe = Some(Instant::now());
+        }
+        changed
+    }
+}
+
+fn validate_scene_change_threshold(threshold: f64) -> Result<f64, String> {
+    if !(1.0..=255.0).contains(&threshold) {
+        return Err(format!("threshold must be between 1 and 255 (got {})", threshold));
+    }
+    Ok(threshold)
+}
+
+// threshold: mean luma difference 1-255 (default 30; lower = more keyframes)
+#[tauri::command]
+fn set_scene_change_detection(enabled: bool, threshold: Option<f64>) -> Result<serde_json::Value, String> {
+    if let Some(threshold) = threshold {
+        *SCENE_CHANGE_THRESHOLD.lock() = validate_scene_change_threshold(threshold)?;
+    }
+    SCENE_CHANGE_ENABLED.store(enabled, Ordering::Relaxed);
+    let threshold = *SCENE_CHANGE_THRESHOLD.lock();
+    println!("Scene change keyframes {} (threshold {})", if enabled { "enabled" } else { "disabled" }, threshold);
+    
+    Ok(serde_json::json!({
+        "enabled": enabled,
+        "threshold": threshold
+    }))
+}
+
+lazy_static::lazy_static! {
+    static ref PARAM_SET_HASH: AtomicBool = AtomicBool::new(false);
+}
+
+// Tag keyframes with a hash of their SPS/PPS so receivers emit parameter-sets-changed
+// when a resolution or profile change replaces them. Off by default: receivers
+// older than this flag would feed the 4-byte tag to their decoder.
+#[tauri::command]
+fn set_parameter_set_hash(enabled: bool) -> serde_json::Value {
+    PARAM_SET_HASH.store(enabled, Ordering::Relaxed);
+    println!("SPS/PPS hash on keyframes {}", if enabled { "enabled" } else { "disabled" });
+    serde_json::json!({ "enabled": enabled })
+}
+
+// app is only used for events (None from the self test).
+// tier: index into STREAM_TIERS for start_stream_auto, which then keeps adapting;
+// its size replaces STREAM_SIZE.
+fn start_h264_streaming(
+    app: Option<tauri::AppHandle>,
+    server_addr: String,
+    fps: u32,
+    low_latency: bool,
+    quality: QualityMode,
+    tier: Option<usize>
+) -> Result<(), String> {
+    // Same fps drives the send loop and the encoder's rate control
+    if fps == 0 || fps > MAX_STREAM_FPS {
+        return Err(format!("fps must be 1-{} (got {})", MAX_STREAM_FPS, fps));
+    }
+    let fps = capped_fps(fps);
+    let (width, height) = match tier {
+        Some(index) => tier_stream_size(&STREAM_TIERS[index]),
+        None => capped_stream_size(),
+    };
+    // Wait for a stopped stream's thread outside the lock: it may still be
+    // sending its end marker, and its loop reads the registry
+    let finished = {
+        let mut streams = STREAMS.lock();
+        if streams.get(&StreamId::Main).is_some_and(|s| s.running.load(Ordering::SeqCst)) {
+            return Err("Already streaming".to_string());
+        }
+        streams.remove(&StreamId::Main)
+    };
+    if let Some(finished) = finished {
+        let _ = finished.handle.join();
+    }
+    let mut streams = STREAMS.lock();
+    if streams.contains_key(&StreamId::Main) {
+        return Err("Already streaming".to_string());
+    }
+    
+    reset_encode_latency();
+    *STREAM_RESOLUTION.lock() = (width, height);
+    *STREAM_QUALITY.lock() = quality;
+    *STREAM_TIER.lock() = tier.map(|index| STREAM_TIERS[index].name);
+    CPU_PRESSURE_DROPS.store(0, Ordering::Relaxed);
+    ENCODER_RESTART_REQUESTED.store(false, Ordering::Relaxed);
+    ENCODER_RESTARTS.store(0, Ordering::Relaxed);
+    SCENE_CHANGE_KEYFRAMES.store(0, Ordering::Relaxed);
+    STREAM_BYTES_ENCODED.store(0, Ordering::Relaxed);
+    STREAM_NETWORK_RECONNECTS.store(0, Ordering::Relaxed);
+    THUMBNAILS_SENT.store(0, Ordering::Relaxed);
+    // Frames from an earlier stream may have another resolution
+    *REPLAY_BUFFER.lock() = ReplayBuffer::new();
+    *STREAM_STARTED.lock() = Some(Instant::now());
+    *STREAMED_FRAME.lock() = None;
+    *PARAMETER_SETS.lock() = None;
+    *STREAM_DISPLAY.lock() = None;
+    *STREAM_DISPLAY_SWITCH.lock() = None;
+    *STREAM_TARGETS.lock() = vec![StreamTarget::new(server_addr.clone())];
+    
+    let running = std::sync::Arc::new(AtomicBool::new(true));
+    let target = server_addr.clone();
+    let thread_running = running.clone();
+    let handle = thread::spawn(move || {
+        let running = thread_running;
+        let mut socket = match bind_stream_socket() {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("UDP bind error: {}", e);
+                running.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        let mut rebinder = SocketRebinder::new();
+        
+        let mut capturer = match ScreenCapturer::new() {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Capturer error: {}", e);
+                running.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        
+        let hw_accel = HW_ACCEL.load(Ordering::Relaxed);
+        let mut encoder = match H264Encoder::with_backend(width, height, fps, low_latency, budgeted_quality(quality), hw_accel) {
+            Ok(e) => {
+                ENCODER_MAX_FPS.store(e.fps, Ordering::Relaxed);
+                *ACTIVE_ENCODER.lock() = e.backend_name();
+                e
+            }
+            Err(e) => {
+                eprintln!("H264 encoder error: {}", e);
+                *ACTIVE_ENCODER.lock() = "none";
+                running.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        
+        let (mut fps, mut quality) = (fps, quality);
+        let mut active_interval = Duration::from_micros(1_000_000 / fps as u64);
+        let mut inactive_fps = INACTIVE_VIEWER_FPS.min(fps);
+        let mut inactive_interval = Duration::from_micros(1_000_000 / inactive_fps as u64);
+        // Auto streams re-pick their tier from live RTT/loss
+        let mut tier = tier;
+        let mut link = tier.map(|_| LinkMonitor::new());
+        let mut viewer_was_active = true;
+        let mut sequence: u32 = 0;
+        let mut last_frame_time = Instant::now();
+        let mut last_forced_keyframe = Instant::now();
+        let mut last_requested_keyframe: Option<Instant> = None;
+        let mut scene = SceneDetector::default();
+        let mut pressure_skip = 0u32;
+        let mut last_liveness_check = Instant::now();
+        let mut last_thumbnail: Option<Instant> = None;
+        let mut thumbnail_seq = 0u32;
+        
+        println!("H.264 UDP streaming started to {} at {} FPS ({}x{})", 
+                 server_addr, fps, width, height);
+        
+        let mut encode_errors = 0u32;
+        
+        while running.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            
+            // Idle viewer: a few standalone keyframes a second. Full rate resumes on the
+            // next tick, and the last of those keyframes is already a clean reference.
+            let viewer_active = VIEWER_ACTIVE.load(Ordering::Relaxed);
+            if viewer_active != viewer_was_active {
+                println!("Viewer {}", if viewer_active { "active" } else { "inactive" });
+                viewer_was_active = viewer_active;
+                pressure_skip = 0;
+            }
+            let (frame_interval, effective_fps) = if viewer_active {
+                (active_interval, fps)
+            } else {
+                encoder.force_keyframe();
+                (inactive_interval, inactive_fps)
+            };
+            EFFECTIVE_STREAM_FPS.store(effective_fps, Ordering::Relaxed);
+            
+            // Same encoder and output size, so viewers just see the new picture from the next keyframe
+            let switch = STREAM_DISPLAY_SWITCH.lock().take();
+            if let Some(index) = switch {
+                match ScreenCapturer::for_display(index) {
+                    Ok(new_capturer) => {
+                        capturer = new_capturer;
+                        *STREAM_DISPLAY.lock() = Some(index);
+                        encoder.force_keyframe();
+                        last_frame_time = Instant::now();
+                        println!("Stream switched to display {} ({}x{})", index, capturer.width, capturer.height);
+                        if let Some(app) = &app {
+                            // Viewer coordinates are relative to the streamed display now
+                            if let Err(e) = target_input_display(app, index) {
+                                eprintln!("Cannot retarget input to display {}: {}", index, e);
+                            }
+                            let _ = app.emit("display-switched", serde_json::json!({
+                                "index": index,
+                                "width": capturer.width,
+                                "height": capturer.height
+                            }));
+                        }
+                    }
+                    Err(e) => eprintln!("Cannot switch to display {}: {}", index, e),
+                }
+            }
+            
+            // Stuck encoder (or the frontend asked): rebuild it with the same settings
+            let requested = ENCODER_RESTART_REQUESTED.swap(false, Ordering::Relaxed);
+            if requested || encoder.consecutive_errors >= ENCODER_RESTART_THRESHOLD {
+                let failures = encoder.consecutive_errors;
+                match encoder.restart() {
+                    Ok(()) => {
+                        // A failed hardware encoder comes back as openh264
+                        *ACTIVE_ENCODER.lock() = encoder.backend_name();
+                        let restarts = ENCODER_RESTARTS.fetch_add(1, Ordering::Relaxed) + 1;
+                        println!("H.264 encoder restarted after {} consecutive failures (restarts: {})", failures, restarts);
+                        if let Some(app) = &app {
+                            let _ = app.emit("encoder-restarted", serde_json::json!({
+                                "reason": if requested { "requested" } else { "errors" },
+                                "consecutive_errors": failures,
+                                "restarts": restarts
+                            }));
+                        }
+                    }
+                    Err(e) => eprintln!("Cannot restart H.264 encoder: {}", e),
+                }
+            }
+            
+            if let Some(link) = link.as_mut() {
+                if let Some(addr) = STREAM_TARGETS.lock().first().and_then(|t| t.resolved) {
+                    link.maybe_probe(&socket, addr);
+                }
+            }
+            let keyframe_requested = poll_heartbeat_acks(&socket, &mut STREAM_TARGETS.lock(), link.as_mut()) | WS_KEYFRAME_REQUESTED.swap(false, Ordering::Relaxed);
+            if keyframe_requested && last_requested_keyframe.is_none_or(|t| t.elapsed() >= KEYFRAME_REQUEST_MIN_INTERVAL) {
+                println!("Keyframe requested by a receiver");
+                encoder.force_keyframe();
+                last_requested_keyframe = Some(Instant::now());
+            }
+            if last_liveness_check.elapsed() >= Duration::from_secs(1) {
+                last_liveness_check = now;
+                let measured = link.as_mut().and_then(|link| link.take_window());
+                if let (Some(current), Some(measured)) = (tier, measured) {
+                    let next = next_tier_index(current, &measured);
+                    if next != current {
+                        let rung = &STREAM_TIERS[next];
+                        let (new_width, new_height) = tier_stream_size(rung);
+                        let new_fps = capped_fps(rung.fps);
+                        let new_quality = QualityMode::Bitrate(rung.bitrate_bps);
+                        match encoder.reconfigure(new_width, new_height, new_fps, budgeted_quality(new_quality)) {
+                            Ok(()) => {
+                                println!("Auto stream: RTT {:.1} ms, loss {:.0}% -> {} tier ({}x{} @ {} FPS)",
+                                         measured.rtt_ms, measured.loss * 100.0, rung.name, new_width, new_height, new_fps);
+                                tier = Some(next);
+                                fps = new_fps;
+                                quality = new_quality;
+                                active_interval = Duration::from_micros(1_000_000 / fps as u64);
+                                inactive_fps = INACTIVE_VIEWER_FPS.min(fps);
+                                inactive_interval = Duration::from_micros(1_000_000 / inactive_fps as u64);
+                                *ACTIVE_ENCODER.lock() = encoder.backend_name();
+                                ENCODER_MAX_FPS.store(fps, Ordering::Relaxed);
+                                *STREAM_RESOLUTION.lock() = (new_width, new_height);
+                                *STREAM_QUALITY.lock() = quality;
+                                *STREAM_TIER.lock() = Some(rung.name);
+                                // Clips can't mix sizes
+                                *REPLAY_BUFFER.lock() = ReplayBuffer::new();
+                                if let Some(app) = &app {
+                                    let _ = app.emit("stream-tier-changed", serde_json::json!({
+                                        "tier": rung.name,
+                                        "rtt_ms": measured.rtt_ms,
+                                        "loss": measured.loss,
+                                        "width": new_width,
+                                        "height": new_height,
+                                        "fps": fps,
+                                        "bitrate_kbps": rung.bitrate_bps / 1000
+                                    }));
+                                }
+                            }
+                            Err(e) => eprintln!("Cannot switch to the {} tier: {}", rung.name, e),
+                        }
+                    }
+                }
+                // Picks up set_total_bandwidth_budget changes while streaming
+                let budgeted = budgeted_quality(quality);
+                if budgeted != encoder.quality {
+                    let previous = encoder.quality.bitrate_bps();
+                    match encoder.restart_with_quality(budgeted) {
+                        Ok(()) => {
+                            *ACTIVE_ENCODER.lock() = encoder.backend_name();
+                            println!("Bandwidth budget: video bitrate {:?} -> {:?}", previous, budgeted.bitrate_bps());
+                        }
+                        Err(e) => eprintln!("Cannot apply bandwidth budget to the encoder: {}", e),
+                    }
+                }
+                if check_unreachable_targets(app.as_ref(), &mut STREAM_TARGETS.lock()) {
+                    println!("All stream targets unreachable, stopping stream");
+                    if let Some(app) = &app {
+                        let _ = app.emit("stream-stopped", serde_json::json!({ "reason": "unreachable" }));
+                    }
+                    break;
+                }
+            }
+            
+            // Behind schedule: drop this tick's frame instead of encoding it late
+            if pressure_skip > 0 {
+                pressure_skip -= 1;
+                if capturer.capture().is_some() {
+                    last_frame_time = Instant::now();
+                }
+                CPU_PRESSURE_DROPS.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(frame_interval.saturating_sub(now.elapsed()));
+                continue;
+            }
+            
+            if let Some(mut bgra) = capturer.capture() {
+                draw_cursor_highlight(&mut bgra, capturer.width, capturer.height);
+                apply_stream_watermark(&mut bgra, capturer.width, capturer.height);
+                
+                if last_thumbnail.is_none_or(|t| t.elapsed() >= THUMBNAIL_INTERVAL) {
+                    let target = THUMBNAIL_TARGET.lock().clone();
+                    if let Some(target) = target {
+                        send_thumbnail(&socket, &target, &bgra, capturer.width, capturer.height, thumbnail_seq);
+                        thumbnail_seq = thumbnail_seq.wrapping_add(1);
+                    }
+                    last_thumbnail = Some(now);
+                }
+                
+                if scene.check(&bgra, capturer.width, capturer.height) {
+                    encoder.force_keyframe();
+                }
+                
+                // Encode to H.264
+                let encode_start = Instant::now();
+                let encoded = encoder.encode(&bgra, capturer.width, capturer.height);
+                record_encode_latency(encode_start.elapsed());
+                let mut streamed_seq = None;
+                
+                if let Some(h264_data) = encoded {
+                    STREAM_BYTES_ENCODED.fetch_add(h264_data.len() as u64, Ordering::Relaxed);
+                    // Send via UDP with H264 magic header
+                    let keyframe = is_h264_keyframe(&h264_data);
+                    if keyframe {
+                        // openh264 repeats SPS/PPS on every IDR; only log when they change
+                        if let Some(sets) = extract_parameter_sets(&h264_data) {
+                            let mut cached = PARAMETER_SETS.lock();
+                            if cached.as_ref() != Some(&sets) {
+                                println!("Stream parameter sets updated (SPS {} bytes, PPS {} bytes)", sets.sps.len(), sets.pps.len());
+                                *cached = Some(sets);
+                            }
+                        }
+                    }
+                    let sent = send_to_stream_targets(&socket, &mut STREAM_TARGETS.lock(), &h264_data, sequence, keyframe);
+                    let delivered = match sent {
+                        Ok(delivered) => {
+                            rebinder.on_send_ok(app.as_ref(), delivered);
+                            delivered
+                        }
+                        Err(e) => {
+                            if let Some(new_socket) = rebinder.on_send_error(&mut STREAM_TARGETS.lock(), &e) {
+                                socket = new_socket;
+                                encoder.force_keyframe();
+                            }
+                            false
+                        }
+                    };
+                    sequence = sequence.wrapping_add(1);
+                    
+                    if delivered {
+                        FRAME_COUNT.fetch_add(1, Ordering::Relaxed);
+                        streamed_seq = Some(sequence.wrapping_sub(1));
+                        if sequence % 30 == 0 {
+                            println!("Sent {} H.264 frames ({} bytes)", sequence, h264_data.len());
+                        }
+                    }
+                    
+                    // Degraded targets only get keyframes, so keep some coming
+                    let (any_degraded, any_cut_off) = {
+                        let targets = STREAM_TARGETS.lock();
+                        (targets.iter().any(|t| t.degraded), targets.iter().any(|t| t.awaiting_keyframe))
+                    };
+                    if any_cut_off {
+                        encoder.force_keyframe();
+                    }
+                    if any_degraded && last_forced_keyframe.elapsed() >= DEGRADED_KEYFRAME_INTERVAL {
+                        encoder.force_keyframe();
+                        last_forced_keyframe = Instant::now();
+                    }
+                    
+                    REPLAY_BUFFER.lock().push(Instant::now(), keyframe, &h264_data);
+                    let mut last = LAST_H264_FRAME.lock();
+                    *last = Some(h264_data);
+                    H264_FRAME_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    encode_errors += 1;
+                    if encode_errors % 30 == 1 {
+                        println!("H.264 encode failed (errors: {})", encode_errors);
+                    }
+                }
+                
+                // Also encode JPEG for preview/fallback
+                if let Some(jpeg) = encode_jpeg(&bgra, capturer.width, capturer.height, 60) {
+                    if let Some(sequence) = streamed_seq {
+                        *STREAMED_FRAME.lock() = Some(StreamedFrame {
+                            jpeg: jpeg.clone(),
+                            sequence,
+                            captured_at: Instant::now(),
+                        });
+                    }
+                    *LAST_JPEG_FRAME.lock() = Some(jpeg);
+                }
+                
+                let elapsed = now.elapsed();
+                if elapsed < frame_interval {
+                    thread::sleep(frame_interval - elapsed);
+                } else {
+                    // This frame ate N intervals; skip about that many so latency stays bounded
+                    let overrun = (elapsed.as_micros() / frame_interval.as_micros().max(1)) as u32;
+                    pressure_skip = overrun.min(MAX_PRESSURE_SKIP);
+                }
+                last_frame_time = Instant::now();
+            } else {
+                thread::sleep(Duration::from_millis(1));
+                
+                if last_frame_time.elapsed() > Duration::from_secs(2) {
+                    let display = *STREAM_DISPLAY.lock();
+                    if let Ok(new_capturer) = stream_capturer(display) {
+                        capturer = new_capturer;
+                        last_frame_time = Instant::now();
+                    }
+                }
+            }
+        }
+        
+        send_stream_end(&socket, &mut STREAM_TARGETS.lock(), &mut encoder, sequence);
+        running.store(false, Ordering::SeqCst);
+        EFFECTIVE_STREAM_FPS.store(0, Ordering::Relaxed);
+        println!("H.264 streaming stopped");
+    });
+    
+    streams.insert(StreamId::Main, ActiveStream {
+        target,
+        running,
+        targets: STREAM_TARGETS.clone(),
+        started: Instant::now(),
+        handle,
+    });
+    Ok(())
+}
+
+// End markers are tiny and the receiver ignores repeats, so send a few against loss
+const STREAM_END_REPEATS: usize = 3;
+
+fn session_ended_frame(width: usize, height: usize) -> Vec<u8> {
+    let mut bgra = vec![0u8; width * height * 4];
+    for px in bgra.chunks_exact_mut(4) {
+        px[3] = 255;
+    }
+    watermark::draw_watermark(&mut bgra, width, height, "SESSION ENDED", WatermarkPosition::Center, 1.0);
+    bgra
+}
+
+// A last keyframe with a placeholder, so the viewer's final picture is
+// intentional rather than whatever half-decoded frame it had, then "EN"
+fn send_stream_end(socket: &UdpSocket, targets: &mut [StreamTarget], encoder: &mut H264Encoder, sequence: u32) {
+    let (width, height) = (encoder.width, encoder.height);
+    let mut last_seq = sequence.wrapping_sub(1);
+    
+    encoder.force_keyframe();
+    if let Some(frame) = encoder.encode(&session_ended_frame(width, height), width, height) {
+        // A keyframe, so degraded targets get it too
+        let _ = send_to_stream_targets(socket, targets, &frame, sequence, true);
+        last_seq = sequence;
+    }
+    
+    let end = protocol::encode_stream_end(last_seq);
+    for _ in 0..STREAM_END_REPEATS {
+        for target in targets.iter() {
+            let _ = match target.resolved {
+                Some(addr) => socket.send_to(&end, addr),
+                None => socket.send_to(&end, target.addr.as_str()),
+            };
+        }
+    }
+}
+
+// ============== Stream Registry ==============
+// Every running H.264 stream: the main one (start_stream) and the per-display
+// ones. Each has its own thread, running flag and targets; get_stream_stats
+// reports one entry per stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum StreamId {
+    Main,
+    Display(usize),
+}
+
+impl StreamId {
+    fn label(self) -> String {
+        match self {
+            StreamId::Main => "main".to_string(),
+            StreamId::Display(index) => format!("display-{}", index),
+        }
+    }
+}
+
+struct ActiveStream {
+    // Address the stream was started with; the main stream may fan out further
+    target: String,
+    running: std::sync::Arc<AtomicBool>,
+    // Per-display streams have a one-entry list so the shared send_to_stream_targets path applies
+    targets: std::sync::Arc<Mutex<Vec<StreamTarget>>>,
+    started: Instant,
+    handle: thread::JoinHandle<()>,
+}
+
+lazy_static::lazy_static! {
+    static ref STREAMS: Mutex<HashMap<StreamId, ActiveStream>> = Mutex::new(HashMap::new());
+}
+
+fn stream_running(id: StreamId) -> bool {
+    STREAMS.lock().get(&id).is_some_and(|s| s.running.load(Ordering::SeqCst))
+}
+
+// The thread sends its end marker and exits on its own; true if it was running
+fn stop_registered_stream(id: StreamId) -> bool {
+    STREAMS.lock().get(&id).is_some_and(|s| s.running.swap(false, Ordering::SeqCst))
+}
+
+// ============== Per-Display Streams ==============
+// Extra independent H.264 streams, one per monitor, each with its own
+// capturer/encoder/thread and target. They go through the same frame prep and
+// target bookkeeping as start_stream (heartbeats, degrade, param-set tags).
+
+#[tauri::command]
+fn list_displays() -> Result<Vec<serde_json::Value>, String> {
+    let displays = Display::all().map_err(|e| e.to_string())?;
+    Ok(displays.iter().enumerate().map(|(index, d)| {
+        serde_json::json!({ "index": index, "width": d.width(), "height": d.height() })
+    }).collect())
+}
+
+#[tauri::command]
+fn start_stream_for_display(
+    app: tauri::AppHandle,
+    index: usize,
+    target: String,
+    fps: u32,
+    low_latency: Option<bool>
+) -> Result<(), String> {
+    require_permission(&app, Permission::View, "start_stream_for_display")?;
+    validate_stream_target(&target)?;
+    
+    let display_count = Display::all().map_err(|e| e.to_string())?.len();
+    if index >= display_count {
+        return Err(format!("Display {} not found ({} available)", index, display_count));
+    }
+    
+    let id = StreamId::Display(index);
+    let mut streams = STREAMS.lock();
+    if let Some(existing) = streams.get(&id) {
+        if existing.running.load(Ordering::SeqCst) {
+            return Err(format!("Display {} is already streaming to {}", index, existing.target));
+        }
+    }
+    // Reap a stream that ended on its own (capture/encoder failure)
+    if let Some(finished) = streams.remove(&id) {
+        let _ = finished.handle.join();
+    }
+    
+    let running = std::sync::Arc::new(AtomicBool::new(true));
+    let targets = std::sync::Arc::new(Mutex::new(vec![StreamTarget::new(target.clone())]));
+    let fps = capped_fps(fps.clamp(1, MAX_STREAM_FPS));
+    let low_latency = low_latency.unwrap_or_else(|| SENDER_LOW_LATENCY.load(Ordering::Relaxed));
+    
+    let handle = {
+        let running = running.clone();
+        let targets = targets.clone();
+        thread::spawn(move || run_display_stream(app, index, fps, low_latency, running, targets))
+    };
+    
+    streams.insert(id, ActiveStream {
+        target,
+        running,
+        targets,
+        started: Instant::now(),
+        handle,
+    });
+    
+    Ok(())
+}
+
+fn run_display_stream(
+    app: tauri::AppHandle,
+    index: usize,
+    fps: u32,
+    low_latency: bool,
+    running: std::sync::Arc<AtomicBool>,
+    targets: std::sync::Arc<Mutex<Vec<StreamTarget>>>
+) {
+    // Capturers aren't Send everywhere, so everything is created on this thread
+    let setup = bind_stream_socket().map_err(|e| format!("UDP bind error: {}", e))
+        .and_then(|socket| Ok((socket, ScreenCapturer::for_display(index)?)))
+        .and_then(|(socket, capturer)| {
+            let (width, height) = capped_stream_size();
+            Ok((socket, capturer, H264Encoder::new(width, height, fps, low_latency)?))
+        });
+    
+    let (mut socket, mut capturer, mut encoder) = match setup {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("Display {} stream error: {}", index, e);
+            running.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+    
+    let frame_interval = Duration::from_micros(1_000_000 / fps as u64);
+    let mut sequence: u32 = 0;
+    let mut rebinder = SocketRebinder::new();
+    let mut scene = SceneDetector::default();
+    let mut last_forced_keyframe = Instant::now();
+    let mut last_requested_keyframe: Option<Instant> = None;
+    let mut last_liveness_check = Instant::now();
+    
+    println!("Display {} streaming at {} FPS", index, fps);
+    
+    while running.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        
+        if poll_heartbeat_acks(&socket, &mut targets.lock(), None)
+            && last_requested_keyframe.is_none_or(|t| t.elapsed() >= KEYFRAME_REQUEST_MIN_INTERVAL)
+        {
+            encoder.force_keyframe();
+            last_requested_keyframe = Some(Instant::now());
+        }
+        if last_liveness_check.elapsed() >= Duration::from_secs(1) {
+            last_liveness_check = now;
+            if check_unreachable_targets(Some(&app), &mut targets.lock()) {
+                println!("Display {} target unreachable, stopping stream", index);
+                break;
+            }
+        }
+        
+        match capturer.capture() {
+            Some(mut bgra) => {
+                draw_cursor_highlight(&mut bgra, capturer.width, capturer.height);
+                apply_stream_watermark(&mut bgra, capturer.width, capturer.height);
+                if scene.check(&bgra, capturer.width, capturer.height) {
+                    encoder.force_keyframe();
+                }
+                
+                if let Some(h264_data) = encoder.encode(&bgra, capturer.width, capturer.height) {
+                    let keyframe = is_h264_keyframe(&h264_data);
+                    let sent = send_to_stream_targets(&socket, &mut targets.lock(), &h264_data, sequence, keyframe);
+                    match sent {
+                        Ok(delivered) => rebinder.on_send_ok(Some(&app), delivered),
+                        Err(e) => {
+                            if let Some(new_socket) = rebinder.on_send_error(&mut targets.lock(), &e) {
+                                socket = new_socket;
+                                encoder.force_keyframe();
+                            }
+                        }
+                    }
+                    sequence = sequence.wrapping_add(1);
+                    
+                    let (degraded, cut_off) = {
+                        let targets = targets.lock();
+                        (targets.iter().any(|t| t.degraded), targets.iter().any(|t| t.awaiting_keyframe))
+                    };
+                    if cut_off {
+                        encoder.force_keyframe();
+                    }
+                    if degraded && last_forced_keyframe.elapsed() >= DEGRADED_KEYFRAME_INTERVAL {
+                        encoder.force_keyframe();
+                        last_forced_keyframe = Instant::now();
+                    }
+                }
+                thread::sleep(frame_interval.saturating_sub(now.elapsed()));
+            }
+            None => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    
+    send_stream_end(&socket, &mut targets.lock(), &mut encoder, sequence);
+    running.store(false, Ordering::SeqCst);
+    println!("Display {} streaming stopped", index);
+}
+
+#[tauri::command]
+fn stop_stream_for_display(index: usize) -> Result<(), String> {
+    let stream = STREAMS.lock().remove(&StreamId::Display(index))
+        .ok_or_else(|| format!("Display {} is not streaming", index))?;
+    
+    stream.running.store(false, Ordering::SeqCst);
+    let _ = stream.handle.join();
+    Ok(())
+}
+
+fn display_stream_stats() -> Vec<serde_json::Value> {
+    let streams = STREAMS.lock();
+    let mut entries: Vec<_> = streams.iter().filter_map(|(id, stream)| match id {
+        StreamId::Display(index) => Some((*id, *index, stream)),
+        StreamId::Main => None,
+    }).collect();
+    entries.sort_by_key(|(id, _, _)| *id);
+    
+    entries.into_iter().map(|(id, index, stream)| {
+        let targets = stream.targets.lock();
+        let frames_sent: u64 = targets.iter().map(|t| t.frames_sent).sum();
+        serde_json::json!({
+            "id": id.label(),
+            "display": index,
+            "target": stream.target,
+            "running": stream.running.load(Ordering::SeqCst),
+            "frames_sent": frames_sent,
+            "targets": targets.clone(),
+            "uptime_secs": stream.started.elapsed().as_secs()
+        })
+    }).collect()
+}
+
+fn send_framed_udp(socket: &UdpSocket, addr: &str, kind: FrameKind, data: &[u8], sequence: u32) -> Result<(), String> {
+    for packet in protocol::packetize(kind, sequence, data) {
+        if socket.send_to(&packet, addr).is_err() {
+            return Err("Send failed".to_string());
+        }
+    }
+    
+    Ok(())
+}
+
+// The flags byte tags the color space the encoder used and the viewer's
+// preferred decode layout, see ColorSpace/PixelFormat::header_flags; extra_flags
+// is FLAG_PARAM_HASH when data was tagged with prepend_param_hash.
+// A full send buffer is waited out for up to SEND_STALL_BUDGET per frame; past
+// that the WouldBlock is returned and the frame may have gone out only in part.
+fn send_h264_udp<A: std::net::ToSocketAddrs + Copy>(socket: &UdpSocket, addr: A, data: &[u8], sequence: u32, extra_flags: u8) -> std::io::Result<()> {
+    let flags = COLOR_SPACE.lock().header_flags() | PIXEL_FORMAT.lock().header_flags() | extra_flags;
+    let started = Instant::now();
+    for packet in protocol::packetize_with_flags(FrameKind::H264, sequence, flags, data) {
+        loop {
+            match socket.send_to(&packet, addr) {
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && started.elapsed() < SEND_STALL_BUDGET => {
+                    thread::sleep(Duration::from_micros(200));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+// ============== Thumbnail Substream ==============
+// A 160x90 JPEG of the frame the main stream just captured, a couple of times a
+// second, to its own target. A grid view can show every machine from these and
+// only pull a full stream for the one in focus. Receivers emit stream-thumbnail.
+const THUMBNAIL_WIDTH: usize = 160;
+const THUMBNAIL_HEIGHT: usize = 90;
+const THUMBNAIL_INTERVAL: Duration = Duration::from_millis(500);
+const THUMBNAIL_QUALITY: u8 = 50;
+
+lazy_static::lazy_static! {
+    // None = no thumbnails
+    static ref THUMBNAIL_TARGET: Mutex<Option<String>> = Mutex::new(None);
+    static ref THUMBNAILS_SENT: AtomicU64 = AtomicU64::new(0);
+}
+
+fn send_thumbnail(socket: &UdpSocket, target: &str, bgra: &[u8], width: usize, height: usize, sequence: u32) {
+    let preserve_aspect = PRESERVE_ASPECT.load(Ordering::Relaxed);
+    let rgb = bgra_to_rgb_resized(bgra, width, height, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, preserve_aspect);
+    let Some(jpeg) = encode_rgb_jpeg(rgb, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, THUMBNAIL_QUALITY) else {
+        return;
+    };
+    if send_framed_udp(socket, target, FrameKind::Thumbnail, &jpeg, sequence).is_ok() {
+        THUMBNAILS_SENT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// ============== Pipe Output ==============
+// Raw Annex-B H.264 written to a FIFO or file, e.g. `mkfifo /tmp/screen.h264` then
+// `ffmpeg -f h264 -i /tmp/screen.h264 out.mp4`. On Windows the path must be an
+// existing named pipe (\\.\pipe\name, created by the reader) or a regular file.
+lazy_static::lazy_static! {
+    static ref PIPE_STREAMING: AtomicBool = AtomicBool::new(false);
+    static ref PIPE_FRAMES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+    static ref PIPE_BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+}
+
+#[tauri::command]
+fn start_stream_to_pipe(app: tauri::AppHandle, pipe_path: String, fps: Option<u32>) -> Result<(), String> {
+    require_permission(&app, Permission::View, "start_stream_to_pipe")?;
+    if PIPE_STREAMING.swap(true, Ordering::SeqCst) {
+        return Err("Already streaming to a pipe".to_string());
+    }
+    
+    PIPE_FRAMES_WRITTEN.store(0, Ordering::Relaxed);
+    PIPE_BYTES_WRITTEN.store(0, Ordering::Relaxed);
+    let fps = capped_fps(fps.unwrap_or(30).clamp(1, MAX_STREAM_FPS));
+    
+    thread::spawn(move || {
+        if let Err(e) = run_pipe_stream(&pipe_path, fps) {
+            eprintln!("Pipe stream error: {}", e);
+        }
+        PIPE_STREAMING.store(false, Ordering::SeqCst);
+        println!("Pipe streaming to {} stopped", pipe_path);
+    });
+    
+    Ok(())
+}
+
+fn run_pipe_stream(pipe_path: &str, fps: u32) -> Result<(), String> {
+    // Opening a FIFO for writing blocks until a reader (ffmpeg) opens the other end
+    let mut sink = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(pipe_path)
+        .map_err(|e| format!("Cannot open {}: {}", pipe_path, e))?;
+    
+    let mut capturer = ScreenCapturer::new()?;
+    let (width, height) = capped_stream_size();
+    let mut encoder = H264Encoder::new(width, height, fps, false)?;
+    let frame_interval = Duration::from_micros(1_000_000 / fps as u64);
+    let mut parameter_sets: Option<ParameterSets> = None;
+    
+    println!("Pipe streaming to {} at {} FPS", pipe_path, fps);
+    
+    while PIPE_STREAMING.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        
+        let Some(mut bgra) = capturer.capture() else {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        };
+        apply_stream_watermark(&mut bgra, capturer.width, capturer.height);
+        
+        if let Some(h264_data) = encoder.encode(&bgra, capturer.width, capturer.height) {
+            let mut out = Vec::with_capacity(h264_data.len() + 64);
+            
+            // A reader can only start decoding at a keyframe that carries SPS/PPS
+            if is_h264_keyframe(&h264_data) {
+                match extract_parameter_sets(&h264_data) {
+                    Some(sets) => parameter_sets = Some(sets),
+                    None => if let Some(sets) = &parameter_sets {
+                        for nal in [&sets.sps, &sets.pps] {
+                            out.extend_from_slice(&[0, 0, 0, 1]);
+                            out.extend_from_slice(nal);
+                        }
+                    },
+                }
+            }
+            out.extend_from_slice(&h264_data);
+            
+            // Broken pipe = the reader went away
+            sink.write_all(&out).map_err(|e| format!("Write error: {}", e))?;
+            PIPE_FRAMES_WRITTEN.fetch_add(1, Ordering::Relaxed);
+            PIPE_BYTES_WRITTEN.fetch_add(out.len() as u64, Ordering::Relaxed);
+        }
+        
+        thread::sleep(frame_interval.saturating_sub(now.elapsed()));
+    }
+    
+    sink.flush().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn stop_stream_to_pipe() -> serde_json::Value {
+    PIPE_STREAMING.store(false, Ordering::SeqCst);
+    serde_json::json!({
+        "frames_written": PIPE_FRAMES_WRITTEN.load(Ordering::Relaxed),
+        "bytes_written": PIPE_BYTES_WRITTEN.load(Ordering::Relaxed)
+    })
+}
+
+// ============== Replay Clips ==============
+// The main stream's last REPLAY_MAX_SECONDS of encoded frames stay in memory,
+// whole GOPs only, so save_clip can export "the last N seconds" without the
+// stream ever recording to disk. Output is a raw Annex-B .h264 file; wrap it
+// with `ffmpeg -i clip.h264 -c copy clip.mp4` if an MP4 is needed.
+const REPLAY_MAX_SECONDS: u64 = 60;
+const REPLAY_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+struct ReplayFrame {
+    at: Instant,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+struct ReplayBuffer {
+    frames: std::collections::VecDeque<ReplayFrame>,
+    bytes: usize,
+}
+
+impl ReplayBuffer {
+    fn new() -> Self {
+        Self { frames: std::collections::VecDeque::new(), bytes: 0 }
+    }
+    
+    fn push(&mut self, at: Instant, keyframe: bool, data: &[u8]) {
+        // Nothing before the first keyframe can be decoded
+        if self.frames.is_empty() && !keyframe {
+            return;
+        }
+        self.frames.push_back(ReplayFrame { at, keyframe, data: data.to_vec() });
+        self.bytes += data.len();
+        
+        // Drop the oldest GOP while it's beyond the window, as long as another one remains
+        let window = Duration::from_secs(REPLAY_MAX_SECONDS);
+        while self.bytes > REPLAY_MAX_BYTES || self.frames.front().is_some_and(|f| at.duration_since(f.at) > window) {
+            let Some(next_gop) = self.frames.iter().skip(1).position(|f| f.keyframe) else {
+                break;
+            };
+            for frame in self.frames.drain(..=next_gop) {
+                self.bytes -= frame.data.len();
+            }
+        }
+    }
+    
+    // Last keyframe at or before `since`, or the oldest one (always at the front)
+    // when less than that is buffered
+    fn clip_start(&self, since: Instant) -> Option<usize> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        Some(self.frames.iter().rposition(|f| f.keyframe && f.at <= since).unwrap_or(0))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref REPLAY_BUFFER: Mutex<ReplayBuffer> = Mutex::new(ReplayBuffer::new());
+}
+
+fn write_clip(app: &tauri::AppHandle, seconds: u64, path: &str) -> Result<serde_json::Value, String> {
+    let (frames, first_at, last_at) = {
+        let buffer = REPLAY_BUFFER.lock();
+        let now = Instant::now();
+        let since = now.checked_sub(Duration::from_secs(seconds)).unwrap_or(now);
+        let start = buffer.clip_start(since).ok_or("No buffered frames, is the stream running?")?;
+        let frames: Vec<Vec<u8>> = buffer.frames.iter().skip(start).map(|f| f.data.clone()).collect();
+        let first_at = buffer.frames[start].at;
+        (frames, first_at, buffer.frames.back().map_or(first_at, |f| f.at))
+    };
+    
+    let total_bytes: usize = frames.iter().map(Vec::len).sum();
+    let mut out = std::io::BufWriter::new(File::create(path).map_err(|e| format!("Cannot create {}: {}", path, e))?);
+    
+    // A player can only start at a keyframe that carries SPS/PPS
+    if extract_parameter_sets(&frames[0]).is_none() {
+        let sets = PARAMETER_SETS.lock().clone().ok_or("No SPS/PPS seen yet")?;
+        for nal in [&sets.sps, &sets.pps] {
+            out.write_all(&[0, 0, 0, 1]).map_err(|e| e.to_string())?;
+            out.write_all(nal).map_err(|e| e.to_string())?;
+        }
+    }
+    
+    let mut written = 0usize;
+    let mut last_progress = 0;
+    for frame in &frames {
+        out.write_all(frame).map_err(|e| format!("Write error: {}", e))?;
+        written += frame.len();
+        let progress = (written as f64 / total_bytes.max(1) as f64 * 100.0) as u32;
+        if progress >= last_progress + 10 || written == total_bytes {
+            let _ = app.emit("clip-progress", serde_json::json!({
+                "path": path,
+                "bytes_written": written,
+                "total_bytes": total_bytes,
+                "progress": progress
+            }));
+            last_progress = progress;
+        }
+    }
+    out.flush().map_err(|e| e.to_string())?;
+    
+    let duration = last_at.duration_since(first_at);
+    println!("Saved {:.1}s clip ({} frames, {} bytes) to {}", duration.as_secs_f64(), frames.len(), total_bytes, path);
+    Ok(serde_json::json!({
+        "path": path,
+        "frames": frames.len(),
+        "bytes": total_bytes,
+        "duration_secs": duration.as_secs_f64(),
+        "format": "h264"
+    }))
+}
+
+// Export the last `seconds` (1-REPLAY_MAX_SECONDS) of the main stream. The clip
+// starts at the keyframe before that point, so it can run slightly longer.
+#[tauri::command]
+async fn save_clip(app: tauri::AppHandle, seconds: u64, path: String) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "save_clip")?;
+    if seconds == 0 || seconds > REPLAY_MAX_SECONDS {
+        return Err(format!("seconds must be 1-{} (got {})", REPLAY_MAX_SECONDS, seconds));
+    }
+    if Path::new(&path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mp4")) {
+        return Err("Clips are raw H.264; save as .h264 (ffmpeg -i clip.h264 -c copy clip.mp4 makes an MP4)".to_string());
+    }
+    
+    tauri::async_runtime::spawn_blocking(move || write_clip(&app, seconds, &path))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// ============== WebSocket Stream ==============
+// For a plain browser on the LAN: every connected client gets the frames the
+// main pipeline already produces (LAST_JPEG_FRAME / LAST_H264_FRAME) as JSON
+// text messages { type: "frame", format, sequence, keyframe, data (base64) }.
+// JPEG goes straight into an <img>; H.264 is Annex-B for WebCodecs, starting
+// at a keyframe. Frames only flow while start_stream (or, for JPEG, the
+// capture loop) is running.
+const WS_H264_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WsFrameFormat {
+    Jpeg,
+    H264,
+}
+
+impl WsFrameFormat {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "h264" | "h.264" => Ok(Self::H264),
+            other => Err(format!("Unknown WebSocket frame format: {} (use jpeg or h264)", other)),
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::H264 => "h264",
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref WS_STREAMING: AtomicBool = AtomicBool::new(false);
+    static ref WS_CLIENTS: AtomicU64 = AtomicU64::new(0);
+    static ref WS_FRAMES_SENT: AtomicU64 = AtomicU64::new(0);
+    // Set when an H.264 viewer joins or falls behind; the main loop answers with a keyframe
+    static ref WS_KEYFRAME_REQUESTED: AtomicBool = AtomicBool::new(false);
+}
+
+// fps only paces JPEG; H.264 clients get every encoded frame, since a skipped
+// P-frame would corrupt the picture until the next keyframe
+#[tauri::command]
+async fn start_websocket_stream(
+    app: tauri::AppHandle,
+    port: u16,
+    format: Option<String>,
+    fps: Option<u32>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "start_websocket_stream")?;
+    let format = WsFrameFormat::parse(format.as_deref().unwrap_or("jpeg"))?;
+    let fps = capped_fps(fps.unwrap_or(15).clamp(1, MAX_STREAM_FPS));
+    
+    if WS_STREAMING.swap(true, Ordering::SeqCst) {
+        return Err("WebSocket stream already running".to_string());
+    }
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            WS_STREAMING.store(false, Ordering::SeqCst);
+            return Err(format!("Cannot bind WebSocket port {}: {}", port, e));
+        }
+    };
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or(port);
+    
+    WS_FRAMES_SENT.store(0, Ordering::Relaxed);
+    register_listening_port("websocket-stream", "TCP", port, "Browser stream viewer (WebSocket)");
+    println!("WebSocket stream listening on port {} ({}, {} FPS)", port, format.name(), fps);
+    tauri::async_runtime::spawn(run_websocket_server(app, listener, format, fps));
+    
+    Ok(serde_json::json!({
+        "status": "started",
+        "port": port,
+        "format": format.name(),
+        "fps": fps
+    }))
+}
+
+async fn run_websocket_server(app: tauri::AppHandle, listener: tokio::net::TcpListener, format: WsFrameFormat, fps: u32) {
+    while WS_STREAMING.load(Ordering::SeqCst) {
+        // Wake up regularly so a stop is noticed even with nobody connecting
+        let (stream, addr) = match tokio::time::timeout(Duration::from_millis(500), listener.accept()).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                eprintln!("WebSocket accept error: {}", e);
+                continue;
+            }
+            Err(_) => continue,
+        };
+        if peer_permitted(&app, addr.ip(), "websocket-stream") {
+            tauri::async_runtime::spawn(serve_websocket_client(stream, addr, format, fps));
+        }
+    }
+    
+    unregister_listening_port("websocket-stream");
+    println!("WebSocket stream stopped");
+}
+
+// Next frame this client hasn't seen, with its H.264 frame sequence number
+fn next_ws_frame(format: WsFrameFormat, last_sent: Option<u64>) -> Option<(u64, Vec<u8>)> {
+    match format {
+        WsFrameFormat::H264 => {
+            let frame = LAST_H264_FRAME.lock();
+            let sequence = H264_FRAME_SEQUENCE.load(Ordering::Relaxed);
+            if last_sent == Some(sequence) {
+                return None;
+            }
+            frame.clone().map(|data| (sequence, data))
+        }
+        WsFrameFormat::Jpeg => LAST_JPEG_FRAME.lock().clone().map(|data| (0, data)),
+    }
+}
+
+async fn serve_websocket_client(stream: tokio::net::TcpStream, addr: SocketAddr, format: WsFrameFormat, fps: u32) {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+    
+    let (mut sink, mut incoming) = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws.split(),
+        Err(e) => {
+            eprintln!("WebSocket handshake with {} failed: {}", addr, e);
+            return;
+        }
+    };
+    let clients = WS_CLIENTS.fetch_add(1, Ordering::Relaxed) + 1;
+    println!("WebSocket viewer {} connected ({} connected)", addr, clients);
+    
+    let poll = match format {
+        WsFrameFormat::H264 => WS_H264_POLL_INTERVAL,
+        WsFrameFormat::Jpeg => Duration::from_micros(1_000_000 / fps as u64),
+    };
+    let mut waiting_for_keyframe = format == WsFrameFormat::H264;
+    if waiting_for_keyframe {
+        WS_KEYFRAME_REQUESTED.store(true, Ordering::Relaxed);
+    }
+    let mut last_h264: Option<u64> = None;
+    let mut last_jpeg: Option<Vec<u8>> = None;
+    let mut sequence = 0u32;
+    
+    let reason = loop {
+        match tokio::time::timeout(poll, incoming.next()).await {
+            // Closing the tab sends Close; a dropped connection just ends the stream
+            Ok(None) | Ok(Some(Ok(Message::Close(_)))) => break "closed by viewer",
+            Ok(Some(Err(_))) => break "connection lost",
+            Ok(Some(Ok(_))) => continue, // pings are answered for us, anything else is ignored
+            Err(_) => {}
+        }
+        if !WS_STREAMING.load(Ordering::SeqCst) {
+            let _ = sink.send(Message::Close(None)).await;
+            break "server stopped";
+        }
+        
+        let Some((frame_sequence, mut data)) = next_ws_frame(format, last_h264) else {
+            continue;
+        };
+        let mut keyframe = false;
+        if format == WsFrameFormat::H264 {
+            // Missed frames leave the decoder without references, so resync on a keyframe
+            if last_h264.is_some_and(|last| frame_sequence != last + 1) && !waiting_for_keyframe {
+                waiting_for_keyframe = true;
+                WS_KEYFRAME_REQUESTED.store(true, Ordering::Relaxed);
+            }
+            last_h264 = Some(frame_sequence);
+            keyframe = is_h264_keyframe(&data);
+            if waiting_for_keyframe && !keyframe {
+                continue;
+            }
+            waiting_for_keyframe = false;
+            
+            if keyframe && extract_parameter_sets(&data).is_none() {
+                if let Some(sets) = PARAMETER_SETS.lock().clone() {
+                    let mut with_sets = Vec::with_capacity(data.len() + sets.sps.len() + sets.pps.len() + 8);
+                    for nal in [&sets.sps, &sets.pps] {
+                        with_sets.extend_from_slice(&[0, 0, 0, 1]);
+                        with_sets.extend_from_slice(nal);
+                    }
+                    with_sets.extend_from_slice(&data);
+                    data = with_sets;
+                }
+            }
+        } else {
+            if last_jpeg.as_ref() == Some(&data) {
+                continue;
+            }
+            last_jpeg = Some(data.clone());
+        }
+        
+        let message = serde_json::json!({
+            "type": "frame",
+            "format": format.name(),
+            "sequence": sequence,
+            "keyframe": keyframe,
+            "data": general_purpose::STANDARD.encode(&data)
+        }).to_string();
+        if sink.send(Message::Text(message)).await.is_err() {
+            break "connection lost";
+        }
+        WS_FRAMES_SENT.fetch_add(1, Ordering::Relaxed);
+        sequence = sequence.wrapping_add(1);
+    };
+    
+    let clients = WS_CLIENTS.fetch_sub(1, Ordering::Relaxed) - 1;
+    println!("WebSocket viewer {} disconnected: {} ({} connected)", addr, reason, clients);
+}
+
+// Clients are sent a Close frame on their next tick
+#[tauri::command]
+fn stop_websocket_stream() -> serde_json::Value {
+    WS_STREAMING.store(false, Ordering::SeqCst);
+    serde_json::json!({
+        "clients": WS_CLIENTS.load(Ordering::Relaxed),
+        "frames_sent": WS_FRAMES_SENT.load(Ordering::Relaxed)
+    })
+}
+
+// ============== JPEG Tile Delta Streaming ==============
+// For mostly-static screens: split the scaled frame into tiles, hash each one
+// and only send the tiles that changed, each as a small JPEG. A full frame is
+// sent periodically so late joiners and lossy links recover.
+//
+// Frame bundle: width(2) + height(2) + tile_size(2) + keyframe(1) + count(2)
+//   then per tile: x(2) + y(2) + len(4) + jpeg
+// Sent over UDP as "JT" chunks (see protocol.rs)
+const DELTA_TILE_SIZE: usize = 64;
+const DELTA_KEYFRAME_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    static ref DELTA_STREAMING: AtomicBool = AtomicBool::new(false);
+}
+
+fn tile_hash(rgb: &[u8], width: usize, x: usize, y: usize, tile_w: usize, tile_h: usize) -> u64 {
+    use std::hash::Hasher;
+    
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for row in y..y + tile_h {
+        let start = (row * width + x) * 3;
+        hasher.write(&rgb[start..start + tile_w * 3]);
+    }
+    hasher.finish()
+}
+
+fn extract_tile(rgb: &[u8], width: usize, x: usize, y: usize, tile_w: usize, tile_h: usize) -> Vec<u8> {
+    let mut tile = Vec::with_capacity(tile_w * tile_h * 3);
+    for row in y..y + tile_h {
+        let start = (row * width + x) * 3;
+        tile.extend_from_slice(&rgb[start..start + tile_w * 3]);
+    }
+    tile
+}
+
+// Build the bundle of tiles whose hash differs from `prev_hashes` (all tiles on keyframes).
+// Returns None when nothing changed.
+fn build_tile_bundle(
+    rgb: &[u8],
+    width: usize,
+    height: usize,
+    prev_hashes: &mut Vec<u64>,
+    keyframe: bool,
+    quality: u8
+) -> Option<Vec<u8>> {
+    let tiles_x = width.div_ceil(DELTA_TILE_SIZE);
+    let tiles_y = height.div_ceil(DELTA_TILE_SIZE);
+    
+    if prev_hashes.len() != tiles_x * tiles_y {
+        prev_hashes.clear();
+        prev_hashes.resize(tiles_x * tiles_y, 0);
+    }
+    
+    let mut tiles = Vec::new();
+    let mut count = 0u16;
+    
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let x = tx * DELTA_TILE_SIZE;
+            let y = ty * DELTA_TILE_SIZE;
+            let tile_w = DELTA_TILE_SIZE.min(width - x);
+            let tile_h = DELTA_TILE_SIZE.min(height - y);
+            
+            let hash = tile_hash(rgb, width, x, y, tile_w, tile_h);
+            let slot = &mut prev_hashes[ty * tiles_x + tx];
+            if !keyframe && *slot == hash {
+                continue;
+            }
+            
+            let jpeg = match encode_rgb_jpeg(extract_tile(rgb, width, x, y, tile_w, tile_h), tile_w, tile_h, quality) {
+                Some(jpeg) => jpeg,
+                None => continue,
+            };
+            *slot = hash;
+            
+            tiles.extend_from_slice(&(x as u16).to_le_bytes());
+            tiles.extend_from_slice(&(y as u16).to_le_bytes());
+            tiles.extend_from_slice(&(jpeg.len() as u32).to_le_bytes());
+            tiles.extend_from_slice(&jpeg);
+            count += 1;
+        }
+    }
+    
+    if count == 0 {
+        return None;
+    }
+    
+    let mut bundle = Vec::with_capacity(9 + tiles.len());
+    bundle.extend_from_slice(&(width as u16).to_le_bytes());
+    bundle.extend_from_slice(&(height as u16).to_le_bytes());
+    bundle.extend_from_slice(&(DELTA_TILE_SIZE as u16).to_le_bytes());
+    bundle.push(keyframe as u8);
+    bundle.extend_from_slice(&count.to_le_bytes());
+    bundle.extend_from_slice(&tiles);
+    Some(bundle)
+}
+
+#[tauri::command]
+fn start_jpeg_delta_stream(app: tauri::AppHandle, server_addr: String, fps: u32, quality: Option<u8>) -> Result<(), String> {
+    require_permission(&app, Permission::View, "start_jpeg_delta_stream")?;
+    validate_stream_target(&server_addr)?;
+    
+    if DELTA_STREAMING.swap(true, Ordering::SeqCst) {
+        return Err("Already streaming".to_string());
+    }
+    
+    let quality = quality.unwrap_or(60).clamp(10, 95);
+    let fps = capped_fps(fps.clamp(1, MAX_STREAM_FPS));
+    let (width, height) = capped_stream_size();
+    
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind("0.0.0.0:0") {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("UDP bind error: {}", e);
+                DELTA_STREAMING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        apply_socket_buffers(socket2::SockRef::from(&socket));
+        
+        let mut capturer = match ScreenCapturer::new() {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Capturer error: {}", e);
+                DELTA_STREAMING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        
+        let frame_interval = Duration::from_micros(1_000_000 / fps as u64);
+        let mut prev_hashes = Vec::new();
+        let mut sequence: u32 = 0;
+        let mut last_keyframe: Option<Instant> = None;
+        
+        println!("JPEG delta streaming started to {} at {} FPS", server_addr, fps);
+        
+        while DELTA_STREAMING.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            
+            if let Some(mut bgra) = capturer.capture() {
+                draw_cursor_highlight(&mut bgra, capturer.width, capturer.height);
+                apply_stream_watermark(&mut bgra, capturer.width, capturer.height);
+                
+                let rgb = bgra_to_rgb_resized(
+                    &bgra,
+                    capturer.width,
+                    capturer.height,
+                    width,
+                    height,
+                    PRESERVE_ASPECT.load(Ordering::Relaxed)
+                );
+                let keyframe = last_keyframe.is_none_or(|t| t.elapsed() >= DELTA_KEYFRAME_INTERVAL);
+                
+                if let Some(bundle) = build_tile_bundle(&rgb, width, height, &mut prev_hashes, keyframe, quality) {
+                    if send_framed_udp(&socket, &server_addr, FrameKind::JpegTiles, &bundle, sequence).is_ok() {
+                        FRAME_COUNT.fetch_add(1, Ordering::Relaxed);
+                    }
+                    sequence = sequence.wrapping_add(1);
+                }
+                if keyframe {
+                    last_keyframe = Some(Instant::now());
+                }
+                
+                let elapsed = now.elapsed();
+                if elapsed < frame_interval {
+                    thread::sleep(frame_interval - elapsed);
+                }
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        
+        println!("JPEG delta streaming stopped");
+    });
+    
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_jpeg_delta_stream() {
+    DELTA_STREAMING.store(false, Ordering::SeqCst);
+}
+
+// Receiver side: the last full frame that changed tiles are drawn onto
+struct TileCanvas {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+    has_keyframe: bool,
+}
+
+impl TileCanvas {
+    fn new() -> Self {
+        Self { width: 0, height: 0, rgb: Vec::new(), has_keyframe: false }
+    }
+    
+    // Apply a bundle; returns true when the canvas holds a displayable frame
+    fn apply_bundle(&mut self, bundle: &[u8]) -> bool {
+        if bundle.len() < 9 {
+            return false;
+        }
+        
+        let width = u16::from_le_bytes([bundle[0], bundle[1]]) as usize;
+        let height = u16::from_le_bytes([bundle[2], bundle[3]]) as usize;
+        let keyframe = bundle[6] != 0;
+        let count = u16::from_le_bytes([bundle[7], bundle[8]]) as usize;
+        
+        if width == 0 || height == 0 {
+            return false;
+        }
+        
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.rgb = vec![0u8; width * height * 3];
+            self.has_keyframe = false;
+        }
+        
+        // Deltas are meaningless until we have a full frame to apply them to
+        if keyframe {
+            self.has_keyframe = true;
+        } else if !self.has_keyframe {
+            return false;
+        }
+        
+        let mut pos = 9;
+        for _ in 0..count {
+            if pos + 8 > bundle.len() {
+                break;
+            }
+            let x = u16::from_le_bytes([bundle[pos], bundle[pos + 1]]) as usize;
+            let y = u16::from_le_bytes([bundle[pos + 2], bundle[pos + 3]]) as usize;
+            let len = u32::from_le_bytes([bundle[pos + 4], bundle[pos + 5], bundle[pos + 6], bundle[pos + 7]]) as usize;
+            pos += 8;
+            
+            if pos + len > bundle.len() {
+                break;
+            }
+            
+            if let Ok(tile) = image::load_from_memory(&bundle[pos..pos + len]) {
+                let tile = tile.to_rgb8();
+                self.blit_tile(x, y, tile.as_raw(), tile.width() as usize, tile.height() as usize);
+            }
+            pos += len;
+        }
+        
+        true
+    }
+    
+    // Tiles come off the wire, so one may start outside the canvas; that one is
+    // skipped and the rest are clipped to the edge.
+    fn blit_tile(&mut self, x: usize, y: usize, tile_rgb: &[u8], tile_width: usize, tile_height: usize) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let tile_w = tile_width.min(self.width - x);
+        let tile_h = tile_height.min(self.height - y);
+        let tile_stride = tile_width * 3;
+        
+        for row in 0..tile_h {
+            let src = row * tile_stride;
+            let dst = ((y + row) * self.width + x) * 3;
+            self.rgb[dst..dst + tile_w * 3].copy_from_slice(&tile_rgb[src..src + tile_w * 3]);
+        }
+    }
+}
+
+// ============== Listening Ports ==============
+// Every socket other machines connect to, so users (or an installer) know what to
+// allow through the firewall. Servers register on bind and unregister on exit.
+#[derive(Clone, serde::Serialize)]
+struct ListeningPort {
+    service: &'static str,
+    protocol: &'static str,
+    port: u16,
+    description: &'static str,
+}
+
+lazy_static::lazy_static! {
+    static ref LISTENING_PORTS: Mutex<Vec<ListeningPort>> = Mutex::new(Vec::new());
+}
+
+fn register_listening_port(service: &'static str, protocol: &'static str, port: u16, description: &'static str) {
+    let mut ports = LISTENING_PORTS.lock();
+    ports.retain(|p| p.service != service);
+    ports.push(ListeningPort { service, protocol, port, description });
+}
+
+fn unregister_listening_port(service: &'static str) {
+    LISTENING_PORTS.lock().retain(|p| p.service != service);
+}
+
+fn firewall_hint(port: &ListeningPort) -> String {
+    if cfg!(target_os = "windows") {
+        format!(
+            "netsh advfirewall firewall add rule name=\"Quan Ly Phong May {}\" dir=in action=allow protocol={} localport={}",
+            port.service, port.protocol, port.port
+        )
+    } else {
+        format!("Allow inbound {} port {} ({})", port.protocol, port.port, port.description)
+    }
+}
+
+#[tauri::command]
+fn get_listening_ports() -> serde_json::Value {
+    let ports: Vec<serde_json::Value> = LISTENING_PORTS.lock().iter().map(|p| {
+        serde_json::json!({
+            "service": p.service,
+            "protocol": p.protocol,
+            "port": p.port,
+            "description": p.description,
+            "firewall_hint": firewall_hint(p)
+        })
+    }).collect();
+    
+    serde_json::json!({ "ports": ports })
+}
+
+// ============== Peer Access Control ==============
+// Allow/deny lists checked on every frame packet and TCP connection. Deny wins;
+// an empty allowlist means any peer not denied is accepted.
+const PEER_BLOCK_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PeerRule {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl PeerRule {
+    // "10.0.0.5", "192.168.1.0/24" or "fd00::/8"; a bare address is a single host
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let network: IpAddr = addr.trim().parse()
+            .map_err(|_| format!("Invalid peer address: {}", text))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in {}", text))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+    
+    fn matches(&self, ip: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 peers as ::ffff:a.b.c.d
+        let (network, ip, bits) = match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        let shift = bits - self.prefix as u32;
+        shift >= bits || (network ^ ip) >> shift == 0
+    }
+    
+    fn label(&self) -> String {
+        format!("{}/{}", self.network, self.prefix)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PEER_ALLOWLIST: Mutex<Vec<PeerRule>> = Mutex::new(Vec::new());
+    static ref PEER_DENYLIST: Mutex<Vec<PeerRule>> = Mutex::new(Vec::new());
+    // Last peer-blocked event per IP, so a flood of packets doesn't flood the frontend
+    static ref PEER_BLOCK_REPORTS: Mutex<HashMap<IpAddr, Instant>> = Mutex::new(HashMap::new());
+}
+
+fn peer_allowed(ip: IpAddr) -> bool {
+    if PEER_DENYLIST.lock().iter().any(|rule| rule.matches(ip)) {
+        return false;
+    }
+    let allowlist = PEER_ALLOWLIST.lock();
+    allowlist.is_empty() || allowlist.iter().any(|rule| rule.matches(ip))
+}
+
+// peer_allowed, plus a (rate-limited) peer-blocked event when it isn't
+fn peer_permitted(app: &tauri::AppHandle, ip: IpAddr, source: &str) -> bool {
+    if peer_allowed(ip) {
+        return true;
+    }
+    let mut reports = PEER_BLOCK_REPORTS.lock();
+    let due = reports.get(&ip).is_none_or(|last| last.elapsed() >= PEER_BLOCK_REPORT_INTERVAL);
+    if due {
+        reports.insert(ip, Instant::now());
+        eprintln!("Blocked {} peer {}", source, ip);
+        let _ = app.emit("peer-blocked", serde_json::json!({
+            "ip": ip.to_string(),
+            "source": source
+        }));
+    }
+    false
+}
+
+fn parse_peer_rules(entries: &[String]) -> Result<Vec<PeerRule>, String> {
+    entries.iter()
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| PeerRule::parse(entry))
+        .collect()
+}
+
+// Replaces the allowlist; an empty list lifts the restriction
+#[tauri::command]
+fn set_peer_allowlist(entries: Vec<String>) -> Result<serde_json::Value, String> {
+    let rules = parse_peer_rules(&entries)?;
+    let labels: Vec<String> = rules.iter().map(PeerRule::label).collect();
+    *PEER_ALLOWLIST.lock() = rules;
+    PEER_BLOCK_REPORTS.lock().clear();
+    println!("Peer allowlist: {:?}", labels);
+    Ok(serde_json::json!({ "allowlist": labels }))
+}
+
+#[tauri::command]
+fn set_peer_denylist(entries: Vec<String>) -> Result<serde_json::Value, String> {
+    let rules = parse_peer_rules(&entries)?;
+    let labels: Vec<String> = rules.iter().map(PeerRule::label).collect();
+    *PEER_DENYLIST.lock() = rules;
+    PEER_BLOCK_REPORTS.lock().clear();
+    println!("Peer denylist: {:?}", labels);
+    Ok(serde_json::json!({ "denylist": labels }))
+}
+
+// ============== H.264 UDP Receiver ==============
+// After a resume, wait this long for a keyframe before emitting whatever arrives
+const RESUME_KEYFRAME_WAIT: Duration = Duration::from_secs(2);
+const HEARTBEAT_ACK_INTERVAL: Duration = Duration::from_secs(1);
+// Several viewers resetting at once still cost the sender one IDR
+const KEYFRAME_REQUEST_MIN_INTERVAL: Duration = Duration::from_millis(500);
+// Frames sent on the frontend channel but not yet acked (ack_frames) before new ones are dropped
+const FRAME_CHANNEL_CREDITS: u64 = 4;
+// A frontend that stopped acking (handler threw, page reloaded) gets its credits back after this
+const FRAME_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+lazy_static::lazy_static! {
+    // Set when the frontend passed on_frame; frames then skip app.emit
+    static ref FRAME_CHANNEL: Mutex<Option<tauri::ipc::Channel<serde_json::Value>>> = Mutex::new(None);
+    static ref FRAMES_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+    static ref LAST_FRAME_ACK: Mutex<Instant> = Mutex::new(Instant::now());
+    static ref BACKPRESSURE_DROPS: AtomicU64 = AtomicU64::new(0);
+    static ref RECEIVER_TUNING: Mutex<ReceiverTuning> = Mutex::new(ReceiverTuning::default());
+    // Picked up by the receive loop, see reset_receiver_decoder
+    static ref RECEIVER_RESET_REQUESTED: AtomicBool = AtomicBool::new(false);
+    // Partial frames abandoned after assembler_timeout
+    static ref RECEIVER_EXPIRED_FRAMES: AtomicU64 = AtomicU64::new(0);
+    static ref RECEIVER_REBINDS: AtomicU64 = AtomicU64::new(0);
+}
+
+// Latency-vs-smoothness knobs, re-read by the receive loop on every iteration
+#[derive(Debug, Clone, Copy)]
+struct ReceiverTuning {
+    // A frame still missing chunks this long after its first one is abandoned
+    assembler_timeout: Duration,
+    // Longest an assembled frame may be held to even out bursty arrival (0 = off)
+    jitter_buffer: Duration,
+    // Min spacing between emitted frames per sender, unless low latency is on
+    emit_interval: Duration,
+    // A sender silent this long is dropped (partial frame included) with peer-stream-ended
+    sender_timeout: Duration,
+    // Rebind attempts after the socket keeps failing before the receiver gives up
+    max_rebinds: u32,
+    // Wait before the first rebind attempt, doubled for each one after it
+    rebind_backoff: Duration,
+}
+
+impl Default for ReceiverTuning {
+    fn default() -> Self {
+        Self {
+            assembler_timeout: Duration::from_millis(2000),
+            jitter_buffer: Duration::ZERO,
+            emit_interval: Duration::from_millis(33),
+            sender_timeout: Duration::from_secs(10),
+            max_rebinds: 5,
+            rebind_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+const ASSEMBLER_TIMEOUT_RANGE_MS: (u64, u64) = (50, 10_000);
+const JITTER_BUFFER_RANGE_MS: (u64, u64) = (0, 500);
+const EMIT_INTERVAL_RANGE_MS: (u64, u64) = (0, 1000);
+const SENDER_TIMEOUT_RANGE_MS: (u64, u64) = (1000, 300_000);
+const REBIND_BACKOFF_RANGE_MS: (u64, u64) = (100, 10_000);
+const MAX_REBINDS_LIMIT: u32 = 100;
+const REBIND_BACKOFF_MAX: Duration = Duration::from_secs(30);
+// Receive timeout when nothing is waiting in a jitter buffer
+const RECEIVER_IDLE_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn validate_tuning_ms(name: &str, value: u64, (min, max): (u64, u64)) -> Result<Duration, String> {
+    if value < min || value > max {
+        return Err(format!("{} must be {}-{} ms (got {})", name, min, max, value));
+    }
+    Ok(Duration::from_millis(value))
+}
+
+// Takes effect on the running receiver without a restart; None keeps the current value
+#[tauri::command]
+fn set_receiver_tuning(
+    assembler_timeout_ms: Option<u64>,
+    jitter_buffer_ms: Option<u64>,
+    emit_interval_ms: Option<u64>,
+    sender_timeout_ms: Option<u64>,
+    max_rebinds: Option<u32>,
+    rebind_backoff_ms: Option<u64>
+) -> Result<serde_json::Value, String> {
+    let assembler_timeout = assembler_timeout_ms
+        .map(|ms| validate_tuning_ms("assembler_timeout_ms", ms, ASSEMBLER_TIMEOUT_RANGE_MS))
+        .transpose()?;
+    let jitter_buffer = jitter_buffer_ms
+        .map(|ms| validate_tuning_ms("jitter_buffer_ms", ms, JITTER_BUFFER_RANGE_MS))
+        .transpose()?;
+    let emit_interval = emit_interval_ms
+        .map(|ms| validate_tuning_ms("emit_interval_ms", ms, EMIT_INTERVAL_RANGE_MS))
+        .transpose()?;
+    let sender_timeout = sender_timeout_ms
+        .map(|ms| validate_tuning_ms("sender_timeout_ms", ms, SENDER_TIMEOUT_RANGE_MS))
+        .transpose()?;
+    let rebind_backoff = rebind_backoff_ms
+        .map(|ms| validate_tuning_ms("rebind_backoff_ms", ms, REBIND_BACKOFF_RANGE_MS))
+        .transpose()?;
+    if let Some(n) = max_rebinds.filter(|&n| n > MAX_REBINDS_LIMIT) {
+        return Err(format!("max_rebinds must be 0-{} (got {})", MAX_REBINDS_LIMIT, n));
+    }
+    
+    let mut tuning = RECEIVER_TUNING.lock();
+    if let Some(timeout) = assembler_timeout {
+        tuning.assembler_timeout = timeout;
+    }
+    if let Some(buffer) = jitter_buffer {
+        tuning.jitter_buffer = buffer;
+    }
+    if let Some(interval) = emit_interval {
+        tuning.emit_interval = interval;
+    }
+    if let Some(timeout) = sender_timeout {
+        tuning.sender_timeout = timeout;
+    }
+    if let Some(n) = max_rebinds {
+        tuning.max_rebinds = n;
+    }
+    if let Some(backoff) = rebind_backoff {
+        tuning.rebind_backoff = backoff;
+    }
+    
+    Ok(serde_json::json!({
+        "assembler_timeout_ms": tuning.assembler_timeout.as_millis() as u64,
+        "jitter_buffer_ms": tuning.jitter_buffer.as_millis() as u64,
+        "emit_interval_ms": tuning.emit_interval.as_millis() as u64,
+        "sender_timeout_ms": tuning.sender_timeout.as_millis() as u64,
+        "max_rebinds": tuning.max_rebinds,
+        "rebind_backoff_ms": tuning.rebind_backoff.as_millis() as u64
+    }))
+}
+
+// What a held frame needs from the ones before it, for the backpressure drop path
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FrameDeps {
+    // JPEG: any one can go
+    Independent,
+    Keyframe,
+    // H.264 P-frame: useless once an earlier frame was dropped
+    Delta,
+}
+
+impl FrameDeps {
+    fn h264(frame: &[u8]) -> Self {
+        if is_h264_keyframe(frame) {
+            Self::Keyframe
+        } else {
+            Self::Delta
+        }
+    }
+}
+
+// Releases assembled frames at the sender's average frame spacing rather than
+// in the bursts they arrived in, never holding one longer than max_hold
+struct JitterBuffer {
+    queue: std::collections::VecDeque<(Instant, &'static str, serde_json::Value, FrameDeps)>,
+    last_arrival: Option<Instant>,
+    avg_spacing: Duration,
+    last_due: Option<Instant>,
+}
+
+impl JitterBuffer {
+    fn new() -> Self {
+        Self {
+            queue: std::collections::VecDeque::new(),
+            last_arrival: None,
+            avg_spacing: Duration::ZERO,
+            last_due: None,
+        }
+    }
+    
+    fn push(&mut self, event: &'static str, payload: serde_json::Value, deps: FrameDeps, max_hold: Duration) {
+        let now = Instant::now();
+        if let Some(last) = self.last_arrival {
+            // Newest gap gets 1/8 weight
+            self.avg_spacing = (self.avg_spacing * 7 + (now - last)) / 8;
+        }
+        self.last_arrival = Some(now);
+        
+        let paced = self.last_due.map_or(now, |due| (due + self.avg_spacing).max(now));
+        let due = paced.min(now + max_hold);
+        self.last_due = Some(due);
+        self.queue.push_back((due, event, payload, deps));
+    }
+    
+    fn pop_due(&mut self, now: Instant) -> Option<(&'static str, serde_json::Value, FrameDeps)> {
+        match self.queue.front() {
+            Some((due, _, _, _)) if *due <= now => self.queue.pop_front().map(|(_, event, payload, deps)| (event, payload, deps)),
+            _ => None,
+        }
+    }
+    
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.front().map(|(due, _, _, _)| *due)
+    }
+}
+
+// Hand a frame to the frontend. Through the channel each frame costs a credit the
+// frontend returns with ack_frames; with none left the frame is dropped (counted in
+// backpressure_drops) instead of piling up messages the UI can't drain. Keyframes
+// are never dropped, they go out on credit. Returns false if the frame was dropped.
+fn deliver_frame(app: &tauri::AppHandle, event: &str, payload: serde_json::Value, deps: FrameDeps) -> bool {
+    let channel = FRAME_CHANNEL.lock().clone();
+    let Some(channel) = channel else {
+        let _ = app.emit(event, payload);
+        return true;
+    };
+    
+    if FRAMES_IN_FLIGHT.load(Ordering::SeqCst) >= FRAME_CHANNEL_CREDITS && deps != FrameDeps::Keyframe {
+        if LAST_FRAME_ACK.lock().elapsed() < FRAME_ACK_TIMEOUT {
+            BACKPRESSURE_DROPS.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        println!("No frame acks for {:?}, resetting channel credits", FRAME_ACK_TIMEOUT);
+        FRAMES_IN_FLIGHT.store(0, Ordering::SeqCst);
+        *LAST_FRAME_ACK.lock() = Instant::now();
+    }
+    
+    if channel.send(serde_json::json!({ "event": event, "payload": payload })).is_ok() {
+        FRAMES_IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    } else {
+        // Frontend channel is gone (page reloaded); later frames go out as events
+        *FRAME_CHANNEL.lock() = None;
+    }
+    true
+}
+
+fn attach_frame_channel(channel: Option<tauri::ipc::Channel<serde_json::Value>>) {
+    FRAMES_IN_FLIGHT.store(0, Ordering::SeqCst);
+    *LAST_FRAME_ACK.lock() = Instant::now();
+    BACKPRESSURE_DROPS.store(0, Ordering::Relaxed);
+    *FRAME_CHANNEL.lock() = channel;
+}
+
+// The frontend handled count (default 1) frames from the on_frame channel
+#[tauri::command]
+fn ack_frames(count: Option<u64>) {
+    let count = count.unwrap_or(1);
+    let _ = FRAMES_IN_FLIGHT.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| Some(n.saturating_sub(count)));
+    *LAST_FRAME_ACK.lock() = Instant::now();
+}
+
+// Reassembly state is kept per source address so two senders on the same
+// port can't collide on sequence numbers
+struct ReceiverSender {
+    frame_buffer: H264FrameAssembler,
+    // Thumbnails number their frames separately from the main stream
+    thumbnail_buffer: H264FrameAssembler,
+    tile_canvas: TileCanvas,
+    last_emit: Instant,
+    last_seen: Instant,
+    last_ack: Option<Instant>,
+    // H.264 flags byte last seen, to emit stream-color-space when it changes
+    color_flags: Option<u8>,
+    // SPS/PPS hash from the sender's last tagged keyframe
+    param_hash: Option<u32>,
+    jitter: JitterBuffer,
+    // A P-frame was dropped for backpressure; the rest wait for a keyframe
+    awaiting_keyframe: bool,
+    last_keyframe_request: Option<Instant>,
+}
+
+impl ReceiverSender {
+    fn release_due_frames(&mut self, app: &tauri::AppHandle, socket: &UdpSocket, addr: SocketAddr, now: Instant) {
+        while let Some((event, payload, deps)) = self.jitter.pop_due(now) {
+            if !self.admit_frame(deps) {
+                BACKPRESSURE_DROPS.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if !deliver_frame(app, event, payload, deps) && deps == FrameDeps::Delta && !self.awaiting_keyframe {
+                self.awaiting_keyframe = true;
+                if self.last_keyframe_request.is_none_or(|t| t.elapsed() >= KEYFRAME_REQUEST_MIN_INTERVAL) {
+                    let newest_seq = self.frame_buffer.last_completed_seq.unwrap_or(0);
+                    let _ = socket.send_to(&protocol::encode_keyframe_request(newest_seq), addr);
+                    self.last_keyframe_request = Some(Instant::now());
+                }
+            }
+        }
+    }
+    
+    // After a dropped P-frame the following ones can't decode; let only a keyframe end that
+    fn admit_frame(&mut self, deps: FrameDeps) -> bool {
+        match deps {
+            FrameDeps::Keyframe => {
+                self.awaiting_keyframe = false;
+                true
+            }
+            FrameDeps::Delta => !self.awaiting_keyframe,
+            FrameDeps::Independent => true,
+        }
+    }
+}
+
+impl ReceiverSender {
+    fn new() -> Self {
+        Self {
+            frame_buffer: H264FrameAssembler::new(),
+            thumbnail_buffer: H264FrameAssembler::new(),
+            tile_canvas: TileCanvas::new(),
+            last_emit: Instant::now(),
+            last_seen: Instant::now(),
+            last_ack: None,
+            color_flags: None,
+            param_hash: None,
+            jitter: JitterBuffer::new(),
+            awaiting_keyframe: false,
+            last_keyframe_request: None,
+        }
+    }
+    
+    // A tagged keyframe's SPS/PPS must match their hash; a new hash means a viewer
+    // decoder configured from the old sets would show garbage from here on
+    fn check_parameter_sets(&mut self, app: &tauri::AppHandle, addr: SocketAddr, hash: u32, frame: &[u8]) {
+        let Some(sets) = extract_parameter_sets(frame) else {
+            return;
+        };
+        if sets.hash() != hash {
+            eprintln!("Keyframe from {} carries SPS/PPS that don't match their hash {:08x}", addr, hash);
+            return;
+        }
+        if self.param_hash.replace(hash).is_some_and(|previous| previous != hash) {
+            println!("Sender {} changed its SPS/PPS (hash {:08x})", addr, hash);
+            let _ = app.emit("parameter-sets-changed", serde_json::json!({
+                "sender": addr.ip().to_string(),
+                "hash": format!("{:08x}", hash),
+                "sps": general_purpose::STANDARD.encode(&sets.sps),
+                "pps": general_purpose::STANDARD.encode(&sets.pps)
+            }));
+        }
+    }
+}
+
+// Drops senders idle for at least `timeout`, returning each one with how long it was silent
+fn sweep_idle_senders(
+    senders: &mut HashMap<SocketAddr, ReceiverSender>,
+    timeout: Duration,
+    now: Instant
+) -> Vec<(SocketAddr, Duration)> {
+    let mut ended = Vec::new();
+    senders.retain(|addr, sender| {
+        let idle = now.saturating_duration_since(sender.last_seen);
+        if idle < timeout {
+            return true;
+        }
+        sender.frame_buffer.flush_chunk_stats();
+        ended.push((*addr, idle));
+        false
+    });
+    ended
+}
+
+fn bind_receiver_socket(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    apply_socket_buffers(socket2::SockRef::from(&socket));
+    socket.set_read_timeout(Some(RECEIVER_IDLE_READ_TIMEOUT))?;
+    Ok(socket)
+}
+
+// Backoff between rebinds; stop_h264_receiver still gets through within 100ms
+fn receiver_backoff(wait: Duration) {
+    let deadline = Instant::now() + wait;
+    while UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100).min(deadline.saturating_duration_since(Instant::now())));
+    }
+}
+
+// The socket keeps failing (e.g. its interface went away): close it and bind the
+// same port again, with the backoff doubling per attempt. None = gave up, or the
+// receiver was stopped meanwhile.
+fn rebind_receiver_socket(
+    app: &tauri::AppHandle,
+    socket: UdpSocket,
+    port: u16,
+    error: &std::io::Error,
+    tuning: &ReceiverTuning
+) -> Option<UdpSocket> {
+    drop(socket);
+    let mut backoff = tuning.rebind_backoff;
+    
+    for attempt in 1..=tuning.max_rebinds {
+        println!("Receiver socket failing ({}), rebind {}/{} in {:?}", error, attempt, tuning.max_rebinds, backoff);
+        let _ = app.emit("receiver-reconnecting", serde_json::json!({
+            "port": port,
+            "attempt": attempt,
+            "max_attempts": tuning.max_rebinds,
+            "backoff_ms": backoff.as_millis() as u64,
+            "error": error.to_string()
+        }));
+        receiver_backoff(backoff);
+        if !UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
+            return None;
+        }
+        
+        match bind_receiver_socket(port) {
+            Ok(socket) => {
+                let rebinds = RECEIVER_REBINDS.fetch_add(1, Ordering::Relaxed) + 1;
+                println!("Receiver socket rebound on port {} (rebinds: {})", port, rebinds);
+                let _ = app.emit("receiver-reconnected", serde_json::json!({
+                    "port": port,
+                    "attempt": attempt,
+                    "rebinds": rebinds
+                }));
+                return Some(socket);
+            }
+            Err(e) => eprintln!("Receiver rebind on port {} failed: {}", port, e),
+        }
+        backoff = (backoff * 2).min(REBIND_BACKOFF_MAX);
+    }
+    None
+}
+
+// Returns the bound port (port 0 = OS-assigned)
+fn start_h264_receiver(app: tauri::AppHandle, port: u16) -> Result<u16, String> {
+    // Held for the whole start so two starts can't race each other
+    let mut receiver_thread = UDP_RECEIVER_THREAD.lock();
+    
+    if UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
+        return Err("Already running".to_string());
+    }
+    
+    // A previous receiver may still be inside its read timeout; wait for it to release the socket
+    if let Some(handle) = receiver_thread.take() {
+        let _ = handle.join();
+    }
+    
+    let mut socket = bind_receiver_socket(port)
+        .map_err(|e| format!("UDP bind error on port {}: {}", port, e))?;
+    // Rebinds reuse the port the OS picked for port 0
+    let port = socket.local_addr().map(|a| a.port()).unwrap_or(port);
+    
+    register_listening_port("frame-receiver", "UDP", port, "Screen stream receiver");
+    
+    UDP_RECEIVER_RUNNING.store(true, Ordering::SeqCst);
+    *RECEIVER_STARTED.lock() = Some(Instant::now());
+    RECEIVER_DUPLICATE_CHUNKS.store(0, Ordering::Relaxed);
+    RECEIVER_OUT_OF_ORDER_CHUNKS.store(0, Ordering::Relaxed);
+    RECEIVER_REBINDS.store(0, Ordering::Relaxed);
+    reset_packet_sizes();
+    RECEIVER_RESET_REQUESTED.store(false, Ordering::SeqCst);
+    
+    *receiver_thread = Some(thread::spawn(move || {
+        let mut senders: HashMap<SocketAddr, ReceiverSender> = HashMap::new();
+        let mut socket_errors = 0u32;
+        let mut last_sweep = Instant::now();
+        let mut buf = [0u8; RECEIVER_BUF_SIZE];
+        let mut read_timeout = RECEIVER_IDLE_READ_TIMEOUT;
+        let mut was_paused = false;
+        let mut resumed_at: Option<Instant> = None;
+        
+        println!("H.264 UDP receiver started on port {}", port);
+        
+        while UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
+            let tuning = *RECEIVER_TUNING.lock();
+            if RECEIVER_RESET_REQUESTED.swap(false, Ordering::SeqCst) {
+                for (addr, sender) in senders.iter_mut() {
+                    let newest_seq = sender.frame_buffer.last_completed_seq.unwrap_or(0);
+                    sender.frame_buffer.flush_chunk_stats();
+                    sender.frame_buffer = H264FrameAssembler::new();
+                    sender.jitter = JitterBuffer::new();
+                    // Re-announce the color space to the fresh decoder
+                    sender.color_flags = None;
+                    let _ = socket.send_to(&protocol::encode_keyframe_request(newest_seq), addr);
+                }
+                // Hold deltas back until the requested keyframe shows up
+                resumed_at = Some(Instant::now());
+                println!("Receiver decoder state reset ({} senders)", senders.len());
+                let _ = app.emit("decoder-reset", serde_json::json!({ "senders": senders.len() }));
+            }
+            if last_sweep.elapsed() >= Duration::from_secs(1) {
+                for (addr, idle) in sweep_idle_senders(&mut senders, tuning.sender_timeout, Instant::now()) {
+                    println!("Sender {} idle for {:?}, dropping its stream state", addr, idle);
+                    let _ = app.emit("peer-stream-ended", serde_json::json!({
+                        "sender": addr.ip().to_string(),
+                        "addr": addr.to_string(),
+                        "idle_secs": idle.as_secs()
+                    }));
+                }
+                last_sweep = Instant::now();
+            }
+            
+            // Release held frames, and wake up in time for the next one
+            let now = Instant::now();
+            let mut next_due: Option<Instant> = None;
+            for (addr, sender) in senders.iter_mut() {
+                sender.release_due_frames(&app, &socket, *addr, now);
+                if let Some(due) = sender.jitter.next_due() {
+                    next_due = Some(next_due.map_or(due, |earliest| earliest.min(due)));
+                }
+            }
+            let wanted_timeout = next_due
+                .map(|due| due.saturating_duration_since(now).clamp(Duration::from_millis(1), RECEIVER_IDLE_READ_TIMEOUT))
+                .unwrap_or(RECEIVER_IDLE_READ_TIMEOUT);
+            if wanted_timeout != read_timeout {
+                let _ = socket.set_read_timeout(Some(wanted_timeout));
+                read_timeout = wanted_timeout;
+            }
+            
+            match socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    socket_errors = 0;
+                    if !peer_permitted(&app, addr.ip(), "frame-receiver") {
+                        continue;
+                    }
+                    record_packet_size(len);
+                    // Link probe from start_stream_auto: echo it straight back
+                    if let Some(probe) = protocol::parse_probe(&buf[..len]) {
+                        let _ = socket.send_to(&protocol::encode_probe_reply(probe), addr);
+                        continue;
+                    }
+                    // Clock sync from sync_clock_with_peer: stamp and return
+                    if let Some((seq, t0)) = protocol::parse_clock_sync(&buf[..len]) {
+                        let t1 = unix_micros();
+                        let reply = protocol::ClockReply { seq, t0, t1, t2: unix_micros() };
+                        let _ = socket.send_to(&protocol::encode_clock_reply(&reply), addr);
+                        continue;
+                    }
+                    // Sender stopped: deliver what it still has queued, then forget it.
+                    // Repeats of the marker find no sender and are ignored.
+                    if let Some(last_seq) = protocol::parse_stream_end(&buf[..len]) {
+                        if let Some(mut sender) = senders.remove(&addr) {
+                            sender.release_due_frames(&app, &socket, addr, Instant::now() + tuning.jitter_buffer);
+                            sender.frame_buffer.flush_chunk_stats();
+                            println!("Sender {} ended its stream (last frame {})", addr, last_seq);
+                            let _ = app.emit("stream-ended", serde_json::json!({
+                                "sender": addr.ip().to_string(),
+                                "addr": addr.to_string(),
+                                "last_seq": last_seq
+                            }));
+                        }
+                        continue;
+                    }
+                    let (header, payload) = match protocol::parse_header(&buf[..len]) {
+                        Some(parsed) => parsed,
+                        None => continue,
+                    };
+                    let seq = header.seq;
+                    let idx = header.idx as usize;
+                    let total = header.total as usize;
+                    let emit_interval = if RECEIVER_LOW_LATENCY.load(Ordering::Relaxed) {
+                        Duration::ZERO
+                    } else {
+                        tuning.emit_interval
+                    };
+                    
+                    let sender = senders.entry(addr).or_insert_with(ReceiverSender::new);
+                    sender.last_seen = Instant::now();
+                    if sender.frame_buffer.expire_stale(tuning.assembler_timeout) {
+                        RECEIVER_EXPIRED_FRAMES.fetch_add(1, Ordering::Relaxed);
+                    }
+                    
+                    // Lets the sender notice when we go away (see check_unreachable_targets)
+                    if sender.last_ack.is_none_or(|t| t.elapsed() >= HEARTBEAT_ACK_INTERVAL) {
+                        let _ = socket.send_to(&protocol::encode_heartbeat_ack(seq), addr);
+                        sender.last_ack = Some(Instant::now());
+                    }
+                    
+                    // While paused we keep draining and assembling, only encode/emit is skipped
+                    let paused = UDP_RECEIVER_PAUSED.load(Ordering::Relaxed);
+                    if was_paused && !paused {
+                        resumed_at = Some(Instant::now());
+                    }
+                    was_paused = paused;
+                    
+                    match header.kind {
+                        FrameKind::H264 => {
+                            let color_flags = header.flags & !protocol::FLAG_PARAM_HASH;
+                            if header.frame_type & protocol::FRAME_TYPE_START != 0 && sender.color_flags != Some(color_flags) {
+                                sender.color_flags = Some(color_flags);
+                                let color = ColorSpace::from_header_flags(color_flags);
+                                let _ = app.emit("stream-color-space", serde_json::json!({
+                                    "sender": addr.ip().to_string(),
+                                    "color_range": color.range_name(),
+                                    "matrix": color.matrix_name(),
+                                    "pixel_format": PixelFormat::from_header_flags(color_flags).name()
+                                }));
+                            }
+                            if let Some(assembled) = sender.frame_buffer.add_chunk(seq, idx, total, payload) {
+                                let (param_hash, h264_frame) = protocol::split_param_hash(header.flags, &assembled);
+                                if let Some(hash) = param_hash {
+                                    sender.check_parameter_sets(&app, addr, hash, h264_frame);
+                                }
+                                let emit = match resumed_at {
+                                    // Deltas after a gap won't decode, so hold out for a keyframe
+                                    Some(t) => is_h264_keyframe(h264_frame) || t.elapsed() >= RESUME_KEYFRAME_WAIT,
+                                    None => sender.last_emit.elapsed() >= emit_interval,
+                                };
+                                if !paused && emit {
+                                    resumed_at = None;
+                                    if RECEIVER_SPLIT_NALS.load(Ordering::Relaxed) {
+                                        let nals: Vec<serde_json::Value> = split_h264_nals(h264_frame).iter().map(|nal| {
+                                            serde_json::json!({
+                                                "type": nal[0] & 0x1F,
+                                                "data": general_purpose::STANDARD.encode(nal)
+                                            })
+                                        }).collect();
+                                        sender.jitter.push("h264-frame", serde_json::json!([addr.ip().to_string(), nals]), FrameDeps::h264(h264_frame), tuning.jitter_buffer);
+                                    } else {
+                                        let base64_str = general_purpose::STANDARD.encode(h264_frame);
+                                        sender.jitter.push("h264-frame", serde_json::json!([addr.ip().to_string(), base64_str]), FrameDeps::h264(h264_frame), tuning.jitter_buffer);
+                                    }
+                                    sender.last_emit = Instant::now();
+                                }
+                            }
+                        }
+                        FrameKind::JpegTiles => {
+                            if let Some(bundle) = sender.frame_buffer.add_chunk(seq, idx, total, payload) {
+                                // Always composite so no tile is lost, only throttle the emit
+                                if sender.tile_canvas.apply_bundle(&bundle) && !paused &&
+                                   (resumed_at.take().is_some() || sender.last_emit.elapsed() >= emit_interval) {
+                                    let rgb = sender.tile_canvas.rgb.clone();
+                                    if let Some(jpeg) = encode_rgb_jpeg(rgb, sender.tile_canvas.width, sender.tile_canvas.height, 80) {
+                                        let base64_str = general_purpose::STANDARD.encode(&jpeg);
+                                        let data_url = format!("data:image/jpeg;base64,{}", base64_str);
+                                        sender.jitter.push("udp-frame", serde_json::json!([addr.ip().to_string(), data_url]), FrameDeps::Independent, tuning.jitter_buffer);
+                                        sender.last_emit = Instant::now();
+                                    }
+                                }
+                            }
+                        }
+                        FrameKind::LegacyJpeg => {
+                            // Backward compatible with older senders
+                            if let Some(jpeg_frame) = sender.frame_buffer.add_chunk(seq, idx, total, payload) {
+                                if !paused && (resumed_at.take().is_some() || sender.last_emit.elapsed() >= emit_interval) {
+                                    let base64_str = general_purpose::STANDARD.encode(&jpeg_frame);
+                                    let data_url = format!("data:image/jpeg;base64,{}", base64_str);
+                                    sender.jitter.push("udp-frame", serde_json::json!([addr.ip().to_string(), data_url]), FrameDeps::Independent, tuning.jitter_buffer);
+                                    sender.last_emit = Instant::now();
+                                }
+                            }
+                        }
+                        FrameKind::Thumbnail => {
+                            // Low fps already; no throttle or jitter buffer
+                            if let Some(jpeg) = sender.thumbnail_buffer.add_chunk(seq, idx, total, payload) {
+                                if !paused {
+                                    let data_url = format!("data:image/jpeg;base64,{}", general_purpose::STANDARD.encode(&jpeg));
+                                    let _ = app.emit("stream-thumbnail", serde_json::json!([addr.ip().to_string(), data_url]));
+                                }
+                            }
+                            sender.thumbnail_buffer.flush_chunk_stats();
+                        }
+                    }
+                    sender.frame_buffer.flush_chunk_stats();
+                    // With no jitter buffer this delivers the frame just assembled
+                    sender.release_due_frames(&app, &socket, addr, Instant::now());
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || 
+                             e.kind() == std::io::ErrorKind::TimedOut => {
+                    // A healthy socket with nothing to read; only failures in a row count
+                    socket_errors = 0;
+                    continue;
+                }
+                // On Windows an ICMP port-unreachable for an ack/reply we sent to a
+                // sender that has gone away surfaces here as WSAECONNRESET; the
+                // socket is still fine, so it must not count toward a rebind
+                Err(ref e) if e.kind() == std::io::ErrorKind::ConnectionReset => {
+                    continue;
+                }
+                Err(e) => {
+                    socket_errors += 1;
+                    if socket_errors < SOCKET_REBIND_AFTER_ERRORS {
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                    socket = match rebind_receiver_socket(&app, socket, port, &e, &tuning) {
+                        Some(socket) => socket,
+                        None => {
+                            if UDP_RECEIVER_RUNNING.swap(false, Ordering::SeqCst) {
+                                eprintln!("Receiver giving up on port {} after {} rebinds: {}", port, tuning.max_rebinds, e);
+                                let _ = app.emit("receiver-failed", serde_json::json!({
+                                    "port": port,
+                                    "attempts": tuning.max_rebinds,
+                                    "error": e.to_string()
+                                }));
+                            }
+                            break;
+                        }
+                    };
+                    socket_errors = 0;
+                    read_timeout = RECEIVER_IDLE_READ_TIMEOUT;
+                }
+            }
+        }
+        
+        unregister_listening_port("frame-receiver");
+        println!("H.264 receiver stopped");
+    }));
+    
+    Ok(port)
+}
+
+// Receive buffer of the frame receiver; a packet filling it was probably cut short
+const RECEIVER_BUF_SIZE: usize = 1500;
+// Packet size bucket upper bounds in bytes, the last one being the whole buffer
+const PACKET_SIZE_BUCKETS: [usize; 9] = [64, 128, 256, 512, 1024, 1280, 1400, 1472, RECEIVER_BUF_SIZE];
+
+lazy_static::lazy_static! {
+    static ref PACKET_SIZE_COUNTS: [AtomicU64; PACKET_SIZE_BUCKETS.len()] = Default::default();
+    static ref PACKET_SIZE_TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
+    static ref PACKET_SIZE_MIN: AtomicU64 = AtomicU64::new(u64::MAX);
+    static ref PACKET_SIZE_MAX: AtomicU64 = AtomicU64::new(0);
+    static ref PACKET_SIZE_FULL_BUFFER: AtomicU64 = AtomicU64::new(0);
+}
+
+fn record_packet_size(len: usize) {
+    let bucket = PACKET_SIZE_BUCKETS.iter().position(|&le| len <= le).unwrap_or(PACKET_SIZE_BUCKETS.len() - 1);
+    PACKET_SIZE_COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+    PACKET_SIZE_TOTAL_BYTES.fetch_add(len as u64, Ordering::Relaxed);
+    PACKET_SIZE_MIN.fetch_min(len as u64, Ordering::Relaxed);
+    PACKET_SIZE_MAX.fetch_max(len as u64, Ordering::Relaxed);
+    if len >= RECEIVER_BUF_SIZE {
+        PACKET_SIZE_FULL_BUFFER.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn reset_packet_sizes() {
+    for count in PACKET_SIZE_COUNTS.iter() {
+        count.store(0, Ordering::Relaxed);
+    }
+    PACKET_SIZE_TOTAL_BYTES.store(0, Ordering::Relaxed);
+    PACKET_SIZE_MIN.store(u64::MAX, Ordering::Relaxed);
+    PACKET_SIZE_MAX.store(0, Ordering::Relaxed);
+    PACKET_SIZE_FULL_BUFFER.store(0, Ordering::Relaxed);
+}
+
+// Sizes of packets the frame receiver has accepted since it started. Anything
+// above protocol::MAX_PACKET_SIZE didn't come from our packetizer; packets that
+// fill the whole buffer were likely truncated (datagram bigger than the buffer).
+#[tauri::command]
+fn get_packet_size_stats() -> serde_json::Value {
+    let counts: Vec<u64> = PACKET_SIZE_COUNTS.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+    let packets: u64 = counts.iter().sum();
+    let total_bytes = PACKET_SIZE_TOTAL_BYTES.load(Ordering::Relaxed);
+    
+    let mut lower = 0;
+    let buckets: Vec<serde_json::Value> = PACKET_SIZE_BUCKETS.iter()
+        .zip(&counts)
+        .map(|(&le, &count)| {
+            let bucket = serde_json::json!({ "gt": lower, "le": le, "count": count });
+            lower = le;
+            bucket
+        })
+        .collect();
+    let oversized: u64 = PACKET_SIZE_BUCKETS.iter()
+        .zip(&counts)
+        .filter(|(&le, _)| le > protocol::MAX_PACKET_SIZE)
+        .map(|(_, &count)| count)
+        .sum();
+    
+    serde_json::json!({
+        "packets": packets,
+        "min_bytes": if packets > 0 { PACKET_SIZE_MIN.load(Ordering::Relaxed) } else { 0 },
+        "max_bytes": PACKET_SIZE_MAX.load(Ordering::Relaxed),
+        "avg_bytes": if packets > 0 { total_bytes as f64 / packets as f64 } else { 0.0 },
+        "buckets": buckets,
+        "oversized": oversized,
+        "likely_truncated": PACKET_SIZE_FULL_BUFFER.load(Ordering::Relaxed),
+        "max_packet_size": protocol::MAX_PACKET_SIZE,
+        "buffer_size": RECEIVER_BUF_SIZE
+    })
+}
+
+lazy_static::lazy_static! {
+    // Receiver-wide totals, flushed from each sender's assembler
+    static ref RECEIVER_DUPLICATE_CHUNKS: AtomicU64 = AtomicU64::new(0);
+    static ref RECEIVER_OUT_OF_ORDER_CHUNKS: AtomicU64 = AtomicU64::new(0);
+}
+
+struct H264FrameAssembler {
+    current_seq: u32,
+    chunks: Vec<Option<Vec<u8>>>,
+    total: usize,
+    received: usize,
+    highest_idx: usize,
+    last_completed_seq: Option<u32>,
+    // When the current frame's first chunk arrived
+    started: Option<Instant>,
+    // Since the last flush_chunk_stats: a retransmitted/FEC copy shows up as a
+    // duplicate, reordering on the path as out_of_order; true loss as neither
+    duplicate_chunks: u64,
+    out_of_order_chunks: u64,
+}
+
+impl H264FrameAssembler {
+    fn new() -> Self {
+        Self {
+            current_seq: u32::MAX,
+            chunks: Vec::new(),
+            total: 0,
+            received: 0,
+            highest_idx: 0,
+            last_completed_seq: None,
+            started: None,
+            duplicate_chunks: 0,
+            out_of_order_chunks: 0,
+        }
+    }
+    
+    fn add_chunk(&mut self, seq: u32, idx: usize, total: usize, data: &[u8]) -> Option<Vec<u8>> {
+        // A malformed header would otherwise "complete" an empty frame
+        if total == 0 {
+            return None;
+        }
+        
+        // A late copy of a frame we already emitted must not restart its assembly
+        if self.last_completed_seq == Some(seq) {
+            self.duplicate_chunks += 1;
+            return None;
+        }
+        
+        if seq != self.current_seq {
+            self.current_seq = seq;
+            self.chunks = vec![None; total];
+            self.total = total;
+            self.received = 0;
+            self.highest_idx = idx;
+            self.started = Some(Instant::now());
+        }
+        
+        if idx < self.total {
+            if self.chunks[idx].is_some() {
+                self.duplicate_chunks += 1;
+            } else {
+                if idx < self.highest_idx {
+                    self.out_of_order_chunks += 1;
+                }
+                self.highest_idx = self.highest_idx.max(idx);
+                self.chunks[idx] = Some(data.to_vec());
+                self.received += 1;
+            }
+        }
+        
+        if self.received == self.total {
+            let mut result = Vec::with_capacity(self.total * 1400);
+            for chunk in &self.chunks {
+                if let Some(data) = chunk {
+                    result.extend_from_slice(data);
+                }
+            }
+            
+            self.last_completed_seq = Some(self.current_seq);
+            self.current_seq = u32::MAX;
+            self.chunks.clear();
+            self.received = 0;
+            self.started = None;
+            
+            return Some(result);
+        }
+        
+        None
+    }
+    
+    // Give up on a frame still incomplete after timeout. Its late chunks are then
+    // ignored like those of a finished frame instead of restarting assembly.
+    fn expire_stale(&mut self, timeout: Duration) -> bool {
+        if self.started.is_none_or(|t| t.elapsed() <= timeout) {
+            return false;
+        }
+        self.last_completed_seq = Some(self.current_seq);
+        self.current_seq = u32::MAX;
+        self.chunks.clear();
+        self.total = 0;
+        self.received = 0;
+        self.started = None;
+        true
+    }
+    
+    // Move this sender's counts into the receiver-wide totals
+    fn flush_chunk_stats(&mut self) {
+        if self.duplicate_chunks > 0 {
+            RECEIVER_DUPLICATE_CHUNKS.fetch_add(std::mem::take(&mut self.duplicate_chunks), Ordering::Relaxed);
+        }
+        if self.out_of_order_chunks > 0 {
+            RECEIVER_OUT_OF_ORDER_CHUNKS.fetch_add(std::mem::take(&mut self.out_of_order_chunks), Ordering::Relaxed);
+        }
+    }
+}
+
+
+// ============== Relay (NAT traversal) ==============
+// Both peers connect out to a relay (another instance running start_relay_server),
+// which forwards already-framed UDP packets between members of the same session.
+// On each peer, the relay client is a local bridge socket: the sender streams to
+// 127.0.0.1:<bridge port>, and packets from the relay are delivered to the local
+// receiver port. LAN users don't need any of this and stay direct.
+const RELAY_JOIN_MAGIC: &[u8; 2] = b"RJ";
+const RELAY_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+const RELAY_PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref RELAY_SERVER_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref RELAY_CLIENT_RUNNING: AtomicBool = AtomicBool::new(false);
+}
+
+// Join/keepalive packet: magic(2) + session id (utf-8)
+fn relay_join_packet(session_id: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(RELAY_JOIN_MAGIC.len() + session_id.len());
+    packet.extend_from_slice(RELAY_JOIN_MAGIC);
+    packet.extend_from_slice(session_id.as_bytes());
+    packet
+}
+
+#[tauri::command]
+fn start_relay_server(port: u16) -> Result<u16, String> {
+    if RELAY_SERVER_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Relay server already running".to_string());
+    }
+    
+    let socket = UdpSocket::bind(("0.0.0.0", port)).map_err(|e| {
+        RELAY_SERVER_RUNNING.store(false, Ordering::SeqCst);
+        format!("Cannot bind relay port {}: {}", port, e)
+    })?;
+    let port = socket.local_addr().map(|a| a.port()).unwrap_or(port);
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(100)));
+    register_listening_port("relay-server", "UDP", port, "NAT traversal relay");
+    
+    thread::spawn(move || {
+        // session id -> peers (addr, last seen)
+        let mut sessions: HashMap<String, Vec<(SocketAddr, Instant)>> = HashMap::new();
+        let mut peer_sessions: HashMap<SocketAddr, String> = HashMap::new();
+        let mut buf = [0u8; 1500];
+        let mut last_sweep = Instant::now();
+        
+        println!("Relay server started on port {}", port);
+        
+        while RELAY_SERVER_RUNNING.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if len > RELAY_JOIN_MAGIC.len() && &buf[..2] == RELAY_JOIN_MAGIC {
+                        let session_id = String::from_utf8_lossy(&buf[2..len]).to_string();
+                        
+                        // A peer belongs to one session at a time
+                        if let Some(previous) = peer_sessions.get(&addr) {
+                            if *previous != session_id {
+                                if let Some(peers) = sessions.get_mut(previous) {
+                                    peers.retain(|(peer, _)| *peer != addr);
+                                }
+                            }
+                        }
+                        
+                        let peers = sessions.entry(session_id.clone()).or_default();
+                        match peers.iter_mut().find(|(peer, _)| *peer == addr) {
+                            Some(peer) => peer.1 = Instant::now(),
+                            None => {
+                                println!("Relay: {} joined session {}", addr, session_id);
+                                peers.push((addr, Instant::now()));
+                            }
+                        }
+                        peer_sessions.insert(addr, session_id);
+                    } else if let Some(session_id) = peer_sessions.get(&addr) {
+                        if let Some(peers) = sessions.get_mut(session_id) {
+                            for (peer, last_seen) in peers.iter_mut() {
+                                if *peer == addr {
+                                    *last_seen = Instant::now();
+                                } else {
+                                    let _ = socket.send_to(&buf[..len], *peer);
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || 
+                             e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            
+            // Drop peers that stopped sending keepalives
+            if last_sweep.elapsed() >= RELAY_KEEPALIVE_INTERVAL {
+                for peers in sessions.values_mut() {
+                    peers.retain(|(_, last_seen)| last_seen.elapsed() < RELAY_PEER_TIMEOUT);
+                }
+                sessions.retain(|_, peers| !peers.is_empty());
+                peer_sessions.retain(|addr, session_id| {
+                    sessions.get(session_id)
+                        .map(|peers| peers.iter().any(|(peer, _)| peer == addr))
+                        .unwrap_or(false)
+                });
+                last_sweep = Instant::now();
+            }
+        }
+        
+        unregister_listening_port("relay-server");
+        println!("Relay server stopped");
+    });
+    
+    Ok(port)
+}
+
+#[tauri::command]
+fn stop_relay_server() {
+    RELAY_SERVER_RUNNING.store(false, Ordering::SeqCst);
+}
+
+// Returns the local bridge port. forward_port = local receiver port that
+// relayed packets are delivered to (viewer side); omit it on the streaming side.
+#[tauri::command]
+fn start_relay_client(relay_addr: String, session_id: String, forward_port: Option<u16>) -> Result<u16, String> {
+    use std::net::ToSocketAddrs;
+    
+    if session_id.is_empty() {
+        return Err("Session id must not be empty".to_string());
+    }
+    
+    let relay = relay_addr.to_socket_addrs()
+        .map_err(|e| format!("Invalid relay address {}: {}", relay_addr, e))?
+        .find(|addr| addr.is_ipv4())
+        .ok_or_else(|| format!("Cannot resolve relay address: {}", relay_addr))?;
+    
+    if RELAY_CLIENT_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Relay client already running".to_string());
+    }
+    
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| {
+        RELAY_CLIENT_RUNNING.store(false, Ordering::SeqCst);
+        format!("Cannot create relay socket: {}", e)
+    })?;
+    let bridge_port = socket.local_addr().map(|a| a.port()).map_err(|e| {
+        RELAY_CLIENT_RUNNING.store(false, Ordering::SeqCst);
+        e.to_string()
+    })?;
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(100)));
+    register_listening_port("relay-bridge", "UDP", bridge_port, "Relay client bridge (outbound to relay)");
+    
+    let join = relay_join_packet(&session_id);
+    let forward_to = forward_port.map(|port| SocketAddr::from(([127, 0, 0, 1], port)));
+    
+    thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        
+        // Join immediately, then keep re-joining to hold the NAT binding open
+        let _ = socket.send_to(&join, relay);
+        let mut last_keepalive = Instant::now();
+        
+        println!("Relay client bridging 127.0.0.1:{} <-> {} (session {})", bridge_port, relay, session_id);
+        
+        while RELAY_CLIENT_RUNNING.load(Ordering::SeqCst) {
+            if last_keepalive.elapsed() >= RELAY_KEEPALIVE_INTERVAL {
+                let _ = socket.send_to(&join, relay);
+                last_keepalive = Instant::now();
+            }
+            
+            match socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if addr == relay {
+                        if let Some(target) = forward_to {
+                            let _ = socket.send_to(&buf[..len], target);
+                        }
+                    } else if addr.ip().is_loopback() {
+                        // Only bridge local senders, not anyone who can reach the port
+                        let _ = socket.send_to(&buf[..len], relay);
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || 
+                             e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        
+        unregister_listening_port("relay-bridge");
+        println!("Relay client stopped");
+    });
+    
+    Ok(bridge_port)
+}
+
+#[tauri::command]
+fn stop_relay_client() {
+    RELAY_CLIENT_RUNNING.store(false, Ordering::SeqCst);
+}
+
+// ============== Input Simulation ==============
+fn send_event(event_type: &EventType) -> Result<(), String> {
+    match simulate(event_type) {
+        Ok(()) => {
+            thread::sleep(Duration::from_millis(5));
+            Ok(())
+        }
+        Err(SimulateError) => Err(format!("Failed: {:?}", event_type)),
+    }
+}
+
+fn js_key_to_rdev(key: &str, code: &str) -> Option<Key> {
+    match code {
+        "KeyA" => Some(Key::KeyA), "KeyB" => Some(Key::KeyB), "KeyC" => Some(Key::KeyC),
+        "KeyD" => Some(Key::KeyD), "KeyE" => Some(Key::KeyE), "KeyF" => Some(Key::KeyF),
+        "KeyG" => Some(Key::KeyG), "KeyH" => Some(Key::KeyH), "KeyI" => Some(Key::KeyI),
+        "KeyJ" => Some(Key::KeyJ), "KeyK" => Some(Key::KeyK), "KeyL" => Some(Key::KeyL),
+        "KeyM" => Some(Key::KeyM), "KeyN" => Some(Key::KeyN), "KeyO" => Some(Key::KeyO),
+        "KeyP" => Some(Key::KeyP), "KeyQ" => Some(Key::KeyQ), "KeyR" => Some(Key::KeyR),
+        "KeyS" => Some(Key::KeyS), "KeyT" => Some(Key::KeyT), "KeyU" => Some(Key::KeyU),
+        "KeyV" => Some(Key::KeyV), "KeyW" => Some(Key::KeyW), "KeyX" => Some(Key::KeyX),
+        "KeyY" => Some(Key::KeyY), "KeyZ" => Some(Key::KeyZ),
+        "Digit0" => Some(Key::Num0), "Digit1" => Some(Key::Num1), "Digit2" => Some(Key::Num2),
+        "Digit3" => Some(Key::Num3), "Digit4" => Some(Key::Num4), "Digit5" => Some(Key::Num5),
+        "Digit6" => Some(Key::Num6), "Digit7" => Some(Key::Num7), "Digit8" => Some(Key::Num8),
+        "Digit9" => Some(Key::Num9),
+        "F1" => Some(Key::F1), "F2" => Some(Key::F2), "F3" => Some(Key::F3), "F4" => Some(Key::F4),
+        "F5" => Some(Key::F5), "F6" => Some(Key::F6), "F7" => Some(Key::F7), "F8" => Some(Key::F8),
+        "F9" => Some(Key::F9), "F10" => Some(Key::F10), "F11" => Some(Key::F11), "F12" => Some(Key::F12),
+        "Enter" | "NumpadEnter" => Some(Key::Return),
+        "Escape" => Some(Key::Escape), "Backspace" => Some(Key::Backspace),
+        "Tab" => Some(Key::Tab), "Space" => Some(Key::Space),
+        "Delete" => Some(Key::Delete), "Insert" => Some(Key::Insert),
+        "Home" => Some(Key::Home), "End" => Some(Key::End),
+        "PageUp" => Some(Key::PageUp), "PageDown" => Some(Key::PageDown),
+        "ArrowUp" => Some(Key::UpArrow), "ArrowDown" => Some(Key::DownArrow),
+        "ArrowLeft" => Some(Key::LeftArrow), "ArrowRight" => Some(Key::RightArrow),
+        "ShiftLeft" | "ShiftRight" => Some(Key::ShiftLeft),
+        "ControlLeft" | "ControlRight" => Some(Key::ControlLeft),
+        "AltLeft" | "AltRight" => Some(Key::Alt),
+        "MetaLeft" | "MetaRight" => Some(Key::MetaLeft),
+        _ => if key == " " { Some(Key::Space) } else { None }
+    }
+}
+
+// ============== Idle Time ==============
+// Seconds since the last keyboard/mouse input on this machine. Input injected by
+// remote control also counts, since the OS can't tell it apart from the user's.
+#[tauri::command]
+fn get_idle_time() -> Result<serde_json::Value, String> {
+    let idle = system_idle_time()?;
+    Ok(serde_json::json!({
+        "idle_secs": idle.as_secs(),
+        "idle_ms": idle.as_millis() as u64
+    }))
+}
+
+#[cfg(windows)]
+fn system_idle_time() -> Result<Duration, String> {
+    use windows_sys::Win32::System::SystemInformation::GetTickCount;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+    
+    let mut info = LASTINPUTINFO {
+        cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+    if unsafe { GetLastInputInfo(&mut info) } == 0 {
+        return Err(format!("GetLastInputInfo failed: {}", std::io::Error::last_os_error()));
+    }
+    
+    // Both are 32-bit tick counts, so wrapping_sub survives the 49-day rollover
+    let now = unsafe { GetTickCount() };
+    Ok(Duration::from_millis(now.wrapping_sub(info.dwTime) as u64))
+}
+
+#[cfg(target_os = "macos")]
+fn system_idle_time() -> Result<Duration, String> {
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceSecondsSinceLastEventType(source_state: i32, event_type: u32) -> f64;
+    }
+    const HID_SYSTEM_STATE: i32 = 1;
+    const ANY_INPUT_EVENT: u32 = u32::MAX;
+    
+    let secs = unsafe { CGEventSourceSecondsSinceLastEventType(HID_SYSTEM_STATE, ANY_INPUT_EVENT) };
+    if secs.is_finite() && secs >= 0.0 {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err("Idle time unavailable".to_string())
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn system_idle_time() -> Result<Duration, String> {
+    Err("Idle time is not supported on this platform".to_string())
+}
+
+// ============== Stream Profiles ==============
+// Every backend tunable in one shareable JSON blob. Missing fields are left as
+// they are; unknown fields are rejected so typos don't silently do nothing.
+// Stream settings apply on the next start_stream, receiver/socket ones live.
+const STREAM_PROFILE_VERSION: u32 = 1;
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StreamProfile {
+    version: Option<u32>,
+    // Only "h264" for now; there so a profile from a later codec is refused
+    codec: Option<String>,
+    fps: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    gop_secs: Option<u32>,
+    quality_mode: Option<String>,
+    qp: Option<u8>,
+    bitrate_kbps: Option<u32>,
+    hw_accel: Option<bool>,
+    performance_cap: Option<PerformanceCap>,
+    scene_change: Option<bool>,
+    scene_change_threshold: Option<f64>,
+    // "" = no watermark
+    watermark_text: Option<String>,
+    watermark_position: Option<String>,
+    watermark_opacity: Option<f32>,
+    color_range: Option<String>,
+    matrix: Option<String>,
+    pixel_format: Option<String>,
+    // 0 = OS default
+    ttl: Option<u32>,
+    // "" = off
+    thumbnail_target: Option<String>,
+    unreachable_timeout_secs: Option<u64>,
+    auto_stop_unreachable: Option<bool>,
+    param_set_hash: Option<bool>,
+    preserve_aspect: Option<bool>,
+    // Encoder side (start_stream); the receiver's is receiver_low_latency
+    low_latency: Option<bool>,
+    receiver_low_latency: Option<bool>,
+    split_nals: Option<bool>,
+    cursor_highlight: Option<bool>,
+    cursor_radius: Option<u32>,
+    cursor_color: Option<[u8; 4]>,
+    nodelay: Option<bool>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+fn current_stream_profile() -> StreamProfile {
+    let cursor = *CURSOR_HIGHLIGHT.lock();
+    let tuning = *SOCKET_TUNING.lock();
+    let quality = *QUALITY_MODE.lock();
+    let color = *COLOR_SPACE.lock();
+    let (width, height) = *STREAM_SIZE.lock();
+    let watermark = STREAM_WATERMARK.lock();
+    
+    StreamProfile {
+        version: Some(STREAM_PROFILE_VERSION),
+        codec: Some("h264".to_string()),
+        fps: Some(STREAM_FPS.load(Ordering::Relaxed)),
+        width: Some(width as u32),
+        height: Some(height as u32),
+        gop_secs: Some(STREAM_GOP_SECS.load(Ordering::Relaxed)),
+        quality_mode: Some(quality.name().to_string()),
+        qp: quality.qp(),
+        bitrate_kbps: quality.bitrate_bps().map(|bps| bps / 1000),
+        hw_accel: Some(HW_ACCEL.load(Ordering::Relaxed)),
+        performance_cap: Some(*PERFORMANCE_CAP.lock()),
+        scene_change: Some(SCENE_CHANGE_ENABLED.load(Ordering::Relaxed)),
+        scene_change_threshold: Some(*SCENE_CHANGE_THRESHOLD.lock()),
+        watermark_text: Some(watermark.as_ref().map(|w| w.text.clone()).unwrap_or_default()),
+        watermark_position: watermark.as_ref().map(|w| w.position.name().to_string()),
+        watermark_opacity: watermark.as_ref().map(|w| w.opacity),
+        color_range: Some(color.range_name().to_string()),
+        matrix: Some(color.matrix_name().to_string()),
+        pixel_format: Some(PIXEL_FORMAT.lock().name().to_string()),
+        ttl: Some(STREAM_TTL.lock().unwrap_or(0)),
+        thumbnail_target: Some(THUMBNAIL_TARGET.lock().clone().unwrap_or_default()),
+        unreachable_timeout_secs: Some(UNREACHABLE_TIMEOUT_SECS.load(Ordering::Relaxed)),
+        auto_stop_unreachable: Some(AUTO_STOP_UNREACHABLE.load(Ordering::Relaxed)),
+        param_set_hash: Some(PARAM_SET_HASH.load(Ordering::Relaxed)),
+        preserve_aspect: Some(PRESERVE_ASPECT.load(Ordering::Relaxed)),
+        low_latency: Some(SENDER_LOW_LATENCY.load(Ordering::Relaxed)),
+        receiver_low_latency: Some(RECEIVER_LOW_LATENCY.load(Ordering::Relaxed)),
+        split_nals: Some(RECEIVER_SPLIT_NALS.load(Ordering::Relaxed)),
+        cursor_highlight: Some(cursor.enabled),
+        cursor_radius: Some(cursor.radius),
+        cursor_color: Some(cursor.color),
+        nodelay: Some(tuning.nodelay),
+        send_buffer_size: Some(tuning.send_buffer_size),
+        recv_buffer_size: Some(tuning.recv_buffer_size),
+    }
+}
+
+// Stream settings a profile sets, parsed up front so a bad field changes nothing
+struct ParsedStreamProfile {
+    size: Option<(usize, usize)>,
+    quality: Option<QualityMode>,
+    color: Option<ColorSpace>,
+    pixel_format: Option<PixelFormat>,
+    // Some(None) = remove
+    watermark: Option<Option<StreamWatermark>>,
+    ttl: Option<Option<u32>>,
+    thumbnail_target: Option<Option<String>>,
+}
+
+fn parse_stream_profile(profile: &StreamProfile) -> Result<ParsedStreamProfile, String> {
+    if let Some(version) = profile.version {
+        if version != STREAM_PROFILE_VERSION {
+            return Err(format!("Unsupported profile version {} (expected {})", version, STREAM_PROFILE_VERSION));
+        }
+    }
+    if let Some(codec) = profile.codec.as_deref() {
+        if !matches!(codec.trim().to_ascii_lowercase().as_str(), "h264" | "h.264") {
+            return Err(format!("Unsupported codec: {} (expected h264)", codec));
+        }
+    }
+    if let Some(fps) = profile.fps {
+        if fps == 0 || fps > MAX_STREAM_FPS {
+            return Err(format!("fps must be 1-{} (got {})", MAX_STREAM_FPS, fps));
+        }
+    }
+    if let Some(secs) = profile.gop_secs {
+        validate_gop_secs(secs)?;
+    }
+    if let Some(cap) = profile.performance_cap {
+        cap.validate()?;
+    }
+    if let Some(threshold) = profile.scene_change_threshold {
+        validate_scene_change_threshold(threshold)?;
+    }
+    if let Some(radius) = profile.cursor_radius {
+        if !(CURSOR_HIGHLIGHT_MIN_RADIUS..=CURSOR_HIGHLIGHT_MAX_RADIUS).contains(&radius) {
+            return Err(format!(
+                "cursor_radius must be between {} and {}",
+                CURSOR_HIGHLIGHT_MIN_RADIUS, CURSOR_HIGHLIGHT_MAX_RADIUS
+            ));
+        }
+    }
+    if let Some(size) = profile.send_buffer_size {
+        validate_socket_buffer("send_buffer_size", size)?;
+    }
+    if let Some(size) = profile.recv_buffer_size {
+        validate_socket_buffer("recv_buffer_size", size)?;
+    }
+    
+    let size = match (profile.width, profile.height) {
+        (None, None) => None,
+        (width, height) => {
+            let (current_w, current_h) = *STREAM_SIZE.lock();
+            Some(validate_stream_size(width.unwrap_or(current_w as u32), height.unwrap_or(current_h as u32))?)
+        }
+    };
+    let quality = if profile.quality_mode.is_some() || profile.qp.is_some() || profile.bitrate_kbps.is_some() {
+        let mode = profile.quality_mode.as_deref().or(profile.qp.map(|_| "qp"));
+        Some(QualityMode::parse(mode, profile.qp)?.with_bitrate_kbps(profile.bitrate_kbps)?)
+    } else {
+        None
+    };
+    let color = if profile.color_range.is_some() || profile.matrix.is_some() {
+        let current = *COLOR_SPACE.lock();
+        let parsed = ColorSpace::parse(profile.color_range.as_deref(), profile.matrix.as_deref())?;
+        Some(ColorSpace {
+            matrix: if profile.matrix.is_some() { parsed.matrix } else { current.matrix },
+            full_range: if profile.color_range.is_some() { parsed.full_range } else { current.full_range },
+        })
+    } else {
+        None
+    };
+    let pixel_format = profile.pixel_format.as_deref().map(PixelFormat::parse).transpose()?;
+    let watermark = match &profile.watermark_text {
+        Some(text) => Some(parse_stream_watermark(Some(text.clone()), profile.watermark_position.as_deref(), profile.watermark_opacity)?),
+        None => None,
+    };
+    let ttl = match profile.ttl {
+        Some(0) => Some(None),
+        Some(ttl) => Some(Some(validate_ttl(ttl)?)),
+        None => None,
+    };
+    let thumbnail_target = profile.thumbnail_target.as_deref().map(parse_thumbnail_target).transpose()?;
+    
+    Ok(ParsedStreamProfile { size, quality, color, pixel_format, watermark, ttl, thumbnail_target })
+}
+
+#[tauri::command]
+fn export_stream_profile() -> Result<String, String> {
+    serde_json::to_string_pretty(&current_stream_profile()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn import_stream_profile(profile: String) -> Result<String, String> {
+    let profile: StreamProfile = serde_json::from_str(&profile)
+        .map_err(|e| format!("Invalid profile: {}", e))?;
+    
+    // Validate everything before touching any setting
+    let parsed = parse_stream_profile(&profile)?;
+    
+    if let Some(v) = profile.fps {
+        STREAM_FPS.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = parsed.size {
+        *STREAM_SIZE.lock() = v;
+    }
+    if let Some(v) = profile.gop_secs {
+        STREAM_GOP_SECS.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = parsed.quality {
+        *QUALITY_MODE.lock() = v;
+    }
+    if let Some(v) = profile.hw_accel {
+        HW_ACCEL.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.performance_cap {
+        *PERFORMANCE_CAP.lock() = v;
+    }
+    if let Some(v) = profile.scene_change {
+        SCENE_CHANGE_ENABLED.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.scene_change_threshold {
+        *SCENE_CHANGE_THRESHOLD.lock() = v;
+    }
+    if let Some(v) = parsed.watermark {
+        *STREAM_WATERMARK.lock() = v;
+    }
+    if let Some(v) = parsed.color {
+        *COLOR_SPACE.lock() = v;
+    }
+    if let Some(v) = parsed.pixel_format {
+        *PIXEL_FORMAT.lock() = v;
+    }
+    if let Some(v) = parsed.ttl {
+        *STREAM_TTL.lock() = v;
+    }
+    if let Some(v) = parsed.thumbnail_target {
+        *THUMBNAIL_TARGET.lock() = v;
+    }
+    if let Some(v) = profile.unreachable_timeout_secs {
+        UNREACHABLE_TIMEOUT_SECS.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.auto_stop_unreachable {
+        AUTO_STOP_UNREACHABLE.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.param_set_hash {
+        PARAM_SET_HASH.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.preserve_aspect {
+        PRESERVE_ASPECT.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.low_latency {
+        SENDER_LOW_LATENCY.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.receiver_low_latency {
+        RECEIVER_LOW_LATENCY.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = profile.split_nals {
+        RECEIVER_SPLIT_NALS.store(v, Ordering::Relaxed);
+    }
+    
+    {
+        let mut cursor = CURSOR_HIGHLIGHT.lock();
+        if let Some(v) = profile.cursor_highlight {
+            cursor.enabled = v;
+        }
+        if let Some(v) = profile.cursor_radius {
+            cursor.radius = v;
+        }
+        if let Some(v) = profile.cursor_color {
+            cursor.color = v;
+        }
+        if cursor.enabled {
+            start_cursor_tracker();
+        }
+    }
+    
+    {
+        let mut tuning = SOCKET_TUNING.lock();
+        if let Some(v) = profile.nodelay {
+            tuning.nodelay = v;
+        }
+        if let Some(v) = profile.send_buffer_size {
+            tuning.send_buffer_size = v;
+        }
+        if let Some(v) = profile.recv_buffer_size {
+            tuning.recv_buffer_size = v;
+        }
+    }
+    
+    export_stream_profile()
+}
+
+// ============== Self Test ==============
+// End-to-end loopback: stream to a local socket and count what reassembles and
+// decodes. Exercises capture, encode, UDP framing and reassembly in one call.
+const SELF_TEST_DEFAULT_DURATION_MS: u64 = 2000;
+const SELF_TEST_FPS: u32 = 15;
+
+fn run_loopback_self_test(duration: Duration) -> Result<serde_json::Value, String> {
+    if stream_running(StreamId::Main) {
+        return Err("Stop the running stream before running the self-test".to_string());
+    }
+    
+    let socket = UdpSocket::bind("127.0.0.1:0")
+        .map_err(|e| format!("Cannot bind loopback socket: {}", e))?;
+    let port = socket.local_addr().map_err(|e| e.to_string())?.port();
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(100)));
+    
+    let mut decoder = openh264::decoder::Decoder::new()
+        .map_err(|e| format!("H264 decoder error: {:?}", e))?;
+    let mut assembler = H264FrameAssembler::new();
+    let mut buf = [0u8; 1500];
+    let mut frames_received = 0u32;
+    let mut frames_decoded = 0u32;
+    let mut stopped_early = false;
+    
+    let sent_before = FRAME_COUNT.load(Ordering::Relaxed);
+    start_h264_streaming(None, format!("127.0.0.1:{}", port), SELF_TEST_FPS, false, *QUALITY_MODE.lock(), None)?;
+    
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        // The streaming thread clears its running flag itself if capture or encoder setup fails
+        if !stream_running(StreamId::Main) {
+            stopped_early = true;
+            break;
+        }
+        
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(_) => continue,
+        };
+        let (header, payload) = match protocol::parse_header(&buf[..len]) {
+            Some(parsed) if parsed.0.kind == FrameKind::H264 => parsed,
+            _ => continue,
+        };
+        
+        if let Some(frame) = assembler.add_chunk(header.seq, header.idx as usize, header.total as usize, payload) {
+            frames_received += 1;
+            let (_, frame) = protocol::split_param_hash(header.flags, &frame);
+            
+            let mut decoded = false;
+            for nal in openh264::nal_units(frame) {
+                if let Ok(Some(_)) = decoder.decode(nal) {
+                    decoded = true;
+                }
+            }
+            if decoded {
+                frames_decoded += 1;
+            }
+        }
+    }
+    
+    stop_registered_stream(StreamId::Main);
+    
+    let frames_sent = FRAME_COUNT.load(Ordering::Relaxed).wrapping_sub(sent_before);
+    let ok = !stopped_early && frames_received > 0 && frames_decoded > 0;
+    
+    println!(
+        "Self-test: sent {}, received {}, decoded {} ({})",
+        frames_sent, frames_received, frames_decoded, if ok { "ok" } else { "FAILED" }
+    );
+    
+    Ok(serde_json::json!({
+        "frames_sent": frames_sent,
+        "frames_received": frames_received,
+        "frames_decoded": frames_decoded,
+        "stopped_early": stopped_early,
+        "ok": ok
+    }))
+}
+
+// Support tool: "run self-test and tell me the numbers"
+#[tauri::command]
+async fn run_self_test(duration_ms: Option<u64>) -> Result<serde_json::Value, String> {
+    let duration = Duration::from_millis(duration_ms.unwrap_or(SELF_TEST_DEFAULT_DURATION_MS).clamp(500, 10_000));
+    
+    tauri::async_runtime::spawn_blocking(move || run_loopback_self_test(duration))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// ============== Encoder Benchmark ==============
+// Encodes a synthetic 1080p frame at each output size for a short burst. The
+// time includes the resize + YUV conversion, so it matches what the stream pays.
+// Constant QP keeps rate control from skipping frames and flattering the numbers.
+const BENCHMARK_RESOLUTIONS: [(&str, usize, usize); 3] = [("360p", 640, 360), ("720p", 1280, 720), ("1080p", 1920, 1080)];
+const BENCHMARK_SOURCE: (usize, usize) = (1920, 1080);
+const BENCHMARK_BURST: Duration = Duration::from_millis(1500);
+const BENCHMARK_MAX_FRAMES: u32 = 120;
+
+// Gradient with a bar that moves every frame, so P-frames have real work to do
+fn benchmark_frame(width: usize, height: usize, frame: usize) -> Vec<u8> {
+    let mut bgra = vec![0u8; width * height * 4];
+    let bar_x = (frame * 16) % width;
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * 4;
+            let on_bar = x >= bar_x && x < bar_x + width / 10;
+            bgra[i] = if on_bar { 255 } else { (x * 255 / width) as u8 };
+            bgra[i + 1] = (y * 255 / height) as u8;
+            bgra[i + 2] = ((x + y + frame) % 256) as u8;
+            bgra[i + 3] = 255;
+        }
+    }
+    bgra
+}
+
+fn run_encoder_benchmark(app: &tauri::AppHandle) -> Result<serde_json::Value, String> {
+    if stream_running(StreamId::Main) {
+        return Err("Stop the running stream before benchmarking the encoder".to_string());
+    }
+    
+    let (src_w, src_h) = BENCHMARK_SOURCE;
+    // A few distinct frames reused in a cycle keeps frame generation out of the timing
+    let frames: Vec<Vec<u8>> = (0..8).map(|n| benchmark_frame(src_w, src_h, n)).collect();
+    let mut results = Vec::new();
+    
+    for (index, &(label, width, height)) in BENCHMARK_RESOLUTIONS.iter().enumerate() {
+        let _ = app.emit("encoder-benchmark-progress", serde_json::json!({
+            "resolution": label,
+            "index": index,
+            "total": BENCHMARK_RESOLUTIONS.len()
+        }));
+        
+        let mut encoder = H264Encoder::with_quality(width, height, MAX_STREAM_FPS, false, QualityMode::ConstantQp(DEFAULT_QP))?;
+        let mut encoded = 0u32;
+        let mut total_bytes = 0usize;
+        let mut encode_time = Duration::ZERO;
+        let mut slowest = Duration::ZERO;
+        
+        let started = Instant::now();
+        while started.elapsed() < BENCHMARK_BURST && encoded < BENCHMARK_MAX_FRAMES {
+            let frame = &frames[encoded as usize % frames.len()];
+            let t = Instant::now();
+            let output = encoder.encode(frame, src_w, src_h);
+            let took = t.elapsed();
+            
+            encoded += 1;
+            encode_time += took;
+            slowest = slowest.max(took);
+            total_bytes += output.map_or(0, |data| data.len());
+        }
+        
+        let avg_ms = encode_time.as_secs_f64() * 1000.0 / encoded.max(1) as f64;
+        let fps = if avg_ms > 0.0 { 1000.0 / avg_ms } else { 0.0 };
+        println!("Encoder benchmark {}: {:.2} ms/frame ({:.0} FPS)", label, avg_ms, fps);
+        results.push(serde_json::json!({
+            "resolution": label,
+            "width": width,
+            "height": height,
+            "frames": encoded,
+            "avg_encode_ms": avg_ms,
+            "max_encode_ms": slowest.as_secs_f64() * 1000.0,
+            "max_fps": fps,
+            "avg_frame_bytes": total_bytes / encoded.max(1) as usize,
+            "sustains_30fps": fps >= 30.0
+        }));
+    }
+    
+    let _ = app.emit("encoder-benchmark-progress", serde_json::json!({
+        "resolution": null,
+        "index": BENCHMARK_RESOLUTIONS.len(),
+        "total": BENCHMARK_RESOLUTIONS.len()
+    }));
+    
+    Ok(serde_json::json!({
+        "encoder": "openh264",
+        "source": format!("{}x{}", src_w, src_h),
+        "results": results
+    }))
+}
+
+// Emits encoder-benchmark-progress before each resolution (and once more when done)
+#[tauri::command]
+async fn benchmark_encoder(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
+    tauri::async_runtime::spawn_blocking(move || run_encoder_benchmark(&app))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// ============== Tauri Commands ==============
+const CAPTURE_DEFAULT_MAX_BYTES: usize = 512 * 1024;
+const CAPTURE_QUALITY: u8 = 60;
+
+fn jpeg_data_url(jpeg: &[u8]) -> String {
+    format!("data:image/jpeg;base64,{}", general_purpose::STANDARD.encode(jpeg))
+}
+
+fn capture_result(jpeg: &[u8], quality: u8) -> serde_json::Value {
+    serde_json::json!({
+        "data_url": jpeg_data_url(jpeg),
+        "quality": quality,
+        "bytes": jpeg.len()
+    })
+}
+
+// max_bytes caps the JPEG size (default 512KB) so one capture can't flood the IPC bridge;
+// quality is lowered until it fits and the achieved quality is returned
+#[tauri::command]
+fn capture_screen(app: tauri::AppHandle, max_bytes: Option<usize>) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "capture_screen")?;
+    let max_bytes = max_bytes.unwrap_or(CAPTURE_DEFAULT_MAX_BYTES);
+    
+    // The streaming loop's preview frame is already quality 60
+    if let Some(jpeg) = LAST_JPEG_FRAME.lock().clone() {
+        if jpeg.len() <= max_bytes {
+            return Ok(capture_result(&jpeg, CAPTURE_QUALITY));
+        }
+    }
+    
+    let mut capturer = ScreenCapturer::new()?;
+    
+    for _ in 0..30 {
+        if let Some(bgra) = capturer.capture() {
+            if let Some((jpeg, quality)) = encode_jpeg_within(&bgra, capturer.width, capturer.height, CAPTURE_QUALITY, max_bytes) {
+                return Ok(capture_result(&jpeg, quality));
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    
+    Err("Capture timeout".to_string())
+}
+
+// Copy the x/y/w/h rectangle (clamped to the frame) out of a possibly padded BGRA
+// frame into a tightly packed one. None when nothing of the rect is on screen.
+fn crop_bgra(
+    bgra: &[u8],
+    width: usize,
+    height: usize,
+    rect: (usize, usize, usize, usize)
+) -> Option<(Vec<u8>, usize, usize)> {
+    let (x, y, w, h) = rect;
+    if height == 0 || x >= width || y >= height {
+        return None;
+    }
+    let (crop_w, crop_h) = (w.min(width - x), h.min(height - y));
+    if crop_w == 0 || crop_h == 0 {
+        return None;
+    }
+    
+    let stride = bgra.len() / height;
+    let mut cropped = Vec::with_capacity(crop_w * crop_h * 4);
+    for row in y..y + crop_h {
+        let start = row * stride + x * 4;
+        cropped.extend_from_slice(bgra.get(start..start + crop_w * 4)?);
+    }
+    Some((cropped, crop_w, crop_h))
+}
+
+// One-shot screenshot of part of the primary screen, in source pixels (no scaling).
+// format: "png" (default) | "jpeg" (quality 1-100, default 90). With output_path the
+// image is written there and the path returned, else it comes back as a data URL.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn capture_region(
+    app: tauri::AppHandle,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    format: Option<String>,
+    quality: Option<u8>,
+    output_path: Option<String>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "capture_region")?;
+    let png = match format.as_deref().map(|f| f.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("png") => true,
+        Some("jpeg") | Some("jpg") => false,
+        Some(other) => return Err(format!("Unknown image format: {} (expected png or jpeg)", other)),
+    };
+    let quality = quality.unwrap_or(90).clamp(1, 100);
+    
+    let mut capturer = ScreenCapturer::new()?;
+    let bgra = (0..30)
+        .find_map(|_| capturer.capture().or_else(|| {
+            thread::sleep(Duration::from_millis(10));
+            None
+        }))
+        .ok_or("Capture timeout")?;
+    
+    let rect = (x as usize, y as usize, width as usize, height as usize);
+    let (cropped, crop_w, crop_h) = crop_bgra(&bgra, capturer.width, capturer.height, rect)
+        .ok_or_else(|| format!(
+            "Region {}x{} at ({}, {}) is empty or outside the {}x{} screen",
+            width, height, x, y, capturer.width, capturer.height
+        ))?;
+    
+    let (bytes, mime) = if png {
+        (encode_png(&cropped, crop_w, crop_h), "image/png")
+    } else {
+        let rgb = bgra_to_rgb_resized(&cropped, crop_w, crop_h, crop_w, crop_h, false);
+        (encode_rgb_jpeg(rgb, crop_w, crop_h, quality), "image/jpeg")
+    };
+    let bytes = bytes.ok_or("Image encoding failed")?;
+    
+    let data_url = match &output_path {
+        Some(path) => {
+            fs::write(path, &bytes).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+            None
+        }
+        None => Some(format!("data:{};base64,{}", mime, general_purpose::STANDARD.encode(&bytes))),
+    };
+    
+    Ok(serde_json::json!({
+        "width": crop_w,
+        "height": crop_h,
+        "bytes": bytes.len(),
+        "path": output_path,
+        "data_url": data_url
+    }))
+}
+
+// Uncompressed capture for the frontend's own processing (OCR, CV, WASM).
+// The BGRA bytes go over `channel` as one binary message (an ArrayBuffer on
+// the JS side), rows `stride` bytes apart. A 1080p frame is ~8MB: this is for
+// single shots, not a frame loop. display = list_displays index (default primary).
+#[tauri::command]
+fn capture_raw_frame(
+    app: tauri::AppHandle,
+    channel: tauri::ipc::Channel<tauri::ipc::InvokeResponseBody>,
+    display: Option<usize>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "capture_raw_frame")?;
+    let mut capturer = stream_capturer(display)?;
+    let bgra = (0..30)
+        .find_map(|_| capturer.capture().or_else(|| {
+            thread::sleep(Duration::from_millis(10));
+            None
+        }))
+        .ok_or("Capture timeout")?;
+    
+    let (width, height) = (capturer.width, capturer.height);
+    let stride = bgra.len() / height.max(1);
+    let bytes = bgra.len();
+    channel.send(tauri::ipc::InvokeResponseBody::Raw(bgra))
+        .map_err(|e| format!("Cannot send frame: {}", e))?;
+    
+    Ok(serde_json::json!({
+        "width": width,
+        "height": height,
+        "stride": stride,
+        "bytes": bytes,
+        "format": "bgra"
+    }))
+}
+
+// What viewers of the main stream see right now, as a JPEG of the same captured
+// frame (cursor highlight and watermark included). Never captures or encodes.
+#[tauri::command]
+fn snapshot_current_stream_frame(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "snapshot_current_stream_frame")?;
+    if !stream_running(StreamId::Main) {
+        return Err("Not streaming".to_string());
+    }
+    
+    let frame = STREAMED_FRAME.lock();
+    let frame = frame.as_ref().ok_or("No frame streamed yet")?;
+    // Size the stream is encoded at (an auto-quality tier may differ from STREAM_SIZE)
+    let (width, height) = *STREAM_RESOLUTION.lock();
+    Ok(serde_json::json!({
+        "data_url": jpeg_data_url(&frame.jpeg),
+        "sequence": frame.sequence,
+        "age_ms": frame.captured_at.elapsed().as_millis() as u64,
+        "width": width,
+        "height": height
+    }))
+}
+
+// Local preview counters, reset by each start_capture_loop
+lazy_static::lazy_static! {
+    static ref CAPTURE_FRAMES_EMITTED: AtomicU64 = AtomicU64::new(0);
+    // Captured but not emitted (JPEG encode failed)
+    static ref CAPTURE_FRAMES_DROPPED: AtomicU64 = AtomicU64::new(0);
+    static ref CAPTURE_ENCODE_TOTAL_US: AtomicU64 = AtomicU64::new(0);
+    // Ticks given up to stay on schedule after a slow capture/encode
+    static ref CAPTURE_FRAMES_SKIPPED: AtomicU64 = AtomicU64::new(0);
+    static ref CAPTURE_INTERVAL_US: AtomicU64 = AtomicU64::new(0);
+    static ref CAPTURE_STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+fn capture_loop_stats() -> serde_json::Value {
+    let emitted = CAPTURE_FRAMES_EMITTED.load(Ordering::Relaxed);
+    let dropped = CAPTURE_FRAMES_DROPPED.load(Ordering::Relaxed);
+    let encoded = emitted + dropped;
+    let total_us = CAPTURE_ENCODE_TOTAL_US.load(Ordering::Relaxed);
+    let interval_us = CAPTURE_INTERVAL_US.load(Ordering::Relaxed);
+    let running_secs = CAPTURE_STARTED.lock().map_or(0.0, |t| t.elapsed().as_secs_f64());
+    
+    serde_json::json!({
+        "capturing": CAPTURING.load(Ordering::SeqCst),
+        "frames_emitted": emitted,
+        "frames_dropped": dropped,
+        "frames_skipped": CAPTURE_FRAMES_SKIPPED.load(Ordering::Relaxed),
+        "avg_encode_ms": if encoded > 0 { total_us as f64 / encoded as f64 / 1000.0 } else { 0.0 },
+        "requested_fps": if interval_us > 0 { 1_000_000.0 / interval_us as f64 } else { 0.0 },
+        "achieved_fps": if running_secs > 0.0 { emitted as f64 / running_secs } else { 0.0 }
+    })
+}
+
+// fps, when given, overrides interval_ms. Ticks run on a fixed schedule: a slow
+// frame is followed by skipped ticks (frames_skipped) rather than a drifting rate.
+#[tauri::command]
+fn start_capture_loop(app: tauri::AppHandle, interval_ms: u64, fps: Option<u32>) {
+    if CAPTURING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    
+    let interval = capped_interval(match fps {
+        Some(fps) => Duration::from_micros(1_000_000 / fps.max(1) as u64),
+        None => Duration::from_millis(interval_ms.max(1)),
+    });
+    
+    CAPTURE_FRAMES_EMITTED.store(0, Ordering::Relaxed);
+    CAPTURE_FRAMES_DROPPED.store(0, Ordering::Relaxed);
+    CAPTURE_ENCODE_TOTAL_US.store(0, Ordering::Relaxed);
+    CAPTURE_FRAMES_SKIPPED.store(0, Ordering::Relaxed);
+    CAPTURE_INTERVAL_US.store(interval.as_micros() as u64, Ordering::Relaxed);
+    *CAPTURE_STARTED.lock() = Some(Instant::now());
+    
+    thread::spawn(move || {
+        let mut capturer = match ScreenCapturer::new() {
+            Ok(c) => c,
+            Err(_) => {
+                CAPTURING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        
+        let mut next_tick = Instant::now();
+        
+        while CAPTURING.load(Ordering::SeqCst) {
+            next_tick += interval;
+            
+            if let Some(mut bgra) = capturer.capture() {
+                draw_cursor_highlight(&mut bgra, capturer.width, capturer.height);
+                
+                let encode_start = Instant::now();
+                let encoded = encode_jpeg(&bgra, capturer.width, capturer.height, 60);
+                CAPTURE_ENCODE_TOTAL_US.fetch_add(encode_start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                
+                if let Some(jpeg) = encoded {
+                    let base64_str = general_purpose::STANDARD.encode(&jpeg);
+                    let data_url = format!("data:image/jpeg;base64,{}", base64_str);
+                    let _ = app.emit("screen-frame", data_url);
+                    CAPTURE_FRAMES_EMITTED.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    CAPTURE_FRAMES_DROPPED.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            
+            let now = Instant::now();
+            if now < next_tick {
+                thread::sleep(next_tick - now);
+            } else {
+                // Behind: skip the ticks we already missed and start again from the next one
+                let missed = ((now - next_tick).as_micros() / interval.as_micros().max(1)) as u32;
+                if missed > 0 {
+                    CAPTURE_FRAMES_SKIPPED.fetch_add(missed as u64, Ordering::Relaxed);
+                    next_tick += interval * missed;
+                }
+            }
+        }
+    });
+}
+
+// Returns the final get_capture_loop_stats of the loop it stopped
+#[tauri::command]
+fn stop_capture_loop() -> serde_json::Value {
+    CAPTURING.store(false, Ordering::SeqCst);
+    capture_loop_stats()
+}
+
+#[tauri::command]
+fn get_capture_loop_stats() -> serde_json::Value {
+    capture_loop_stats()
+}
+
+// ============== Lossless Capture ==============
+// Native-resolution PNG frames for pixel-exact debugging. Each frame is several
+// MB of base64, so this is opt-in, rate-capped and separate from the preview loop.
+const LOSSLESS_MIN_INTERVAL_MS: u64 = 1000;
+
+lazy_static::lazy_static! {
+    static ref LOSSLESS_CAPTURING: AtomicBool = AtomicBool::new(false);
+    static ref LOSSLESS_FRAMES_EMITTED: AtomicU64 = AtomicU64::new(0);
+    // Identical to the previous frame, so not sent again
+    static ref LOSSLESS_FRAMES_UNCHANGED: AtomicU64 = AtomicU64::new(0);
+}
+
+fn lossless_capture_stats() -> serde_json::Value {
+    serde_json::json!({
+        "capturing": LOSSLESS_CAPTURING.load(Ordering::SeqCst),
+        "frames_emitted": LOSSLESS_FRAMES_EMITTED.load(Ordering::Relaxed),
+        "frames_unchanged": LOSSLESS_FRAMES_UNCHANGED.load(Ordering::Relaxed)
+    })
+}
+
+// Emits "lossless-frame" { data_url, width, height, bytes } at most once per
+// LOSSLESS_MIN_INTERVAL_MS (interval_ms below that is raised); unchanged screens are skipped
+#[tauri::command]
+fn start_lossless_capture(app: tauri::AppHandle, interval_ms: Option<u64>) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "start_lossless_capture")?;
+    if LOSSLESS_CAPTURING.swap(true, Ordering::SeqCst) {
+        return Err("Lossless capture already running".to_string());
+    }
+    
+    let interval_ms = interval_ms.unwrap_or(LOSSLESS_MIN_INTERVAL_MS).max(LOSSLESS_MIN_INTERVAL_MS);
+    let interval = Duration::from_millis(interval_ms);
+    LOSSLESS_FRAMES_EMITTED.store(0, Ordering::Relaxed);
+    LOSSLESS_FRAMES_UNCHANGED.store(0, Ordering::Relaxed);
+    
+    thread::spawn(move || {
+        let mut capturer = match ScreenCapturer::new() {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Lossless capture error: {}", e);
+                LOSSLESS_CAPTURING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        println!("Lossless capture started ({}x{}, every {} ms)", capturer.width, capturer.height, interval_ms);
+        
+        let mut previous: Option<Vec<u8>> = None;
+        
+        while LOSSLESS_CAPTURING.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            
+            // scrap may have no new frame yet; retry briefly instead of waiting a whole interval
+            let mut frame = None;
+            for _ in 0..20 {
+                frame = capturer.capture();
+                if frame.is_some() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            
+            if let Some(bgra) = frame {
+                if previous.as_ref() == Some(&bgra) {
+                    LOSSLESS_FRAMES_UNCHANGED.fetch_add(1, Ordering::Relaxed);
+                } else if let Some(png) = encode_png(&bgra, capturer.width, capturer.height) {
+                    let _ = app.emit("lossless-frame", serde_json::json!({
+                        "data_url": format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(&png)),
+                        "width": capturer.width,
+                        "height": capturer.height,
+                        "bytes": png.len()
+                    }));
+                    LOSSLESS_FRAMES_EMITTED.fetch_add(1, Ordering::Relaxed);
+                    previous = Some(bgra);
+                }
+            }
+            
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+        println!("Lossless capture stopped");
+    });
+    
+    Ok(serde_json::json!({ "interval_ms": interval_ms }))
+}
+
+#[tauri::command]
+fn stop_lossless_capture() -> serde_json::Value {
+    LOSSLESS_CAPTURING.store(false, Ordering::SeqCst);
+    lossless_capture_stats()
+}
+
+// fps: 1-60 (default 15); width/height: encode size, even, 64x36 up to 3840x2160
+// (default 640x360), both still clamped by set_performance_cap
+// gop_secs: seconds between periodic keyframes, 0-60 (default 0 = encoder's choice)
+// preserve_aspect: letterbox to the encode size instead of stretching (default off)
+// low_latency: low-complexity encoder profile for remote control. Encode time
+// drops (compare get_encode_latency_histogram) and a bitrate spike skips a frame
+// rather than delaying the ones behind it. Pair with start_frame_receiver(low_latency)
+// on the viewer to also drop its emit throttle, which alone holds frames up to 33ms.
+// hw_accel: encode on the GPU through ffmpeg (NVENC/QSV/AMF/Media Foundation on
+// Windows, VideoToolbox on macOS, NVENC/VAAPI/QSV on Linux), falling back to openh264
+// when none works; get_stream_stats "encoder" shows which one ran
+// unreachable_timeout_secs: emit stream-target-unreachable after this long without a
+// receiver heartbeat (default 5, 0 = off); auto_stop_unreachable stops once all are gone
+// quality_mode: "bitrate" (default) or "qp" for constant quality at qp (0-51, default 26);
+// bitrate_kbps: bitrate mode's target, 50-50000 (default 500)
+// color_range: "limited" (default) | "full"; matrix: "bt601" (default) | "bt709", usually
+// right for HD content. Viewers get the choice as stream-color-space events.
+// pixel_format: "i420" (default) | "nv12", the layout the viewer's decoder should
+// output (also in stream-color-space); the encoder input is NV12 for hw_accel, else I420.
+// ttl: IP TTL / multicast hop limit (1-255) for crossing routers; by default the
+// OS values apply, which keep multicast on the local subnet
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn start_stream(
+    app: tauri::AppHandle,
+    server_addr: String,
+    fps: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    gop_secs: Option<u32>,
+    preserve_aspect: Option<bool>,
+    low_latency: Option<bool>,
+    hw_accel: Option<bool>,
+    unreachable_timeout_secs: Option<u64>,
+    auto_stop_unreachable: Option<bool>,
+    quality_mode: Option<String>,
+    qp: Option<u8>,
+    bitrate_kbps: Option<u32>,
+    color_range: Option<String>,
+    matrix: Option<String>,
+    pixel_format: Option<String>,
+    ttl: Option<u32>,
+    thumbnail_substream: Option<String>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "start_stream")?;
+    if stream_running(StreamId::Main) {
+        // stats[0].targets has per-target acks/drops to judge whether it is healthy
+        return Ok(already_running(serde_json::json!({
+            "uptime_secs": uptime_secs(*STREAM_STARTED.lock()),
+            "stats": get_stream_stats()
+        })));
+    }
+    if let Some(fps) = fps {
+        if fps == 0 || fps > MAX_STREAM_FPS {
+            return Err(format!("fps must be 1-{} (got {})", MAX_STREAM_FPS, fps));
+        }
+        STREAM_FPS.store(fps, Ordering::Relaxed);
+    }
+    if width.is_some() || height.is_some() {
+        let (current_w, current_h) = *STREAM_SIZE.lock();
+        *STREAM_SIZE.lock() = validate_stream_size(width.unwrap_or(current_w as u32), height.unwrap_or(current_h as u32))?;
+    }
+    if let Some(secs) = gop_secs {
+        STREAM_GOP_SECS.store(validate_gop_secs(secs)?, Ordering::Relaxed);
+    }
+    if quality_mode.is_some() || qp.is_some() || bitrate_kbps.is_some() {
+        // qp alone implies constant-QP mode
+        let mode = quality_mode.as_deref().or(qp.map(|_| "qp"));
+        *QUALITY_MODE.lock() = QualityMode::parse(mode, qp)?.with_bitrate_kbps(bitrate_kbps)?;
+    }
+    if color_range.is_some() || matrix.is_some() {
+        let current = *COLOR_SPACE.lock();
+        let parsed = ColorSpace::parse(color_range.as_deref(), matrix.as_deref())?;
+        // An omitted half keeps its current value
+        *COLOR_SPACE.lock() = ColorSpace {
+            matrix: if matrix.is_some() { parsed.matrix } else { current.matrix },
+            full_range: if color_range.is_some() { parsed.full_range } else { current.full_range },
+        };
+    }
+    if let Some(format) = pixel_format.as_deref() {
+        *PIXEL_FORMAT.lock() = PixelFormat::parse(format)?;
+    }
+    // Omitted options keep their current value (e.g. from an imported profile)
+    if let Some(v) = preserve_aspect {
+        PRESERVE_ASPECT.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = low_latency {
+        SENDER_LOW_LATENCY.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = hw_accel {
+        HW_ACCEL.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = unreachable_timeout_secs {
+        UNREACHABLE_TIMEOUT_SECS.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = auto_stop_unreachable {
+        AUTO_STOP_UNREACHABLE.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = ttl {
+        *STREAM_TTL.lock() = Some(validate_ttl(v)?);
+    }
+    if let Some(target) = thumbnail_substream.as_deref() {
+        *THUMBNAIL_TARGET.lock() = parse_thumbnail_target(target)?;
+    }
+    let quality = *QUALITY_MODE.lock();
+    let fps = STREAM_FPS.load(Ordering::Relaxed);
+    start_h264_streaming(Some(app), server_addr, fps, SENDER_LOW_LATENCY.load(Ordering::Relaxed), quality, None)?;
+    Ok(serde_json::json!({ "status": "started" }))
+}
+
+// "host:port" for the thumbnail substream, "" turns it off
+fn parse_thumbnail_target(target: &str) -> Result<Option<String>, String> {
+    let target = target.trim();
+    if !target.is_empty() && resolve_target(target).is_none() {
+        return Err(format!("Cannot resolve thumbnail target: {}", target));
+    }
+    Ok((!target.is_empty()).then(|| target.to_string()))
+}
+
+// Move the running stream to another display (index into list_displays);
+// the switch happens on the loop's next tick and emits display-switched
+#[tauri::command]
+fn switch_stream_display(index: usize) -> Result<(), String> {
+    if !stream_running(StreamId::Main) {
+        return Err("Not streaming".to_string());
+    }
+    
+    let count = Display::all().map_err(|e| format!("No display: {}", e))?.len();
+    if index >= count {
+        return Err(format!("Display {} not found ({} available)", index, count));
+    }
+    
+    *STREAM_DISPLAY_SWITCH.lock() = Some(index);
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_stream() {
+    stop_registered_stream(StreamId::Main);
+}
+
+// Frontend focus signal: while false the main stream sends INACTIVE_VIEWER_FPS
+// keyframes a second instead of the full rate, to save CPU during idle monitoring
+#[tauri::command]
+fn set_viewer_active(active: bool) -> serde_json::Value {
+    VIEWER_ACTIVE.store(active, Ordering::Relaxed);
+    serde_json::json!({ "active": active })
+}
+
+// ============== Auto Quality ==============
+// start_stream_auto: probe the receiver, pick a rung of the ladder, then start
+// the normal stream with it. Rungs trade resolution, fps and bitrate (the
+// performance cap still applies on top). While streaming, the send loop keeps
+// probing over the stream socket and moves one rung per window toward the
+// rung the live RTT/loss calls for.
+const LINK_PROBE_COUNT: u32 = 20;
+const LINK_PROBE_SPACING: Duration = Duration::from_millis(20);
+// Replies later than this after the last probe count as lost
+const LINK_PROBE_GRACE: Duration = Duration::from_millis(500);
+// In-stream probing
+const LINK_MONITOR_PROBE_INTERVAL: Duration = Duration::from_millis(200);
+const LINK_MONITOR_WINDOW: Duration = Duration::from_secs(5);
+
+struct StreamTier {
+    name: &'static str,
+    // Chosen when the link is no worse than both limits
+    max_rtt_ms: f64,
+    max_loss: f64,
+    width: usize,
+    height: usize,
+    fps: u32,
+    bitrate_bps: u32,
+}
+
+// Best first; the last rung catches everything
+const STREAM_TIERS: [StreamTier; 3] = [
+    StreamTier { name: "good", max_rtt_ms: 30.0, max_loss: 0.01, width: 1280, height: 720, fps: 30, bitrate_bps: 2_000_000 },
+    StreamTier { name: "fair", max_rtt_ms: 100.0, max_loss: 0.05, width: 640, height: 360, fps: 20, bitrate_bps: 800_000 },
+    StreamTier { name: "poor", max_rtt_ms: f64::INFINITY, max_loss: 1.0, width: 480, height: 270, fps: 15, bitrate_bps: 300_000 },
+];
+
+fn tier_stream_size(tier: &StreamTier) -> (usize, usize) {
+    PERFORMANCE_CAP.lock().size(tier.width, tier.height)
+}
+
+lazy_static::lazy_static! {
+    // Current tier of a start_stream_auto stream; None for a manual start_stream
+    static ref STREAM_TIER: Mutex<Option<&'static str>> = Mutex::new(None);
+}
+
+struct LinkQuality {
+    rtt_ms: f64,
+    loss: f64,
+}
+
+// Needs a frame receiver listening on target, which echoes our probes
+fn measure_link(target: &str) -> Result<LinkQuality, String> {
+    use std::net::ToSocketAddrs;
+    
+    let addr = target.to_socket_addrs()
+        .map_err(|e| format!("Invalid target {}: {}", target, e))?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve target: {}", target))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("UDP bind error: {}", e))?;
+    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+    
+    let mut sent_at = Vec::with_capacity(LINK_PROBE_COUNT as usize);
+    let mut rtts: Vec<Duration> = Vec::new();
+    let mut buf = [0u8; 64];
+    let mut drain = |rtts: &mut Vec<Duration>, sent_at: &[Instant]| {
+        while let Ok((len, from)) = socket.recv_from(&mut buf) {
+            if from != addr {
+                continue;
+            }
+            if let Some(seq) = protocol::parse_probe_reply(&buf[..len]) {
+                if let Some(t) = sent_at.get(seq as usize) {
+                    rtts.push(t.elapsed());
+                }
+            }
+        }
+    };
+    
+    for seq in 0..LINK_PROBE_COUNT {
+        sent_at.push(Instant::now());
+        socket.send_to(&protocol::encode_probe(seq), addr)
+            .map_err(|e| format!("Probe send error: {}", e))?;
+        thread::sleep(LINK_PROBE_SPACING);
+        drain(&mut rtts, &sent_at);
+    }
+    
+    let deadline = Instant::now() + LINK_PROBE_GRACE;
+    while rtts.len() < sent_at.len() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+        drain(&mut rtts, &sent_at);
+    }
+    
+    if rtts.is_empty() {
+        return Err(format!("No probe replies from {} (is the frame receiver running?)", target));
+    }
+    let rtt_ms = rtts.iter().map(|d| d.as_secs_f64() * 1000.0).sum::<f64>() / rtts.len() as f64;
+    let loss = 1.0 - rtts.len().min(sent_at.len()) as f64 / sent_at.len() as f64;
+    Ok(LinkQuality { rtt_ms, loss })
+}
+
+// Index into STREAM_TIERS
+fn pick_stream_tier(link: &LinkQuality) -> usize {
+    STREAM_TIERS.iter()
+        .position(|tier| link.rtt_ms <= tier.max_rtt_ms && link.loss <= tier.max_loss)
+        .unwrap_or(STREAM_TIERS.len() - 1)
+}
+
+// One rung per step, so a single bad window doesn't jump straight from good to poor
+fn next_tier_index(current: usize, link: &LinkQuality) -> usize {
+    let wanted = pick_stream_tier(link);
+    match wanted.cmp(&current) {
+        std::cmp::Ordering::Less => current - 1,
+        std::cmp::Ordering::Greater => current + 1,
+        std::cmp::Ordering::Equal => current,
+    }
+}
+
+// Live RTT/loss for an auto stream: probes go out on the stream socket and the
+// replies come back through poll_heartbeat_acks.
+struct LinkMonitor {
+    next_seq: u32,
+    // seq -> send time, for this window's probes
+    pending: HashMap<u32, Instant>,
+    sent: u32,
+    rtts: Vec<Duration>,
+    last_probe: Option<Instant>,
+    window_started: Instant,
+}
+
+impl LinkMonitor {
+    fn new() -> Self {
+        Self {
+            next_seq: 0,
+            pending: HashMap::new(),
+            sent: 0,
+            rtts: Vec::new(),
+            last_probe: None,
+            window_started: Instant::now(),
+        }
+    }
+    
+    fn maybe_probe(&mut self, socket: &UdpSocket, addr: SocketAddr) {
+        if self.last_probe.is_some_and(|t| t.elapsed() < LINK_MONITOR_PROBE_INTERVAL) {
+            return;
+        }
+        self.last_probe = Some(Instant::now());
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if socket.send_to(&protocol::encode_probe(seq), addr).is_ok() {
+            self.pending.insert(seq, Instant::now());
+            self.sent += 1;
+        }
+    }
+    
+    fn on_reply(&mut self, seq: u32) {
+        if let Some(sent_at) = self.pending.remove(&seq) {
+            self.rtts.push(sent_at.elapsed());
+        }
+    }
+    
+    // The finished window's link quality, once a window has passed; replies still
+    // outstanding at that point count as lost.
+    fn take_window(&mut self) -> Option<LinkQuality> {
+        if self.window_started.elapsed() < LINK_MONITOR_WINDOW {
+            return None;
+        }
+        let (sent, rtts) = (self.sent, std::mem::take(&mut self.rtts));
+        self.window_started = Instant::now();
+        self.sent = 0;
+        self.pending.clear();
+        if sent == 0 {
+            return None;
+        }
+        let rtt_ms = if rtts.is_empty() {
+            f64::INFINITY
+        } else {
+            rtts.iter().map(|d| d.as_secs_f64() * 1000.0).sum::<f64>() / rtts.len() as f64
+        };
+        let loss = 1.0 - rtts.len().min(sent as usize) as f64 / sent as f64;
+        Some(LinkQuality { rtt_ms, loss })
+    }
+}
+
+// One-click streaming: measures RTT/loss to target, then streams with the matching
+// tier's size, fps and bitrate, re-picking the tier as the link changes.
+// The tier applies to this stream only; QUALITY_MODE and STREAM_SIZE are untouched.
+#[tauri::command]
+async fn start_stream_auto(app: tauri::AppHandle, target: String) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "start_stream_auto")?;
+    if stream_running(StreamId::Main) {
+        return Err("Already streaming".to_string());
+    }
+    
+    let probe_target = target.clone();
+    let link = tauri::async_runtime::spawn_blocking(move || measure_link(&probe_target))
+        .await
+        .map_err(|e| e.to_string())??;
+    let index = pick_stream_tier(&link);
+    let tier = &STREAM_TIERS[index];
+    println!("Auto stream to {}: RTT {:.1} ms, loss {:.0}% -> {} tier", target, link.rtt_ms, link.loss * 100.0, tier.name);
+    
+    let quality = QualityMode::Bitrate(tier.bitrate_bps);
+    start_h264_streaming(Some(app), target, tier.fps, SENDER_LOW_LATENCY.load(Ordering::Relaxed), quality, Some(index))?;
+    let (width, height) = *STREAM_RESOLUTION.lock();
+    
+    Ok(serde_json::json!({
+        "tier": tier.name,
+        "rtt_ms": link.rtt_ms,
+        "loss": link.loss,
+        "width": width,
+        "height": height,
+        "fps": tier.fps,
+        "bitrate_kbps": tier.bitrate_bps / 1000
+    }))
+}
+
+// Recreate the stream's encoder on its next tick, as after repeated encode errors
+#[tauri::command]
+fn restart_stream_encoder() -> Result<(), String> {
+    if !stream_running(StreamId::Main) {
+        return Err("Not streaming".to_string());
+    }
+    ENCODER_RESTART_REQUESTED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+// low_latency: emit every completed frame immediately instead of throttling to ~30/s
+// split_nals: h264-frame carries [{ type, data }] per NAL unit (no start codes) instead of one blob
+// on_frame: deliver frames as { event, payload } on this channel instead of events. Ack
+// each handled frame with ack_frames; with FRAME_CHANNEL_CREDITS unacked, P-frames are dropped
+// up to the next keyframe (requested from the sender), keyframes always go through.
+// Calling again while running (e.g. after a reload) re-attaches on_frame and applies the options.
+#[tauri::command]
+fn start_frame_receiver(
+    app: tauri::AppHandle,
+    port: u16,
+    low_latency: Option<bool>,
+    split_nals: Option<bool>,
+    on_frame: Option<tauri::ipc::Channel<serde_json::Value>>
+) -> Result<serde_json::Value, String> {
+    attach_frame_channel(on_frame);
+    if let Some(v) = low_latency {
+        RECEIVER_LOW_LATENCY.store(v, Ordering::Relaxed);
+    }
+    if let Some(v) = split_nals {
+        RECEIVER_SPLIT_NALS.store(v, Ordering::Relaxed);
+    }
+    if UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
+        // packets_received not moving between two calls = nothing is arriving
+        let port = LISTENING_PORTS.lock().iter().find(|p| p.service == "frame-receiver").map(|p| p.port);
+        return Ok(already_running(serde_json::json!({
+            "port": port,
+            "uptime_secs": uptime_secs(*RECEIVER_STARTED.lock()),
+            "paused": UDP_RECEIVER_PAUSED.load(Ordering::SeqCst),
+            "packets_received": PACKET_SIZE_COUNTS.iter().map(|c| c.load(Ordering::Relaxed)).sum::<u64>(),
+            "expired_frames": RECEIVER_EXPIRED_FRAMES.load(Ordering::Relaxed)
+        })));
+    }
+    let port = start_h264_receiver(app, port)?;
+    Ok(serde_json::json!({ "status": "started", "port": port }))
+}
+
+#[tauri::command]
+fn stop_frame_receiver() {
+    UDP_RECEIVER_RUNNING.store(false, Ordering::SeqCst);
+}
+
+// How far above a busy port is_udp_port_available looks for a free one
+const PORT_PROBE_RANGE: u16 = 100;
+
+fn udp_port_free(port: u16) -> bool {
+    // Dropped right away; another process can still grab it before the real bind
+    UdpSocket::bind(("0.0.0.0", port)).is_ok()
+}
+
+// suggested_port: port itself when free, else the next free one above it (null if none in range)
+#[tauri::command]
+fn is_udp_port_available(port: u16) -> serde_json::Value {
+    let available = port != 0 && udp_port_free(port);
+    let suggested_port = if available {
+        Some(port)
+    } else {
+        (port.saturating_add(1)..=port.saturating_add(PORT_PROBE_RANGE))
+            .find(|&p| udp_port_free(p))
+    };
+    
+    serde_json::json!({
+        "port": port,
+        "available": available,
+        "suggested_port": suggested_port
+    })
+}
+
+// Stop the receiver, wait for its socket to close, then rebind on new_port.
+// Returns the newly bound port.
+#[tauri::command]
+fn change_receiver_port(app: tauri::AppHandle, new_port: u16) -> Result<u16, String> {
+    UDP_RECEIVER_RUNNING.store(false, Ordering::SeqCst);
+    start_h264_receiver(app, new_port)
+}
+
+// Recover a wedged viewer without rebinding: drops partial and jitter-buffered
+// frames, asks every sender for a keyframe, then emits "decoder-reset" so the
+// frontend can reinitialize its decoder before that keyframe arrives
+#[tauri::command]
+fn reset_receiver_decoder() -> Result<(), String> {
+    if !UDP_RECEIVER_RUNNING.load(Ordering::SeqCst) {
+        return Err("Frame receiver is not running".to_string());
+    }
+    RECEIVER_RESET_REQUESTED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Keeps the port bound and the socket drained, but stops encoding/emitting frames
+#[tauri::command]
+fn set_receiver_paused(paused: bool) -> serde_json::Value {
+    UDP_RECEIVER_PAUSED.store(paused, Ordering::SeqCst);
+    println!("Frame receiver {}", if paused { "paused" } else { "resumed" });
+    serde_json::json!({ "paused": paused })
+}
+
+// One entry per stream, "main" first (present even when stopped, it also carries
+// the receiver and machine-wide counters), then "display-N" ones by index
+#[tauri::command]
+fn get_stream_stats() -> Vec<serde_json::Value> {
+    let mut stats = vec![main_stream_stats()];
+    stats.extend(display_stream_stats());
+    stats
+}
+
+fn main_stream_stats() -> serde_json::Value {
+    let quality = active_quality();
+    let color = *COLOR_SPACE.lock();
+    let target = STREAMS.lock().get(&StreamId::Main).map(|s| s.target.clone());
+    serde_json::json!({
+        "id": StreamId::Main.label(),
+        "running": stream_running(StreamId::Main),
+        "target": target,
+        "uptime_secs": uptime_secs(*STREAM_STARTED.lock()),
+        "capturing": CAPTURING.load(Ordering::SeqCst),
+        "frames_sent": FRAME_COUNT.load(Ordering::Relaxed),
+        "cpu_pressure_drops": CPU_PRESSURE_DROPS.load(Ordering::Relaxed),
+        "encoder_restarts": ENCODER_RESTARTS.load(Ordering::Relaxed),
+        "encoder_max_fps": ENCODER_MAX_FPS.load(Ordering::Relaxed),
+        "viewer_active": VIEWER_ACTIVE.load(Ordering::Relaxed),
+        "effective_fps": EFFECTIVE_STREAM_FPS.load(Ordering::Relaxed),
+        "scene_change_keyframes": SCENE_CHANGE_KEYFRAMES.load(Ordering::Relaxed),
+        "scene_change_threshold": scene_change_threshold(), // null = disabled
+        "quality_mode": quality.name(),
+        "qp": quality.qp(),
+        "color_range": color.range_name(),
+        "matrix": color.matrix_name(),
+        "pixel_format": PIXEL_FORMAT.lock().name(),
+        "target_bitrate_kbps": quality.bitrate_bps().map(|bps| bps / 1000),
+        "tier": *STREAM_TIER.lock(),
+        "avg_bitrate_kbps": stream_avg_bitrate_kbps(),
+        // Receiver side: duplicates point at retransmission, out-of-order at the network path
+        "duplicate_chunks": RECEIVER_DUPLICATE_CHUNKS.load(Ordering::Relaxed),
+        "out_of_order_chunks": RECEIVER_OUT_OF_ORDER_CHUNKS.load(Ordering::Relaxed),
+        "backpressure_drops": BACKPRESSURE_DROPS.load(Ordering::Relaxed),
+        "frames_in_flight": FRAMES_IN_FLIGHT.load(Ordering::Relaxed),
+        "expired_frames": RECEIVER_EXPIRED_FRAMES.load(Ordering::Relaxed),
+        "receiver_rebinds": RECEIVER_REBINDS.load(Ordering::Relaxed),
+        "codec": "H.264",
+        "encoder": *ACTIVE_ENCODER.lock(),
+        "hw_accel_requested": HW_ACCEL.load(Ordering::Relaxed),
+        "resolution": stream_resolution_label(),
+        "performance_cap": *PERFORMANCE_CAP.lock(),
+        "display": *STREAM_DISPLAY.lock(), // null = primary
+        "targets": STREAM_TARGETS.lock().clone(),
+        "websocket_clients": WS_CLIENTS.load(Ordering::Relaxed),
+        "network_reconnects": STREAM_NETWORK_RECONNECTS.load(Ordering::Relaxed),
+        "ttl": *STREAM_TTL.lock(), // null = OS default
+        "thumbnail_target": THUMBNAIL_TARGET.lock().clone(),
+        "thumbnails_sent": THUMBNAILS_SENT.load(Ordering::Relaxed),
+        "bandwidth_budget": bandwidth_budget_json()
+    })
+}
+
+// Current SPS/PPS (base64, no start codes) so a new viewer can configure its
+// decoder before the next keyframe; null until the stream's first keyframe
+#[tauri::command]
+fn get_stream_parameter_sets() -> serde_json::Value {
+    match PARAMETER_SETS.lock().as_ref() {
+        Some(sets) => serde_json::json!({
+            "available": true,
+            "sps": general_purpose::STANDARD.encode(&sets.sps),
+            "pps": general_purpose::STANDARD.encode(&sets.pps),
+            "resolution": stream_resolution_label()
+        }),
+        None => serde_json::json!({
+            "available": false,
+            "sps": null,
+            "pps": null
+        }),
+    }
+}
+
+// Extra viewers for the running stream, in addition to start_stream's server_addr
+#[tauri::command]
+fn add_stream_target(addr: String) -> Result<(), String> {
+    validate_stream_target(&addr)?;
+    
+    let mut targets = STREAM_TARGETS.lock();
+    if !targets.iter().any(|t| t.addr == addr) {
+        targets.push(StreamTarget::new(addr));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn remove_stream_target(addr: String) {
+    STREAM_TARGETS.lock().retain(|t| t.addr != addr);
+}
+
+#[tauri::command]
+fn get_encode_latency_histogram() -> serde_json::Value {
+    let counts: Vec<u64> = ENCODE_LATENCY_COUNTS.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+    let total: u64 = counts.iter().sum();
+    let total_us = ENCODE_LATENCY_TOTAL_US.load(Ordering::Relaxed);
+    
+    let buckets: Vec<serde_json::Value> = LATENCY_BUCKETS_US.iter()
+        .zip(&counts)
+        .map(|(&le, &count)| serde_json::json!({
+            // null = overflow bucket
+            "le_ms": if le == u64::MAX { None } else { Some(le as f64 / 1000.0) },
+            "count": count
+        }))
+        .collect();
+    
+    serde_json::json!({
+        "buckets": buckets,
+        "frames": total,
+        "avg_ms": if total > 0 { total_us as f64 / total as f64 / 1000.0 } else { 0.0 },
+        "p50_ms": latency_percentile_ms(&counts, total, 0.50),
+        "p95_ms": latency_percentile_ms(&counts, total, 0.95),
+        "p99_ms": latency_percentile_ms(&counts, total, 0.99)
+    })
+}
+
+#[tauri::command]
+fn set_socket_tuning(
+    nodelay: Option<bool>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>
+) -> Result<serde_json::Value, String> {
+    let send_buffer_size = send_buffer_size
+        .map(|size| validate_socket_buffer("send_buffer_size", size))
+        .transpose()?;
+    let recv_buffer_size = recv_buffer_size
+        .map(|size| validate_socket_buffer("recv_buffer_size", size))
+        .transpose()?;
+    
+    let mut tuning = SOCKET_TUNING.lock();
+    
+    if let Some(size) = send_buffer_size {
+        tuning.send_buffer_size = size;
+    }
+    if let Some(size) = recv_buffer_size {
+        tuning.recv_buffer_size = size;
+    }
+    if let Some(nodelay) = nodelay {
+        tuning.nodelay = nodelay;
+    }
+    
+    Ok(serde_json::json!({
+        "nodelay": tuning.nodelay,
+        "send_buffer_size": tuning.send_buffer_size,
+        "recv_buffer_size": tuning.recv_buffer_size
+    }))
+}
+
+#[tauri::command]
+fn get_screen_size() -> Result<serde_json::Value, String> {
+    let display = Display::primary().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({ "width": display.width(), "height": display.height() }))
+}
+
+const LOCK_WINDOW_PREFIX: &str = "lock-blackout-";
+
+lazy_static::lazy_static! {
+    // Labels of the blackout windows opened by the current lock
+    static ref LOCK_WINDOWS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+// The main window goes fullscreen on its monitor; every other monitor gets a
+// borderless always-on-top blackout window (public/lock.html) showing the message
+#[tauri::command]
+async fn set_lock_screen(app: tauri::AppHandle, lock: bool, message: String) -> Result<(), String> {
+    let window = app.get_webview_window("main").ok_or("Window not found")?;
+    if lock {
+        window.set_fullscreen(true).map_err(|e| e.to_string())?;
+        window.set_always_on_top(true).map_err(|e| e.to_string())?;
+        window.set_focus().map_err(|e| e.to_string())?;
+        open_lock_windows(&app, &window, &message)?;
+    } else {
+        close_lock_windows(&app);
+        window.set_always_on_top(false).map_err(|e| e.to_string())?;
+        window.set_fullscreen(false).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn open_lock_windows(app: &tauri::AppHandle, main: &tauri::WebviewWindow, message: &str) -> Result<(), String> {
+    // Locking twice must not stack windows
+    close_lock_windows(app);
+    
+    let main_monitor = main.current_monitor().ok().flatten().map(|m| *m.position());
+    let monitors = app.available_monitors().map_err(|e| e.to_string())?;
+    let script = format!(
+        "window.__LOCK_MESSAGE__ = {};",
+        serde_json::to_string(message).map_err(|e| e.to_string())?
+    );
+    
+    let mut labels = LOCK_WINDOWS.lock();
+    for (i, monitor) in monitors.iter().enumerate() {
+        let position = *monitor.position();
+        if main_monitor.is_some_and(|p| p.x == position.x && p.y == position.y) {
+            continue;
+        }
+        
+        let scale = monitor.scale_factor();
+        let pos = position.to_logical::<f64>(scale);
+        let size = monitor.size().to_logical::<f64>(scale);
+        let label = format!("{}{}", LOCK_WINDOW_PREFIX, i);
+        
+        let built = tauri::WebviewWindowBuilder::new(app, &label, tauri::WebviewUrl::App("lock.html".into()))
+            .title("Locked")
+            .initialization_script(&script)
+            .position(pos.x, pos.y)
+            .inner_size(size.width, size.height)
+            .decorations(false)
+            .resizable(false)
+            .always_on_top(true)
+            .skip_taskbar(true)
+            .build();
+        
+        match built {
+            Ok(_) => labels.push(label),
+            Err(e) => eprintln!("Cannot cover monitor {}: {}", i, e),
+        }
+    }
+    
+    println!("Lock screen covering {} extra monitor(s)", labels.len());
+    Ok(())
+}
+
+fn close_lock_windows(app: &tauri::AppHandle) {
+    let labels = std::mem::take(&mut *LOCK_WINDOWS.lock());
+    
+    for label in &labels {
+        if let Some(window) = app.get_webview_window(label) {
+            if let Err(e) = window.destroy() {
+                eprintln!("Cannot close lock window {}: {}", label, e);
+            }
+        }
+    }
+    
+    // Sweep by prefix too, in case a window outlived our bookkeeping
+    for (label, window) in app.webview_windows() {
+        if label.starts_with(LOCK_WINDOW_PREFIX) && !labels.contains(&label) {
+            let _ = window.destroy();
+        }
+    }
+}
+
+#[tauri::command]
+fn set_cursor_highlight(enabled: bool, radius: u32, color: [u8; 4]) -> serde_json::Value {
+    let radius = radius.clamp(CURSOR_HIGHLIGHT_MIN_RADIUS, CURSOR_HIGHLIGHT_MAX_RADIUS);
+    *CURSOR_HIGHLIGHT.lock() = CursorHighlight { enabled, radius, color };
+    
+    if enabled {
+        start_cursor_tracker();
+    }
+    
+    serde_json::json!({ "enabled": enabled, "radius": radius, "color": color })
+}
+
+// ============== Clock Sync ==============
+// NTP-style offset estimate against a peer's frame receiver, so capture
+// timestamps from that peer can be mapped onto this machine's clock.
+const CLOCK_SYNC_ROUNDS: u32 = 8;
+const CLOCK_SYNC_SPACING: Duration = Duration::from_millis(20);
+const CLOCK_SYNC_REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn unix_micros() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+// (offset, rtt) in microseconds for one exchange; offset = peer clock - local clock
+fn clock_sample(reply: &protocol::ClockReply, t3: u64) -> (i64, i64) {
+    let (t0, t1, t2, t3) = (reply.t0 as i64, reply.t1 as i64, reply.t2 as i64, t3 as i64);
+    let offset = ((t1 - t0) + (t2 - t3)) / 2;
+    let rtt = (t3 - t0) - (t2 - t1);
+    (offset, rtt)
+}
+
+fn measure_clock_offset(addr: SocketAddr) -> Result<Vec<(i64, i64)>, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("UDP bind error: {}", e))?;
+    socket.set_read_timeout(Some(CLOCK_SYNC_REPLY_TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut samples = Vec::with_capacity(CLOCK_SYNC_ROUNDS as usize);
+    let mut buf = [0u8; 64];
+    
+    for seq in 0..CLOCK_SYNC_ROUNDS {
+        socket.send_to(&protocol::encode_clock_sync(seq, unix_micros()), addr)
+            .map_err(|e| format!("Clock sync send error: {}", e))?;
+        
+        // Late replies to earlier rounds are skipped by seq
+        while let Ok((len, from)) = socket.recv_from(&mut buf) {
+            let t3 = unix_micros();
+            match protocol::parse_clock_reply(&buf[..len]) {
+                Some(reply) if from == addr && reply.seq == seq => {
+                    samples.push(clock_sample(&reply, t3));
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        thread::sleep(CLOCK_SYNC_SPACING);
+    }
+    Ok(samples)
+}
+
+// Needs the peer's frame receiver running on port. Uses the exchange with the
+// lowest RTT, the one least skewed by queueing on either path.
+#[tauri::command]
+async fn sync_clock_with_peer(peer_ip: String, port: u16) -> Result<serde_json::Value, String> {
+    let ip: IpAddr = peer_ip.trim().parse()
+        .map_err(|_| format!("Invalid peer IP: {}", peer_ip))?;
+    let addr = SocketAddr::new(ip, port);
+    
+    let samples = tauri::async_runtime::spawn_blocking(move || measure_clock_offset(addr))
+        .await
+        .map_err(|e| e.to_string())??;
+    let Some(&(offset, rtt)) = samples.iter().min_by_key(|(_, rtt)| *rtt) else {
+        return Err(format!("No clock sync replies from {} (is the frame receiver running?)", addr));
+    };
+    
+    let offset_ms = offset as f64 / 1000.0;
+    let rtt_ms = rtt.max(0) as f64 / 1000.0;
+    println!("Clock offset to {}: {:+.2} ms (RTT {:.2} ms, {}/{} replies)", addr, offset_ms, rtt_ms, samples.len(), CLOCK_SYNC_ROUNDS);
+    
+    Ok(serde_json::json!({
+        "offset_ms": offset_ms,
+        "rtt_ms": rtt_ms,
+        // Half the RTT bounds how far off the offset can be
+        "uncertainty_ms": rtt_ms / 2.0,
+        "samples": samples.len(),
+        "sent": CLOCK_SYNC_ROUNDS
+    }))
+}
+
+// ============== Session Permissions ==============
+// What the connected admin may do on this machine, granted by the local user.
+// Defaults to view-only: the screen can be shared without handing over input.
+// The client page's permission panel and accept prompts call set_session_permissions.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct SessionPermissions {
+    view: bool,
+    control_mouse: bool,
+    control_keyboard: bool,
+    file_transfer: bool,
+    // Lock/log off/sleep/shut down; absent from older frontends
+    #[serde(default)]
+    power_control: bool,
+    // Clipboard sync and pushes from the admin
+    #[serde(default)]
+    clipboard: bool,
+}
+
+impl Default for SessionPermissions {
+    fn default() -> Self {
+        Self {
+            view: true,
+            control_mouse: false,
+            control_keyboard: false,
+            file_transfer: false,
+            power_control: false,
+            clipboard: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Permission {
+    View,
+    ControlMouse,
+    ControlKeyboard,
+    FileTransfer,
+    PowerControl,
+    Clipboard,
+}
+
+impl Permission {
+    fn name(self) -> &'static str {
+        match self {
+            Permission::View => "view",
+            Permission::ControlMouse => "control_mouse",
+            Permission::ControlKeyboard => "control_keyboard",
+            Permission::FileTransfer => "file_transfer",
+            Permission::PowerControl => "power_control",
+            Permission::Clipboard => "clipboard",
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SESSION_PERMISSIONS: Mutex<SessionPermissions> = Mutex::new(SessionPermissions::default());
+}
+
+// Err (and a "permission-denied" event) when the session hasn't granted `permission`
+fn require_permission(app: &tauri::AppHandle, permission: Permission, action: &str) -> Result<(), String> {
+    let permissions = *SESSION_PERMISSIONS.lock();
+    let granted = match permission {
+        Permission::View => permissions.view,
+        Permission::ControlMouse => permissions.control_mouse,
+        Permission::ControlKeyboard => permissions.control_keyboard,
+        Permission::FileTransfer => permissions.file_transfer,
+        Permission::PowerControl => permissions.power_control,
+        Permission::Clipboard => permissions.clipboard,
+    };
+    if granted {
+        return Ok(());
+    }
+    
+    let _ = app.emit("permission-denied", serde_json::json!({
+        "permission": permission.name(),
+        "action": action
+    }));
+    Err(format!("Permission denied: {} requires {}", action, permission.name()))
+}
+
+#[tauri::command]
+fn set_session_permissions(permissions: SessionPermissions) -> SessionPermissions {
+    println!("Session permissions: {:?}", permissions);
+    *SESSION_PERMISSIONS.lock() = permissions;
+    permissions
+}
+
+#[tauri::command]
+fn get_session_permissions() -> SessionPermissions {
+    *SESSION_PERMISSIONS.lock()
+}
+
+// ============== Input Target Display ==============
+// remote_mouse_move coordinates are relative to the target display; they are
+// shifted into that monitor's region of the virtual desktop before injection.
+// No target = the primary display, whose origin is the desktop origin.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+struct InputTarget {
+    index: usize,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl InputTarget {
+    // Keeps the pointer on the target monitor even if the viewer overshoots
+    fn desktop_point(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.x + x.clamp(0.0, (self.width - 1.0).max(0.0)),
+            self.y + y.clamp(0.0, (self.height - 1.0).max(0.0)),
+        )
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref INPUT_TARGET: Mutex<Option<InputTarget>> = Mutex::new(None);
+}
+
+// rdev takes logical points on macOS and physical pixels elsewhere
+fn monitor_region(index: usize, monitor: &tauri::Monitor) -> InputTarget {
+    let position = *monitor.position();
+    let size = *monitor.size();
+    let scale = if cfg!(target_os = "macos") { monitor.scale_factor() } else { 1.0 };
+    InputTarget {
+        index,
+        x: position.x as f64 / scale,
+        y: position.y as f64 / scale,
+        width: size.width as f64 / scale,
+        height: size.height as f64 / scale,
+    }
+}
+
+// Input displays are numbered like list_displays (scrap's order, which the streams
+// use), but regions come from tauri's monitors, which may be listed differently.
+// Pair them by pixel size, same-size monitors in enumeration order; if sizes don't
+// line up at all (scaled sizes on macOS) fall back to the same index.
+fn match_display_to_monitor(displays: &[(u32, u32)], monitors: &[(u32, u32)], index: usize) -> Option<usize> {
+    let size = *displays.get(index)?;
+    let nth = displays[..index].iter().filter(|&&s| s == size).count();
+    monitors.iter().enumerate()
+        .filter(|(_, &s)| s == size)
+        .nth(nth)
+        .map(|(i, _)| i)
+        .or_else(|| (index < monitors.len()).then_some(index))
+}
+
+// tauri monitor for each list_displays index
+fn display_monitors(app: &tauri::AppHandle) -> Result<Vec<Option<tauri::Monitor>>, String> {
+    let displays: Vec<(u32, u32)> = Display::all().map_err(|e| format!("No display: {}", e))?
+        .iter()
+        .map(|d| (d.width() as u32, d.height() as u32))
+        .collect();
+    let monitors = app.available_monitors().map_err(|e| e.to_string())?;
+    let monitor_sizes: Vec<(u32, u32)> = monitors.iter().map(|m| (m.size().width, m.size().height)).collect();
+    
+    Ok((0..displays.len())
+        .map(|index| match_display_to_monitor(&displays, &monitor_sizes, index).map(|i| monitors[i].clone()))
+        .collect())
+}
+
+fn target_input_display(app: &tauri::AppHandle, index: usize) -> Result<InputTarget, String> {
+    let monitors = display_monitors(app)?;
+    let monitor = monitors.get(index)
+        .ok_or_else(|| format!("Display {} not found ({} available)", index, monitors.len()))?
+        .as_ref()
+        .ok_or_else(|| format!("Display {} has no matching monitor", index))?;
+    let target = monitor_region(index, monitor);
+    
+    *INPUT_TARGET.lock() = Some(target);
+    println!("Input target display {} at ({}, {}) {}x{}", index, target.x, target.y, target.width, target.height);
+    Ok(target)
+}
+
+fn input_point(x: f64, y: f64) -> (f64, f64) {
+    match *INPUT_TARGET.lock() {
+        Some(target) => target.desktop_point(x, y),
+        None => (x, y),
+    }
+}
+
+// Same indices as list_displays
+#[tauri::command]
+fn list_input_displays(app: tauri::AppHandle) -> Result<Vec<serde_json::Value>, String> {
+    let monitors = display_monitors(&app)?;
+    let primary = app.primary_monitor().ok().flatten().map(|m| *m.position());
+    let selected = INPUT_TARGET.lock().map(|t| t.index);
+    
+    Ok(monitors.iter().enumerate().filter_map(|(index, monitor)| {
+        let monitor = monitor.as_ref()?;
+        let region = monitor_region(index, monitor);
+        Some(serde_json::json!({
+            "index": index,
+            "name": monitor.name(),
+            "x": region.x,
+            "y": region.y,
+            "width": region.width,
+            "height": region.height,
+            "primary": primary == Some(*monitor.position()),
+            "selected": selected == Some(index)
+        }))
+    }).collect())
+}
+
+// index as in list_displays; switching the main stream's display also retargets input
+#[tauri::command]
+fn set_input_target_display(app: tauri::AppHandle, index: usize) -> Result<InputTarget, String> {
+    target_input_display(&app, index)
+}
+
+// ============== Input Pairing ==============
+// Binds remote input to a session the local user consented to: this machine
+// shows a one-time code, the admin submits it to authorize_input_session and
+// gets a token, and every remote_* input command must carry that token.
+const PAIRING_CODE_TTL: Duration = Duration::from_secs(120);
+// Wrong guesses before the code is burned and a new one must be shown
+const PAIRING_MAX_ATTEMPTS: u32 = 5;
+// Wrong guesses across codes: a fresh code resets the per-code count above, so
+// this many within the window locks pairing out entirely for PAIRING_LOCKOUT
+const PAIRING_LOCKOUT_FAILURES: usize = 10;
+const PAIRING_FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+const PAIRING_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+const INPUT_TOKEN_TTL: Duration = Duration::from_secs(8 * 3600);
+
+struct PairingCode {
+    code: String,
+    issued: Instant,
+    attempts: u32,
+}
+
+struct InputToken {
+    token: String,
+    issued: Instant,
+}
+
+// Wrong codes across every pairing request; survives start_input_pairing
+#[derive(Default)]
+struct PairingFailures {
+    recent: Vec<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl PairingFailures {
+    // Time left on the lockout, if pairing is locked at now
+    fn locked(&mut self, now: Instant) -> Option<Duration> {
+        match self.locked_until {
+            Some(until) if until > now => Some(until - now),
+            Some(_) => {
+                self.locked_until = None;
+                None
+            }
+            None => None,
+        }
+    }
+    
+    // Returns true when this failure locks pairing
+    fn record(&mut self, now: Instant) -> bool {
+        self.recent.retain(|t| now.duration_since(*t) < PAIRING_FAILURE_WINDOW);
+        self.recent.push(now);
+        if self.recent.len() < PAIRING_LOCKOUT_FAILURES {
+            return false;
+        }
+        self.recent.clear();
+        self.locked_until = Some(now + PAIRING_LOCKOUT);
+        true
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PAIRING_CODE: Mutex<Option<PairingCode>> = Mutex::new(None);
+    static ref PAIRING_FAILURES: Mutex<PairingFailures> = Mutex::new(PairingFailures::default());
+    static ref INPUT_TOKEN: Mutex<Option<InputToken>> = Mutex::new(None);
+}
+
+fn pairing_locked_error(remaining: Duration) -> String {
+    format!("Input pairing is locked after too many wrong codes; try again in {} s", remaining.as_secs().max(1))
+}
+
+// The code part of start_input_pairing; refused while pairing is locked out
+fn issue_pairing_code() -> Result<String, String> {
+    if let Some(remaining) = PAIRING_FAILURES.lock().locked(Instant::now()) {
+        return Err(pairing_locked_error(remaining));
+    }
+    // Modulo bias over a u64 is far below one in a million
+    let code = format!("{:06}", u64::from_le_bytes(random_bytes()?) % 1_000_000);
+    *PAIRING_CODE.lock() = Some(PairingCode {
+        code: code.clone(),
+        issued: Instant::now(),
+        attempts: 0,
+    });
+    Ok(code)
+}
+
+// Codes and tokens straight from the OS CSPRNG
+fn random_bytes<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("OS random source failed: {}", e))?;
+    Ok(bytes)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Err (and an "input-unauthorized" event) unless token is the live session token
+fn require_input_token(app: &tauri::AppHandle, token: &str, action: &str) -> Result<(), String> {
+    let mut session = INPUT_TOKEN.lock();
+    if session.as_ref().is_some_and(|s| s.issued.elapsed() >= INPUT_TOKEN_TTL) {
+        *session = None;
+    }
+    let valid = session.as_ref()
+        .is_some_and(|s| constant_time_eq(s.token.as_bytes(), token.as_bytes()));
+    if valid {
+        return Ok(());
+    }
+    drop(session);
+    
+    let _ = app.emit("input-unauthorized", serde_json::json!({ "action": action }));
+    Err(format!("Unauthorized: {} needs a paired input session", action))
+}
+
+// Issue a fresh code for the local user to read out; emits "input-pairing-code"
+// so whichever window is showing the consent prompt can display it
+#[tauri::command]
+fn start_input_pairing(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
+    let code = issue_pairing_code()?;
+    
+    let result = serde_json::json!({
+        "code": code,
+        "expires_in_secs": PAIRING_CODE_TTL.as_secs()
+    });
+    let _ = app.emit("input-pairing-code", result.clone());
+    Ok(result)
+}
+
+// Trade the on-screen code for an input token. The code works once.
+#[tauri::command]
+fn authorize_input_session(app: tauri::AppHandle, code: String) -> Result<serde_json::Value, String> {
+    let mut pairing = PAIRING_CODE.lock();
+    if let Some(remaining) = PAIRING_FAILURES.lock().locked(Instant::now()) {
+        *pairing = None;
+        return Err(pairing_locked_error(remaining));
+    }
+    let Some(pending) = pairing.as_mut() else {
+        return Err("No pairing code is active; ask the user to show one".to_string());
+    };
+    if pending.issued.elapsed() >= PAIRING_CODE_TTL {
+        *pairing = None;
+        return Err("Pairing code expired".to_string());
+    }
+    
+    if !constant_time_eq(pending.code.as_bytes(), code.trim().as_bytes()) {
+        pending.attempts += 1;
+        if PAIRING_FAILURES.lock().record(Instant::now()) {
+            *pairing = None;
+            eprintln!("Input pairing locked for {} s after repeated wrong codes", PAIRING_LOCKOUT.as_secs());
+            let _ = app.emit("input-pairing-failed", serde_json::json!({
+                "reason": "locked",
+                "locked_for_secs": PAIRING_LOCKOUT.as_secs()
+            }));
+            return Err(pairing_locked_error(PAIRING_LOCKOUT));
+        }
+        let remaining = PAIRING_MAX_ATTEMPTS.saturating_sub(pending.attempts);
+        if remaining == 0 {
+            *pairing = None;
+            let _ = app.emit("input-pairing-failed", serde_json::json!({ "reason": "too many attempts" }));
+            return Err("Wrong pairing code; too many attempts, a new code is needed".to_string());
+        }
+        return Err(format!("Wrong pairing code ({} attempts left)", remaining));
+    }
+    *pairing = None;
+    drop(pairing);
+    PAIRING_FAILURES.lock().recent.clear();
+    
+    let token = hex::encode(random_bytes::<32>()?);
+    *INPUT_TOKEN.lock() = Some(InputToken {
+        token: token.clone(),
+        issued: Instant::now(),
+    });
+    println!("Input session authorized");
+    let _ = app.emit("input-session-authorized", serde_json::json!({
+        "expires_in_secs": INPUT_TOKEN_TTL.as_secs()
+    }));
+    
+    Ok(serde_json::json!({
+        "token": token,
+        "expires_in_secs": INPUT_TOKEN_TTL.as_secs()
+    }))
+}
+
+// End the paired session; input stops until the user pairs again
+#[tauri::command]
+fn revoke_input_session() {
+    *INPUT_TOKEN.lock() = None;
+    *PAIRING_CODE.lock() = None;
+    println!("Input session revoked");
+}
+
+#[tauri::command]
+fn remote_mouse_move(app: tauri::AppHandle, token: String, x: f64, y: f64) -> Result<(), String> {
+    require_input_token(&app, &token, "remote_mouse_move")?;
+    require_permission(&app, Permission::ControlMouse, "remote_mouse_move")?;
+    let (x, y) = input_point(x, y);
+    send_event(&EventType::MouseMove { x, y })?;
+    *CURSOR_POS.lock() = Some((x, y));
+    Ok(())
+}
+
+#[tauri::command]
+fn remote_mouse_click(app: tauri::AppHandle, token: String, button: String) -> Result<(), String> {
+    require_input_token(&app, &token, "remote_mouse_click")?;
+    require_permission(&app, Permission::ControlMouse, "remote_mouse_click")?;
+    let btn = match button.as_str() {
+        "right" => Button::Right,
+        "middle" => Button::Middle,
+        _ => Button::Left,
+    };
+    send_event(&EventType::ButtonPress(btn))?;
+    send_event(&EventType::ButtonRelease(btn))?;
+    snapshot_on_input(&app, match btn {
+        Button::Right => "click_right",
+        Button::Middle => "click_middle",
+        _ => "click_left",
+    });
+    Ok(())
+}
+
+#[tauri::command]
+fn remote_mouse_scroll(app: tauri::AppHandle, token: String, delta_x: i64, delta_y: i64) -> Result<(), String> {
+    require_input_token(&app, &token, "remote_mouse_scroll")?;
+    require_permission(&app, Permission::ControlMouse, "remote_mouse_scroll")?;
+    send_event(&EventType::Wheel { delta_x, delta_y })
+}
+
+// ============== Modifier Tracking ==============
+// Simulated modifiers that are currently down. A key combo that fails halfway
+// would otherwise leave e.g. Ctrl held on the OS and turn every later key into
+// a shortcut, so failures release whatever is still marked here.
+const MODIFIER_KEYS: [(&str, Key); 4] = [
+    ("ctrl", Key::ControlLeft),
+    ("alt", Key::Alt),
+    ("shift", Key::ShiftLeft),
+    ("meta", Key::MetaLeft),
+];
+
+lazy_static::lazy_static! {
+    static ref HELD_MODIFIERS: Mutex<[bool; MODIFIER_KEYS.len()]> = Mutex::new([false; MODIFIER_KEYS.len()]);
+}
+
+// Modifiers in MODIFIER_KEYS order, pressed first and released in reverse
+fn send_key_combo(key: Option<Key>, modifiers: [bool; MODIFIER_KEYS.len()]) -> Result<(), String> {
+    for (i, &(_, modifier)) in MODIFIER_KEYS.iter().enumerate() {
+        if modifiers[i] {
+            send_event(&EventType::KeyPress(modifier))?;
+            HELD_MODIFIERS.lock()[i] = true;
+        }
+    }
+    
+    if let Some(key) = key {
+        send_event(&EventType::KeyPress(key))?;
+        send_event(&EventType::KeyRelease(key))?;
+    }
+    
+    for (i, &(_, modifier)) in MODIFIER_KEYS.iter().enumerate().rev() {
+        if modifiers[i] {
+            send_event(&EventType::KeyRelease(modifier))?;
+            HELD_MODIFIERS.lock()[i] = false;
+        }
+    }
+    Ok(())
+}
+
+// Release the modifiers (all four with `force`, else only tracked ones) and
+// return the ones that were still marked as held. Emits modifiers-released.
+fn release_modifiers(app: &tauri::AppHandle, force: bool) -> Vec<&'static str> {
+    let mut held = HELD_MODIFIERS.lock();
+    let mut stuck = Vec::new();
+    
+    for (i, &(name, modifier)) in MODIFIER_KEYS.iter().enumerate() {
+        if !held[i] && !force {
+            continue;
+        }
+        if held[i] {
+            stuck.push(name);
+        }
+        // A failed release stays marked so the next attempt retries it
+        match send_event(&EventType::KeyRelease(modifier)) {
+            Ok(()) => held[i] = false,
+            Err(e) => eprintln!("Cannot release {}: {}", name, e),
+        }
+    }
+    
+    if !stuck.is_empty() {
+        println!("Released stuck modifiers: {:?}", stuck);
+        let _ = app.emit("modifiers-released", serde_json::json!({ "modifiers": stuck }));
+    }
+    stuck
+}
+
+// Panic button for "everything is a shortcut now"; no token needed since it
+// can only let keys go
+#[tauri::command]
+fn release_all_modifiers(app: tauri::AppHandle) -> serde_json::Value {
+    let stuck = release_modifiers(&app, true);
+    serde_json::json!({ "stuck": stuck })
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn remote_key_press(app: tauri::AppHandle, token: String, key: String, code: String, ctrl: bool, alt: bool, shift: bool, meta: bool) -> Result<(), String> {
+    require_input_token(&app, &token, "remote_key_press")?;
+    require_permission(&app, Permission::ControlKeyboard, "remote_key_press")?;
+    if let Err(e) = send_key_combo(js_key_to_rdev(&key, &code), [ctrl, alt, shift, meta]) {
+        release_modifiers(&app, false);
+        return Err(e);
+    }
+    snapshot_on_input(&app, "key");
+    Ok(())
+}
+
+// ============== Input Self-Test ==============
+// Walks the cursor around a small square and back to where it started, so an
+// admin can confirm this machine accepts injected input before a session.
+const INPUT_TEST_SQUARE: f64 = 50.0;
+const INPUT_TEST_STEP_DELAY: Duration = Duration::from_millis(100);
+
+// Letters, digits and spaces; uppercase goes through Shift
+fn marker_keys(marker: &str) -> Result<Vec<(Key, bool)>, String> {
+    marker.chars().map(|c| {
+        let code = match c {
+            'a'..='z' | 'A'..='Z' => format!("Key{}", c.to_ascii_uppercase()),
+            '0'..='9' => format!("Digit{}", c),
+            ' ' => "Space".to_string(),
+            _ => return Err(format!("Marker can only contain letters, digits and spaces (got {:?})", c)),
+        };
+        js_key_to_rdev("", &code)
+            .map(|key| (key, c.is_ascii_uppercase()))
+            .ok_or_else(|| format!("No key for {:?}", c))
+    }).collect()
+}
+
+fn input_test_step(action: &str, result: Result<(), String>, failures: &mut u32) -> serde_json::Value {
+    if result.is_err() {
+        *failures += 1;
+    }
+    serde_json::json!({
+        "action": action,
+        "ok": result.is_ok(),
+        "error": result.err()
+    })
+}
+
+// Returns whether everything worked, and the report
+fn run_input_pattern(app: &tauri::AppHandle, origin: (f64, f64), marker: Vec<(Key, bool)>) -> (bool, serde_json::Value) {
+    let (x0, y0) = origin;
+    let corners = [
+        (x0 + INPUT_TEST_SQUARE, y0),
+        (x0 + INPUT_TEST_SQUARE, y0 + INPUT_TEST_SQUARE),
+        (x0, y0 + INPUT_TEST_SQUARE),
+        (x0, y0),
+    ];
+    let mut steps = Vec::new();
+    let mut failures = 0;
+    // Only the global hook sees what the OS actually did with our events
+    let mut cursor_followed = None;
+    
+    for (i, &(x, y)) in corners.iter().enumerate() {
+        let result = send_event(&EventType::MouseMove { x, y });
+        steps.push(input_test_step(&format!("move {:.0},{:.0}", x, y), result, &mut failures));
+        thread::sleep(INPUT_TEST_STEP_DELAY);
+        
+        if i == 1 && CURSOR_TRACKER_STARTED.load(Ordering::Relaxed) {
+            cursor_followed = Some(CURSOR_POS.lock().is_some_and(|(cx, cy)| (cx - x).abs() <= 2.0 && (cy - y).abs() <= 2.0));
+        }
+    }
+    
+    for (key, shift) in marker {
+        let result = send_key_combo(Some(key), [false, false, shift, false]);
+        if result.is_err() {
+            release_modifiers(app, false);
+        }
+        steps.push(input_test_step(&format!("key {:?}", key), result, &mut failures));
+    }
+    
+    let ok = failures == 0 && cursor_followed != Some(false);
+    (ok, serde_json::json!({
+        "ok": ok,
+        "origin": [x0, y0],
+        "cursor_followed": cursor_followed, // null = cursor tracker not running
+        "steps": steps
+    }))
+}
+
+// Moves the mouse around a 50px square from the current cursor position (the
+// input display's centre if it isn't known) and back. marker: typed into
+// whatever has focus afterwards. Each step reports whether simulate succeeded.
+// Real input, so it needs the paired session token like remote_mouse_move.
+#[tauri::command]
+async fn test_input_pattern(app: tauri::AppHandle, token: String, marker: Option<String>) -> Result<serde_json::Value, String> {
+    require_input_token(&app, &token, "test_input_pattern")?;
+    require_permission(&app, Permission::ControlMouse, "test_input_pattern")?;
+    let marker = match marker.as_deref().filter(|m| !m.is_empty()) {
+        Some(m) => {
+            require_permission(&app, Permission::ControlKeyboard, "test_input_pattern")?;
+            marker_keys(m)?
+        }
+        None => Vec::new(),
+    };
+    
+    let origin = match *CURSOR_POS.lock() {
+        Some(pos) => pos,
+        None => {
+            let target = *INPUT_TARGET.lock();
+            let region = match target {
+                Some(target) => target,
+                None => {
+                    let monitor = app.primary_monitor().map_err(|e| e.to_string())?
+                        .ok_or("No display to test input on")?;
+                    monitor_region(0, &monitor)
+                }
+            };
+            region.desktop_point(region.width / 2.0, region.height / 2.0)
+        }
+    };
+    
+    let handle = app.clone();
+    let (ok, report) = tauri::async_runtime::spawn_blocking(move || run_input_pattern(&handle, origin, marker))
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("Input self-test: {}", if ok { "ok" } else { "failed" });
+    Ok(report)
+}
+
+// ============== Input Snapshots ==============
+// Audit trail: save a screenshot whenever a remote click/key is injected.
+// Emits "input-snapshot" so the UI can attach the file to its input log entry.
+const INPUT_SNAPSHOT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    static ref INPUT_SNAPSHOT_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+    static ref LAST_INPUT_SNAPSHOT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+#[tauri::command]
+fn set_snapshot_on_input(enabled: bool, dir: Option<String>) -> Result<serde_json::Value, String> {
+    if !enabled {
+        *INPUT_SNAPSHOT_DIR.lock() = None;
+        return Ok(serde_json::json!({ "enabled": false }));
+    }
+    
+    let dir = dir.filter(|d| !d.trim().is_empty())
+        .ok_or("A snapshot directory is required")?;
+    let path = PathBuf::from(&dir);
+    fs::create_dir_all(&path).map_err(|e| format!("Cannot create {}: {}", dir, e))?;
+    
+    *INPUT_SNAPSHOT_DIR.lock() = Some(path);
+    Ok(serde_json::json!({ "enabled": true, "dir": dir }))
+}
+
+fn snapshot_on_input(app: &tauri::AppHandle, action: &'static str) {
+    let dir = match INPUT_SNAPSHOT_DIR.lock().clone() {
+        Some(dir) => dir,
+        None => return,
+    };
+    
+    {
+        let mut last = LAST_INPUT_SNAPSHOT.lock();
+        if last.is_some_and(|t| t.elapsed() < INPUT_SNAPSHOT_MIN_INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+    }
+    
+    let timestamp_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    
+    // Don't hold up input injection on disk I/O or a fresh capture
+    let app = app.clone();
+    thread::spawn(move || {
+        let jpeg = match LAST_JPEG_FRAME.lock().clone() {
+            Some(jpeg) => Some(jpeg),
+            None => ScreenCapturer::new().ok().and_then(|mut capturer| {
+                (0..30).find_map(|_| {
+                    let frame = capturer.capture()
+                        .and_then(|bgra| encode_jpeg(&bgra, capturer.width, capturer.height, 60));
+                    if frame.is_none() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    frame
+                })
+            }),
+        };
+        
+        let jpeg = match jpeg {
+            Some(jpeg) => jpeg,
+            None => {
+                eprintln!("Input snapshot: no frame available");
+                return;
+            }
+        };
+        
+        let path = dir.join(format!("input_{}_{}.jpg", timestamp_ms, action));
+        match fs::write(&path, &jpeg) {
+            Ok(()) => {
+                let _ = app.emit("input-snapshot", serde_json::json!({
+                    "action": action,
+                    "timestamp": timestamp_ms as u64,
+                    "path": path.to_string_lossy()
+                }));
+            }
+            Err(e) => eprintln!("Input snapshot write error: {}", e),
+        }
+    });
+}
+
+// ============== Clipboard Images ==============
+// Returns a PNG data URL, or None when the clipboard holds no image
+#[tauri::command]
+fn get_clipboard_image(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    require_permission(&app, Permission::Clipboard, "get_clipboard_image")?;
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard error: {}", e))?;
+    
+    let image = match clipboard.get_image() {
+        Ok(image) => image,
+        Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+        Err(e) => return Err(format!("Clipboard error: {}", e)),
+    };
+    
+    let png = clipboard_png(image)?;
+    Ok(Some(png_data_url(&png)))
+}
+
+fn clipboard_png(image: arboard::ImageData) -> Result<Vec<u8>, String> {
+    let rgba = image::RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned())
+        .ok_or("Clipboard image has an invalid size")?;
+    
+    let mut png = std::io::Cursor::new(Vec::new());
+    rgba.write_to(&mut png, image::ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+    Ok(png.into_inner())
+}
+
+fn png_data_url(png: &[u8]) -> String {
+    format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(png))
+}
+
+// Accepts any data URL (or bare base64) the image crate can decode
+#[tauri::command]
+fn set_clipboard_image(app: tauri::AppHandle, data_url: String) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::Clipboard, "set_clipboard_image")?;
+    let encoded = match data_url.split_once(',') {
+        Some((_, data)) => data,
+        None => data_url.as_str(),
+    };
+    let bytes = general_purpose::STANDARD.decode(encoded.trim())
+        .map_err(|e| format!("Base64 decode error: {}", e))?;
+    
+    let rgba = image::load_from_memory(&bytes)
+        .map_err(|e| format!("Cannot decode image: {}", e))?
+        .to_rgba8();
+    let (width, height) = (rgba.width() as usize, rgba.height() as usize);
+    
+    let image = arboard::ImageData {
+        width,
+        height,
+        bytes: std::borrow::Cow::Owned(rgba.into_raw()),
+    };
+    let fingerprint = ClipboardContent::Image(image.clone()).fingerprint();
+    
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard error: {}", e))?;
+    clipboard.set_image(image).map_err(|e| format!("Clipboard error: {}", e))?;
+    note_clipboard_push(fingerprint);
+    
+    Ok(serde_json::json!({ "width": width, "height": height }))
+}
+
+#[tauri::command]
+fn set_clipboard_text(app: tauri::AppHandle, text: String) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::Clipboard, "set_clipboard_text")?;
+    let fingerprint = ClipboardContent::Text(text.clone()).fingerprint();
+    
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard error: {}", e))?;
+    clipboard.set_text(text.as_str()).map_err(|e| format!("Clipboard error: {}", e))?;
+    note_clipboard_push(fingerprint);
+    
+    Ok(serde_json::json!({ "bytes": text.len() }))
+}
+
+// ============== Clipboard Sync ==============
+// Polls the clipboard and emits clipboard-changed with the new text or image, so
+// the admin side can mirror it. A change is only sent once the clipboard has held
+// still for CLIPBOARD_DEBOUNCE (copying in a loop sends the last value, once).
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const CLIPBOARD_DEBOUNCE: Duration = Duration::from_millis(500);
+const DEFAULT_CLIPBOARD_MAX_BYTES: usize = 1024 * 1024;
+const CLIPBOARD_MAX_BYTES_LIMIT: usize = 16 * 1024 * 1024;
+// A change settling this soon after a set_clipboard_* push is that push coming back
+// (images may not read back byte-identical), so it isn't sent to the admin again
+const CLIPBOARD_ECHO_WINDOW: Duration = Duration::from_secs(2);
+
+lazy_static::lazy_static! {
+    static ref CLIPBOARD_SYNC_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref CLIPBOARD_CHANGES_SENT: AtomicU64 = AtomicU64::new(0);
+    // Fingerprint and time of the last set_clipboard_* write
+    static ref CLIPBOARD_PUSHED: Mutex<Option<(u64, Instant)>> = Mutex::new(None);
+}
+
+fn note_clipboard_push(fingerprint: u64) {
+    *CLIPBOARD_PUSHED.lock() = Some((fingerprint, Instant::now()));
+}
+
+// True when this settled change is our own write. Only the first change after a
+// push is checked against it.
+fn is_clipboard_echo(pushed: &mut Option<(u64, Instant)>, fingerprint: u64, now: Instant) -> bool {
+    match pushed.take() {
+        Some((f, at)) => f == fingerprint || now.duration_since(at) <= CLIPBOARD_ECHO_WINDOW,
+        None => false,
+    }
+}
+
+enum ClipboardContent {
+    Text(String),
+    Image(arboard::ImageData<'static>),
+}
+
+impl ClipboardContent {
+    // Text wins when both are offered, e.g. a copied spreadsheet range
+    fn read(clipboard: &mut arboard::Clipboard) -> Option<Self> {
+        if let Ok(text) = clipboard.get_text() {
+            return Some(Self::Text(text));
+        }
+        clipboard.get_image().ok().map(Self::Image)
+    }
+    
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        match self {
+            Self::Text(text) => {
+                hasher.update(b"text");
+                hasher.update(text.as_bytes());
+            }
+            Self::Image(image) => {
+                hasher.update(b"image");
+                hasher.update(&(image.width as u64).to_le_bytes());
+                hasher.update(&image.bytes);
+            }
+        }
+        hasher.digest()
+    }
+}
+
+// Decides when a polled fingerprint (None = empty clipboard) becomes an event
+struct ClipboardDebounce {
+    sent: Option<u64>,
+    pending: Option<(u64, Instant)>,
+}
+
+impl ClipboardDebounce {
+    // The clipboard as sync starts is the baseline, not a change
+    fn new(baseline: Option<u64>) -> Self {
+        Self { sent: baseline, pending: None }
+    }
+    
+    fn observe(&mut self, fingerprint: Option<u64>, now: Instant) -> bool {
+        let Some(fingerprint) = fingerprint.filter(|&f| Some(f) != self.sent) else {
+            self.pending = None;
+            return false;
+        };
+        match self.pending {
+            Some((pending, since)) if pending == fingerprint => {
+                if now.duration_since(since) < CLIPBOARD_DEBOUNCE {
+                    return false;
+                }
+                self.sent = Some(fingerprint);
+                self.pending = None;
+                true
+            }
+            _ => {
+                self.pending = Some((fingerprint, now));
+                false
+            }
+        }
+    }
+}
+
+fn clipboard_change_json(content: ClipboardContent, max_bytes: usize) -> serde_json::Value {
+    match content {
+        ClipboardContent::Text(text) if text.len() > max_bytes => {
+            serde_json::json!({ "kind": "text", "bytes": text.len(), "skipped": true })
+        }
+        ClipboardContent::Text(text) => {
+            serde_json::json!({ "kind": "text", "bytes": text.len(), "skipped": false, "text": text })
+        }
+        ClipboardContent::Image(image) => {
+            let (width, height) = (image.width, image.height);
+            match clipboard_png(image) {
+                Ok(png) if png.len() <= max_bytes => serde_json::json!({
+                    "kind": "image",
+                    "bytes": png.len(),
+                    "skipped": false,
+                    "width": width,
+                    "height": height,
+                    "data_url": png_data_url(&png)
+                }),
+                Ok(png) => serde_json::json!({
+                    "kind": "image",
+                    "bytes": png.len(),
+                    "skipped": true,
+                    "width": width,
+                    "height": height
+                }),
+                Err(e) => serde_json::json!({ "kind": "image", "skipped": true, "error": e }),
+            }
+        }
+    }
+}
+
+// max_bytes: larger text/PNG is reported as { skipped: true, bytes } without the
+// content (default 1 MB, at most 16 MB)
+#[tauri::command]
+fn start_clipboard_sync(app: tauri::AppHandle, max_bytes: Option<usize>) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::Clipboard, "start_clipboard_sync")?;
+    let max_bytes = max_bytes.unwrap_or(DEFAULT_CLIPBOARD_MAX_BYTES);
+    if max_bytes == 0 || max_bytes > CLIPBOARD_MAX_BYTES_LIMIT {
+        return Err(format!("max_bytes must be 1-{} (got {})", CLIPBOARD_MAX_BYTES_LIMIT, max_bytes));
+    }
+    if CLIPBOARD_SYNC_RUNNING.swap(true, Ordering::SeqCst) {
+        return Ok(already_running(serde_json::json!({
+            "changes_sent": CLIPBOARD_CHANGES_SENT.load(Ordering::Relaxed)
+        })));
+    }
+    
+    // Fail here rather than on the thread; the handle itself isn't Send everywhere
+    if let Err(e) = arboard::Clipboard::new() {
+        CLIPBOARD_SYNC_RUNNING.store(false, Ordering::SeqCst);
+        return Err(format!("Clipboard error: {}", e));
+    }
+    CLIPBOARD_CHANGES_SENT.store(0, Ordering::Relaxed);
+    
+    thread::spawn(move || {
+        let mut clipboard = match arboard::Clipboard::new() {
+            Ok(clipboard) => clipboard,
+            Err(e) => {
+                eprintln!("Clipboard sync error: {}", e);
+                CLIPBOARD_SYNC_RUNNING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        let baseline = ClipboardContent::read(&mut clipboard).map(|content| content.fingerprint());
+        let mut debounce = ClipboardDebounce::new(baseline);
+        println!("Clipboard sync started");
+        
+        while CLIPBOARD_SYNC_RUNNING.load(Ordering::SeqCst) {
+            thread::sleep(CLIPBOARD_POLL_INTERVAL);
+            // set_session_permissions can take clipboard away mid-sync
+            if !SESSION_PERMISSIONS.lock().clipboard {
+                println!("Clipboard permission revoked");
+                CLIPBOARD_SYNC_RUNNING.store(false, Ordering::SeqCst);
+                break;
+            }
+            let content = ClipboardContent::read(&mut clipboard);
+            if !debounce.observe(content.as_ref().map(ClipboardContent::fingerprint), Instant::now()) {
+                continue;
+            }
+            if let Some(content) = content {
+                if is_clipboard_echo(&mut CLIPBOARD_PUSHED.lock(), content.fingerprint(), Instant::now()) {
+                    continue;
+                }
+                CLIPBOARD_CHANGES_SENT.fetch_add(1, Ordering::Relaxed);
+                let _ = app.emit("clipboard-changed", clipboard_change_json(content, max_bytes));
+            }
+        }
+        println!("Clipboard sync stopped");
+    });
+    
+    Ok(serde_json::json!({ "status": "started", "max_bytes": max_bytes }))
+}
+
+#[tauri::command]
+fn stop_clipboard_sync() -> serde_json::Value {
+    let was_running = CLIPBOARD_SYNC_RUNNING.swap(false, Ordering::SeqCst);
+    serde_json::json!({
+        "stopped": was_running,
+        "changes_sent": CLIPBOARD_CHANGES_SENT.load(Ordering::Relaxed)
+    })
+}
+
+// ============== Capture Backends ==============
+// Windows Graphics Capture needs Windows 10 1903+; scrap (DXGI/X11/CoreGraphics) works everywhere else
+#[tauri::command]
+fn get_capture_backend() -> serde_json::Value {
+    let scrap = match Display::primary() {
+        Ok(display) => serde_json::json!({
+            "backend": "scrap",
+            "available": true,
+            "details": format!("Primary display {}x{}", display.width(), display.height())
+        }),
+        Err(e) => serde_json::json!({
+            "backend": "scrap",
+            "available": false,
+            "details": format!("No display: {}", e)
+        }),
+    };
+    
+    let wgc = windows_graphics_capture_status();
+    let preferred = if wgc["available"].as_bool() == Some(true) { wgc.clone() } else { scrap.clone() };
+    
+    serde_json::json!({
+        "backend": preferred["backend"],
+        "available": preferred["available"],
+        "details": preferred["details"],
+        // What the running capturer actually uses; null when nothing is capturing
+        "active": active_capture_backend(),
+        "backends": [wgc, scrap]
+    })
+}
+
+// WGC only ever runs for start_capture; the streams and every other capture loop go through scrap
+fn active_capture_backend() -> Option<&'static str> {
+    #[cfg(target_os = "windows")]
+    if windows_capture_handler::is_capturing() {
+        return Some("windows-graphics-capture");
+    }
+    let scrap_running = [&CAPTURING, &LOSSLESS_CAPTURING, &DELTA_STREAMING, &PIPE_STREAMING]
+        .iter()
+        .any(|flag| flag.load(Ordering::SeqCst))
+        || STREAMS.lock().values().any(|stream| stream.running.load(Ordering::SeqCst));
+    scrap_running.then_some("scrap")
+}
+
+#[cfg(target_os = "windows")]
+fn windows_graphics_capture_status() -> serde_json::Value {
+    use windows_capture::graphics_capture_api::GraphicsCaptureApi;
+    
+    match GraphicsCaptureApi::is_supported() {
+        Ok(true) => serde_json::json!({
+            "backend": "windows-graphics-capture",
+            "available": true,
+            "details": "Windows Graphics Capture API is supported"
+        }),
+        Ok(false) => serde_json::json!({
+            "backend": "windows-graphics-capture",
+            "available": false,
+            "details": "Windows Graphics Capture requires Windows 10 version 1903 or later"
+        }),
+        Err(e) => serde_json::json!({
+            "backend": "windows-graphics-capture",
+            "available": false,
+            "details": format!("Cannot query Windows Graphics Capture: {}", e)
+        }),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn windows_graphics_capture_status() -> serde_json::Value {
+    serde_json::json!({
+        "backend": "windows-graphics-capture",
+        "available": false,
+        "details": "Only available on Windows"
+    })
+}
+
+// ============== Capture Commands ==============
+// Prefer Windows Graphics Capture where it works, otherwise the scrap-based
+// capture loop. Both emit the same "screen-frame" events.
+#[tauri::command]
+fn start_capture(app: tauri::AppHandle, interval_ms: u64) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::View, "start_capture")?;
+    #[cfg(target_os = "windows")]
+    let fallback_reason = match windows_capture_handler::start_capture(app.clone(), capped_interval(Duration::from_millis(interval_ms))) {
+        Ok(()) => return Ok(serde_json::json!({ "backend": "windows-graphics-capture" })),
+        Err(e) => {
+            eprintln!("{}, falling back to scrap", e);
+            let _ = app.emit("capture-backend-fallback", serde_json::json!({
+                "from": "windows-graphics-capture",
+                "to": "scrap",
+                "reason": e
+            }));
+            Some(e)
+        }
+    };
+    #[cfg(not(target_os = "windows"))]
+    let fallback_reason: Option<String> = None;
+    
+    start_capture_loop(app, interval_ms, None);
+    Ok(serde_json::json!({ "backend": "scrap", "fallback_reason": fallback_reason }))
+}
+
+#[tauri::command]
+fn stop_capture() -> Result<(), String> {
+    stop_capture_loop();
+    
+    #[cfg(target_os = "windows")]
+    windows_capture_handler::stop_capture()?;
+    
+    Ok(())
+}
+
+// One JPEG data URL of the current screen
+#[tauri::command]
+fn capture_single_frame(app: tauri::AppHandle) -> Result<String, String> {
+    require_permission(&app, Permission::View, "capture_single_frame")?;
+    #[cfg(target_os = "windows")]
+    {
+        match windows_capture_handler::capture_single_frame() {
+            Ok(frame) => {
+                if let Some(jpeg) = encode_jpeg(&frame.bgra, frame.width, frame.height, CAPTURE_QUALITY) {
+                    return Ok(jpeg_data_url(&jpeg));
+                }
+            }
+            Err(e) => eprintln!("{}, falling back to scrap", e),
+        }
+    }
+    
+    let capture = capture_screen(app, None)?;
+    capture["data_url"].as_str()
+        .map(|url| url.to_string())
+        .ok_or_else(|| "Capture failed".to_string())
+}
+
+// Most recent frame from a running capture/stream, None if nothing has been captured yet
+#[tauri::command]
+fn get_last_frame() -> Option<String> {
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(frame) = windows_capture_handler::get_last_frame() {
+            if let Some(jpeg) = encode_jpeg(&frame.bgra, frame.width, frame.height, CAPTURE_QUALITY) {
+                return Some(jpeg_data_url(&jpeg));
+            }
+        }
+    }
+    
+    LAST_JPEG_FRAME.lock().as_deref().map(jpeg_data_url)
+}
+
+// ============== Window Enumeration ==============
+// Visible top-level windows for the window-capture picker
+#[tauri::command]
+fn list_windows() -> Result<Vec<serde_json::Value>, String> {
+    enumerate_windows()
+}
+
+#[cfg(target_os = "windows")]
+fn enumerate_windows() -> Result<Vec<serde_json::Value>, String> {
+    use windows_capture::window::Window;
+    
+    // enumerate() already skips invisible, tool and untitled windows
+    let windows = Window::enumerate()
+        .map_err(|e| format!("Cannot enumerate windows: {}", e))?;
+    
+    let mut result = Vec::new();
+    
+    for window in windows {
+        let title = match window.title() {
+            Ok(title) if !title.trim().is_empty() => title,
+            _ => continue,
+        };
+        
+        // Nothing to capture from zero-size windows
+        match (window.width(), window.height()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => {}
+            _ => continue,
+        }
+        
+        let process_name = window.process_name().unwrap_or_else(|_| "unknown".to_string());
+        
+        result.push(serde_json::json!({
+            "handle_id": window.as_raw_hwnd() as usize,
+            "title": title,
+            "process_name": process_name
+        }));
+    }
+    
+    Ok(result)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn enumerate_windows() -> Result<Vec<serde_json::Value>, String> {
+    Err("Window enumeration is only supported on Windows".to_string())
+}
+
+// ============== LAN Scan ==============
+// Each worker is one thread holding at most one socket at a time, so concurrency
+// is also the peak number of file descriptors the scan uses. Keep it well under
+// the per-process fd limit (often 256 on macOS, 1024 on Linux).
+const SCAN_DEFAULT_CONCURRENCY: usize = 50;
+const SCAN_MAX_CONCURRENCY: usize = 256;
+
+lazy_static::lazy_static! {
+    // Set by emergency_stop; workers stop picking up new hosts
+    static ref SCAN_CANCELLED: AtomicBool = AtomicBool::new(false);
+}
+
+// concurrency: worker threads probing hosts in parallel (default 50, 1-256)
+#[tauri::command]
+async fn scan_lan(app: tauri::AppHandle, concurrency: Option<usize>) -> Result<Vec<serde_json::Value>, String> {
+    use std::net::{IpAddr, Ipv4Addr, TcpStream, SocketAddr};
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    
+    let concurrency = concurrency.unwrap_or(SCAN_DEFAULT_CONCURRENCY);
+    if concurrency == 0 || concurrency > SCAN_MAX_CONCURRENCY {
+        return Err(format!("concurrency must be between 1 and {}", SCAN_MAX_CONCURRENCY));
+    }
+    
+    // Get local IP to determine subnet
+    let local_ip = local_ip_address::local_ip()
+        .map_err(|e| format!("Cannot get local IP: {}", e))?;
+    
+    let base_ip = match local_ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!("{}.{}.{}", octets[0], octets[1], octets[2])
+        }
+        _ => return Err("IPv6 not supported".to_string()),
+    };
+    
+    SCAN_CANCELLED.store(false, Ordering::SeqCst);
+    println!("Scanning LAN: {}.1-254 with {} workers", base_ip, concurrency);
+    let _ = app.emit("scan-progress", serde_json::json!({
+        "status": "scanning",
+        "base": base_ip,
+        "concurrency": concurrency
+    }));
+    
+    let found_hosts: Arc<parking_lot::Mutex<Vec<serde_json::Value>>> = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let scanned = Arc::new(AtomicUsize::new(0));
+    // Next host octet to probe, shared by the workers
+    let next_host = Arc::new(AtomicUsize::new(1));
+    
+    let mut handles = vec![];
+    
+    for _ in 0..concurrency {
+        let base_ip = base_ip.clone();
+        let found = Arc::clone(&found_hosts);
+        let count = Arc::clone(&scanned);
+        let next_host = Arc::clone(&next_host);
+        
+        let handle = thread::spawn(move || loop {
+            let i = next_host.fetch_add(1, Ordering::Relaxed);
+            if i > 254 || SCAN_CANCELLED.load(Ordering::Relaxed) {
+                break;
+            }
+            let ip_str = format!("{}.{}", base_ip, i);
+            let ip: Ipv4Addr = ip_str.parse().unwrap();
+            let addr = SocketAddr::new(IpAddr::V4(ip), APP_PORT); // Check if our app port is open
+            
+            // Quick TCP connect check with timeout
+            let is_online = TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_ok();
+            
+            // Also try ping-like check (any common port)
+            let has_any_service = if !is_online {
+                // Try common ports: 22 (SSH), 445 (SMB), 139 (NetBIOS)
+                [22, 445, 139, 80, 443].iter().any(|&port| {
+                    let addr = SocketAddr::new(IpAddr::V4(ip), port);
+                    TcpStream::connect_timeout(&addr, Duration::from_millis(50)).is_ok()
+                })
+            } else {
+                true
+            };
+            
+            count.fetch_add(1, Ordering::Relaxed);
+            
+            if is_online || has_any_service {
+                let mut hosts = found.lock();
+                hosts.push(serde_json::json!({
+                    "ip": ip_str,
+                    "hasApp": is_online,
+                    "online": true
+                }));
+            }
+        });
+        
+        handles.push(handle);
+    }
+    
+    // Wait for all threads
+    for h in handles {
+        let _ = h.join();
+    }
+    
+    let results = found_hosts.lock().clone();
+    println!("Scan complete: {} hosts found", results.len());
+    let _ = app.emit("scan-progress", serde_json::json!({
+        "status": "complete",
+        "count": results.len(),
+        "concurrency": concurrency,
+        "cancelled": SCAN_CANCELLED.load(Ordering::SeqCst)
+    }));
+    
+    Ok(results)
+}
+
+// ============== Discovery Beacon ==============
+// Clients broadcast "DISC" + JSON every few seconds; admins listen instead of (or
+// as well as) scan_lan. Works where TCP connect probes are filtered.
+const DISCOVERY_PORT: u16 = 3004;
+const DISCOVERY_MAGIC: &[u8; 4] = b"DISC";
+const DISCOVERY_DEFAULT_INTERVAL_MS: u64 = 2000;
+const DISCOVERY_DEFAULT_LISTEN_MS: u64 = 5000;
+const APP_PORT: u16 = 3001;
+
+lazy_static::lazy_static! {
+    static ref BEACON_RUNNING: AtomicBool = AtomicBool::new(false);
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DiscoveryBeacon {
+    hostname: String,
+    name: String,
+    port: u16,
+    #[serde(default)]
+    mac: Option<String>,
+}
+
+#[cfg(windows)]
+fn local_hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn local_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(any(windows, unix)))]
+fn local_hostname() -> String {
+    "unknown".to_string()
+}
+
+// Client: announce this machine as `name` until stop_discovery_beacon
+#[tauri::command]
+fn start_discovery_beacon(name: String, interval_ms: Option<u64>) -> Result<(), String> {
+    let interval = Duration::from_millis(interval_ms.unwrap_or(DISCOVERY_DEFAULT_INTERVAL_MS).max(100));
+    let beacon = DiscoveryBeacon {
+        hostname: local_hostname(),
+        name,
+        port: APP_PORT,
+        mac: mac_address::get_mac_address().ok().flatten().map(|m| m.to_string()),
+    };
+    let mut packet = DISCOVERY_MAGIC.to_vec();
+    packet.extend(serde_json::to_vec(&beacon).map_err(|e| e.to_string())?);
+    
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Cannot create socket: {}", e))?;
+    socket.set_broadcast(true).map_err(|e| format!("Cannot enable broadcast: {}", e))?;
+    
+    if BEACON_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Discovery beacon already running".to_string());
+    }
+    
+    thread::spawn(move || {
+        println!("Discovery beacon started ({} as \"{}\")", beacon.hostname, beacon.name);
+        let mut targets = vec![format!("255.255.255.255:{}", DISCOVERY_PORT)];
+        // Some routers drop limited broadcast, so also try the /24 one like wake_on_lan
+        if let Ok(IpAddr::V4(ip)) = local_ip_address::local_ip() {
+            let o = ip.octets();
+            targets.push(format!("{}.{}.{}.255:{}", o[0], o[1], o[2], DISCOVERY_PORT));
+        }
+        
+        while BEACON_RUNNING.load(Ordering::SeqCst) {
+            for target in &targets {
+                let _ = socket.send_to(&packet, target);
+            }
+            
+            // Short sleeps so stop takes effect quickly
+            let started = Instant::now();
+            while started.elapsed() < interval && BEACON_RUNNING.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        println!("Discovery beacon stopped");
+    });
+    
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_discovery_beacon() {
+    BEACON_RUNNING.store(false, Ordering::SeqCst);
+}
+
+// Admin: collect beacons for timeout_ms (default 5000), emitting "peer-announced"
+// for each one; returns one entry per peer IP with its latest announcement
+#[tauri::command]
+async fn listen_for_beacons(app: tauri::AppHandle, timeout_ms: Option<u64>) -> Result<Vec<serde_json::Value>, String> {
+    let timeout = Duration::from_millis(timeout_ms.unwrap_or(DISCOVERY_DEFAULT_LISTEN_MS));
+    
+    tauri::async_runtime::spawn_blocking(move || {
+        let socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT))
+            .map_err(|e| format!("Cannot listen on UDP {}: {}", DISCOVERY_PORT, e))?;
+        let _ = socket.set_read_timeout(Some(Duration::from_millis(200)));
+        
+        // (ip, latest announcement) in first-seen order
+        let mut peers: Vec<(String, serde_json::Value)> = Vec::new();
+        let mut buf = [0u8; 1500];
+        let deadline = Instant::now() + timeout;
+        
+        while Instant::now() < deadline {
+            let (len, addr) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(_) => continue, // read timeout: check the deadline again
+            };
+            let beacon: DiscoveryBeacon = match buf[..len].strip_prefix(DISCOVERY_MAGIC.as_slice())
+                .and_then(|json| serde_json::from_slice(json).ok())
+            {
+                Some(beacon) => beacon,
+                None => continue,
+            };
+            
+            let ip = addr.ip().to_string();
+            let peer = serde_json::json!({
+                "ip": ip,
+                "hostname": beacon.hostname,
+                "name": beacon.name,
+                "port": beacon.port,
+                "mac": beacon.mac
+            });
+            let _ = app.emit("peer-announced", peer.clone());
+            
+            match peers.iter_mut().find(|(peer_ip, _)| *peer_ip == ip) {
+                Some((_, existing)) => *existing = peer,
+                None => peers.push((ip, peer)),
+            }
+        }
+        
+        Ok(peers.into_iter().map(|(_, peer)| peer).collect())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// ============== Wake-on-LAN ==============
+#[tauri::command]
+fn wake_on_lan(mac_address: String) -> Result<String, String> {
+    // Parse MAC address (formats: AA:BB:CC:DD:EE:FF or AA-BB-CC-DD-EE-FF)
+    let mac_str = mac_address.replace("-", ":").to_uppercase();
+    let mac_bytes: Vec<u8> = mac_str
+        .split(':')
+        .map(|s| u8::from_str_radix(s, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid MAC address: {}", mac_address))?;
+    
+    if mac_bytes.len() != 6 {
+        return Err(format!("MAC address must have 6 bytes, got {}", mac_bytes.len()));
+    }
+    
+    // Build magic packet: 6 bytes of 0xFF + MAC repeated 16 times
+    let mut magic_packet = vec![0xFFu8; 6];
+    for _ in 0..16 {
+        magic_packet.extend_from_slice(&mac_bytes);
+    }
+    
+    // Send to broadcast address on port 9 (standard WOL port)
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .map_err(|e| format!("Cannot create socket: {}", e))?;
+    
+    socket.set_broadcast(true)
+        .map_err(|e| format!("Cannot enable broadcast: {}", e))?;
+    
+    // Send to multiple broadcast addresses for better compatibility
+    let broadcasts = ["255.255.255.255:9", "255.255.255.255:7"];
+    
+    for addr in broadcasts {
+        if let Err(e) = socket.send_to(&magic_packet, addr) {
+            println!("WOL send to {} failed: {}", addr, e);
+        }
+    }
+    
+    // Also try subnet broadcast
+    if let Ok(local_ip) = local_ip_address::local_ip() {
+        if let IpAddr::V4(ip) = local_ip {
+            let octets = ip.octets();
+            let subnet_broadcast = format!("{}.{}.{}.255:9", octets[0], octets[1], octets[2]);
+            let _ = socket.send_to(&magic_packet, &subnet_broadcast);
+        }
+    }
+    
+    println!("WOL packet sent to {}", mac_address);
+    Ok(format!("Wake-on-LAN packet sent to {}", mac_address))
+}
+
+// ============== Power Actions ==============
+// Remote lock / log off / sleep / shutdown / reboot of this machine. Anything that
+// can lose unsaved work needs force: true, so a stray click can't do it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerAction {
+    Lock,
+    Logoff,
+    Sleep,
+    Shutdown,
+    Reboot,
+}
+
+impl PowerAction {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "lock" => Ok(Self::Lock),
+            "logoff" | "logout" => Ok(Self::Logoff),
+            "sleep" | "suspend" => Ok(Self::Sleep),
+            "shutdown" => Ok(Self::Shutdown),
+            "reboot" | "restart" => Ok(Self::Reboot),
+            other => Err(format!("Unknown power action: {} (expected lock, logoff, sleep, shutdown or reboot)", other)),
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            Self::Lock => "lock",
+            Self::Logoff => "logoff",
+            Self::Sleep => "sleep",
+            Self::Shutdown => "shutdown",
+            Self::Reboot => "reboot",
+        }
+    }
+    
+    // Ends the user's programs, so open documents are lost
+    fn destructive(self) -> bool {
+        matches!(self, Self::Logoff | Self::Shutdown | Self::Reboot)
+    }
+}
+
+// Emits "power-action" right before the OS call, since after it the app may be gone
+#[tauri::command]
+fn system_power_action(app: tauri::AppHandle, action: String, force: Option<bool>) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::PowerControl, "system_power_action")?;
+    let action = PowerAction::parse(&action)?;
+    let force = force.unwrap_or(false);
+    if action.destructive() && !force {
+        return Err(format!("{} closes the user's programs; pass force: true to confirm", action.name()));
+    }
+    
+    println!("Power action: {}", action.name());
+    let _ = app.emit("power-action", serde_json::json!({
+        "action": action.name(),
+        "force": force
+    }));
+    run_power_action(action)?;
+    
+    Ok(serde_json::json!({ "action": action.name() }))
+}
+
+#[cfg(not(windows))]
+fn run_power_command(program: &str, args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| format!("Cannot run {}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed ({})", program, status))
+    }
+}
+
+#[cfg(windows)]
+fn run_power_action(action: PowerAction) -> Result<(), String> {
+    use windows_sys::Win32::System::Power::SetSuspendState;
+    use windows_sys::Win32::System::Shutdown::{
+        ExitWindowsEx, LockWorkStation, EWX_LOGOFF, SHTDN_REASON_FLAG_PLANNED, SHTDN_REASON_MAJOR_OTHER,
+    };
+    
+    let ok = match action {
+        PowerAction::Lock => unsafe { LockWorkStation() != 0 },
+        PowerAction::Logoff => unsafe { ExitWindowsEx(EWX_LOGOFF, SHTDN_REASON_MAJOR_OTHER | SHTDN_REASON_FLAG_PLANNED) != 0 },
+        PowerAction::Sleep => unsafe { SetSuspendState(0, 0, 0) != 0 },
+        // ExitWindowsEx would need SeShutdownPrivilege adjusted into our token first
+        PowerAction::Shutdown | PowerAction::Reboot => {
+            let flag = if action == PowerAction::Shutdown { "/s" } else { "/r" };
+            let output = std::process::Command::new("shutdown")
+                .args([flag, "/t", "0"])
+                .output()
+                .map_err(|e| format!("Cannot run shutdown: {}", e))?;
+            if output.status.success() {
+                return Ok(());
+            }
+            // last_os_error says nothing about a child process; its exit code and output do
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // shutdown.exe prints some errors to stdout instead
+            let message = if stderr.trim().is_empty() { String::from_utf8_lossy(&output.stdout) } else { stderr };
+            return Err(format!("{} failed ({}): {}", action.name(), output.status, message.trim()));
+        }
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", action.name(), std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn run_power_action(action: PowerAction) -> Result<(), String> {
+    let system_events = |verb: &str| format!("tell application \"System Events\" to {}", verb);
+    match action {
+        // Same as Ctrl+Cmd+Q
+        PowerAction::Lock => run_power_command("osascript", &["-e", &system_events("keystroke \"q\" using {control down, command down}")]),
+        PowerAction::Logoff => run_power_command("osascript", &["-e", &system_events("log out")]),
+        PowerAction::Sleep => run_power_command("pmset", &["sleepnow"]),
+        PowerAction::Shutdown => run_power_command("osascript", &["-e", &system_events("shut down")]),
+        PowerAction::Reboot => run_power_command("osascript", &["-e", &system_events("restart")]),
+    }
+}
+
+// systemd-logind, which every mainstream desktop distro runs
+#[cfg(not(any(windows, target_os = "macos")))]
+fn run_power_action(action: PowerAction) -> Result<(), String> {
+    match action {
+        PowerAction::Lock => run_power_command("loginctl", &["lock-session"]),
+        PowerAction::Logoff => match std::env::var("XDG_SESSION_ID") {
+            Ok(session) => run_power_command("loginctl", &["terminate-session", &session]),
+            Err(_) => Err("Cannot log off: XDG_SESSION_ID is not set".to_string()),
+        },
+        PowerAction::Sleep => run_power_command("systemctl", &["suspend"]),
+        PowerAction::Shutdown => run_power_command("systemctl", &["poweroff"]),
+        PowerAction::Reboot => run_power_command("systemctl", &["reboot"]),
+    }
+}
+
+// ============== Get Local Network Info ==============
+#[tauri::command]
+fn get_network_info() -> Result<serde_json::Value, String> {
+    let local_ip = local_ip_address::local_ip()
+        .map_err(|e| format!("Cannot get local IP: {}", e))?;
+    
+    let mac = mac_address::get_mac_address()
+        .map_err(|e| format!("Cannot get MAC: {}", e))?
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    
+    Ok(serde_json::json!({
+        "ip": local_ip.to_string(),
+        "mac": mac
+    }))
+}
+
+// ============== System Info ==============
+// Machine summary for the admin's inventory view. Only RAM and the CPU list are
+// refreshed (no per-process or per-core usage sampling), so it is cheap to call.
+#[tauri::command]
+fn get_system_info() -> serde_json::Value {
+    use sysinfo::{CpuRefreshKind, MemoryRefreshKind, RefreshKind, System};
+    
+    let sys = System::new_with_specifics(
+        RefreshKind::new()
+            .with_memory(MemoryRefreshKind::new().with_ram())
+            .with_cpu(CpuRefreshKind::new())
+    );
+    let cpu_model = sys.cpus().first().map(|cpu| cpu.brand().trim().to_string());
+    // null when there is no display (headless) or capture isn't permitted
+    let display = Display::primary().ok().map(|d| serde_json::json!({ "width": d.width(), "height": d.height() }));
+    
+    serde_json::json!({
+        "os_name": System::name(),
+        "os_version": System::long_os_version().or_else(System::os_version),
+        "kernel_version": System::kernel_version(),
+        "hostname": System::host_name().unwrap_or_else(local_hostname),
+        "cpu_model": cpu_model,
+        "cpu_logical_cores": sys.cpus().len(),
+        "cpu_physical_cores": sys.physical_core_count(),
+        "total_memory_bytes": sys.total_memory(),
+        "available_memory_bytes": sys.available_memory(),
+        "primary_display": display
+    })
+}
+
+// ============== Power Status ==============
+// Desktops (no battery) report on_battery false and percent null.
+// With several batteries percent is their average charge.
+#[tauri::command]
+fn get_power_status() -> Result<serde_json::Value, String> {
+    let manager = battery::Manager::new().map_err(|e| format!("Cannot read power status: {}", e))?;
+    let batteries: Vec<battery::Battery> = manager.batteries()
+        .map_err(|e| format!("Cannot list batteries: {}", e))?
+        .filter_map(|b| b.ok())
+        .collect();
+    
+    if batteries.is_empty() {
+        return Ok(serde_json::json!({
+            "has_battery": false,
+            "on_battery": false,
+            "percent": null,
+            "charging": false
+        }));
+    }
+    
+    let charge: f32 = batteries.iter().map(|b| b.state_of_charge().value).sum::<f32>() / batteries.len() as f32;
+    let percent = (charge * 100.0).round().clamp(0.0, 100.0) as u8;
+    let states: Vec<battery::State> = batteries.iter().map(|b| b.state()).collect();
+    
+    Ok(serde_json::json!({
+        "has_battery": true,
+        "on_battery": states.iter().any(|s| matches!(s, battery::State::Discharging | battery::State::Empty)),
+        "percent": percent,
+        "charging": states.contains(&battery::State::Charging),
+        "battery_count": batteries.len()
+    }))
+}
+
+// ============== Audio Devices ==============
+// Output devices whose audio can be captured by loopback, for the audio stream's
+// device_name. Machines without audio hardware return an empty list, not an error.
+#[tauri::command]
+fn list_audio_devices() -> Result<Vec<serde_json::Value>, String> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+    
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let devices = match host.output_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("Cannot list audio devices ({}): {}", host.id().name(), e);
+            return Ok(Vec::new());
+        }
+    };
+    
+    Ok(devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            // Preview of what a capture would get; null when the device won't say
+            let config = device.default_output_config().ok();
+            Some(serde_json::json!({
+                "default": default_name.as_deref() == Some(name.as_str()),
+                "sample_rate": config.as_ref().map(|c| c.sample_rate().0),
+                "channels": config.as_ref().map(|c| c.channels()),
+                "name": name
+            }))
+        })
+        .collect())
+}
+
+// ============== File Transfer with Chunk + Resume ==============
+use sha2::{Sha256, Digest};
+use std::fs::{self, File};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
+const MAX_KNOWN_SAVE_DIRS: usize = 8;
+
+lazy_static::lazy_static! {
+    // Track ongoing transfers: transfer_id -> TransferState
+    static ref TRANSFERS: Mutex<HashMap<String, TransferState>> = Mutex::new(HashMap::new());
+    // Save dirs used by earlier transfers, most recent first (for resume lookup)
+    static ref KNOWN_SAVE_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    // Where KNOWN_SAVE_DIRS is kept between runs, set in setup
+    static ref KNOWN_SAVE_DIRS_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+// One path per line, most recent first
+fn read_known_save_dirs(path: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(path)
+        .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).take(MAX_KNOWN_SAVE_DIRS).collect())
+        .unwrap_or_default()
+}
+
+fn write_known_save_dirs(path: &Path, dirs: &[PathBuf]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let text: String = dirs.iter().map(|dir| format!("{}\n", dir.display())).collect();
+    fs::write(path, text)
+}
+
+// A resume after an app restart still finds `.tmp`s in dirs from earlier runs
+fn load_known_save_dirs(app_data_dir: &Path) {
+    let path = app_data_dir.join("known_save_dirs.txt");
+    *KNOWN_SAVE_DIRS.lock() = read_known_save_dirs(&path);
+    *KNOWN_SAVE_DIRS_FILE.lock() = Some(path);
+}
+
+#[derive(Clone, serde::Serialize)]
+struct TransferState {
+    file_name: String,
+    file_size: u64,
+    total_chunks: u32,
+    received_chunks: u32,
+    file_hash: String,
+    algorithm: HashAlgorithm,
+    temp_path: String,
+    completed: bool,
+    // Chunks below received_chunks that failed verify_partial_transfer and must be re-sent
+    invalid_chunks: std::collections::BTreeSet<u32>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+// ============== Transfer History ==============
+// Finished transfers (either direction, chunked or TCP), newest last, so the UI
+// can show "recent transfers" after their live state is gone.
+const TRANSFER_HISTORY_MAX: usize = 50;
+
+lazy_static::lazy_static! {
+    static ref TRANSFER_HISTORY: Mutex<std::collections::VecDeque<TransferRecord>> = Mutex::new(std::collections::VecDeque::new());
+}
+
+enum TransferOutcome {
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct TransferRecord {
+    transfer_id: String,
+    file_name: String,
+    file_size: u64,
+    direction: &'static str, // "receive" | "send"
+    outcome: &'static str,   // "completed" | "failed" | "cancelled"
+    error: Option<String>,
+    duration_ms: u64,
+    // Completed only; a resumed transfer counts the whole file
+    avg_speed_bps: Option<f64>,
+    finished_at: u64, // unix seconds
+}
+
+fn record_transfer(
+    transfer_id: &str,
+    file_name: &str,
+    file_size: u64,
+    direction: &'static str,
+    started: Instant,
+    outcome: TransferOutcome
+) {
+    let elapsed = started.elapsed();
+    let (outcome, error, avg_speed_bps) = match outcome {
+        TransferOutcome::Completed => ("completed", None, Some(file_size as f64 / elapsed.as_secs_f64().max(0.001))),
+        TransferOutcome::Failed(e) => ("failed", Some(e), None),
+        TransferOutcome::Cancelled => ("cancelled", None, None),
+    };
+    
+    let mut history = TRANSFER_HISTORY.lock();
+    if history.len() >= TRANSFER_HISTORY_MAX {
+        history.pop_front();
+    }
+    history.push_back(TransferRecord {
+        transfer_id: transfer_id.to_string(),
+        file_name: file_name.to_string(),
+        file_size,
+        direction,
+        outcome,
+        error,
+        duration_ms: elapsed.as_millis() as u64,
+        avg_speed_bps,
+        finished_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    });
+}
+
+fn file_name_of(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
 }
 
+// Newest first, at most TRANSFER_HISTORY_MAX entries
 #[tauri::command]
-fn start_capture_loop(app: tauri::AppHandle, interval_ms: u64) {
-    if CAPTURING.swap(true, Ordering::SeqCst) {
-        return;
-    }
+fn get_transfer_history() -> Vec<TransferRecord> {
+    TRANSFER_HISTORY.lock().iter().rev().cloned().collect()
+}
+
+// Admin: Read file and prepare for transfer
+// algorithm: "sha256" (default) | "blake3" | "xxh3"; pass the returned one on to the receiver
+#[tauri::command]
+fn prepare_file_transfer(file_path: String, algorithm: Option<String>) -> Result<serde_json::Value, String> {
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
+    transfer_metadata(&file_path, algorithm, |_| {})
+}
+
+// Admin: same as prepare_file_transfer but hashes on a thread, so big files don't
+// freeze the UI. Returns a placeholder id at once; prepare-progress events follow
+// and prepare-complete carries the real metadata (or prepare-error).
+#[tauri::command]
+fn prepare_file_transfer_async(
+    app: tauri::AppHandle,
+    file_path: String,
+    algorithm: Option<String>
+) -> Result<serde_json::Value, String> {
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
+    let file_size = fs::metadata(&file_path)
+        .map_err(|_| format!("File not found: {}", file_path))?
+        .len();
+    
+    static NEXT_PREPARE: AtomicU32 = AtomicU32::new(0);
+    let placeholder_id = format!("pending_{}_{}", chrono_lite_timestamp(), NEXT_PREPARE.fetch_add(1, Ordering::Relaxed));
     
+    let id = placeholder_id.clone();
+    let path = file_path.clone();
     thread::spawn(move || {
-        let mut capturer = match ScreenCapturer::new() {
-            Ok(c) => c,
-            Err(_) => {
-                CAPTURING.store(false, Ordering::SeqCst);
-                return;
+        let mut last_progress = 0u32;
+        let result = transfer_metadata(&path, algorithm, |hashed| {
+            let progress = (hashed as f64 / file_size.max(1) as f64 * 100.0) as u32;
+            if progress >= last_progress + 5 || hashed == file_size {
+                let _ = app.emit("prepare-progress", serde_json::json!({
+                    "placeholder_id": id,
+                    "bytes_hashed": hashed,
+                    "total_bytes": file_size,
+                    "progress": progress
+                }));
+                last_progress = progress;
             }
-        };
-        
-        let interval = Duration::from_millis(interval_ms);
+        });
         
-        while CAPTURING.load(Ordering::SeqCst) {
-            let start = Instant::now();
-            
-            if let Some(bgra) = capturer.capture() {
-                if let Some(jpeg) = encode_jpeg(&bgra, capturer.width, capturer.height, 60) {
-                    let base64_str = general_purpose::STANDARD.encode(&jpeg);
-                    let data_url = format!("data:image/jpeg;base64,{}", base64_str);
-                    let _ = app.emit("screen-frame", data_url);
+        match result {
+            Ok(mut metadata) => {
+                if let serde_json::Value::Object(fields) = &mut metadata {
+                    fields.insert("placeholder_id".to_string(), serde_json::json!(id));
                 }
+                let _ = app.emit("prepare-complete", metadata);
             }
-            
-            let elapsed = start.elapsed();
-            if elapsed < interval {
-                thread::sleep(interval - elapsed);
+            Err(e) => {
+                eprintln!("Prepare transfer error: {}", e);
+                let _ = app.emit("prepare-error", serde_json::json!({
+                    "placeholder_id": id,
+                    "error": e
+                }));
             }
         }
     });
+    
+    Ok(serde_json::json!({
+        "transfer_id": placeholder_id,
+        "file_path": file_path,
+        "file_size": file_size,
+        "status": "hashing"
+    }))
 }
 
-#[tauri::command]
-fn stop_capture_loop() {
-    CAPTURING.store(false, Ordering::SeqCst);
-}
-
-#[tauri::command]
-fn start_stream(server_addr: String, fps: u32) -> Result<(), String> {
-    start_h264_streaming(server_addr, fps)
+// What the receiver needs to accept file_path: name, size, chunking and hash
+fn transfer_metadata(
+    file_path: &str,
+    algorithm: HashAlgorithm,
+    on_progress: impl FnMut(u64)
+) -> Result<serde_json::Value, String> {
+    let path = PathBuf::from(file_path);
+    
+    if !path.exists() {
+        return Err(format!("File not found: {}", file_path));
+    }
+    
+    let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
+    let file_size = metadata.len();
+    let file_name = path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+    
+    let file_hash = hash_file_with_progress(&path, algorithm, on_progress)?;
+    let total_chunks = ((file_size as usize + CHUNK_SIZE - 1) / CHUNK_SIZE) as u32;
+    let transfer_id = format!("{}_{}", file_hash[..16].to_string(), chrono_lite_timestamp());
+    
+    Ok(serde_json::json!({
+        "transfer_id": transfer_id,
+        "file_name": file_name,
+        "file_path": file_path,
+        "file_size": file_size,
+        "total_chunks": total_chunks,
+        "file_hash": file_hash,
+        "algorithm": algorithm,
+        "chunk_size": CHUNK_SIZE
+    }))
 }
 
-#[tauri::command]
-fn stop_stream() {
-    STREAMING.store(false, Ordering::SeqCst);
+// Whole-file checksum for transfers. SHA-256 stays the default so older peers
+// interoperate; blake3/xxh3 are several times cheaper on big files over a trusted LAN.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    Xxh3,
 }
 
-#[tauri::command]
-fn start_frame_receiver(app: tauri::AppHandle, port: u16) -> Result<(), String> {
-    start_h264_receiver(app, port)
+impl HashAlgorithm {
+    fn parse(name: Option<&str>) -> Result<Self, String> {
+        match name.map(|n| n.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("sha256") => Ok(Self::Sha256),
+            Some("blake3") => Ok(Self::Blake3),
+            Some("xxh3") => Ok(Self::Xxh3),
+            Some(other) => Err(format!("Unknown hash algorithm: {}", other)),
+        }
+    }
 }
 
-#[tauri::command]
-fn stop_frame_receiver() {
-    UDP_RECEIVER_RUNNING.store(false, Ordering::SeqCst);
+// Incremental state for one HashAlgorithm
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
 }
 
-#[tauri::command]
-fn get_stream_stats() -> serde_json::Value {
-    serde_json::json!({
-        "streaming": STREAMING.load(Ordering::SeqCst),
-        "capturing": CAPTURING.load(Ordering::SeqCst),
-        "frames_sent": FRAME_COUNT.load(Ordering::Relaxed),
-        "codec": "H.264",
-        "resolution": format!("{}x{}", STREAM_WIDTH, STREAM_HEIGHT)
-    })
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+    
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Blake3(h) => { h.update(data); }
+            Self::Xxh3(h) => h.update(data),
+        }
+    }
+    
+    // Lowercase hex, like the SHA-256 hashes peers already exchange
+    fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(h) => hex::encode(h.finalize()),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+            Self::Xxh3(h) => format!("{:016x}", h.digest()),
+        }
+    }
 }
 
-#[tauri::command]
-fn get_screen_size() -> Result<serde_json::Value, String> {
-    let display = Display::primary().map_err(|e| e.to_string())?;
-    Ok(serde_json::json!({ "width": display.width(), "height": display.height() }))
+fn hash_file(path: &Path, algorithm: HashAlgorithm) -> Result<String, String> {
+    hash_file_with_progress(path, algorithm, |_| {})
 }
 
-#[tauri::command]
-async fn set_lock_screen(app: tauri::AppHandle, lock: bool, _message: String) -> Result<(), String> {
-    let window = app.get_webview_window("main").ok_or("Window not found")?;
-    if lock {
-        window.set_fullscreen(true).map_err(|e| e.to_string())?;
-        window.set_always_on_top(true).map_err(|e| e.to_string())?;
-        window.set_focus().map_err(|e| e.to_string())?;
-    } else {
-        window.set_always_on_top(false).map_err(|e| e.to_string())?;
-        window.set_fullscreen(false).map_err(|e| e.to_string())?;
+// on_progress gets the bytes hashed so far after each chunk
+fn hash_file_with_progress(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    mut on_progress: impl FnMut(u64)
+) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut hashed = 0u64;
+    
+    loop {
+        let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if bytes_read == 0 { break; }
+        hasher.update(&buffer[..bytes_read]);
+        hashed += bytes_read as u64;
+        on_progress(hashed);
     }
-    Ok(())
+    
+    Ok(hasher.finalize_hex())
 }
 
-#[tauri::command]
-fn remote_mouse_move(x: f64, y: f64) -> Result<(), String> {
-    send_event(&EventType::MouseMove { x, y })
-}
+// Files smaller than this hash quickly enough that progress events are noise
+const HASH_PROGRESS_MIN_SIZE: u64 = 16 * 1024 * 1024;
 
+// Re-check a file already on disk (finished earlier or received out-of-band).
+// expected_hash is hex, compared case-insensitively; algorithm defaults to sha256.
 #[tauri::command]
-fn remote_mouse_click(button: String) -> Result<(), String> {
-    let btn = match button.as_str() {
-        "right" => Button::Right,
-        "middle" => Button::Middle,
-        _ => Button::Left,
-    };
-    send_event(&EventType::ButtonPress(btn))?;
-    send_event(&EventType::ButtonRelease(btn))
+async fn verify_file_hash(
+    app: tauri::AppHandle,
+    path: String,
+    expected_hash: String,
+    algorithm: Option<String>
+) -> Result<serde_json::Value, String> {
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
+    
+    tauri::async_runtime::spawn_blocking(move || {
+        let file_path = Path::new(&path);
+        let total = fs::metadata(file_path)
+            .map_err(|e| format!("Cannot read {}: {}", path, e))?
+            .len();
+        let mut last_progress = 0u32;
+        
+        let computed_hash = hash_file_with_progress(file_path, algorithm, |hashed| {
+            if total < HASH_PROGRESS_MIN_SIZE {
+                return;
+            }
+            let progress = (hashed as f64 / total as f64 * 100.0) as u32;
+            if progress >= last_progress + 5 || hashed == total {
+                let _ = app.emit("hash-progress", serde_json::json!({
+                    "path": path,
+                    "bytes_hashed": hashed,
+                    "total_bytes": total,
+                    "progress": progress
+                }));
+                last_progress = progress;
+            }
+        })?;
+        
+        Ok(serde_json::json!({
+            "match": computed_hash.eq_ignore_ascii_case(expected_hash.trim()),
+            "computed_hash": computed_hash,
+            "algorithm": algorithm
+        }))
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-#[tauri::command]
-fn remote_mouse_scroll(delta_x: i64, delta_y: i64) -> Result<(), String> {
-    send_event(&EventType::Wheel { delta_x, delta_y })
-}
+// ============== Directory Zipping ==============
+// Pack a folder into one .zip so it can go through the normal single-file
+// transfer. Files are streamed into the archive chunk by chunk, never held whole.
 
-#[tauri::command]
-fn remote_key_press(key: String, code: String, ctrl: bool, alt: bool, shift: bool, meta: bool) -> Result<(), String> {
-    if ctrl { send_event(&EventType::KeyPress(Key::ControlLeft))?; }
-    if alt { send_event(&EventType::KeyPress(Key::Alt))?; }
-    if shift { send_event(&EventType::KeyPress(Key::ShiftLeft))?; }
-    if meta { send_event(&EventType::KeyPress(Key::MetaLeft))?; }
+#[derive(Default)]
+struct DirListing {
+    files: Vec<(PathBuf, String, u64)>, // (path, name inside the archive, size)
+    dirs: Vec<String>,
+    total_bytes: u64,
+}
 
-    if let Some(rdev_key) = js_key_to_rdev(&key, &code) {
-        send_event(&EventType::KeyPress(rdev_key))?;
-        send_event(&EventType::KeyRelease(rdev_key))?;
+// Walks dir recursively; symlinks are skipped so a link loop can't recurse forever.
+// `skip` is the archive being written, in case it lives inside dir.
+fn list_dir_for_zip(root: &Path, dir: &Path, skip: &Path, listing: &mut DirListing) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+        // Zip entries always use '/' regardless of platform
+        let name = path.strip_prefix(root)
+            .map_err(|e| e.to_string())?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        
+        if file_type.is_dir() {
+            listing.dirs.push(format!("{}/", name));
+            list_dir_for_zip(root, &path, skip, listing)?;
+        } else if file_type.is_file() && path != skip {
+            let size = entry.metadata().map_err(|e| e.to_string())?.len();
+            listing.total_bytes += size;
+            listing.files.push((path, name, size));
+        }
     }
-
-    if meta { send_event(&EventType::KeyRelease(Key::MetaLeft))?; }
-    if shift { send_event(&EventType::KeyRelease(Key::ShiftLeft))?; }
-    if alt { send_event(&EventType::KeyRelease(Key::Alt))?; }
-    if ctrl { send_event(&EventType::KeyRelease(Key::ControlLeft))?; }
     Ok(())
 }
 
-// ============== LAN Scan ==============
-#[tauri::command]
-async fn scan_lan(app: tauri::AppHandle) -> Result<Vec<serde_json::Value>, String> {
-    use std::net::{IpAddr, Ipv4Addr, TcpStream, SocketAddr};
-    use std::sync::Arc;
-    use std::sync::atomic::AtomicUsize;
-    
-    // Get local IP to determine subnet
-    let local_ip = local_ip_address::local_ip()
-        .map_err(|e| format!("Cannot get local IP: {}", e))?;
-    
-    let base_ip = match local_ip {
-        IpAddr::V4(ip) => {
-            let octets = ip.octets();
-            format!("{}.{}.{}", octets[0], octets[1], octets[2])
-        }
-        _ => return Err("IPv6 not supported".to_string()),
-    };
+fn write_dir_zip(
+    listing: &DirListing,
+    out_path: &Path,
+    mut on_progress: impl FnMut(u64, usize)
+) -> Result<u64, String> {
+    use zip::write::SimpleFileOptions;
     
-    println!("Scanning LAN: {}.1-254", base_ip);
-    let _ = app.emit("scan-progress", serde_json::json!({ "status": "scanning", "base": base_ip }));
+    let out = File::create(out_path).map_err(|e| format!("Cannot create {}: {}", out_path.display(), e))?;
+    let mut zip = zip::ZipWriter::new(BufWriter::new(out));
+    let dir_options = SimpleFileOptions::default();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut written = 0u64;
     
-    let found_hosts: Arc<parking_lot::Mutex<Vec<serde_json::Value>>> = Arc::new(parking_lot::Mutex::new(Vec::new()));
-    let scanned = Arc::new(AtomicUsize::new(0));
+    // Explicit entries so empty folders survive the round trip
+    for name in &listing.dirs {
+        zip.add_directory(name.as_str(), dir_options).map_err(|e| e.to_string())?;
+    }
     
-    let mut handles = vec![];
+    for (index, (path, name, size)) in listing.files.iter().enumerate() {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(*size >= u32::MAX as u64);
+        zip.start_file(name.as_str(), options).map_err(|e| e.to_string())?;
+        
+        let mut file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        loop {
+            let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+            if bytes_read == 0 { break; }
+            zip.write_all(&buffer[..bytes_read]).map_err(|e| e.to_string())?;
+            written += bytes_read as u64;
+            on_progress(written, index);
+        }
+        on_progress(written, index + 1);
+    }
     
-    for i in 1..=254u8 {
-        let ip_str = format!("{}.{}", base_ip, i);
-        let found = Arc::clone(&found_hosts);
-        let count = Arc::clone(&scanned);
+    let mut out = zip.finish().map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())?;
+    let out = out.into_inner().map_err(|e| e.to_string())?;
+    out.sync_all().map_err(|e| e.to_string())?;
+    Ok(out.metadata().map_err(|e| e.to_string())?.len())
+}
+
+// Zip dir_path into out_path, emitting `zip-progress` every 5%; the result can
+// then be sent with the existing file-transfer commands. A failed run removes
+// the partial archive.
+#[tauri::command]
+async fn zip_directory(
+    app: tauri::AppHandle,
+    dir_path: String,
+    out_path: String
+) -> Result<serde_json::Value, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let root = Path::new(&dir_path);
+        if !root.is_dir() {
+            return Err(format!("Not a directory: {}", dir_path));
+        }
+        let out = Path::new(&out_path);
         
-        let handle = thread::spawn(move || {
-            let ip: Ipv4Addr = ip_str.parse().unwrap();
-            let addr = SocketAddr::new(IpAddr::V4(ip), 3001); // Check if our app port is open
-            
-            // Quick TCP connect check with timeout
-            let is_online = TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_ok();
-            
-            // Also try ping-like check (any common port)
-            let has_any_service = if !is_online {
-                // Try common ports: 22 (SSH), 445 (SMB), 139 (NetBIOS)
-                [22, 445, 139, 80, 443].iter().any(|&port| {
-                    let addr = SocketAddr::new(IpAddr::V4(ip), port);
-                    TcpStream::connect_timeout(&addr, Duration::from_millis(50)).is_ok()
-                })
+        let mut listing = DirListing::default();
+        list_dir_for_zip(root, root, out, &mut listing)?;
+        let total_bytes = listing.total_bytes;
+        let total_files = listing.files.len();
+        println!("Zipping {} ({} files, {} bytes) -> {}", dir_path, total_files, total_bytes, out_path);
+        
+        let mut last_progress = 0u32;
+        let result = write_dir_zip(&listing, out, |bytes_done, files_done| {
+            let progress = if total_bytes == 0 {
+                100
             } else {
-                true
+                (bytes_done as f64 / total_bytes as f64 * 100.0) as u32
             };
-            
-            count.fetch_add(1, Ordering::Relaxed);
-            
-            if is_online || has_any_service {
-                let mut hosts = found.lock();
-                hosts.push(serde_json::json!({
-                    "ip": ip_str,
-                    "hasApp": is_online,
-                    "online": true
+            if progress >= last_progress + 5 || files_done == total_files {
+                let _ = app.emit("zip-progress", serde_json::json!({
+                    "dir_path": dir_path,
+                    "out_path": out_path,
+                    "bytes_done": bytes_done,
+                    "total_bytes": total_bytes,
+                    "files_done": files_done,
+                    "total_files": total_files,
+                    "progress": progress
                 }));
+                last_progress = progress;
             }
         });
         
-        handles.push(handle);
+        match result {
+            Ok(zip_size) => Ok(serde_json::json!({
+                "path": out_path,
+                "files": total_files,
+                "directories": listing.dirs.len(),
+                "total_bytes": total_bytes,
+                "zip_size": zip_size
+            })),
+            Err(e) => {
+                let _ = fs::remove_file(out);
+                Err(format!("Zip failed: {}", e))
+            }
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Find the partial `.tmp` for a transfer even if the user picked a different
+// save_dir (or temp_dir) since the previous session, and move it into the new one
+fn locate_partial_temp(transfer_id: &str, save_dir: &Path) -> PathBuf {
+    let temp_name = format!("{}.tmp", transfer_id);
+    let temp_path = save_dir.join(&temp_name);
+    
+    if !temp_path.exists() {
+        // A transfer still registered in this session knows exactly where its temp lives
+        let registered = TRANSFERS.lock()
+            .get(transfer_id)
+            .map(|state| PathBuf::from(&state.temp_path));
         
-        // Limit concurrent threads
-        if handles.len() >= 50 {
-            if let Some(h) = handles.pop() {
-                let _ = h.join();
+        let known_dirs = KNOWN_SAVE_DIRS.lock().clone();
+        let candidates = registered
+            .into_iter()
+            .chain(known_dirs.iter().map(|dir| dir.join(&temp_name)));
+        
+        for candidate in candidates {
+            if candidate == temp_path || !candidate.exists() {
+                continue;
+            }
+            
+            let moved = fs::rename(&candidate, &temp_path).is_ok()
+                || (fs::copy(&candidate, &temp_path).is_ok() && fs::remove_file(&candidate).is_ok());
+            
+            if moved {
+                println!("Resuming {} from previous dir: {}", transfer_id, candidate.display());
+                break;
             }
         }
     }
     
-    // Wait for all threads
-    for h in handles {
-        let _ = h.join();
+    let mut known_dirs = KNOWN_SAVE_DIRS.lock();
+    known_dirs.retain(|dir| dir.as_path() != save_dir);
+    known_dirs.insert(0, save_dir.to_path_buf());
+    known_dirs.truncate(MAX_KNOWN_SAVE_DIRS);
+    if let Some(file) = KNOWN_SAVE_DIRS_FILE.lock().as_ref() {
+        if let Err(e) = write_known_save_dirs(file, &known_dirs) {
+            eprintln!("Cannot save known save dirs to {}: {}", file.display(), e);
+        }
     }
     
-    let results = found_hosts.lock().clone();
-    println!("Scan complete: {} hosts found", results.len());
-    let _ = app.emit("scan-progress", serde_json::json!({ "status": "complete", "count": results.len() }));
-    
-    Ok(results)
+    temp_path
 }
 
-// ============== Wake-on-LAN ==============
-#[tauri::command]
-fn wake_on_lan(mac_address: String) -> Result<String, String> {
-    // Parse MAC address (formats: AA:BB:CC:DD:EE:FF or AA-BB-CC-DD-EE-FF)
-    let mac_str = mac_address.replace("-", ":").to_uppercase();
-    let mac_bytes: Vec<u8> = mac_str
-        .split(':')
-        .map(|s| u8::from_str_radix(s, 16))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|_| format!("Invalid MAC address: {}", mac_address))?;
-    
-    if mac_bytes.len() != 6 {
-        return Err(format!("MAC address must have 6 bytes, got {}", mac_bytes.len()));
+// Where a transfer's `.tmp` lives: temp_dir when given (fast local disk while
+// save_dir is a network share or USB stick), else save_dir as before
+fn resolve_temp_dir(save_dir: &Path, temp_dir: Option<&str>) -> Result<PathBuf, String> {
+    let dir = match temp_dir.map(str::trim).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => save_dir.to_path_buf(),
+    };
+    if !dir.exists() {
+        fs::create_dir_all(&dir).map_err(|e| format!("Cannot create temp dir {}: {}", dir.display(), e))?;
     }
-    
-    // Build magic packet: 6 bytes of 0xFF + MAC repeated 16 times
-    let mut magic_packet = vec![0xFFu8; 6];
-    for _ in 0..16 {
-        magic_packet.extend_from_slice(&mac_bytes);
+    Ok(dir)
+}
+
+// fs::rename, falling back to copy + delete when from and to are on different
+// mounts (EXDEV). The copy goes to a `.moving` sibling of `to` first and is then
+// renamed into place, so `to` never exists half-written.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    move_file_with(from, to, |from, to| fs::rename(from, to))
+}
+
+fn move_file_with(
+    from: &Path,
+    to: &Path,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>
+) -> std::io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let file_name = to.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+            let staging = to.with_file_name(format!("{}.moving", file_name));
+            
+            let copied = fs::copy(from, &staging)
+                .and_then(|_| File::open(&staging)?.sync_all())
+                .and_then(|_| fs::rename(&staging, to));
+            if let Err(e) = copied {
+                let _ = fs::remove_file(&staging);
+                return Err(e);
+            }
+            println!("Moved {} across devices by copying", to.display());
+            fs::remove_file(from)
+        }
+        result => result,
     }
+}
+
+// On hash mismatch move the temp aside as `{name}.corrupt` (or delete it) so the
+// next resume doesn't wrongly continue from bad data, and report both hashes
+fn handle_hash_mismatch(
+    app: &tauri::AppHandle,
+    transfer_id: &str,
+    temp_path: &Path,
+    final_path: &Path,
+    expected_hash: &str,
+    computed_hash: &str,
+    discard_on_mismatch: bool
+) -> String {
+    let corrupt_path = if discard_on_mismatch {
+        let _ = fs::remove_file(temp_path);
+        None
+    } else {
+        let file_name = final_path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+        let corrupt_path = final_path.with_file_name(format!("{}.corrupt", file_name));
+        match move_file(temp_path, &corrupt_path) {
+            Ok(()) => Some(corrupt_path),
+            Err(e) => {
+                eprintln!("Cannot move corrupt temp aside: {}", e);
+                let _ = fs::remove_file(temp_path);
+                None
+            }
+        }
+    };
     
-    // Send to broadcast address on port 9 (standard WOL port)
-    let socket = UdpSocket::bind("0.0.0.0:0")
-        .map_err(|e| format!("Cannot create socket: {}", e))?;
+    let _ = app.emit("file-transfer-hash-mismatch", serde_json::json!({
+        "transfer_id": transfer_id,
+        "expected_hash": expected_hash,
+        "computed_hash": computed_hash,
+        "corrupt_path": corrupt_path.as_ref().map(|p| p.to_string_lossy().to_string())
+    }));
     
-    socket.set_broadcast(true)
-        .map_err(|e| format!("Cannot enable broadcast: {}", e))?;
+    format!("Hash mismatch! Expected: {}, Got: {}", expected_hash, computed_hash)
+}
+
+fn chrono_lite_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Deflate a chunk, but only report it when it actually got smaller
+fn deflate_chunk(data: &[u8]) -> Option<Vec<u8>> {
+    use flate2::write::DeflateEncoder;
     
-    // Send to multiple broadcast addresses for better compatibility
-    let broadcasts = ["255.255.255.255:9", "255.255.255.255:7"];
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), flate2::Compression::fast());
+    encoder.write_all(data).ok()?;
+    let compressed = encoder.finish().ok()?;
     
-    for addr in broadcasts {
-        if let Err(e) = socket.send_to(&magic_packet, addr) {
-            println!("WOL send to {} failed: {}", addr, e);
-        }
+    if compressed.len() < data.len() {
+        Some(compressed)
+    } else {
+        None
     }
+}
+
+fn inflate_chunk(data: &[u8]) -> Result<Vec<u8>, String> {
+    use flate2::read::DeflateDecoder;
     
-    // Also try subnet broadcast
-    if let Ok(local_ip) = local_ip_address::local_ip() {
-        if let IpAddr::V4(ip) = local_ip {
-            let octets = ip.octets();
-            let subnet_broadcast = format!("{}.{}.{}.255:9", octets[0], octets[1], octets[2]);
-            let _ = socket.send_to(&magic_packet, &subnet_broadcast);
-        }
+    // A chunk never inflates past CHUNK_SIZE; read one more byte to detect bogus input
+    let mut out = Vec::with_capacity(CHUNK_SIZE);
+    DeflateDecoder::new(data)
+        .take(CHUNK_SIZE as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| format!("Inflate error: {}", e))?;
+    
+    if out.len() > CHUNK_SIZE {
+        return Err(format!("Inflated chunk exceeds {} bytes", CHUNK_SIZE));
     }
     
-    println!("WOL packet sent to {}", mac_address);
-    Ok(format!("Wake-on-LAN packet sent to {}", mac_address))
+    Ok(out)
 }
 
-// ============== Get Local Network Info ==============
+// Admin: Read a specific chunk from file
+// compress = deflate before base64 when it shrinks the chunk; chunk_hash is always over the raw bytes
 #[tauri::command]
-fn get_network_info() -> Result<serde_json::Value, String> {
-    let local_ip = local_ip_address::local_ip()
-        .map_err(|e| format!("Cannot get local IP: {}", e))?;
+fn read_file_chunk(file_path: String, chunk_index: u32, compress: Option<bool>) -> Result<serde_json::Value, String> {
+    let mut file = File::open(&file_path).map_err(|e| e.to_string())?;
+    let offset = chunk_index as u64 * CHUNK_SIZE as u64;
     
-    let mac = mac_address::get_mac_address()
-        .map_err(|e| format!("Cannot get MAC: {}", e))?
-        .map(|m| m.to_string())
-        .unwrap_or_else(|| "unknown".to_string());
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+    buffer.truncate(bytes_read);
+    
+    let chunk_hash = hex::encode(Sha256::digest(&buffer));
+    let deflated = if compress.unwrap_or(false) { deflate_chunk(&buffer) } else { None };
+    let compressed = deflated.is_some();
+    let data_base64 = general_purpose::STANDARD.encode(deflated.as_deref().unwrap_or(&buffer));
     
     Ok(serde_json::json!({
-        "ip": local_ip.to_string(),
-        "mac": mac
+        "chunk_index": chunk_index,
+        "data": data_base64,
+        "size": bytes_read,
+        "compressed": compressed,
+        "chunk_hash": chunk_hash
     }))
 }
 
-// ============== File Transfer with Chunk + Resume ==============
-use sha2::{Sha256, Digest};
-use std::fs::{self, File};
-use std::io::{Read, Write, Seek, SeekFrom};
-use std::path::PathBuf;
-use std::collections::HashMap;
-
-const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
+// sha256 of each CHUNK_SIZE chunk, the same hash read_file_chunk reports
+fn chunk_hashes(reader: &mut impl Read) -> std::io::Result<Vec<String>> {
+    let mut hashes = Vec::new();
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        buffer.clear();
+        reader.take(CHUNK_SIZE as u64).read_to_end(&mut buffer)?;
+        if buffer.is_empty() {
+            return Ok(hashes);
+        }
+        hashes.push(hex::encode(Sha256::digest(&buffer)));
+    }
+}
 
-lazy_static::lazy_static! {
-    // Track ongoing transfers: transfer_id -> TransferState
-    static ref TRANSFERS: Mutex<HashMap<String, TransferState>> = Mutex::new(HashMap::new());
+// Admin: per-chunk hash manifest for the receiver's verify_partial_transfer
+#[tauri::command]
+async fn get_chunk_manifest(file_path: String) -> Result<serde_json::Value, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut file = BufReader::new(File::open(&file_path).map_err(|e| e.to_string())?);
+        let hashes = chunk_hashes(&mut file).map_err(|e| e.to_string())?;
+        Ok(serde_json::json!({
+            "chunk_size": CHUNK_SIZE,
+            "chunk_hashes": hashes
+        }))
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-#[derive(Clone, serde::Serialize)]
-struct TransferState {
-    file_name: String,
-    file_size: u64,
-    total_chunks: u32,
-    received_chunks: u32,
-    file_hash: String,
-    temp_path: String,
-    completed: bool,
+// Chunks among the first `chunks` whose bytes don't match the manifest,
+// including ones cut short by an interrupted write
+fn find_corrupt_chunks<F: Read + Seek>(file: &mut F, chunks: u32, manifest: &[String]) -> Result<Vec<u32>, String> {
+    let mut corrupt = Vec::new();
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+    for (index, expected) in (0..chunks).zip(manifest) {
+        file.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64)).map_err(|e| e.to_string())?;
+        buffer.clear();
+        file.by_ref().take(CHUNK_SIZE as u64).read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+        if !hex::encode(Sha256::digest(&buffer)).eq_ignore_ascii_case(expected) {
+            corrupt.push(index);
+        }
+    }
+    Ok(corrupt)
 }
 
-// Admin: Read file and prepare for transfer
+// Client: before resuming, re-check every chunk already in the `.tmp` against
+// the sender's get_chunk_manifest. Failed chunks are marked invalid (see
+// get_transfer_status) and listed in resend_chunks; they must be sent again
+// before finalize_file_transfer, alongside the usual resume from resume_from.
 #[tauri::command]
-fn prepare_file_transfer(file_path: String) -> Result<serde_json::Value, String> {
-    let path = PathBuf::from(&file_path);
-    
-    if !path.exists() {
-        return Err(format!("File not found: {}", file_path));
-    }
-    
-    let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
-    let file_size = metadata.len();
-    let file_name = path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
-        .to_string();
+async fn verify_partial_transfer(
+    app: tauri::AppHandle,
+    transfer_id: String,
+    chunk_hashes: Vec<String>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::FileTransfer, "verify_partial_transfer")?;
+    let (temp_path, received) = {
+        let transfers = TRANSFERS.lock();
+        let state = transfers.get(&transfer_id)
+            .ok_or_else(|| format!("Transfer not found: {}", transfer_id))?;
+        if chunk_hashes.len() != state.total_chunks as usize {
+            return Err(format!("Manifest has {} chunk hashes, transfer has {} chunks", chunk_hashes.len(), state.total_chunks));
+        }
+        (state.temp_path.clone(), state.received_chunks)
+    };
     
-    // Calculate file hash
-    let mut file = File::open(&path).map_err(|e| e.to_string())?;
-    let mut hasher = Sha256::new();
-    let mut buffer = vec![0u8; CHUNK_SIZE];
+    // Without holding TRANSFERS, the whole file may take a while
+    let corrupt = tauri::async_runtime::spawn_blocking(move || {
+        let mut file = File::open(&temp_path).map_err(|e| e.to_string())?;
+        find_corrupt_chunks(&mut file, received, &chunk_hashes)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
     
-    loop {
-        let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
-        if bytes_read == 0 { break; }
-        hasher.update(&buffer[..bytes_read]);
+    let mut transfers = TRANSFERS.lock();
+    let state = transfers.get_mut(&transfer_id)
+        .ok_or_else(|| format!("Transfer not found: {}", transfer_id))?;
+    state.invalid_chunks.extend(&corrupt);
+    if !corrupt.is_empty() {
+        println!("Transfer {}: {} of {} written chunks failed verification", transfer_id, corrupt.len(), received);
     }
     
-    let file_hash = hex::encode(hasher.finalize());
-    let total_chunks = ((file_size as usize + CHUNK_SIZE - 1) / CHUNK_SIZE) as u32;
-    let transfer_id = format!("{}_{}", file_hash[..16].to_string(), chrono_lite_timestamp());
-    
     Ok(serde_json::json!({
         "transfer_id": transfer_id,
-        "file_name": file_name,
-        "file_path": file_path,
-        "file_size": file_size,
-        "total_chunks": total_chunks,
-        "file_hash": file_hash,
-        "chunk_size": CHUNK_SIZE
+        "verified_chunks": received as usize - corrupt.len(),
+        "corrupt_chunks": corrupt,
+        "resend_chunks": state.invalid_chunks,
+        "resume_from": state.received_chunks
     }))
 }
 
-fn chrono_lite_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
+// Largest range read_file_range returns in one call (base64 grows it by a third)
+const MAX_RANGE_LENGTH: u64 = 8 * 1024 * 1024;
+
+// Exactly `length` bytes at `offset`; the range must lie inside the file
+fn read_range(file: &mut File, offset: u64, length: u64) -> Result<Vec<u8>, String> {
+    if length == 0 || length > MAX_RANGE_LENGTH {
+        return Err(format!("length must be between 1 and {} bytes (got {})", MAX_RANGE_LENGTH, length));
+    }
+    let file_size = file.metadata().map_err(|e| e.to_string())?.len();
+    if offset.checked_add(length).is_none_or(|end| end > file_size) {
+        return Err(format!("Range {}+{} is outside the file ({} bytes)", offset, length, file_size));
+    }
+    
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    let mut buffer = vec![0u8; length as usize];
+    file.read_exact(&mut buffer).map_err(|e| e.to_string())?;
+    Ok(buffer)
 }
 
-// Admin: Read a specific chunk from file
+// Admin: Read an arbitrary byte range, for byte-accurate resume and parallel range downloads
+// range_hash is sha256 of the returned bytes
 #[tauri::command]
-fn read_file_chunk(file_path: String, chunk_index: u32) -> Result<serde_json::Value, String> {
+fn read_file_range(file_path: String, offset: u64, length: u64) -> Result<serde_json::Value, String> {
     let mut file = File::open(&file_path).map_err(|e| e.to_string())?;
-    let offset = chunk_index as u64 * CHUNK_SIZE as u64;
-    
-    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
-    
-    let mut buffer = vec![0u8; CHUNK_SIZE];
-    let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
-    buffer.truncate(bytes_read);
-    
-    let data_base64 = general_purpose::STANDARD.encode(&buffer);
+    let buffer = read_range(&mut file, offset, length)?;
     
     Ok(serde_json::json!({
-        "chunk_index": chunk_index,
-        "data": data_base64,
-        "size": bytes_read
+        "offset": offset,
+        "size": buffer.len(),
+        "data": general_purpose::STANDARD.encode(&buffer),
+        "range_hash": hex::encode(Sha256::digest(&buffer))
     }))
 }
 
 // Client: Initialize file receive
+// algorithm must match the one prepare_file_transfer used (default sha256)
+// temp_dir: where the `.tmp` grows until finalize moves it into save_dir (default save_dir)
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn init_file_receive(
     app: tauri::AppHandle,
     transfer_id: String,
@@ -903,14 +8908,19 @@ fn init_file_receive(
     file_size: u64,
     total_chunks: u32,
     file_hash: String,
-    save_dir: String
+    save_dir: String,
+    algorithm: Option<String>,
+    temp_dir: Option<String>
 ) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::FileTransfer, "init_file_receive")?;
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
     let save_path = PathBuf::from(&save_dir);
     if !save_path.exists() {
         fs::create_dir_all(&save_path).map_err(|e| e.to_string())?;
     }
+    let temp_dir = resolve_temp_dir(&save_path, temp_dir.as_deref())?;
     
-    let temp_path = save_path.join(format!("{}.tmp", transfer_id));
+    let temp_path = locate_partial_temp(&transfer_id, &temp_dir);
     
     // Check if we have partial transfer (for resume)
     let received_chunks = if temp_path.exists() {
@@ -930,8 +8940,11 @@ fn init_file_receive(
         total_chunks,
         received_chunks,
         file_hash,
+        algorithm,
         temp_path: temp_path.to_string_lossy().to_string(),
         completed: false,
+        invalid_chunks: std::collections::BTreeSet::new(),
+        started: Instant::now(),
     };
     
     TRANSFERS.lock().insert(transfer_id.clone(), state.clone());
@@ -955,16 +8968,31 @@ fn receive_file_chunk(
     app: tauri::AppHandle,
     transfer_id: String,
     chunk_index: u32,
-    data: String // base64
+    data: String, // base64
+    compressed: Option<bool>,
+    chunk_hash: Option<String>
 ) -> Result<serde_json::Value, String> {
+    // Checked per chunk too, so revoking file_transfer stops a transfer in progress
+    require_permission(&app, Permission::FileTransfer, "receive_file_chunk")?;
     let mut transfers = TRANSFERS.lock();
     let state = transfers.get_mut(&transfer_id)
         .ok_or_else(|| format!("Transfer not found: {}", transfer_id))?;
     
     // Decode chunk data
-    let chunk_data = general_purpose::STANDARD.decode(&data)
+    let mut chunk_data = general_purpose::STANDARD.decode(&data)
         .map_err(|e| format!("Base64 decode error: {}", e))?;
     
+    if compressed.unwrap_or(false) {
+        chunk_data = inflate_chunk(&chunk_data)?;
+    }
+    
+    if let Some(expected) = chunk_hash {
+        let computed = hex::encode(Sha256::digest(&chunk_data));
+        if !computed.eq_ignore_ascii_case(&expected) {
+            return Err(format!("Chunk {} hash mismatch! Expected: {}, Got: {}", chunk_index, expected, computed));
+        }
+    }
+    
     // Write chunk to temp file
     let mut file = fs::OpenOptions::new()
         .write(true)
@@ -975,7 +9003,9 @@ fn receive_file_chunk(
     file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
     file.write_all(&chunk_data).map_err(|e| e.to_string())?;
     
-    state.received_chunks = chunk_index + 1;
+    // A re-sent chunk (see verify_partial_transfer) must not move the resume point back
+    state.received_chunks = state.received_chunks.max(chunk_index + 1);
+    state.invalid_chunks.remove(&chunk_index);
     
     let progress = (state.received_chunks as f64 / state.total_chunks as f64 * 100.0) as u32;
     
@@ -999,34 +9029,41 @@ fn receive_file_chunk(
 fn finalize_file_transfer(
     app: tauri::AppHandle,
     transfer_id: String,
-    save_dir: String
+    save_dir: String,
+    discard_on_mismatch: Option<bool>
 ) -> Result<serde_json::Value, String> {
     let mut transfers = TRANSFERS.lock();
     let state = transfers.get_mut(&transfer_id)
         .ok_or_else(|| format!("Transfer not found: {}", transfer_id))?;
+    if !state.invalid_chunks.is_empty() {
+        return Err(format!("{} chunk(s) still need to be re-sent: {:?}", state.invalid_chunks.len(), state.invalid_chunks));
+    }
     
     // Verify file hash
-    let mut file = File::open(&state.temp_path).map_err(|e| e.to_string())?;
-    let mut hasher = Sha256::new();
-    let mut buffer = vec![0u8; CHUNK_SIZE];
-    
-    loop {
-        let bytes_read = file.read(&mut buffer).map_err(|e| e.to_string())?;
-        if bytes_read == 0 { break; }
-        hasher.update(&buffer[..bytes_read]);
-    }
+    let computed_hash = hash_file(Path::new(&state.temp_path), state.algorithm)?;
     
-    let computed_hash = hex::encode(hasher.finalize());
+    let final_path = PathBuf::from(&save_dir).join(&state.file_name);
     
     if computed_hash != state.file_hash {
-        return Err(format!("Hash mismatch! Expected: {}, Got: {}", state.file_hash, computed_hash));
+        let error = handle_hash_mismatch(
+            &app,
+            &transfer_id,
+            Path::new(&state.temp_path),
+            &final_path,
+            &state.file_hash,
+            &computed_hash,
+            discard_on_mismatch.unwrap_or(false)
+        );
+        record_transfer(&transfer_id, &state.file_name, state.file_size, "receive", state.started, TransferOutcome::Failed(error.clone()));
+        transfers.remove(&transfer_id);
+        return Err(error);
     }
     
     // Rename temp file to final name
-    let final_path = PathBuf::from(&save_dir).join(&state.file_name);
-    fs::rename(&state.temp_path, &final_path).map_err(|e| e.to_string())?;
+    move_file(Path::new(&state.temp_path), &final_path).map_err(|e| e.to_string())?;
     
     state.completed = true;
+    record_transfer(&transfer_id, &state.file_name, state.file_size, "receive", state.started, TransferOutcome::Completed);
     
     let _ = app.emit("file-transfer-complete", serde_json::json!({
         "transfer_id": transfer_id,
@@ -1054,6 +9091,7 @@ fn get_transfer_status(transfer_id: String) -> Result<serde_json::Value, String>
             "found": true,
             "received_chunks": state.received_chunks,
             "total_chunks": state.total_chunks,
+            "invalid_chunks": state.invalid_chunks,
             "completed": state.completed
         }))
     } else {
@@ -1071,111 +9109,550 @@ fn cancel_file_transfer(transfer_id: String) -> Result<(), String> {
     if let Some(state) = transfers.remove(&transfer_id) {
         // Delete temp file
         let _ = fs::remove_file(&state.temp_path);
+        record_transfer(&transfer_id, &state.file_name, state.file_size, "receive", state.started, TransferOutcome::Cancelled);
+    }
+    
+    Ok(())
+}
+
+// ============== Direct TCP File Transfer ==============
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::io::{BufReader, BufWriter};
+
+const TCP_FILE_PORT: u16 = 3003;
+const TCP_CHUNK_SIZE: usize = 256 * 1024; // 256KB for TCP (larger than UDP)
+const TCP_MIN_BUFFER_SIZE: usize = 64 * 1024;
+const TCP_MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+const TCP_DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+const TCP_DEFAULT_MAX_RETRIES: u32 = 3;
+const TCP_RECONNECT_WAIT: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref TCP_SERVER_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref TCP_TRANSFER_ACTIVE: AtomicBool = AtomicBool::new(false);
+    // Set by emergency_stop; the copy loops bail out with ConnectionAborted.
+    // Cleared whenever a new transfer or server starts.
+    static ref TCP_TRANSFER_ABORT: AtomicBool = AtomicBool::new(false);
+    // The server holding TCP_SERVER_RUNNING, for start_tcp_file_server's already_running
+    static ref TCP_SERVER_INFO: Mutex<Option<TcpServerInfo>> = Mutex::new(None);
+    static ref TCP_SERVER_BYTES: AtomicU64 = AtomicU64::new(0);
+}
+
+struct TcpServerInfo {
+    transfer_id: String,
+    file_name: String,
+    port: u16,
+    started: Instant,
+}
+
+fn set_tcp_server_info(transfer_id: &str, file_name: &str, port: u16) {
+    TCP_SERVER_BYTES.store(0, Ordering::Relaxed);
+    *TCP_SERVER_INFO.lock() = Some(TcpServerInfo {
+        transfer_id: transfer_id.to_string(),
+        file_name: file_name.to_string(),
+        port,
+        started: Instant::now(),
+    });
+}
+
+// What the receiving side knows about an incoming TCP transfer
+struct TcpReceiveJob {
+    transfer_id: String,
+    file_name: String,
+    file_size: u64,
+    file_hash: String,
+    algorithm: HashAlgorithm,
+    save_dir: String,
+    temp_dir: PathBuf, // holds the `.tmp` until the final move into save_dir
+    discard_on_mismatch: bool,
+    read_timeout: Duration,
+    max_retries: u32, // reconnects allowed after a stall
+}
+
+// How one connection's worth of receiving ended
+enum TcpReceiveOutcome {
+    Complete,
+    // Timed out or dropped mid-file; the temp file holds bytes_received for resume
+    Stalled { bytes_received: u64, reason: String },
+}
+
+// Sender-side tunables for the bulk copy
+struct TcpSendOptions {
+    buffer_size: usize,
+    zero_copy: bool, // sendfile/TransmitFile where available
+}
+
+impl Default for TcpSendOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: TCP_CHUNK_SIZE,
+            zero_copy: true,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+struct TcpTransferProgress {
+    transfer_id: String,
+    bytes_transferred: u64,
+    total_bytes: u64,
+    progress: u32,
+    // Sender is holding back for a live stream or the bandwidth budget (always false when receiving)
+    throttled: bool,
+}
+
+// While this machine streams video, TCP sends are paced to link_mbps - reserve_mbps
+// so a file transfer can't starve the stream; full speed comes back when it stops
+#[derive(Debug, Clone, Copy)]
+struct TransferYield {
+    enabled: bool,
+    link_mbps: u32,
+    reserve_mbps: u32,
+}
+
+impl Default for TransferYield {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            link_mbps: 100,
+            reserve_mbps: 20,
+        }
+    }
+}
+
+impl TransferYield {
+    fn limit_bytes_per_sec(&self) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        let mbps = self.link_mbps.saturating_sub(self.reserve_mbps).max(1);
+        Some(mbps as u64 * 125_000)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TRANSFER_YIELD: Mutex<TransferYield> = Mutex::new(TransferYield::default());
+    static ref TCP_SEND_THROTTLED: AtomicBool = AtomicBool::new(false);
+}
+
+// Send rate cap for right now: the stream yield while a stream is going out,
+// and the files share of the bandwidth budget, whichever is lower
+fn transfer_rate_limit() -> Option<u64> {
+    let yield_limit = if stream_running(StreamId::Main) || DELTA_STREAMING.load(Ordering::Relaxed) {
+        TRANSFER_YIELD.lock().limit_bytes_per_sec()
+    } else {
+        None
+    };
+    let budget_limit = bandwidth_allocation().map(|allocation| allocation.files_bps / 8);
+    yield_limit.into_iter().chain(budget_limit).min()
+}
+
+// How long to wait so that `bytes` sent since the pacing window opened `elapsed` ago
+// stay at or under `rate` bytes/s
+fn pacing_delay(bytes: u64, elapsed: Duration, rate: u64) -> Duration {
+    Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64).saturating_sub(elapsed)
+}
+
+// link_mbps: assumed link capacity (default 100); reserve_mbps: left for the stream (default 20)
+#[tauri::command]
+fn set_transfer_stream_yield(
+    enabled: Option<bool>,
+    link_mbps: Option<u32>,
+    reserve_mbps: Option<u32>
+) -> Result<serde_json::Value, String> {
+    let mut config = *TRANSFER_YIELD.lock();
+    if let Some(v) = enabled {
+        config.enabled = v;
+    }
+    if let Some(v) = link_mbps {
+        config.link_mbps = v;
+    }
+    if let Some(v) = reserve_mbps {
+        config.reserve_mbps = v;
+    }
+    if config.link_mbps == 0 || config.reserve_mbps >= config.link_mbps {
+        return Err(format!(
+            "reserve_mbps ({}) must be below link_mbps ({})",
+            config.reserve_mbps, config.link_mbps
+        ));
+    }
+    *TRANSFER_YIELD.lock() = config;
+    
+    Ok(serde_json::json!({
+        "enabled": config.enabled,
+        "link_mbps": config.link_mbps,
+        "reserve_mbps": config.reserve_mbps,
+        "throttled": TCP_SEND_THROTTLED.load(Ordering::Relaxed)
+    }))
+}
+
+// ============== Bandwidth Budget ==============
+// One cap on everything this machine sends. Subsystems are served in priority
+// order (interactive video, then audio, then files), each up to what it asks
+// for; files get the rest, never less than BUDGET_FILES_FLOOR_BPS so a transfer
+// keeps moving. Audio is not governed: nothing streams it in this build, so
+// audio_demand_bps() is 0 and its share always goes to files.
+const BUDGET_MIN_BPS: u64 = 256_000;
+const BUDGET_FILES_FLOOR_BPS: u64 = 64_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BandwidthAllocation {
+    video_bps: u64,
+    audio_bps: u64,
+    files_bps: u64,
+}
+
+lazy_static::lazy_static! {
+    // None = no budget, every subsystem uses its own limits
+    static ref BANDWIDTH_BUDGET: Mutex<Option<u64>> = Mutex::new(None);
+}
+
+fn allocate_bandwidth(budget_bps: u64, video_demand_bps: u64, audio_demand_bps: u64) -> BandwidthAllocation {
+    let mut remaining = budget_bps.saturating_sub(BUDGET_FILES_FLOOR_BPS);
+    let video_bps = video_demand_bps.min(remaining);
+    remaining -= video_bps;
+    let audio_bps = audio_demand_bps.min(remaining);
+    BandwidthAllocation {
+        video_bps,
+        audio_bps,
+        files_bps: budget_bps - video_bps - audio_bps,
+    }
+}
+
+// What the H.264 stream asks for: its target bitrate, or what it has been
+// averaging in qp mode, where the bitrate floats and can't be capped
+fn video_demand_bps() -> u64 {
+    if !stream_running(StreamId::Main) {
+        return 0;
+    }
+    match *STREAM_QUALITY.lock() {
+        QualityMode::Bitrate(bps) => bps as u64,
+        QualityMode::ConstantQp(_) => (stream_avg_bitrate_kbps() * 1000.0) as u64,
+    }
+}
+
+// Hook for an audio sender to report its bitrate; there is none yet (cpal is
+// only used to list devices)
+fn audio_demand_bps() -> u64 {
+    0
+}
+
+fn bandwidth_allocation() -> Option<BandwidthAllocation> {
+    let budget = (*BANDWIDTH_BUDGET.lock())?;
+    Some(allocate_bandwidth(budget, video_demand_bps(), audio_demand_bps()))
+}
+
+// The running stream's rate control, else what the next start_stream will use
+fn active_quality() -> QualityMode {
+    if stream_running(StreamId::Main) {
+        *STREAM_QUALITY.lock()
+    } else {
+        *QUALITY_MODE.lock()
     }
-    
-    Ok(())
 }
 
-// ============== Direct TCP File Transfer ==============
-use std::net::{TcpListener, TcpStream, SocketAddr};
-use std::io::{BufReader, BufWriter};
+// Encoder settings for the stream under the current budget
+fn budgeted_quality(requested: QualityMode) -> QualityMode {
+    match (requested, *BANDWIDTH_BUDGET.lock()) {
+        (QualityMode::Bitrate(bps), Some(budget)) => {
+            let allocation = allocate_bandwidth(budget, bps as u64, audio_demand_bps());
+            QualityMode::Bitrate(allocation.video_bps as u32)
+        }
+        _ => requested,
+    }
+}
 
-const TCP_FILE_PORT: u16 = 3003;
-const TCP_CHUNK_SIZE: usize = 256 * 1024; // 256KB for TCP (larger than UDP)
+fn bandwidth_budget_json() -> serde_json::Value {
+    let allocation = bandwidth_allocation();
+    serde_json::json!({
+        "budget_bps": *BANDWIDTH_BUDGET.lock(),
+        "video_demand_bps": video_demand_bps(),
+        "audio_demand_bps": audio_demand_bps(),
+        "video_bps": allocation.map(|a| a.video_bps),
+        "audio_bps": allocation.map(|a| a.audio_bps),
+        "files_bps": allocation.map(|a| a.files_bps),
+        "video_capped": matches!(active_quality(), QualityMode::Bitrate(_)),
+        "files_throttled": TCP_SEND_THROTTLED.load(Ordering::Relaxed)
+    })
+}
 
-lazy_static::lazy_static! {
-    static ref TCP_SERVER_RUNNING: AtomicBool = AtomicBool::new(false);
-    static ref TCP_TRANSFER_ACTIVE: AtomicBool = AtomicBool::new(false);
+// bps: total send rate for video + audio + files (0 = no budget). A running
+// stream switches bitrate within a second; transfers follow on their next chunk.
+// No audio is sent in this build, so in practice the budget splits video and files.
+#[tauri::command]
+fn set_total_bandwidth_budget(bps: u64) -> Result<serde_json::Value, String> {
+    if bps != 0 && bps < BUDGET_MIN_BPS {
+        return Err(format!("Bandwidth budget must be 0 (off) or at least {} bps (got {})", BUDGET_MIN_BPS, bps));
+    }
+    *BANDWIDTH_BUDGET.lock() = (bps != 0).then_some(bps);
+    println!("Bandwidth budget: {}", if bps == 0 { "off".to_string() } else { format!("{} kbps", bps / 1000) });
+    Ok(bandwidth_budget_json())
 }
 
-#[derive(Clone, serde::Serialize)]
-struct TcpTransferProgress {
-    transfer_id: String,
-    bytes_transferred: u64,
-    total_bytes: u64,
-    progress: u32,
+// Per-subsystem share of the budget right now (the *_bps shares are null without one)
+#[tauri::command]
+fn get_bandwidth_allocation() -> serde_json::Value {
+    bandwidth_budget_json()
 }
 
 // Client: Start TCP server to receive file
+// bind_addr defaults to 0.0.0.0, port defaults to TCP_FILE_PORT (0 = OS-assigned)
+// On a stall the server keeps listening for the sender to reconnect and resume, up to max_retries (default 3)
+// algorithm: hash used for file_hash, as returned by prepare_file_transfer (default sha256)
+// temp_dir: where the `.tmp` grows until it is moved into save_dir (default save_dir)
+// Returns { status: "started", port }, or { status: "already_running", running } with the
+// current server's transfer and progress
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn start_tcp_file_server(
     app: tauri::AppHandle,
     transfer_id: String,
     file_name: String,
     file_size: u64,
     file_hash: String,
-    save_dir: String
-) -> Result<u16, String> {
+    algorithm: Option<String>,
+    save_dir: String,
+    discard_on_mismatch: Option<bool>,
+    bind_addr: Option<String>,
+    port: Option<u16>,
+    read_timeout_secs: Option<u64>,
+    max_retries: Option<u32>,
+    temp_dir: Option<String>
+) -> Result<serde_json::Value, String> {
+    require_permission(&app, Permission::FileTransfer, "start_tcp_file_server")?;
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
+    // With a separate temp_dir nothing else would create save_dir before the final move
+    fs::create_dir_all(&save_dir).map_err(|e| format!("Cannot create save dir {}: {}", save_dir, e))?;
+    let temp_dir = resolve_temp_dir(Path::new(&save_dir), temp_dir.as_deref())?;
+    let bind_ip: IpAddr = match bind_addr.as_deref() {
+        Some(addr) => addr.trim().parse()
+            .map_err(|_| format!("Invalid bind address: {}", addr))?,
+        None => IpAddr::from([0, 0, 0, 0]),
+    };
+    let requested_port = port.unwrap_or(TCP_FILE_PORT);
+    let bind_to = SocketAddr::new(bind_ip, requested_port);
+    
     if TCP_SERVER_RUNNING.swap(true, Ordering::SeqCst) {
-        return Err("TCP server already running".to_string());
+        let info = TCP_SERVER_INFO.lock();
+        return Ok(already_running(serde_json::json!({
+            "transfer_id": info.as_ref().map(|i| i.transfer_id.clone()),
+            "file_name": info.as_ref().map(|i| i.file_name.clone()),
+            "port": info.as_ref().map(|i| i.port),
+            "uptime_secs": uptime_secs(info.as_ref().map(|i| i.started)),
+            "bytes_received": TCP_SERVER_BYTES.load(Ordering::Relaxed),
+            "transfer_active": TCP_TRANSFER_ACTIVE.load(Ordering::SeqCst)
+        })));
     }
+    TCP_TRANSFER_ABORT.store(false, Ordering::SeqCst);
     
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", TCP_FILE_PORT))
+    let listener = TcpListener::bind(bind_to)
         .map_err(|e| {
             TCP_SERVER_RUNNING.store(false, Ordering::SeqCst);
-            format!("Cannot bind TCP port {}: {}", TCP_FILE_PORT, e)
+            if e.kind() == std::io::ErrorKind::AddrInUse {
+                format!("Address in use: {} is already bound by another process", bind_to)
+            } else {
+                format!("Cannot bind TCP {}: {}", bind_to, e)
+            }
         })?;
     
-    let port = listener.local_addr().map(|a| a.port()).unwrap_or(TCP_FILE_PORT);
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or(requested_port);
     
     println!("TCP file server started on port {}", port);
+    register_listening_port("tcp-file-server", "TCP", port, "Direct file transfer");
+    set_tcp_server_info(&transfer_id, &file_name, port);
+    
+    let job = TcpReceiveJob {
+        transfer_id,
+        file_name,
+        file_size,
+        file_hash,
+        algorithm,
+        save_dir,
+        temp_dir,
+        discard_on_mismatch: discard_on_mismatch.unwrap_or(false),
+        read_timeout: Duration::from_secs(read_timeout_secs.unwrap_or(TCP_DEFAULT_READ_TIMEOUT_SECS).max(1)),
+        max_retries: max_retries.unwrap_or(TCP_DEFAULT_MAX_RETRIES),
+    };
     
     thread::spawn(move || {
-        // Set timeout for accept
         let _ = listener.set_nonblocking(false);
         
-        // Wait for connection (timeout 60s)
-        let accept_result = listener.accept();
+        // First connection: wait as long as it takes
+        let mut accept_result = accept_permitted(&app, &listener);
+        let started = Instant::now();
+        let mut attempt = 0u32;
         
-        match accept_result {
-            Ok((stream, addr)) => {
-                println!("TCP connection from: {}", addr);
-                
-                if let Err(e) = receive_file_via_tcp(
-                    &app,
-                    stream,
-                    &transfer_id,
-                    &file_name,
-                    file_size,
-                    &file_hash,
-                    &save_dir
-                ) {
+        let outcome = loop {
+            let (stream, addr) = match accept_result {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("TCP accept error: {}", e);
+                    let error = format!("Accept failed: {}", e);
+                    let _ = app.emit("tcp-transfer-error", serde_json::json!({
+                        "transfer_id": job.transfer_id,
+                        "error": error
+                    }));
+                    break TransferOutcome::Failed(error);
+                }
+            };
+            
+            println!("TCP connection from: {}", addr);
+            
+            match receive_file_via_tcp(&app, stream, &job) {
+                Ok(TcpReceiveOutcome::Complete) => break TransferOutcome::Completed,
+                Ok(TcpReceiveOutcome::Stalled { bytes_received, reason }) => {
+                    if attempt >= job.max_retries {
+                        let error = format!("{} - gave up after {} retries, can resume", reason, job.max_retries);
+                        let _ = app.emit("tcp-transfer-error", serde_json::json!({
+                            "transfer_id": job.transfer_id,
+                            "error": error
+                        }));
+                        break TransferOutcome::Failed(error);
+                    }
+                    attempt += 1;
+                    
+                    eprintln!("TCP transfer stalled ({}), waiting for reconnect {}/{}", reason, attempt, job.max_retries);
+                    let _ = app.emit("tcp-transfer-stalled", serde_json::json!({
+                        "transfer_id": job.transfer_id,
+                        "bytes_received": bytes_received,
+                        "total_bytes": job.file_size,
+                        "attempt": attempt,
+                        "max_retries": job.max_retries,
+                        "reason": reason
+                    }));
+                    
+                    accept_result = accept_with_timeout(&app, &listener, TCP_RECONNECT_WAIT);
+                    if accept_result.is_ok() {
+                        let _ = app.emit("tcp-transfer-resumed", serde_json::json!({
+                            "transfer_id": job.transfer_id,
+                            "resume_offset": bytes_received,
+                            "attempt": attempt
+                        }));
+                    }
+                }
+                Err(e) => {
                     eprintln!("TCP receive error: {}", e);
                     let _ = app.emit("tcp-transfer-error", serde_json::json!({
-                        "transfer_id": transfer_id,
+                        "transfer_id": job.transfer_id,
                         "error": e
                     }));
+                    break TransferOutcome::Failed(e);
                 }
             }
-            Err(e) => {
-                eprintln!("TCP accept error: {}", e);
-                let _ = app.emit("tcp-transfer-error", serde_json::json!({
-                    "transfer_id": transfer_id,
-                    "error": format!("Accept failed: {}", e)
-                }));
-            }
-        }
+        };
+        record_transfer(&job.transfer_id, &job.file_name, job.file_size, "receive", started, outcome);
         
+        *TCP_SERVER_INFO.lock() = None;
         TCP_SERVER_RUNNING.store(false, Ordering::SeqCst);
+        unregister_listening_port("tcp-file-server");
         println!("TCP file server stopped");
     });
     
-    Ok(port)
+    Ok(serde_json::json!({ "status": "started", "port": port }))
+}
+
+// Wait as long as it takes for the first sender, turning away peers outside the
+// allow/deny lists. Polls so stop_tcp_file_server / emergency_stop end the wait.
+fn accept_permitted(app: &tauri::AppHandle, listener: &TcpListener) -> std::io::Result<(TcpStream, SocketAddr)> {
+    accept_polling(app, listener, &TCP_SERVER_RUNNING, "tcp-file-server", None)
+}
+
+// Wait for a reconnecting sender; gives up early if the server is stopped.
+// Disallowed peers are dropped without ending the wait.
+fn accept_with_timeout(
+    app: &tauri::AppHandle,
+    listener: &TcpListener,
+    timeout: Duration
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    accept_polling(app, listener, &TCP_SERVER_RUNNING, "tcp-file-server", Some(Instant::now() + timeout))
+}
+
+// running: the owning server's flag; clearing it ends the wait.
+// service: listening-port name, for peer-blocked reports.
+fn accept_polling(
+    app: &tauri::AppHandle,
+    listener: &TcpListener,
+    running: &AtomicBool,
+    service: &str,
+    deadline: Option<Instant>
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    listener.set_nonblocking(true)?;
+    
+    let result = loop {
+        match listener.accept() {
+            Ok((_, addr)) if !peer_permitted(app, addr.ip(), service) => continue,
+            Ok(conn) => break Ok(conn),
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if !running.load(Ordering::SeqCst) {
+                    break Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "server stopped"));
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    break Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "sender did not reconnect"));
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    
+    let _ = listener.set_nonblocking(false);
+    let (stream, addr) = result?;
+    stream.set_nonblocking(false)?;
+    Ok((stream, addr))
+}
+
+// Copy from reader until `total` bytes have been written, starting the count at
+// `received` (the resume offset). Short reads of any size are fine; only EOF
+// before `total` is an error (UnexpectedEof), and Interrupted is retried.
+// Returns the bytes received so far alongside the result, so a stall can resume.
+fn receive_stream_bytes<R: Read, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    mut received: u64,
+    total: u64,
+    mut on_progress: impl FnMut(u64)
+) -> (u64, std::io::Result<()>) {
+    let mut buffer = vec![0u8; TCP_CHUNK_SIZE];
+    
+    while received < total {
+        if TCP_TRANSFER_ABORT.load(Ordering::Relaxed) {
+            return (received, Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "Transfer aborted")));
+        }
+        let to_read = std::cmp::min(TCP_CHUNK_SIZE as u64, total - received) as usize;
+        
+        let n = match reader.read(&mut buffer[..to_read]) {
+            Ok(0) => return (received, Err(std::io::ErrorKind::UnexpectedEof.into())),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return (received, Err(e)),
+        };
+        
+        if let Err(e) = out.write_all(&buffer[..n]) {
+            return (received, Err(e));
+        }
+        received += n as u64;
+        on_progress(received);
+    }
+    
+    (received, Ok(()))
 }
 
 fn receive_file_via_tcp(
     app: &tauri::AppHandle,
     stream: TcpStream,
-    transfer_id: &str,
-    file_name: &str,
-    file_size: u64,
-    expected_hash: &str,
-    save_dir: &str
-) -> Result<(), String> {
-    let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+    job: &TcpReceiveJob
+) -> Result<TcpReceiveOutcome, String> {
+    let transfer_id = job.transfer_id.as_str();
+    let file_name = job.file_name.as_str();
+    let file_size = job.file_size;
+    let expected_hash = job.file_hash.as_str();
+    let save_dir = job.save_dir.as_str();
+    
+    let _ = stream.set_read_timeout(Some(job.read_timeout));
+    apply_tcp_tuning(&stream);
     
     let save_path = PathBuf::from(save_dir);
-    let temp_path = save_path.join(format!("{}.tmp", transfer_id));
+    let temp_path = locate_partial_temp(transfer_id, &job.temp_dir);
     let final_path = save_path.join(file_name);
     
     // Check for resume
@@ -1198,8 +9675,6 @@ fn receive_file_via_tcp(
     }
     
     let mut reader = BufReader::with_capacity(TCP_CHUNK_SIZE, stream);
-    let mut buffer = vec![0u8; TCP_CHUNK_SIZE];
-    let mut bytes_received = resume_offset;
     let mut last_progress = 0u32;
     
     // Send resume offset to sender
@@ -1209,43 +9684,50 @@ fn receive_file_via_tcp(
     
     println!("Receiving file: {} ({} bytes, resume from {})", file_name, file_size, resume_offset);
     
-    while bytes_received < file_size {
-        let to_read = std::cmp::min(TCP_CHUNK_SIZE, (file_size - bytes_received) as usize);
+    let (bytes_received, result) = receive_stream_bytes(&mut reader, &mut file, resume_offset, file_size, |bytes_received| {
+        TCP_SERVER_BYTES.store(bytes_received, Ordering::Relaxed);
+        let progress = (bytes_received as f64 / file_size as f64 * 100.0) as u32;
         
-        match reader.read(&mut buffer[..to_read]) {
-            Ok(0) => {
-                // Connection closed
-                if bytes_received < file_size {
-                    return Err(format!("Connection closed early: {}/{} bytes", bytes_received, file_size));
-                }
-                break;
+        // Emit progress every 5%
+        if progress >= last_progress + 5 || bytes_received == file_size {
+            let _ = app.emit("tcp-transfer-progress", TcpTransferProgress {
+                transfer_id: transfer_id.to_string(),
+                bytes_transferred: bytes_received,
+                total_bytes: file_size,
+                progress,
+                throttled: false,
+            });
+            last_progress = progress;
+        }
+    });
+    
+    if let Err(e) = result {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                file.flush().map_err(|e| e.to_string())?;
+                return Ok(TcpReceiveOutcome::Stalled {
+                    bytes_received,
+                    reason: format!("Connection closed early: {}/{} bytes", bytes_received, file_size),
+                });
             }
-            Ok(n) => {
-                file.write_all(&buffer[..n]).map_err(|e| e.to_string())?;
-                bytes_received += n as u64;
-                
-                let progress = (bytes_received as f64 / file_size as f64 * 100.0) as u32;
-                
-                // Emit progress every 5%
-                if progress >= last_progress + 5 || bytes_received == file_size {
-                    let _ = app.emit("tcp-transfer-progress", TcpTransferProgress {
-                        transfer_id: transfer_id.to_string(),
-                        bytes_transferred: bytes_received,
-                        total_bytes: file_size,
-                        progress,
-                    });
-                    last_progress = progress;
-                }
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                // Timeout - save progress so the sender can reconnect and resume
+                file.flush().map_err(|e| e.to_string())?;
+                return Ok(TcpReceiveOutcome::Stalled {
+                    bytes_received,
+                    reason: format!("Timeout at {}/{} bytes", bytes_received, file_size),
+                });
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || 
-                         e.kind() == std::io::ErrorKind::TimedOut => {
-                // Timeout - save progress and return error for resume
+            std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted => {
                 file.flush().map_err(|e| e.to_string())?;
-                return Err(format!("Timeout at {}/{} bytes - can resume", bytes_received, file_size));
+                return Ok(TcpReceiveOutcome::Stalled {
+                    bytes_received,
+                    reason: format!("Connection lost at {}/{} bytes", bytes_received, file_size),
+                });
             }
-            Err(e) => {
+            _ => {
                 file.flush().map_err(|e| e.to_string())?;
-                return Err(format!("Read error: {} at {}/{} bytes", e, bytes_received, file_size));
+                return Err(format!("Read/write error: {} at {}/{} bytes", e, bytes_received, file_size));
             }
         }
     }
@@ -1254,24 +9736,22 @@ fn receive_file_via_tcp(
     drop(file);
     
     // Verify hash
-    let mut verify_file = File::open(&temp_path).map_err(|e| e.to_string())?;
-    let mut hasher = Sha256::new();
-    let mut verify_buf = vec![0u8; TCP_CHUNK_SIZE];
-    
-    loop {
-        let n = verify_file.read(&mut verify_buf).map_err(|e| e.to_string())?;
-        if n == 0 { break; }
-        hasher.update(&verify_buf[..n]);
-    }
-    
-    let computed_hash = hex::encode(hasher.finalize());
+    let computed_hash = hash_file(&temp_path, job.algorithm)?;
     
     if computed_hash != expected_hash {
-        return Err(format!("Hash mismatch! Expected: {}, Got: {}", expected_hash, computed_hash));
+        return Err(handle_hash_mismatch(
+            app,
+            transfer_id,
+            &temp_path,
+            &final_path,
+            expected_hash,
+            &computed_hash,
+            job.discard_on_mismatch
+        ));
     }
     
     // Rename to final path
-    fs::rename(&temp_path, &final_path).map_err(|e| e.to_string())?;
+    move_file(&temp_path, &final_path).map_err(|e| e.to_string())?;
     
     let _ = app.emit("tcp-transfer-complete", serde_json::json!({
         "transfer_id": transfer_id,
@@ -1282,36 +9762,58 @@ fn receive_file_via_tcp(
     
     println!("File received successfully: {}", final_path.display());
     
-    Ok(())
+    Ok(TcpReceiveOutcome::Complete)
 }
 
 // Admin: Send file directly to client via TCP
+// buffer_size is clamped to 64KB..16MB; zero_copy (default on) falls back to the copy loop when unsupported
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn send_file_tcp(
     app: tauri::AppHandle,
     transfer_id: String,
     file_path: String,
     client_ip: String,
     client_port: u16,
-    resume_offset: u64
+    resume_offset: u64,
+    buffer_size: Option<usize>,
+    zero_copy: Option<bool>
 ) -> Result<(), String> {
     if TCP_TRANSFER_ACTIVE.swap(true, Ordering::SeqCst) {
         return Err("Another TCP transfer is active".to_string());
     }
+    TCP_TRANSFER_ABORT.store(false, Ordering::SeqCst);
+    
+    let defaults = TcpSendOptions::default();
+    let options = TcpSendOptions {
+        buffer_size: buffer_size
+            .unwrap_or(defaults.buffer_size)
+            .clamp(TCP_MIN_BUFFER_SIZE, TCP_MAX_BUFFER_SIZE),
+        zero_copy: zero_copy.unwrap_or(defaults.zero_copy),
+    };
     
     let app_clone = app.clone();
     let transfer_id_clone = transfer_id.clone();
     
     thread::spawn(move || {
+        let started = Instant::now();
         let result = send_file_via_tcp(
             &app_clone,
             &transfer_id_clone,
             &file_path,
             &client_ip,
             client_port,
-            resume_offset
+            resume_offset,
+            &options
         );
         
+        let file_size = fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+        let outcome = match &result {
+            Ok(()) => TransferOutcome::Completed,
+            Err(e) => TransferOutcome::Failed(e.clone()),
+        };
+        record_transfer(&transfer_id_clone, file_name_of(&file_path), file_size, "send", started, outcome);
+        
         if let Err(e) = result {
             eprintln!("TCP send error: {}", e);
             let _ = app_clone.emit("tcp-send-error", serde_json::json!({
@@ -1332,9 +9834,15 @@ fn send_file_via_tcp(
     file_path: &str,
     client_ip: &str,
     client_port: u16,
-    resume_offset: u64
+    resume_offset: u64,
+    options: &TcpSendOptions
 ) -> Result<(), String> {
-    let addr = format!("{}:{}", client_ip, client_port);
+    let stream = connect_tcp_peer(client_ip, client_port)?;
+    send_file_over_stream(app, transfer_id, file_path, &stream, resume_offset, options)
+}
+
+fn connect_tcp_peer(ip: &str, port: u16) -> Result<TcpStream, String> {
+    let addr = format!("{}:{}", ip, port);
     
     println!("Connecting to {} for file transfer...", addr);
     
@@ -1344,61 +9852,432 @@ fn send_file_via_tcp(
     ).map_err(|e| format!("Cannot connect to {}: {}", addr, e))?;
     
     let _ = stream.set_write_timeout(Some(Duration::from_secs(30)));
-    let _ = stream.set_nodelay(true); // Disable Nagle for better throughput
-    
+    apply_tcp_tuning(&stream);
+    Ok(stream)
+}
+
+// Push file_path from resume_offset over an already connected stream
+fn send_file_over_stream(
+    app: &tauri::AppHandle,
+    transfer_id: &str,
+    file_path: &str,
+    stream: &TcpStream,
+    resume_offset: u64,
+    options: &TcpSendOptions
+) -> Result<(), String> {
     let mut file = File::open(file_path).map_err(|e| e.to_string())?;
     let file_size = file.metadata().map_err(|e| e.to_string())?.len();
     
-    // Seek to resume position
     if resume_offset > 0 {
-        file.seek(SeekFrom::Start(resume_offset)).map_err(|e| e.to_string())?;
         println!("Resuming from offset: {}", resume_offset);
     }
     
-    let mut writer = BufWriter::with_capacity(TCP_CHUNK_SIZE, stream);
-    let mut buffer = vec![0u8; TCP_CHUNK_SIZE];
-    let mut bytes_sent = resume_offset;
     let mut last_progress = 0u32;
     
     println!("Sending file: {} ({} bytes)", file_path, file_size);
     
-    while bytes_sent < file_size {
-        let n = file.read(&mut buffer).map_err(|e| e.to_string())?;
-        if n == 0 { break; }
-        
-        writer.write_all(&buffer[..n]).map_err(|e| format!("Write error: {}", e))?;
-        bytes_sent += n as u64;
+    let bytes_sent = copy_file_to_stream(&mut file, stream, resume_offset, file_size, options, |bytes_sent| {
+        let progress = (bytes_sent as f64 / file_size as f64 * 100.0) as u32;
+        
+        // Emit progress every 5%
+        if progress >= last_progress + 5 || bytes_sent == file_size {
+            let _ = app.emit("tcp-send-progress", TcpTransferProgress {
+                transfer_id: transfer_id.to_string(),
+                bytes_transferred: bytes_sent,
+                total_bytes: file_size,
+                progress,
+                throttled: TCP_SEND_THROTTLED.load(Ordering::Relaxed),
+            });
+            last_progress = progress;
+        }
+    })?;
+    
+    let _ = app.emit("tcp-send-complete", serde_json::json!({
+        "transfer_id": transfer_id,
+        "bytes_sent": bytes_sent
+    }));
+    
+    println!("File sent successfully: {} bytes", bytes_sent);
+    
+    Ok(())
+}
+
+// Send `file` from `offset` to `file_size`, one buffer_size chunk at a time so
+// progress can be reported. Returns the final byte position.
+fn copy_file_to_stream(
+    file: &mut File,
+    stream: &TcpStream,
+    offset: u64,
+    file_size: u64,
+    options: &TcpSendOptions,
+    mut on_progress: impl FnMut(u64)
+) -> Result<u64, String> {
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    
+    let mut use_zero_copy = options.zero_copy;
+    let mut writer = BufWriter::with_capacity(options.buffer_size, stream);
+    let mut buffer = vec![0u8; options.buffer_size];
+    let mut bytes_sent = offset;
+    // Pacing window, restarted whenever the rate limit changes (stream started/stopped)
+    let mut pace_limit: Option<u64> = None;
+    let mut pace_start = Instant::now();
+    let mut pace_bytes = 0u64;
+    
+    while bytes_sent < file_size {
+        if TCP_TRANSFER_ABORT.load(Ordering::Relaxed) {
+            return Err("Transfer aborted".to_string());
+        }
+        let limit = transfer_rate_limit();
+        if limit != pace_limit {
+            if limit.is_some() != pace_limit.is_some() {
+                println!("TCP send {}", if limit.is_some() { "throttled" } else { "back to full speed" });
+            }
+            TCP_SEND_THROTTLED.store(limit.is_some(), Ordering::Relaxed);
+            pace_limit = limit;
+            pace_start = Instant::now();
+            pace_bytes = 0;
+        }
+        
+        let n = if use_zero_copy {
+            let len = (file_size - bytes_sent).min(options.buffer_size as u64) as usize;
+            
+            match sendfile_chunk(file, stream, bytes_sent, len) {
+                Ok(n) => n,
+                Err(e) if bytes_sent == offset => {
+                    // Unsupported for this file/socket: use the copy loop for the whole transfer
+                    println!("Zero-copy send unavailable ({}), using copy loop", e);
+                    use_zero_copy = false;
+                    file.seek(SeekFrom::Start(bytes_sent)).map_err(|e| e.to_string())?;
+                    continue;
+                }
+                Err(e) => return Err(format!("Sendfile error: {}", e)),
+            }
+        } else {
+            let n = file.read(&mut buffer).map_err(|e| e.to_string())?;
+            writer.write_all(&buffer[..n]).map_err(|e| format!("Write error: {}", e))?;
+            n
+        };
+        
+        if n == 0 { break; }
+        
+        bytes_sent += n as u64;
+        on_progress(bytes_sent);
+        
+        if let Some(rate) = pace_limit {
+            pace_bytes += n as u64;
+            thread::sleep(pacing_delay(pace_bytes, pace_start.elapsed(), rate));
+        }
+    }
+    TCP_SEND_THROTTLED.store(false, Ordering::Relaxed);
+    
+    writer.flush().map_err(|e| e.to_string())?;
+    
+    Ok(bytes_sent)
+}
+
+// Kernel-side copy of up to `len` bytes at `offset`, no user-space buffer
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sendfile_chunk(file: &File, stream: &TcpStream, offset: u64, len: usize) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    
+    let mut file_offset = offset as libc::off_t;
+    let n = unsafe { libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &mut file_offset, len) };
+    
+    if n < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn sendfile_chunk(file: &File, stream: &TcpStream, offset: u64, len: usize) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    
+    // In: bytes to send, out: bytes actually sent (also set on EINTR/EAGAIN)
+    let mut sent = len as libc::off_t;
+    let ret = unsafe {
+        libc::sendfile(
+            file.as_raw_fd(),
+            stream.as_raw_fd(),
+            offset as libc::off_t,
+            &mut sent,
+            std::ptr::null_mut(),
+            0
+        )
+    };
+    
+    if ret < 0 && sent == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+#[cfg(windows)]
+fn sendfile_chunk(file: &File, stream: &TcpStream, offset: u64, len: usize) -> std::io::Result<usize> {
+    use std::os::windows::io::{AsRawHandle, AsRawSocket};
+    use windows_sys::Win32::Networking::WinSock::TransmitFile;
+    
+    // TransmitFile sends from the current file pointer
+    let mut handle = file;
+    handle.seek(SeekFrom::Start(offset))?;
+    
+    let len = len.min(u32::MAX as usize);
+    let ok = unsafe {
+        TransmitFile(
+            stream.as_raw_socket() as usize,
+            file.as_raw_handle() as _,
+            len as u32,
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            0
+        )
+    };
+    
+    if ok == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(len)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", windows)))]
+fn sendfile_chunk(_file: &File, _stream: &TcpStream, _offset: u64, _len: usize) -> std::io::Result<usize> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "sendfile not available"))
+}
+
+// ============== Reverse TCP Transfer (client -> admin) ==============
+// For "send me that log file": the admin listens and the client connects out and
+// pushes the file. The client opens with a u32 LE length + JSON header, the admin
+// answers with the u64 LE resume offset, then raw bytes follow as in send_file_tcp.
+const REVERSE_CONNECT_WAIT: Duration = Duration::from_secs(120);
+const REVERSE_HEADER_MAX: usize = 64 * 1024;
+
+lazy_static::lazy_static! {
+    // Separate from TCP_SERVER_RUNNING so a reverse request and start_tcp_file_server don't block each other
+    static ref REVERSE_SERVER_RUNNING: AtomicBool = AtomicBool::new(false);
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ReverseTransferHeader {
+    transfer_id: String,
+    file_path: String,
+    file_name: String,
+    file_size: u64,
+    file_hash: String,
+    #[serde(default)] // absent from older clients, which only hash with SHA-256
+    algorithm: HashAlgorithm,
+}
+
+// Admin: wait for the client to push remote_path into save_dir. Relay the returned
+// port with transfer_id/remote_path to the client, which calls send_file_to_admin.
+// Requesting again with the same transfer_id resumes a stalled transfer.
+#[tauri::command]
+fn request_file_from_client(
+    app: tauri::AppHandle,
+    transfer_id: String,
+    remote_path: String,
+    save_dir: String,
+    port: Option<u16>,
+    discard_on_mismatch: Option<bool>
+) -> Result<u16, String> {
+    require_permission(&app, Permission::FileTransfer, "request_file_from_client")?;
+    if REVERSE_SERVER_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Already waiting for a reverse transfer".to_string());
+    }
+    TCP_TRANSFER_ABORT.store(false, Ordering::SeqCst);
+    
+    let listener = TcpListener::bind(("0.0.0.0", port.unwrap_or(0)))
+        .map_err(|e| {
+            REVERSE_SERVER_RUNNING.store(false, Ordering::SeqCst);
+            format!("Cannot bind TCP: {}", e)
+        })?;
+    let port = match listener.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(e) => {
+            REVERSE_SERVER_RUNNING.store(false, Ordering::SeqCst);
+            return Err(e.to_string());
+        }
+    };
+    
+    println!("Waiting for client to send {} on port {}", remote_path, port);
+    register_listening_port("reverse-file-server", "TCP", port, "Reverse file transfer");
+    
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut file_size = 0;
+        let deadline = Instant::now() + REVERSE_CONNECT_WAIT;
+        let result = accept_polling(&app, &listener, &REVERSE_SERVER_RUNNING, "reverse-file-server", Some(deadline))
+            .map_err(|e| format!("Client did not connect: {}", e))
+            .and_then(|(stream, addr)| {
+                println!("Reverse transfer connection from: {}", addr);
+                receive_reverse_transfer(&app, stream, &transfer_id, &remote_path, &save_dir, discard_on_mismatch.unwrap_or(false), &mut file_size)
+            });
+        
+        let error = match result {
+            Ok(TcpReceiveOutcome::Complete) => None,
+            Ok(TcpReceiveOutcome::Stalled { reason, .. }) => Some(format!("{} - request again to resume", reason)),
+            Err(e) => Some(e),
+        };
+        let outcome = match &error {
+            Some(error) => TransferOutcome::Failed(error.clone()),
+            None => TransferOutcome::Completed,
+        };
+        record_transfer(&transfer_id, file_name_of(&remote_path), file_size, "receive", started, outcome);
+        if let Some(error) = error {
+            eprintln!("Reverse transfer error: {}", error);
+            let _ = app.emit("tcp-transfer-error", serde_json::json!({
+                "transfer_id": transfer_id,
+                "error": error
+            }));
+        }
+        
+        REVERSE_SERVER_RUNNING.store(false, Ordering::SeqCst);
+        unregister_listening_port("reverse-file-server");
+    });
+    
+    Ok(port)
+}
+
+fn receive_reverse_transfer(
+    app: &tauri::AppHandle,
+    mut stream: TcpStream,
+    transfer_id: &str,
+    remote_path: &str,
+    save_dir: &str,
+    discard_on_mismatch: bool,
+    file_size: &mut u64 // set once the header arrives, for the history record
+) -> Result<TcpReceiveOutcome, String> {
+    let read_timeout = Duration::from_secs(TCP_DEFAULT_READ_TIMEOUT_SECS);
+    let _ = stream.set_read_timeout(Some(read_timeout));
+    
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).map_err(|e| format!("Cannot read header: {}", e))?;
+    let header_len = u32::from_le_bytes(len_buf) as usize;
+    if header_len > REVERSE_HEADER_MAX {
+        return Err(format!("Header too large: {} bytes", header_len));
+    }
+    
+    let mut header_buf = vec![0u8; header_len];
+    stream.read_exact(&mut header_buf).map_err(|e| format!("Cannot read header: {}", e))?;
+    let header: ReverseTransferHeader = serde_json::from_slice(&header_buf)
+        .map_err(|e| format!("Invalid header: {}", e))?;
+    
+    if header.transfer_id != transfer_id || header.file_path != remote_path {
+        return Err(format!("Client offered {} ({}), expected {}", header.file_path, header.transfer_id, remote_path));
+    }
+    *file_size = header.file_size;
+    // Never let the sender pick a path outside save_dir
+    let file_name = Path::new(&header.file_name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid file name: {}", header.file_name))?
+        .to_string();
+    
+    let temp_path = locate_partial_temp(transfer_id, Path::new(save_dir));
+    let resume_offset = fs::metadata(&temp_path).map(|m| m.len()).unwrap_or(0);
+    stream.write_all(&resume_offset.to_le_bytes()).map_err(|e| format!("Cannot send resume offset: {}", e))?;
+    
+    let job = TcpReceiveJob {
+        transfer_id: transfer_id.to_string(),
+        file_name,
+        file_size: header.file_size,
+        file_hash: header.file_hash,
+        algorithm: header.algorithm,
+        save_dir: save_dir.to_string(),
+        temp_dir: PathBuf::from(save_dir),
+        discard_on_mismatch,
+        read_timeout,
+        max_retries: 0,
+    };
+    receive_file_via_tcp(app, stream, &job)
+}
+
+// Client: push file_path to an admin waiting in request_file_from_client.
+// Progress/complete/error events are the same tcp-send-* ones as send_file_tcp.
+#[tauri::command]
+async fn send_file_to_admin(
+    app: tauri::AppHandle,
+    transfer_id: String,
+    file_path: String,
+    admin_ip: String,
+    admin_port: u16,
+    algorithm: Option<String>
+) -> Result<(), String> {
+    require_permission(&app, Permission::FileTransfer, "send_file_to_admin")?;
+    let algorithm = HashAlgorithm::parse(algorithm.as_deref())?;
+    if TCP_TRANSFER_ACTIVE.swap(true, Ordering::SeqCst) {
+        return Err("Another TCP transfer is active".to_string());
+    }
+    TCP_TRANSFER_ABORT.store(false, Ordering::SeqCst);
+    
+    thread::spawn(move || {
+        let started = Instant::now();
+        let result = send_file_via_reverse_tcp(&app, &transfer_id, &file_path, &admin_ip, admin_port, algorithm);
         
-        let progress = (bytes_sent as f64 / file_size as f64 * 100.0) as u32;
+        let file_size = fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+        let outcome = match &result {
+            Ok(()) => TransferOutcome::Completed,
+            Err(e) => TransferOutcome::Failed(e.clone()),
+        };
+        record_transfer(&transfer_id, file_name_of(&file_path), file_size, "send", started, outcome);
         
-        // Emit progress every 5%
-        if progress >= last_progress + 5 || bytes_sent == file_size {
-            let _ = app.emit("tcp-send-progress", TcpTransferProgress {
-                transfer_id: transfer_id.to_string(),
-                bytes_transferred: bytes_sent,
-                total_bytes: file_size,
-                progress,
-            });
-            last_progress = progress;
+        if let Err(e) = result {
+            eprintln!("Reverse TCP send error: {}", e);
+            let _ = app.emit("tcp-send-error", serde_json::json!({
+                "transfer_id": transfer_id,
+                "error": e
+            }));
         }
-    }
+        
+        TCP_TRANSFER_ACTIVE.store(false, Ordering::SeqCst);
+    });
     
-    writer.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn send_file_via_reverse_tcp(
+    app: &tauri::AppHandle,
+    transfer_id: &str,
+    file_path: &str,
+    admin_ip: &str,
+    admin_port: u16,
+    algorithm: HashAlgorithm
+) -> Result<(), String> {
+    let path = Path::new(file_path);
+    let file_size = fs::metadata(path).map_err(|e| format!("File not found: {} ({})", file_path, e))?.len();
+    let header = ReverseTransferHeader {
+        transfer_id: transfer_id.to_string(),
+        file_path: file_path.to_string(),
+        file_name: path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        file_size,
+        file_hash: hash_file(path, algorithm)?,
+        algorithm,
+    };
+    let header = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
     
-    let _ = app.emit("tcp-send-complete", serde_json::json!({
-        "transfer_id": transfer_id,
-        "bytes_sent": bytes_sent
-    }));
+    let mut stream = connect_tcp_peer(admin_ip, admin_port)?;
+    stream.write_all(&(header.len() as u32).to_le_bytes()).map_err(|e| e.to_string())?;
+    stream.write_all(&header).map_err(|e| e.to_string())?;
     
-    println!("File sent successfully: {} bytes", bytes_sent);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(TCP_DEFAULT_READ_TIMEOUT_SECS)));
+    let mut offset_buf = [0u8; 8];
+    stream.read_exact(&mut offset_buf).map_err(|e| format!("Admin did not answer: {}", e))?;
+    let resume_offset = u64::from_le_bytes(offset_buf);
     
-    Ok(())
+    send_file_over_stream(app, transfer_id, file_path, &stream, resume_offset, &TcpSendOptions::default())
 }
 
-// Stop TCP server (for cleanup)
+// Stop TCP server (for cleanup), and a pending request_file_from_client
 #[tauri::command]
 fn stop_tcp_file_server() {
     TCP_SERVER_RUNNING.store(false, Ordering::SeqCst);
+    REVERSE_SERVER_RUNNING.store(false, Ordering::SeqCst);
 }
 
 // Get TCP transfer status
@@ -1406,10 +10285,86 @@ fn stop_tcp_file_server() {
 fn get_tcp_transfer_status() -> serde_json::Value {
     serde_json::json!({
         "server_running": TCP_SERVER_RUNNING.load(Ordering::SeqCst),
+        "reverse_server_running": REVERSE_SERVER_RUNNING.load(Ordering::SeqCst),
         "transfer_active": TCP_TRANSFER_ACTIVE.load(Ordering::SeqCst)
     })
 }
 
+// ============== Emergency Stop ==============
+// One call that halts everything that touches the network or the input devices.
+// Only flips flags (nothing is joined), so it returns immediately; each loop
+// notices on its next iteration. Safe to call repeatedly or with nothing running.
+#[tauri::command]
+fn emergency_stop(app: tauri::AppHandle) -> serde_json::Value {
+    let mut stopped: Vec<&str> = Vec::new();
+    let flags: [(&str, &AtomicBool); 12] = [
+        ("websocket_stream", &WS_STREAMING),
+        ("pipe_stream", &PIPE_STREAMING),
+        ("delta_stream", &DELTA_STREAMING),
+        ("receiver", &UDP_RECEIVER_RUNNING),
+        ("capture", &CAPTURING),
+        ("lossless_capture", &LOSSLESS_CAPTURING),
+        ("relay_server", &RELAY_SERVER_RUNNING),
+        ("relay_client", &RELAY_CLIENT_RUNNING),
+        ("beacon", &BEACON_RUNNING),
+        ("tcp_server", &TCP_SERVER_RUNNING),
+        ("reverse_file_server", &REVERSE_SERVER_RUNNING),
+        ("clipboard_sync", &CLIPBOARD_SYNC_RUNNING),
+    ];
+    for (name, flag) in flags {
+        if flag.swap(false, Ordering::SeqCst) {
+            stopped.push(name);
+        }
+    }
+    
+    // Registered H.264 streams: signal only; their threads exit on their own and
+    // the next start for the same stream reaps them
+    if stop_registered_stream(StreamId::Main) {
+        stopped.push("stream");
+    }
+    let mut displays_stopped = false;
+    for (id, stream) in STREAMS.lock().iter() {
+        if *id != StreamId::Main && stream.running.swap(false, Ordering::SeqCst) {
+            displays_stopped = true;
+        }
+    }
+    if displays_stopped {
+        stopped.push("display_streams");
+    }
+    
+    #[cfg(target_os = "windows")]
+    if let Err(e) = windows_capture_handler::stop_capture() {
+        eprintln!("Emergency stop: {}", e);
+    }
+    
+    // Transfers in flight abort on their next chunk and clear TCP_TRANSFER_ACTIVE themselves
+    TCP_TRANSFER_ABORT.store(true, Ordering::SeqCst);
+    if TCP_TRANSFER_ACTIVE.load(Ordering::SeqCst) {
+        stopped.push("tcp_transfer");
+    }
+    SCAN_CANCELLED.store(true, Ordering::SeqCst);
+    
+    // Input off: drop the paired session and the control grants
+    if INPUT_TOKEN.lock().is_some() {
+        stopped.push("input_session");
+    }
+    revoke_input_session();
+    release_modifiers(&app, false);
+    {
+        let mut permissions = SESSION_PERMISSIONS.lock();
+        permissions.control_mouse = false;
+        permissions.control_keyboard = false;
+        permissions.file_transfer = false;
+        permissions.power_control = false;
+        permissions.clipboard = false;
+    }
+    
+    println!("Emergency stop: {:?}", stopped);
+    let result = serde_json::json!({ "stopped": stopped });
+    let _ = app.emit("emergency-stopped", &result);
+    result
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -1418,44 +10373,945 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             capture_screen,
+            snapshot_current_stream_frame,
             start_capture_loop,
             stop_capture_loop,
+            get_capture_loop_stats,
+            start_lossless_capture,
+            stop_lossless_capture,
             start_stream,
+            start_stream_auto,
             stop_stream,
+            restart_stream_encoder,
+            switch_stream_display,
+            add_stream_target,
+            remove_stream_target,
+            start_jpeg_delta_stream,
+            stop_jpeg_delta_stream,
+            start_stream_to_pipe,
+            stop_stream_to_pipe,
             start_frame_receiver,
             stop_frame_receiver,
+            is_udp_port_available,
+            set_receiver_paused,
+            change_receiver_port,
+            set_snapshot_on_input,
+            run_self_test,
+            export_stream_profile,
+            import_stream_profile,
+            get_clipboard_image,
+            set_clipboard_image,
+            list_displays,
+            get_capture_backend,
+            start_capture,
+            stop_capture,
+            capture_single_frame,
+            get_last_frame,
+            start_stream_for_display,
+            stop_stream_for_display,
+            get_listening_ports,
+            start_relay_server,
+            stop_relay_server,
+            start_relay_client,
+            stop_relay_client,
             get_stream_stats,
+            get_stream_parameter_sets,
+            get_encode_latency_histogram,
+            set_socket_tuning,
             get_screen_size,
             set_lock_screen,
+            set_cursor_highlight,
+            get_idle_time,
+            set_stream_watermark,
+            set_session_permissions,
+            get_session_permissions,
+            start_input_pairing,
+            authorize_input_session,
+            revoke_input_session,
             remote_mouse_move,
             remote_mouse_click,
             remote_mouse_scroll,
             remote_key_press,
+            list_windows,
             scan_lan,
             wake_on_lan,
+            start_discovery_beacon,
+            stop_discovery_beacon,
+            listen_for_beacons,
             get_network_info,
+            get_power_status,
             // File transfer (Socket.IO)
             prepare_file_transfer,
+            prepare_file_transfer_async,
             read_file_chunk,
+            read_file_range,
             init_file_receive,
             receive_file_chunk,
             finalize_file_transfer,
+            verify_file_hash,
+            emergency_stop,
+            set_scene_change_detection,
+            list_audio_devices,
+            set_viewer_active,
+            set_performance_cap,
+            get_performance_cap,
+            system_power_action,
+            get_system_info,
+            set_transfer_stream_yield,
+            capture_region,
+            list_input_displays,
+            set_input_target_display,
+            start_websocket_stream,
+            stop_websocket_stream,
+            benchmark_encoder,
+            get_chunk_manifest,
+            verify_partial_transfer,
+            capture_raw_frame,
+            save_clip,
+            release_all_modifiers,
+            set_total_bandwidth_budget,
+            get_bandwidth_allocation,
+            test_input_pattern,
+            set_parameter_set_hash,
+            start_clipboard_sync,
+            stop_clipboard_sync,
+            set_clipboard_text,
+            ack_frames,
+            reset_receiver_decoder,
+            get_packet_size_stats,
+            set_receiver_tuning,
+            sync_clock_with_peer,
+            set_peer_allowlist,
+            set_peer_denylist,
+            zip_directory,
             get_transfer_status,
+            get_transfer_history,
             cancel_file_transfer,
             // Direct TCP file transfer
             start_tcp_file_server,
             send_file_tcp,
             stop_tcp_file_server,
+            request_file_from_client,
+            send_file_to_admin,
             get_tcp_transfer_status
         ])
-        .setup(|_app| {
+        .setup(|app| {
             #[cfg(debug_assertions)]
             {
-                let window = _app.get_webview_window("main").unwrap();
+                let window = app.get_webview_window("main").unwrap();
                 window.open_devtools();
             }
+            match app.path().app_data_dir() {
+                Ok(dir) => load_known_save_dirs(&dir),
+                Err(e) => eprintln!("No app data dir, resume only finds this run's save dirs: {}", e),
+            }
             Ok(())
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loopback throughput of the copy loop vs the zero-copy path:
+    // cargo test --release tcp_send_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn tcp_send_throughput() {
+        const FILE_SIZE: u64 = 512 * 1024 * 1024;
+
+        let path = std::env::temp_dir().join("quan_ly_phong_may_tcp_bench.bin");
+        File::create(&path).unwrap().set_len(FILE_SIZE).unwrap();
+
+        let runs = [
+            ("copy 256KB", TcpSendOptions { buffer_size: TCP_CHUNK_SIZE, zero_copy: false }),
+            ("copy 4MB", TcpSendOptions { buffer_size: 4 * 1024 * 1024, zero_copy: false }),
+            ("zero-copy 4MB", TcpSendOptions { buffer_size: 4 * 1024 * 1024, zero_copy: true }),
+        ];
+
+        for (label, options) in runs {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let reader = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                std::io::copy(&mut stream, &mut std::io::sink()).unwrap()
+            });
+
+            let start = Instant::now();
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut file = File::open(&path).unwrap();
+            let sent = copy_file_to_stream(&mut file, &stream, 0, FILE_SIZE, &options, |_| {}).unwrap();
+            drop(stream);
+            let received = reader.join().unwrap();
+            let elapsed = start.elapsed().as_secs_f64();
+
+            assert_eq!(sent, FILE_SIZE);
+            assert_eq!(received, FILE_SIZE);
+            println!("{:<14} {:>8.1} MB/s", label, FILE_SIZE as f64 / 1_000_000.0 / elapsed);
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    // Hands out data a few bytes at a time in an uneven pattern, with an
+    // Interrupted thrown in, like a slow socket near the end of a transfer
+    struct TrickleReader {
+        data: Vec<u8>,
+        pos: usize,
+        reads: usize,
+    }
+
+    impl Read for TrickleReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            if self.reads == 3 {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let n = [1, 7, 3, 13, 2][self.reads % 5].min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn receive_completes_across_tiny_reads() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31) as u8).collect();
+        let mut reader = TrickleReader { data: data.clone(), pos: 0, reads: 0 };
+        let mut out = Vec::new();
+        let mut last_progress = 0;
+
+        let (received, result) = receive_stream_bytes(&mut reader, &mut out, 0, data.len() as u64, |n| last_progress = n);
+        assert!(result.is_ok());
+        assert_eq!(received, data.len() as u64);
+        assert_eq!(last_progress, data.len() as u64);
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn receive_reports_missing_bytes_only() {
+        let mut reader = TrickleReader { data: vec![9; 50], pos: 0, reads: 0 };
+        let mut out = Vec::new();
+
+        // Resuming at 10 of 70: the 50 bytes on the wire leave 10 missing
+        let (received, result) = receive_stream_bytes(&mut reader, &mut out, 10, 70, |_| {});
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(received, 60);
+        assert_eq!(out.len(), 50);
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quan_ly_phong_may_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn transfer_yield_paces_to_link_minus_reserve() {
+        let config = TransferYield::default();
+        assert_eq!(config.limit_bytes_per_sec(), Some(80 * 125_000));
+        assert_eq!(TransferYield { enabled: false, ..config }.limit_bytes_per_sec(), None);
+
+        // 1 MB at 1 MB/s is due after a second; time already spent counts
+        assert_eq!(pacing_delay(1_000_000, Duration::ZERO, 1_000_000), Duration::from_secs(1));
+        assert_eq!(pacing_delay(1_000_000, Duration::from_millis(400), 1_000_000), Duration::from_millis(600));
+        assert_eq!(pacing_delay(1_000, Duration::from_secs(1), 1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn bandwidth_budget_serves_video_first() {
+        // Video takes what it asks for, files get the rest
+        assert_eq!(
+            allocate_bandwidth(5_000_000, 2_000_000, 0),
+            BandwidthAllocation { video_bps: 2_000_000, audio_bps: 0, files_bps: 3_000_000 }
+        );
+        // A greedy stream still leaves the files floor
+        let tight = allocate_bandwidth(1_000_000, 2_000_000, 128_000);
+        assert_eq!(tight.video_bps, 1_000_000 - BUDGET_FILES_FLOOR_BPS);
+        assert_eq!(tight.audio_bps, 0);
+        assert_eq!(tight.files_bps, BUDGET_FILES_FLOOR_BPS);
+    }
+
+    #[test]
+    fn read_range_checks_bounds() {
+        let dir = scratch_dir("read_range");
+        let path = dir.join("data.bin");
+        fs::write(&path, b"0123456789").unwrap();
+        let mut file = File::open(&path).unwrap();
+
+        assert_eq!(read_range(&mut file, 3, 4).unwrap(), b"3456");
+        assert_eq!(read_range(&mut file, 9, 1).unwrap(), b"9");
+        assert!(read_range(&mut file, 9, 2).is_err());
+        assert!(read_range(&mut file, 0, 0).is_err());
+        assert!(read_range(&mut file, u64::MAX, 1).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn known_save_dirs_survive_a_restart() {
+        let dir = scratch_dir("known_dirs");
+        let file = dir.join("data").join("known_save_dirs.txt");
+        let dirs = vec![dir.join("Downloads"), dir.join("D drive")];
+
+        write_known_save_dirs(&file, &dirs).unwrap();
+        assert_eq!(read_known_save_dirs(&file), dirs);
+        assert!(read_known_save_dirs(&dir.join("missing.txt")).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn temp_dir_defaults_to_save_dir() {
+        let dir = scratch_dir("temp_dir");
+        let local = dir.join("local").join("tmp");
+
+        assert_eq!(resolve_temp_dir(&dir, None).unwrap(), dir);
+        assert_eq!(resolve_temp_dir(&dir, Some("  ")).unwrap(), dir);
+        assert_eq!(resolve_temp_dir(&dir, local.to_str()).unwrap(), local);
+        assert!(local.is_dir());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn move_file_copies_across_devices() {
+        let dir = scratch_dir("move_exdev");
+        let (from, to) = (dir.join("transfer.tmp"), dir.join("report.pdf"));
+        fs::write(&from, b"payload").unwrap();
+
+        // Every direct rename of from -> to fails the way a cross-mount rename does
+        let exdev = |src: &Path, dst: &Path| {
+            if src == from && dst == to {
+                Err(std::io::Error::from(std::io::ErrorKind::CrossesDevices))
+            } else {
+                fs::rename(src, dst)
+            }
+        };
+        move_file_with(&from, &to, exdev).unwrap();
+
+        assert_eq!(fs::read(&to).unwrap(), b"payload");
+        assert!(!from.exists());
+        assert!(!dir.join("report.pdf.moving").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn move_file_keeps_other_rename_errors() {
+        let dir = scratch_dir("move_denied");
+        let (from, to) = (dir.join("transfer.tmp"), dir.join("report.pdf"));
+        fs::write(&from, b"payload").unwrap();
+
+        let denied = |_: &Path, _: &Path| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        let err = move_file_with(&from, &to, denied).unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(from.exists() && !to.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn solid_bgra(r: u8, g: u8, b: u8) -> Vec<u8> {
+        [b, g, r, 255].repeat(4)
+    }
+
+    fn luma(color: ColorSpace, r: u8, g: u8, b: u8) -> u8 {
+        bgra_to_yuv420_resized(&solid_bgra(r, g, b), 2, 2, 2, 2, false, color, PixelFormat::I420).unwrap()[0]
+    }
+
+    #[test]
+    fn hardware_output_is_cut_into_access_units() {
+        let aud = [0, 0, 0, 1, 9, 0xF0];
+        let sps_idr = [0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x65, 0x88];
+        let p_frame = [0, 0, 1, 0x41, 0x9A];
+        let mut stream = Vec::new();
+        for part in [&aud[..], &sps_idr, &aud[1..], &p_frame, &aud, &p_frame[..3]] {
+            stream.extend_from_slice(part);
+        }
+
+        let units = take_access_units(&mut stream);
+        assert_eq!(units, vec![sps_idr.to_vec(), p_frame.to_vec()]);
+        // The last unit is still arriving; the rest of it completes it
+        assert_eq!(stream[..6], aud);
+        stream.extend_from_slice(&p_frame[3..]);
+        stream.extend_from_slice(&aud);
+        assert_eq!(take_access_units(&mut stream), vec![p_frame.to_vec()]);
+        assert!(take_access_units(&mut stream).is_empty());
+    }
+
+    #[test]
+    fn hardware_constant_qp_needs_a_qp_mode() {
+        let backend = HardwareBackend {
+            encoder: "h264_test",
+            device_args: &[],
+            filter: None,
+            low_latency_args: &[],
+            qp_args: Some(&["-rc", "constqp", "-qp"]),
+        };
+        let args = backend.output_args(30, false, QualityMode::ConstantQp(20)).unwrap();
+        assert!(args.windows(2).any(|w| w == ["-qp", "20"]));
+        assert!(args.windows(2).any(|w| w == ["-g", "60"]));
+        assert_eq!(args.last().map(String::as_str), Some("-"));
+        let no_qp = HardwareBackend { qp_args: None, ..backend };
+        assert!(no_qp.output_args(30, false, QualityMode::ConstantQp(20)).is_err());
+        assert!(no_qp.output_args(30, false, QualityMode::Bitrate(DEFAULT_BITRATE_BPS)).is_ok());
+    }
+
+    #[test]
+    fn yuv_luma_matches_reference_for_each_matrix() {
+        // (Kr, Kb) from the standards; Kg = 1 - Kr - Kb
+        for (matrix, kr, kb) in [(ColorMatrix::Bt601, 0.299, 0.114), (ColorMatrix::Bt709, 0.2126, 0.0722)] {
+            for full_range in [false, true] {
+                let color = ColorSpace { matrix, full_range };
+                for (r, g, b) in [(255, 0, 0), (0, 255, 0), (0, 0, 255), (128, 64, 32)] {
+                    let e = kr * r as f64 + (1.0 - kr - kb) * g as f64 + kb * b as f64;
+                    let expected = if full_range { e } else { 16.0 + e * 219.0 / 255.0 };
+                    let got = luma(color, r, g, b) as f64;
+                    assert!((got - expected).abs() <= 1.0, "{:?} ({}, {}, {}): got {}, expected {:.1}", color, r, g, b, got, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn yuv_range_endpoints_and_neutral_chroma() {
+        for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709] {
+            let limited = ColorSpace { matrix, full_range: false };
+            let full = ColorSpace { matrix, full_range: true };
+            assert_eq!((luma(limited, 0, 0, 0), luma(limited, 255, 255, 255)), (16, 235));
+            assert_eq!((luma(full, 0, 0, 0), luma(full, 255, 255, 255)), (0, 255));
+
+            for color in [limited, full] {
+                let yuv = bgra_to_yuv420_resized(&solid_bgra(90, 90, 90), 2, 2, 2, 2, false, color, PixelFormat::I420).unwrap();
+                assert_eq!((yuv[4], yuv[5]), (128, 128));
+                assert_eq!(ColorSpace::from_header_flags(color.header_flags()), color);
+            }
+        }
+    }
+
+    #[test]
+    fn color_space_parse() {
+        assert_eq!(ColorSpace::parse(None, None).unwrap(), ColorSpace::default());
+        assert_eq!(
+            ColorSpace::parse(Some("Full"), Some("BT.709")).unwrap(),
+            ColorSpace { matrix: ColorMatrix::Bt709, full_range: true }
+        );
+        assert!(ColorSpace::parse(Some("studio"), None).is_err());
+        assert!(ColorSpace::parse(None, Some("bt2020")).is_err());
+    }
+
+    // 4x4 frame, one color per 2x2 block so every chroma sample differs
+    fn quad_bgra() -> Vec<u8> {
+        let blocks = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [200, 120, 40]];
+        let mut bgra = Vec::with_capacity(4 * 4 * 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                let [r, g, b] = blocks[(y / 2) * 2 + x / 2];
+                bgra.extend_from_slice(&[b, g, r, 255]);
+            }
+        }
+        bgra
+    }
+
+    #[test]
+    fn nv12_interleaves_the_i420_chroma_planes() {
+        let color = ColorSpace::default();
+        let i420 = bgra_to_yuv420_resized(&quad_bgra(), 4, 4, 4, 4, false, color, PixelFormat::I420).unwrap();
+        let nv12 = bgra_to_yuv420_resized(&quad_bgra(), 4, 4, 4, 4, false, color, PixelFormat::Nv12).unwrap();
+
+        let (y_size, uv_size) = (16, 4);
+        assert_eq!(nv12.len(), i420.len());
+        assert_eq!(nv12[..y_size], i420[..y_size]);
+
+        let (u_plane, v_plane) = i420[y_size..].split_at(uv_size);
+        let expected: Vec<u8> = u_plane.iter().zip(v_plane).flat_map(|(&u, &v)| [u, v]).collect();
+        assert_eq!(nv12[y_size..], expected[..]);
+        // The blocks really differ, so a swapped or planar layout can't pass
+        assert!(u_plane.windows(2).any(|w| w[0] != w[1]));
+        assert_ne!(u_plane, v_plane);
+    }
+
+    #[test]
+    fn pixel_format_parse_and_flags() {
+        assert_eq!(PixelFormat::parse("NV12").unwrap(), PixelFormat::Nv12);
+        assert_eq!(PixelFormat::parse("yuv420p").unwrap(), PixelFormat::I420);
+        assert!(PixelFormat::parse("rgb24").is_err());
+        for format in [PixelFormat::I420, PixelFormat::Nv12] {
+            let flags = format.header_flags() | ColorSpace { matrix: ColorMatrix::Bt709, full_range: true }.header_flags();
+            assert_eq!(PixelFormat::from_header_flags(flags), format);
+            assert_eq!(ColorSpace::from_header_flags(flags).matrix, ColorMatrix::Bt709);
+        }
+    }
+
+    #[test]
+    fn stream_profiles_validate_every_stream_setting() {
+        assert!(parse_stream_profile(&current_stream_profile()).is_ok());
+        assert!(parse_stream_profile(&StreamProfile::default()).is_ok());
+
+        let bad = [
+            StreamProfile { codec: Some("vp9".to_string()), ..Default::default() },
+            StreamProfile { fps: Some(0), ..Default::default() },
+            StreamProfile { width: Some(641), height: Some(360), ..Default::default() },
+            StreamProfile { gop_secs: Some(600), ..Default::default() },
+            StreamProfile { quality_mode: Some("qp".to_string()), bitrate_kbps: Some(800), ..Default::default() },
+            StreamProfile { bitrate_kbps: Some(10), ..Default::default() },
+            StreamProfile { performance_cap: Some(PerformanceCap { max_width: 10, ..Default::default() }), ..Default::default() },
+            StreamProfile { scene_change_threshold: Some(0.5), ..Default::default() },
+            StreamProfile { watermark_text: Some("x".to_string()), watermark_opacity: Some(2.0), ..Default::default() },
+            StreamProfile { ttl: Some(256), ..Default::default() },
+        ];
+        for profile in &bad {
+            assert!(parse_stream_profile(profile).is_err());
+        }
+
+        let good = StreamProfile {
+            width: Some(1280),
+            height: Some(720),
+            bitrate_kbps: Some(2000),
+            ttl: Some(0),
+            watermark_text: Some(String::new()),
+            ..Default::default()
+        };
+        let parsed = parse_stream_profile(&good).unwrap();
+        assert_eq!(parsed.size, Some((1280, 720)));
+        assert_eq!(parsed.quality, Some(QualityMode::Bitrate(2_000_000)));
+        assert_eq!(parsed.ttl, Some(None));
+        assert!(matches!(parsed.watermark, Some(None)));
+    }
+
+    #[test]
+    fn power_actions_that_lose_work_are_destructive() {
+        assert_eq!(PowerAction::parse(" Restart ").unwrap(), PowerAction::Reboot);
+        assert!(PowerAction::parse("hibernate").is_err());
+        assert!(!PowerAction::Lock.destructive());
+        assert!(!PowerAction::Sleep.destructive());
+        assert!(PowerAction::Logoff.destructive());
+        assert!(PowerAction::Shutdown.destructive());
+    }
+
+    #[test]
+    fn performance_cap_keeps_aspect_and_even_sizes() {
+        let none = PerformanceCap::default();
+        assert_eq!(none.size(640, 360), (640, 360));
+        assert_eq!(none.fps(60), 60);
+
+        let cap = PerformanceCap { max_width: 320, max_height: 0, max_fps: 10 };
+        assert_eq!(cap.size(640, 360), (320, 180));
+        assert_eq!((cap.fps(30), cap.fps(5)), (10, 5));
+
+        // The tighter of the two limits wins; odd results round down to even
+        let cap = PerformanceCap { max_width: 600, max_height: 101, max_fps: 0 };
+        assert_eq!(cap.size(640, 360), (178, 100));
+        assert_eq!(cap.size(160, 90), (160, 90));
+    }
+
+    #[test]
+    fn replay_buffer_keeps_whole_gops() {
+        let t0 = Instant::now();
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+        let mut buffer = ReplayBuffer::new();
+        // Leading delta frame is useless without its keyframe
+        buffer.push(at(0), false, &[0]);
+        assert!(buffer.frames.is_empty());
+
+        // A GOP every 10s, a frame every 5s
+        for secs in (0..=REPLAY_MAX_SECONDS + 20).step_by(5) {
+            buffer.push(at(secs), secs % 10 == 0, &[secs as u8]);
+        }
+        let front = buffer.frames.front().unwrap();
+        assert!(front.keyframe);
+        assert!(at(REPLAY_MAX_SECONDS + 20).duration_since(front.at) <= Duration::from_secs(REPLAY_MAX_SECONDS));
+
+        // "Last 12s" from t=80 starts at the keyframe at t=60
+        let start = buffer.clip_start(at(REPLAY_MAX_SECONDS + 8)).unwrap();
+        assert_eq!(buffer.frames[start].at, at(REPLAY_MAX_SECONDS));
+    }
+
+    #[test]
+    fn corrupt_chunks_are_found_against_the_manifest() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let manifest = chunk_hashes(&mut &data[..]).unwrap();
+        assert_eq!(manifest.len(), 3);
+
+        // Chunk 1 damaged, chunk 2 cut short by an interrupted write
+        let mut partial = data[..CHUNK_SIZE * 2 + 40].to_vec();
+        partial[CHUNK_SIZE + 7] ^= 0xFF;
+        let mut file = std::io::Cursor::new(partial);
+        assert_eq!(find_corrupt_chunks(&mut file, 3, &manifest).unwrap(), vec![1, 2]);
+        // Only chunks already counted as received are checked
+        assert_eq!(find_corrupt_chunks(&mut file, 1, &manifest).unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn idle_senders_are_swept() {
+        let now = Instant::now();
+        let quiet: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let live: SocketAddr = "10.0.0.3:5000".parse().unwrap();
+        let mut senders = HashMap::new();
+        senders.insert(quiet, ReceiverSender::new());
+        senders.insert(live, ReceiverSender::new());
+        senders.get_mut(&quiet).unwrap().last_seen = now - Duration::from_secs(30);
+
+        let ended = sweep_idle_senders(&mut senders, Duration::from_secs(10), now);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].0, quiet);
+        assert!(ended[0].1 >= Duration::from_secs(30));
+        assert!(senders.contains_key(&live) && !senders.contains_key(&quiet));
+    }
+
+    #[test]
+    fn clipboard_changes_are_debounced() {
+        let t0 = Instant::now();
+        let mut debounce = ClipboardDebounce::new(Some(1));
+        // The starting content is not a change
+        assert!(!debounce.observe(Some(1), t0));
+        
+        // 2 is replaced before it settles; only 3 goes out, once
+        assert!(!debounce.observe(Some(2), t0));
+        assert!(!debounce.observe(Some(3), t0 + CLIPBOARD_DEBOUNCE / 2));
+        assert!(!debounce.observe(Some(3), t0 + CLIPBOARD_DEBOUNCE));
+        assert!(debounce.observe(Some(3), t0 + CLIPBOARD_DEBOUNCE * 2));
+        assert!(!debounce.observe(Some(3), t0 + CLIPBOARD_DEBOUNCE * 4));
+        
+        // Emptying the clipboard sends nothing
+        assert!(!debounce.observe(None, t0 + CLIPBOARD_DEBOUNCE * 5));
+    }
+
+    #[test]
+    fn clipboard_pushes_are_not_echoed() {
+        let t0 = Instant::now();
+        let mut pushed = None;
+        assert!(!is_clipboard_echo(&mut pushed, 7, t0));
+
+        // Same content comes back, even late: swallowed once
+        pushed = Some((7, t0));
+        assert!(is_clipboard_echo(&mut pushed, 7, t0 + CLIPBOARD_ECHO_WINDOW * 3));
+        assert!(!is_clipboard_echo(&mut pushed, 7, t0 + CLIPBOARD_ECHO_WINDOW * 3));
+
+        // Different bytes right after the push (image re-encoded by the OS) too
+        pushed = Some((7, t0));
+        assert!(is_clipboard_echo(&mut pushed, 8, t0 + CLIPBOARD_ECHO_WINDOW / 2));
+        // A later user copy is a real change, and clears the push
+        pushed = Some((7, t0));
+        assert!(!is_clipboard_echo(&mut pushed, 9, t0 + CLIPBOARD_ECHO_WINDOW * 2));
+        assert!(pushed.is_none());
+    }
+
+    #[test]
+    fn input_test_marker_maps_to_keys() {
+        assert_eq!(
+            marker_keys("Ok 1").unwrap(),
+            vec![(Key::KeyO, true), (Key::KeyK, false), (Key::Space, false), (Key::Num1, false)]
+        );
+        assert!(marker_keys("ok!").is_err());
+    }
+
+    #[test]
+    fn input_target_offsets_into_a_secondary_display() {
+        // Second monitor to the left of the primary
+        let target = InputTarget { index: 1, x: -1920.0, y: 0.0, width: 1920.0, height: 1080.0 };
+        assert_eq!(target.desktop_point(100.0, 50.0), (-1820.0, 50.0));
+        // Overshooting the viewer stays on the target monitor
+        assert_eq!(target.desktop_point(5000.0, -10.0), (-1.0, 0.0));
+    }
+
+    #[test]
+    fn crop_clamps_to_the_frame_and_skips_row_padding() {
+        // 3x2 frame with 4 bytes of padding per row; pixel value = 10 * row + col
+        let (w, h, stride) = (3, 2, 16);
+        let mut bgra = vec![0xEEu8; stride * h];
+        for y in 0..h {
+            for x in 0..w {
+                bgra[y * stride + x * 4..y * stride + x * 4 + 4].fill((10 * y + x) as u8);
+            }
+        }
+
+        let (cropped, cw, ch) = crop_bgra(&bgra, w, h, (1, 0, 10, 10)).unwrap();
+        assert_eq!((cw, ch), (2, 2));
+        let pixels: Vec<u8> = cropped.chunks(4).map(|px| px[0]).collect();
+        assert_eq!(pixels, vec![1, 2, 11, 12]);
+
+        assert!(crop_bgra(&bgra, w, h, (3, 0, 1, 1)).is_none());
+        assert!(crop_bgra(&bgra, w, h, (0, 0, 0, 1)).is_none());
+    }
+
+    #[test]
+    fn displays_map_to_monitors_by_size_not_position() {
+        let displays = [(1920, 1080), (2560, 1440), (1920, 1080)];
+        // tauri lists the big monitor first
+        let monitors = [(2560, 1440), (1920, 1080), (1920, 1080)];
+        assert_eq!(match_display_to_monitor(&displays, &monitors, 0), Some(1));
+        assert_eq!(match_display_to_monitor(&displays, &monitors, 1), Some(0));
+        assert_eq!(match_display_to_monitor(&displays, &monitors, 2), Some(2));
+        assert_eq!(match_display_to_monitor(&displays, &monitors, 3), None);
+        // Scaled sizes never match: same index
+        assert_eq!(match_display_to_monitor(&displays, &[(1280, 720), (1707, 960)], 1), Some(1));
+    }
+
+    #[test]
+    fn backpressure_drops_deltas_until_the_next_keyframe() {
+        let mut sender = ReceiverSender::new();
+        assert!(sender.admit_frame(FrameDeps::Delta));
+        // As release_due_frames does after deliver_frame dropped a P-frame
+        sender.awaiting_keyframe = true;
+        assert!(!sender.admit_frame(FrameDeps::Delta));
+        assert!(sender.admit_frame(FrameDeps::Independent));
+        assert!(sender.admit_frame(FrameDeps::Keyframe));
+        assert!(sender.admit_frame(FrameDeps::Delta));
+        
+        assert_eq!(FrameDeps::h264(&[0, 0, 0, 1, 0x65, 0x88]), FrameDeps::Keyframe);
+        assert_eq!(FrameDeps::h264(&[0, 0, 0, 1, 0x41, 0x9a]), FrameDeps::Delta);
+    }
+    
+    #[test]
+    fn cut_off_targets_wait_for_a_keyframe() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut targets = vec![StreamTarget::new(receiver.local_addr().unwrap().to_string())];
+        targets[0].awaiting_keyframe = true;
+        let frame = [0u8, 0, 0, 1, 0x41, 1, 2, 3];
+
+        assert!(!send_to_stream_targets(&socket, &mut targets, &frame, 0, false).unwrap());
+        assert_eq!((targets[0].frames_sent, targets[0].frames_dropped), (0, 1));
+        assert!(send_to_stream_targets(&socket, &mut targets, &frame, 1, true).unwrap());
+        assert!(!targets[0].awaiting_keyframe);
+        assert!(send_to_stream_targets(&socket, &mut targets, &frame, 2, false).unwrap());
+        assert_eq!(targets[0].frames_sent, 2);
+    }
+
+    #[test]
+    fn socket_rebinds_back_off_until_a_send_gets_through() {
+        let error = std::io::Error::new(std::io::ErrorKind::NetworkUnreachable, "network down");
+        let mut targets = vec![StreamTarget::new("127.0.0.1:9".to_string())];
+        let mut rebinder = SocketRebinder::new();
+        let mut fail = |rebinder: &mut SocketRebinder, n: u32| {
+            (0..n).filter(|_| rebinder.on_send_error(&mut targets, &error).is_some()).count()
+        };
+
+        assert_eq!(fail(&mut rebinder, SOCKET_REBIND_AFTER_ERRORS - 1), 0);
+        assert_eq!(fail(&mut rebinder, 1), 1);
+        assert!(rebinder.unconfirmed.is_some());
+        // Still failing right after the rebind: wait out the backoff
+        assert_eq!(fail(&mut rebinder, SOCKET_REBIND_AFTER_ERRORS * 3), 0);
+        assert_eq!(rebinder.backoff, SOCKET_REBIND_MIN_BACKOFF * 2);
+
+        // Sends that reach nobody don't confirm the reconnect
+        rebinder.on_send_ok(None, false);
+        assert!(rebinder.unconfirmed.is_some());
+        rebinder.on_send_ok(None, true);
+        assert!(rebinder.unconfirmed.is_none());
+        assert_eq!(rebinder.backoff, SOCKET_REBIND_MIN_BACKOFF);
+    }
+
+    #[test]
+    fn scene_difference_tracks_luma_changes() {
+        let (w, h) = (320, 180);
+        let black = vec![0u8; w * h * 4];
+        let white = vec![255u8; w * h * 4];
+        let mut cursor = black.clone();
+        // A small bright square (think mouse cursor) is not a scene change
+        for y in 10..20 {
+            for x in 10..20 {
+                cursor[(y * w + x) * 4..(y * w + x) * 4 + 3].copy_from_slice(&[255, 255, 255]);
+            }
+        }
+
+        let base = luma_signature(&black, w, h);
+        assert_eq!(base.len(), SCENE_GRID_W * SCENE_GRID_H);
+        assert_eq!(scene_difference(&base, &luma_signature(&black, w, h)), 0.0);
+        assert!(scene_difference(&base, &luma_signature(&white, w, h)) > 250.0);
+        assert!(scene_difference(&base, &luma_signature(&cursor, w, h)) < SCENE_CHANGE_DEFAULT_THRESHOLD);
+        assert_eq!(scene_difference(&base, &[]), 255.0);
+    }
+
+    fn chunks_of(frame: &[u8], size: usize) -> Vec<&[u8]> {
+        frame.chunks(size).collect()
+    }
+
+    #[test]
+    fn assembler_in_order() {
+        let frame: Vec<u8> = (0..=255).collect();
+        let chunks = chunks_of(&frame, 100);
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(1, 0, chunks.len(), chunks[0]), None);
+        assert_eq!(asm.add_chunk(1, 1, chunks.len(), chunks[1]), None);
+        assert_eq!(asm.add_chunk(1, 2, chunks.len(), chunks[2]), Some(frame));
+    }
+
+    #[test]
+    fn assembler_out_of_order() {
+        let frame: Vec<u8> = (0..=255).collect();
+        let chunks = chunks_of(&frame, 100);
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(7, 2, chunks.len(), chunks[2]), None);
+        assert_eq!(asm.add_chunk(7, 0, chunks.len(), chunks[0]), None);
+        // Output is in index order, not arrival order
+        assert_eq!(asm.add_chunk(7, 1, chunks.len(), chunks[1]), Some(frame));
+    }
+
+    #[test]
+    fn assembler_ignores_duplicates() {
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(3, 0, 2, b"ab"), None);
+        assert_eq!(asm.add_chunk(3, 0, 2, b"ab"), None);
+        assert_eq!(asm.add_chunk(3, 0, 2, b"zz"), None);
+        assert_eq!(asm.received, 1);
+        // First copy wins
+        assert_eq!(asm.add_chunk(3, 1, 2, b"cd"), Some(b"abcd".to_vec()));
+    }
+
+    #[test]
+    fn assembler_dropped_chunk_never_completes() {
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(5, 0, 3, b"a"), None);
+        assert_eq!(asm.add_chunk(5, 2, 3, b"c"), None);
+        assert_eq!(asm.received, 2);
+        // Out-of-range index doesn't count towards completion
+        assert_eq!(asm.add_chunk(5, 3, 3, b"x"), None);
+        assert_eq!(asm.received, 2);
+    }
+
+    #[test]
+    fn assembler_new_sequence_resets() {
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(10, 0, 2, b"old"), None);
+        // Next frame starts before the first finished: the partial frame is dropped
+        assert_eq!(asm.add_chunk(11, 0, 2, b"new"), None);
+        assert_eq!(asm.received, 1);
+        assert_eq!(asm.add_chunk(10, 1, 2, b"tail"), None);
+        assert_eq!(asm.current_seq, 10);
+        assert_eq!(asm.add_chunk(11, 1, 2, b"er"), None);
+
+        assert_eq!(asm.add_chunk(12, 0, 2, b"ab"), None);
+        assert_eq!(asm.add_chunk(12, 1, 2, b"cd"), Some(b"abcd".to_vec()));
+    }
+
+    #[test]
+    fn assembler_counts_duplicates_and_reordering() {
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(4, 2, 3, b"c"), None);
+        assert_eq!(asm.add_chunk(4, 0, 3, b"a"), None);
+        assert_eq!(asm.add_chunk(4, 0, 3, b"a"), None);
+        assert_eq!(asm.add_chunk(4, 1, 3, b"b"), Some(b"abc".to_vec()));
+        assert_eq!((asm.duplicate_chunks, asm.out_of_order_chunks), (1, 2));
+
+        // A retransmitted chunk of the finished frame is a duplicate, not a new frame
+        assert_eq!(asm.add_chunk(4, 1, 3, b"b"), None);
+        assert_eq!(asm.duplicate_chunks, 2);
+        assert_eq!(asm.current_seq, u32::MAX);
+    }
+
+    #[test]
+    fn assembler_rejects_zero_total() {
+        let mut asm = H264FrameAssembler::new();
+
+        assert_eq!(asm.add_chunk(1, 0, 0, b""), None);
+        assert_eq!(asm.add_chunk(1, 0, 1, b"x"), Some(b"x".to_vec()));
+    }
+
+    #[test]
+    fn assembler_expires_stale_partial_frame() {
+        let mut asm = H264FrameAssembler::new();
+        assert!(!asm.expire_stale(Duration::ZERO));
+
+        assert_eq!(asm.add_chunk(5, 0, 2, b"a"), None);
+        assert!(!asm.expire_stale(Duration::from_secs(60)));
+        thread::sleep(Duration::from_millis(2));
+        assert!(asm.expire_stale(Duration::from_millis(1)));
+
+        // The late tail must not revive frame 5
+        assert_eq!(asm.add_chunk(5, 1, 2, b"b"), None);
+        assert_eq!(asm.add_chunk(6, 0, 1, b"c"), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn peer_rule_cidr_matching() {
+        let subnet = PeerRule::parse("192.168.1.0/24").unwrap();
+        assert!(subnet.matches("192.168.1.77".parse().unwrap()));
+        assert!(!subnet.matches("192.168.2.1".parse().unwrap()));
+        // IPv4-mapped form from dual-stack sockets
+        assert!(subnet.matches("::ffff:192.168.1.9".parse().unwrap()));
+
+        let host = PeerRule::parse(" 10.0.0.5 ").unwrap();
+        assert_eq!(host.label(), "10.0.0.5/32");
+        assert!(!host.matches("10.0.0.6".parse().unwrap()));
+
+        assert!(PeerRule::parse("0.0.0.0/0").unwrap().matches("8.8.8.8".parse().unwrap()));
+        assert!(PeerRule::parse("fd00::/8").unwrap().matches("fd12::1".parse().unwrap()));
+        assert!(PeerRule::parse("10.0.0.0/33").is_err());
+        assert!(PeerRule::parse("not-an-ip").is_err());
+    }
+    
+    #[test]
+    fn pairing_lockout_survives_new_codes() {
+        let start = Instant::now();
+        let mut failures = PairingFailures::default();
+        for i in 0..PAIRING_LOCKOUT_FAILURES - 1 {
+            assert!(!failures.record(start + Duration::from_secs(i as u64)));
+        }
+        // Failures older than the window don't count
+        let later = start + PAIRING_FAILURE_WINDOW + Duration::from_secs(1);
+        assert!(!failures.record(later));
+        assert!(failures.locked(later).is_none());
+        
+        // Re-requesting a code in between doesn't reset the budget
+        for _ in 0..PAIRING_LOCKOUT_FAILURES - 1 {
+            assert!(issue_pairing_code().is_ok());
+            assert_eq!(PAIRING_CODE.lock().as_ref().unwrap().attempts, 0);
+            assert!(!PAIRING_FAILURES.lock().record(Instant::now()));
+        }
+        assert!(PAIRING_FAILURES.lock().record(Instant::now()));
+        assert!(issue_pairing_code().is_err());
+        assert!(PAIRING_FAILURES.lock().locked(Instant::now() + PAIRING_LOCKOUT).is_none());
+    }
+    
+    #[test]
+    fn tiles_outside_the_canvas_are_skipped() {
+        let mut canvas = TileCanvas::new();
+        canvas.width = 4;
+        canvas.height = 2;
+        canvas.rgb = vec![0u8; 4 * 2 * 3];
+        let tile = vec![255u8; 2 * 2 * 3];
+        
+        // Would index past the end of the last row without the bounds check
+        canvas.blit_tile(4, 1, &tile, 2, 2);
+        canvas.blit_tile(0, 2, &tile, 2, 2);
+        canvas.blit_tile(60000, 60000, &tile, 2, 2);
+        assert!(canvas.rgb.iter().all(|&b| b == 0));
+        
+        // Straddling the right/bottom edge: only the inside part is drawn
+        canvas.blit_tile(3, 1, &tile, 2, 2);
+        assert_eq!(&canvas.rgb[(4 + 3) * 3..], &[255, 255, 255]);
+        assert_eq!(canvas.rgb.iter().filter(|&&b| b == 255).count(), 3);
+    }
+    
+    #[test]
+    fn auto_tier_moves_one_rung_per_window() {
+        let bad = LinkQuality { rtt_ms: 250.0, loss: 0.2 };
+        let clean = LinkQuality { rtt_ms: 5.0, loss: 0.0 };
+        assert_eq!(pick_stream_tier(&bad), 2);
+        assert_eq!(next_tier_index(0, &bad), 1);
+        assert_eq!(next_tier_index(1, &bad), 2);
+        assert_eq!(next_tier_index(2, &bad), 2);
+        assert_eq!(next_tier_index(2, &clean), 1);
+        assert_eq!(next_tier_index(0, &clean), 0);
+        assert!(STREAM_TIERS.windows(2).all(|pair| pair[0].width > pair[1].width));
+        
+        // Unanswered probes count as lost once the window closes
+        let mut link = LinkMonitor::new();
+        link.window_started = Instant::now() - LINK_MONITOR_WINDOW;
+        link.sent = 4;
+        for seq in 0..2 {
+            link.pending.insert(seq, Instant::now());
+            link.on_reply(seq);
+        }
+        let window = link.take_window().unwrap();
+        assert!((window.loss - 0.5).abs() < 1e-9);
+        assert!(link.take_window().is_none());
+    }
+}
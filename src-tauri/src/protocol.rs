@@ -0,0 +1,409 @@
+// ============== UDP Framing Protocol ==============
+// Every stream packet is one chunk of a frame. Sender and receiver both go
+// through encode_header/parse_header so the layouts can't drift apart.
+//
+// H.264 "H4":       magic(2) + type(1) + flags(1) + seq(4) + idx(2) + total(2)
+//   with FLAG_PARAM_HASH the frame data starts with hash(4) of its SPS/PPS
+// JPEG tiles "JT":  magic(2) + flags(1) + seq(4) + idx(2) + total(2)
+// Legacy JPEG "SF": magic(2) + seq(4) + idx(2) + total(2)
+// Thumbnail "TN":   magic(2) + seq(4) + idx(2) + total(2), a small JPEG beside the main stream
+// Heartbeat ack "HA": magic(2) + seq(4), receiver -> sender, newest seq seen
+// Link probe "PR" / reply "PA": magic(2) + seq(4), the receiver echoes each probe
+// Keyframe request "KR": magic(2) + seq(4), receiver -> sender, newest seq seen
+// Stream end "EN": magic(2) + seq(4), sender -> receiver, seq of the final frame
+// Clock sync "CS": magic(2) + seq(4) + t0(8); reply "CR" adds t1(8) + t2(8)
+//   t0 = request sent, t1 = request received, t2 = reply sent; UNIX microseconds
+// All integers are little-endian.
+
+pub(crate) const MAX_PACKET_SIZE: usize = 1400;
+
+const H264_MAGIC: &[u8; 2] = b"H4";
+// No magic may be a prefix of another, or of "SF" + a seq byte
+const JPEG_TILES_MAGIC: &[u8; 2] = b"JT";
+const LEGACY_JPEG_MAGIC: &[u8; 2] = b"SF";
+const THUMBNAIL_MAGIC: &[u8; 2] = b"TN";
+const HEARTBEAT_ACK_MAGIC: &[u8; 2] = b"HA";
+const PROBE_MAGIC: &[u8; 2] = b"PR";
+const PROBE_REPLY_MAGIC: &[u8; 2] = b"PA";
+const KEYFRAME_REQUEST_MAGIC: &[u8; 2] = b"KR";
+const STREAM_END_MAGIC: &[u8; 2] = b"EN";
+const CLOCK_SYNC_MAGIC: &[u8; 2] = b"CS";
+const CLOCK_REPLY_MAGIC: &[u8; 2] = b"CR";
+
+// H.264 type byte: set on the first chunk of a frame
+pub(crate) const FRAME_TYPE_START: u8 = 0x01;
+
+// H.264 flags byte: how the sender converted RGB to YUV, for the viewer's decoder.
+// Neither bit set = limited-range BT.601, what older senders always produced.
+pub(crate) const FLAG_FULL_RANGE: u8 = 0x01;
+pub(crate) const FLAG_BT709: u8 = 0x02;
+// Viewer should have its decoder output NV12 rather than I420
+pub(crate) const FLAG_NV12: u8 = 0x04;
+// Keyframe data is prefixed with a hash of its SPS/PPS (older receivers don't strip it)
+pub(crate) const FLAG_PARAM_HASH: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameKind {
+    H264,
+    JpegTiles,
+    LegacyJpeg,
+    Thumbnail,
+}
+
+impl FrameKind {
+    pub(crate) fn header_len(self) -> usize {
+        match self {
+            FrameKind::H264 => 12,
+            FrameKind::JpegTiles => 11,
+            FrameKind::LegacyJpeg | FrameKind::Thumbnail => 10,
+        }
+    }
+
+    pub(crate) fn max_payload(self) -> usize {
+        MAX_PACKET_SIZE - self.header_len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameHeader {
+    pub kind: FrameKind,
+    pub frame_type: u8, // H.264 only, 0 elsewhere
+    pub flags: u8,      // H.264 and JT only, 0 elsewhere
+    pub seq: u32,
+    pub idx: u16,
+    pub total: u16,
+}
+
+pub(crate) fn encode_header(header: &FrameHeader, out: &mut Vec<u8>) {
+    match header.kind {
+        FrameKind::H264 => {
+            out.extend_from_slice(H264_MAGIC);
+            out.push(header.frame_type);
+            out.push(header.flags);
+        }
+        FrameKind::JpegTiles => {
+            out.extend_from_slice(JPEG_TILES_MAGIC);
+            out.push(header.flags);
+        }
+        FrameKind::LegacyJpeg => {
+            out.extend_from_slice(LEGACY_JPEG_MAGIC);
+        }
+        FrameKind::Thumbnail => {
+            out.extend_from_slice(THUMBNAIL_MAGIC);
+        }
+    }
+    out.extend_from_slice(&header.seq.to_le_bytes());
+    out.extend_from_slice(&header.idx.to_le_bytes());
+    out.extend_from_slice(&header.total.to_le_bytes());
+}
+
+// Returns the header and the payload that follows it
+pub(crate) fn parse_header(packet: &[u8]) -> Option<(FrameHeader, &[u8])> {
+    let (kind, frame_type, flags, rest) = if packet.starts_with(H264_MAGIC) && packet.len() >= 4 {
+        (FrameKind::H264, packet[2], packet[3], &packet[4..])
+    } else if packet.starts_with(JPEG_TILES_MAGIC) && packet.len() >= 3 {
+        (FrameKind::JpegTiles, 0, packet[2], &packet[3..])
+    } else if packet.starts_with(LEGACY_JPEG_MAGIC) {
+        (FrameKind::LegacyJpeg, 0, 0, &packet[2..])
+    } else if packet.starts_with(THUMBNAIL_MAGIC) {
+        (FrameKind::Thumbnail, 0, 0, &packet[2..])
+    } else {
+        return None;
+    };
+
+    if rest.len() < 8 {
+        return None;
+    }
+
+    let header = FrameHeader {
+        kind,
+        frame_type,
+        flags,
+        seq: u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]),
+        idx: u16::from_le_bytes([rest[4], rest[5]]),
+        total: u16::from_le_bytes([rest[6], rest[7]]),
+    };
+
+    Some((header, &rest[8..]))
+}
+
+// Control packets are all magic(2) + seq(4)
+fn encode_control(magic: &[u8; 2], seq: u32) -> [u8; 6] {
+    let seq = seq.to_le_bytes();
+    [magic[0], magic[1], seq[0], seq[1], seq[2], seq[3]]
+}
+
+fn parse_control(magic: &[u8; 2], packet: &[u8]) -> Option<u32> {
+    match packet {
+        [m0, m1, a, b, c, d] if [*m0, *m1] == *magic => Some(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+pub(crate) fn encode_heartbeat_ack(seq: u32) -> [u8; 6] {
+    encode_control(HEARTBEAT_ACK_MAGIC, seq)
+}
+
+pub(crate) fn parse_heartbeat_ack(packet: &[u8]) -> Option<u32> {
+    parse_control(HEARTBEAT_ACK_MAGIC, packet)
+}
+
+pub(crate) fn encode_probe(seq: u32) -> [u8; 6] {
+    encode_control(PROBE_MAGIC, seq)
+}
+
+pub(crate) fn parse_probe(packet: &[u8]) -> Option<u32> {
+    parse_control(PROBE_MAGIC, packet)
+}
+
+pub(crate) fn encode_probe_reply(seq: u32) -> [u8; 6] {
+    encode_control(PROBE_REPLY_MAGIC, seq)
+}
+
+pub(crate) fn parse_probe_reply(packet: &[u8]) -> Option<u32> {
+    parse_control(PROBE_REPLY_MAGIC, packet)
+}
+
+pub(crate) fn encode_keyframe_request(seq: u32) -> [u8; 6] {
+    encode_control(KEYFRAME_REQUEST_MAGIC, seq)
+}
+
+pub(crate) fn parse_keyframe_request(packet: &[u8]) -> Option<u32> {
+    parse_control(KEYFRAME_REQUEST_MAGIC, packet)
+}
+
+pub(crate) fn encode_stream_end(seq: u32) -> [u8; 6] {
+    encode_control(STREAM_END_MAGIC, seq)
+}
+
+pub(crate) fn parse_stream_end(packet: &[u8]) -> Option<u32> {
+    parse_control(STREAM_END_MAGIC, packet)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClockReply {
+    pub seq: u32,
+    pub t0: u64,
+    pub t1: u64,
+    pub t2: u64,
+}
+
+pub(crate) fn encode_clock_sync(seq: u32, t0: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(14);
+    out.extend_from_slice(CLOCK_SYNC_MAGIC);
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(&t0.to_le_bytes());
+    out
+}
+
+// (seq, t0)
+pub(crate) fn parse_clock_sync(packet: &[u8]) -> Option<(u32, u64)> {
+    if packet.len() != 14 || &packet[..2] != CLOCK_SYNC_MAGIC {
+        return None;
+    }
+    let seq = u32::from_le_bytes(packet[2..6].try_into().ok()?);
+    let t0 = u64::from_le_bytes(packet[6..14].try_into().ok()?);
+    Some((seq, t0))
+}
+
+pub(crate) fn encode_clock_reply(reply: &ClockReply) -> Vec<u8> {
+    let mut out = Vec::with_capacity(30);
+    out.extend_from_slice(CLOCK_REPLY_MAGIC);
+    out.extend_from_slice(&reply.seq.to_le_bytes());
+    for t in [reply.t0, reply.t1, reply.t2] {
+        out.extend_from_slice(&t.to_le_bytes());
+    }
+    out
+}
+
+pub(crate) fn parse_clock_reply(packet: &[u8]) -> Option<ClockReply> {
+    if packet.len() != 30 || &packet[..2] != CLOCK_REPLY_MAGIC {
+        return None;
+    }
+    let time_at = |at: usize| packet[at..at + 8].try_into().ok().map(u64::from_le_bytes);
+    Some(ClockReply {
+        seq: u32::from_le_bytes(packet[2..6].try_into().ok()?),
+        t0: time_at(6)?,
+        t1: time_at(14)?,
+        t2: time_at(22)?,
+    })
+}
+
+pub(crate) fn prepend_param_hash(hash: u32, frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + frame.len());
+    out.extend_from_slice(&hash.to_le_bytes());
+    out.extend_from_slice(frame);
+    out
+}
+
+// Takes the hash back off an assembled frame; untagged frames pass through
+pub(crate) fn split_param_hash(flags: u8, frame: &[u8]) -> (Option<u32>, &[u8]) {
+    match frame.split_first_chunk::<4>() {
+        Some((hash, rest)) if flags & FLAG_PARAM_HASH != 0 => (Some(u32::from_le_bytes(*hash)), rest),
+        _ => (None, frame),
+    }
+}
+
+// Split a frame into ready-to-send packets
+pub(crate) fn packetize(kind: FrameKind, seq: u32, data: &[u8]) -> Vec<Vec<u8>> {
+    packetize_with_flags(kind, seq, 0, data)
+}
+
+// flags goes on every packet; LegacyJpeg and Thumbnail have no flags byte and drop it
+pub(crate) fn packetize_with_flags(kind: FrameKind, seq: u32, flags: u8, data: &[u8]) -> Vec<Vec<u8>> {
+    let chunk_size = kind.max_payload();
+    let total = data.len().div_ceil(chunk_size) as u16;
+
+    data.chunks(chunk_size).enumerate().map(|(i, chunk)| {
+        let header = FrameHeader {
+            kind,
+            frame_type: if kind == FrameKind::H264 && i == 0 { FRAME_TYPE_START } else { 0 },
+            flags,
+            seq,
+            idx: i as u16,
+            total,
+        };
+
+        let mut packet = Vec::with_capacity(kind.header_len() + chunk.len());
+        encode_header(&header, &mut packet);
+        packet.extend_from_slice(chunk);
+        packet
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(kind: FrameKind) -> FrameHeader {
+        FrameHeader {
+            kind,
+            frame_type: if kind == FrameKind::H264 { FRAME_TYPE_START } else { 0 },
+            flags: if matches!(kind, FrameKind::LegacyJpeg | FrameKind::Thumbnail) { 0 } else { 0x5a },
+            seq: 0xdead_beef,
+            idx: 3,
+            total: 0x1234,
+        }
+    }
+
+    #[test]
+    fn round_trip_all_kinds() {
+        for kind in [FrameKind::H264, FrameKind::JpegTiles, FrameKind::LegacyJpeg, FrameKind::Thumbnail] {
+            let mut packet = Vec::new();
+            encode_header(&header(kind), &mut packet);
+            assert_eq!(packet.len(), kind.header_len());
+            packet.extend_from_slice(b"payload");
+
+            let (parsed, payload) = parse_header(&packet).unwrap();
+            assert_eq!(parsed, header(kind));
+            assert_eq!(payload, b"payload");
+        }
+    }
+
+    #[test]
+    fn h264_layout_is_stable() {
+        let mut packet = Vec::new();
+        encode_header(&header(FrameKind::H264), &mut packet);
+        assert_eq!(packet, [b'H', b'4', 0x01, 0x5a, 0xef, 0xbe, 0xad, 0xde, 3, 0, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn tiles_and_legacy_are_not_confused() {
+        let mut packet = Vec::new();
+        encode_header(&header(FrameKind::JpegTiles), &mut packet);
+        assert_eq!(parse_header(&packet).unwrap().0.kind, FrameKind::JpegTiles);
+
+        // seq 50 puts '2' right after "SF", which the old "SF2" magic claimed
+        let legacy = FrameHeader { kind: FrameKind::LegacyJpeg, frame_type: 0, flags: 0, seq: 50, idx: 0, total: 1 };
+        let mut packet = Vec::new();
+        encode_header(&legacy, &mut packet);
+        assert_eq!(&packet[..3], b"SF2");
+        assert_eq!(parse_header(&packet).unwrap().0, legacy);
+    }
+
+    #[test]
+    fn rejects_unknown_and_truncated() {
+        assert_eq!(parse_header(b"XX0123456789"), None);
+        assert_eq!(parse_header(b"H4\x01\x00\x01\x00"), None);
+        assert_eq!(parse_header(b""), None);
+    }
+
+    #[test]
+    fn heartbeat_ack_round_trip() {
+        let ack = encode_heartbeat_ack(0x0102_0304);
+        assert_eq!(parse_heartbeat_ack(&ack), Some(0x0102_0304));
+        // Never confused with a frame packet, and vice versa
+        assert_eq!(parse_header(&ack), None);
+        assert_eq!(parse_heartbeat_ack(b"H4\x01\x00\x01\x00"), None);
+    }
+
+    #[test]
+    fn probe_and_reply_are_distinct() {
+        let probe = encode_probe(7);
+        assert_eq!(parse_probe(&probe), Some(7));
+        assert_eq!(parse_probe_reply(&probe), None);
+        assert_eq!(parse_heartbeat_ack(&probe), None);
+        assert_eq!(parse_header(&probe), None);
+
+        let reply = encode_probe_reply(7);
+        assert_eq!(parse_probe_reply(&reply), Some(7));
+        assert_eq!(parse_probe(&reply), None);
+
+        let request = encode_keyframe_request(7);
+        assert_eq!(parse_keyframe_request(&request), Some(7));
+        assert_eq!(parse_heartbeat_ack(&request), None);
+        assert_eq!(parse_keyframe_request(&probe), None);
+
+        let end = encode_stream_end(7);
+        assert_eq!(parse_stream_end(&end), Some(7));
+        assert_eq!(parse_header(&end), None);
+        assert_eq!(parse_stream_end(&request), None);
+    }
+
+    #[test]
+    fn clock_sync_round_trip() {
+        let request = encode_clock_sync(3, 1_700_000_000_000_000);
+        assert_eq!(parse_clock_sync(&request), Some((3, 1_700_000_000_000_000)));
+        assert_eq!(parse_clock_reply(&request), None);
+        assert_eq!(parse_header(&request), None);
+
+        let reply = ClockReply { seq: 3, t0: 10, t1: 25, t2: 26 };
+        let packet = encode_clock_reply(&reply);
+        assert_eq!(parse_clock_reply(&packet), Some(reply));
+        assert_eq!(parse_clock_sync(&packet), None);
+        assert_eq!(parse_clock_reply(&packet[..29]), None);
+    }
+
+    #[test]
+    fn param_hash_round_trip() {
+        let tagged = prepend_param_hash(0xcafe_f00d, b"\0\0\0\x01\x67");
+        assert_eq!(split_param_hash(FLAG_PARAM_HASH | FLAG_BT709, &tagged), (Some(0xcafe_f00d), &b"\0\0\0\x01\x67"[..]));
+        // Without the flag the bytes are frame data
+        assert_eq!(split_param_hash(FLAG_BT709, &tagged), (None, &tagged[..]));
+    }
+
+    #[test]
+    fn packetize_round_trip() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let packets = packetize(FrameKind::H264, 42, &data);
+        assert_eq!(packets.len(), data.len().div_ceil(FrameKind::H264.max_payload()));
+
+        let mut rebuilt = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            assert!(packet.len() <= MAX_PACKET_SIZE);
+            let (h, payload) = parse_header(packet).unwrap();
+            assert_eq!((h.seq, h.idx as usize, h.total as usize), (42, i, packets.len()));
+            assert_eq!(h.frame_type == FRAME_TYPE_START, i == 0);
+            rebuilt.extend_from_slice(payload);
+        }
+        assert_eq!(rebuilt, data);
+    }
+
+    #[test]
+    fn packetize_carries_flags() {
+        let packets = packetize_with_flags(FrameKind::H264, 1, FLAG_FULL_RANGE | FLAG_BT709, &[0u8; 3000]);
+        for packet in &packets {
+            assert_eq!(parse_header(packet).unwrap().0.flags, FLAG_FULL_RANGE | FLAG_BT709);
+        }
+    }
+}
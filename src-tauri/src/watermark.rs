@@ -0,0 +1,224 @@
+// ============== Watermark Rendering ==============
+// Burns a line of text into a BGRA frame with a built-in 5x7 bitmap font, before
+// any JPEG/YUV conversion, so every output path (and any recording of it) has it.
+// The font covers A-Z (lowercase is upper-cased), 0-9 and common punctuation;
+// anything else renders as '?'.
+
+const GLYPH_W: usize = 5;
+const GLYPH_H: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub(crate) fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            "center" => Ok(Self::Center),
+            other => Err(format!("Unknown watermark position: {}", other)),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::TopLeft => "top-left",
+            Self::TopRight => "top-right",
+            Self::BottomLeft => "bottom-left",
+            Self::BottomRight => "bottom-right",
+            Self::Center => "center",
+        }
+    }
+}
+
+// One row per byte, bit 4 = leftmost column
+fn glyph(c: char) -> [u8; GLYPH_H] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; GLYPH_H],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' | '\u{2013}' | '\u{2014}' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '@' => [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // '?'
+    }
+}
+
+// Blend `value` into the B, G and R channels at pixel i
+fn blend(bgra: &mut [u8], i: usize, value: u8, alpha: u32) {
+    if i + 2 < bgra.len() {
+        let inv_alpha = 255 - alpha;
+        for channel in &mut bgra[i..i + 3] {
+            *channel = ((*channel as u32 * inv_alpha + value as u32 * alpha) / 255) as u8;
+        }
+    }
+}
+
+// White text on a half-opacity black box; opacity is 0.0-1.0
+pub(crate) fn draw_watermark(
+    bgra: &mut [u8],
+    width: usize,
+    height: usize,
+    text: &str,
+    position: WatermarkPosition,
+    opacity: f32
+) {
+    if text.is_empty() || width == 0 || height == 0 {
+        return;
+    }
+
+    // Source pixels per font pixel, so the text stays readable after downscaling
+    let scale = (height / 360).max(2);
+    let pad = 2 * scale;
+    let margin = 4 * scale;
+    let advance = (GLYPH_W + 1) * scale;
+    let chars = text.chars().count();
+
+    let box_w = (chars * advance + 2 * pad - scale).min(width);
+    let box_h = (GLYPH_H * scale + 2 * pad).min(height);
+    let max_x = width - box_w;
+    let max_y = height - box_h;
+    let (box_x, box_y) = match position {
+        WatermarkPosition::TopLeft => (margin.min(max_x), margin.min(max_y)),
+        WatermarkPosition::TopRight => (max_x.saturating_sub(margin), margin.min(max_y)),
+        WatermarkPosition::BottomLeft => (margin.min(max_x), max_y.saturating_sub(margin)),
+        WatermarkPosition::BottomRight => (max_x.saturating_sub(margin), max_y.saturating_sub(margin)),
+        WatermarkPosition::Center => (max_x / 2, max_y / 2),
+    };
+
+    let stride = bgra.len() / height;
+    let text_alpha = (opacity.clamp(0.0, 1.0) * 255.0) as u32;
+    let box_alpha = text_alpha / 2;
+
+    for y in box_y..box_y + box_h {
+        for x in box_x..box_x + box_w {
+            blend(bgra, y * stride + x * 4, 0, box_alpha);
+        }
+    }
+
+    for (n, c) in text.chars().enumerate() {
+        let origin_x = box_x + pad + n * advance;
+        if origin_x + GLYPH_W * scale > width {
+            break; // clipped at the right edge
+        }
+
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_W {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    let y = box_y + pad + row * scale + dy;
+                    if y >= height {
+                        continue;
+                    }
+                    for dx in 0..scale {
+                        blend(bgra, y * stride + (origin_x + col * scale + dx) * 4, 255, text_alpha);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// "YYYY-MM-DD HH:MM:SS UTC" without pulling in a date crate
+pub(crate) fn format_utc_timestamp(unix_secs: u64) -> String {
+    let days = (unix_secs / 86_400) as i64;
+    let secs = unix_secs % 86_400;
+
+    // Civil-from-days (Howard Hinnant), proleptic Gregorian
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_format() {
+        assert_eq!(format_utc_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc_timestamp(1_700_000_000), "2023-11-14 22:13:20 UTC");
+        // Leap day
+        assert_eq!(format_utc_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+    }
+
+    #[test]
+    fn draws_inside_frame_only() {
+        let (w, h) = (64, 32);
+        let mut bgra = vec![100u8; w * h * 4];
+        // Far too long for the frame: must clip, not panic
+        draw_watermark(&mut bgra, w, h, "SESSION RECORDED 2024", WatermarkPosition::BottomRight, 1.0);
+
+        assert!(bgra.chunks(4).any(|px| px[0] == 255));
+        // Alpha channel is never touched
+        assert!(bgra.chunks(4).all(|px| px[3] == 100));
+    }
+
+    #[test]
+    fn zero_opacity_is_a_no_op() {
+        let mut bgra = vec![7u8; 200 * 100 * 4];
+        draw_watermark(&mut bgra, 200, 100, "x", WatermarkPosition::Center, 0.0);
+        assert!(bgra.iter().all(|&b| b == 7));
+    }
+}
@@ -0,0 +1,171 @@
+// ============== Windows Graphics Capture ==============
+// Capture path for Windows 10 1903+ through the windows-capture crate. Frames
+// are pushed to us by the OS (no polling), and the cursor is composited by WGC.
+// Mirrors the scrap-based capture loop: throttled "screen-frame" JPEG events
+// plus the latest raw frame kept around for single-frame grabs.
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use windows_capture::capture::{CaptureControl, Context, GraphicsCaptureApiHandler};
+use windows_capture::frame::Frame;
+use windows_capture::graphics_capture_api::InternalCaptureControl;
+use windows_capture::monitor::Monitor;
+use windows_capture::settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings};
+
+type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+// How long a new session gets to either deliver a frame or die on its thread
+const STARTUP_GRACE: Duration = Duration::from_millis(500);
+
+pub struct CapturedFrame {
+    pub bgra: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref LAST_FRAME: Mutex<Option<CapturedFrame>> = Mutex::new(None);
+    static ref CONTROL: Mutex<Option<CaptureControl<WgcHandler, HandlerError>>> = Mutex::new(None);
+    static ref FRAME_ARRIVED: AtomicBool = AtomicBool::new(false);
+}
+
+pub struct WgcFlags {
+    // None = only keep LAST_FRAME fresh, don't emit events
+    app: Option<tauri::AppHandle>,
+    interval: Duration,
+}
+
+pub struct WgcHandler {
+    app: Option<tauri::AppHandle>,
+    interval: Duration,
+    last_emit: Option<Instant>,
+}
+
+impl GraphicsCaptureApiHandler for WgcHandler {
+    type Flags = WgcFlags;
+    type Error = HandlerError;
+
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            app: ctx.flags.app,
+            interval: ctx.flags.interval,
+            last_emit: None,
+        })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        _capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        let width = frame.width() as usize;
+        let height = frame.height() as usize;
+        let mut buffer = frame.buffer()?;
+        let bgra = buffer.as_nopadding_buffer()?.to_vec();
+
+        if let Some(app) = &self.app {
+            if self.last_emit.is_none_or(|t| t.elapsed() >= self.interval) {
+                if let Some(jpeg) = crate::encode_jpeg(&bgra, width, height, 60) {
+                    let _ = app.emit("screen-frame", crate::jpeg_data_url(&jpeg));
+                }
+                self.last_emit = Some(Instant::now());
+            }
+        }
+
+        *LAST_FRAME.lock() = Some(CapturedFrame { bgra, width, height });
+        FRAME_ARRIVED.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn on_closed(&mut self) -> Result<(), Self::Error> {
+        println!("Windows Graphics Capture session closed");
+        Ok(())
+    }
+}
+
+fn start_session(app: Option<tauri::AppHandle>, interval: Duration) -> Result<(), String> {
+    let mut control = CONTROL.lock();
+    if control.is_some() {
+        return Err("Capture already running".to_string());
+    }
+
+    let monitor = Monitor::primary().map_err(|e| format!("No display: {}", e))?;
+    let settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithCursor,
+        DrawBorderSettings::WithoutBorder,
+        ColorFormat::Bgra8,
+        WgcFlags { app, interval },
+    );
+
+    FRAME_ARRIVED.store(false, Ordering::Relaxed);
+    let session = WgcHandler::start_free_threaded(settings)
+        .map_err(|e| format!("Cannot start Windows Graphics Capture: {}", e))?;
+
+    // Remote desktop sessions and some GPUs accept the start, then fail on the
+    // capture thread; catch that here so the caller can still fall back
+    let deadline = Instant::now() + STARTUP_GRACE;
+    while !FRAME_ARRIVED.load(Ordering::Relaxed) && Instant::now() < deadline {
+        if session.is_finished() {
+            return Err(match session.wait() {
+                Err(e) => format!("Windows Graphics Capture failed to start: {}", e),
+                Ok(()) => "Windows Graphics Capture session ended immediately".to_string(),
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    *control = Some(session);
+    Ok(())
+}
+
+// Continuous capture emitting "screen-frame" every interval, like start_capture_loop
+pub fn start_capture(app: tauri::AppHandle, interval: Duration) -> Result<(), String> {
+    start_session(Some(app), interval)
+}
+
+// A session from start_capture that hasn't ended
+pub fn is_capturing() -> bool {
+    CONTROL.lock().as_ref().is_some_and(|session| !session.is_finished())
+}
+
+pub fn stop_capture() -> Result<(), String> {
+    if let Some(session) = CONTROL.lock().take() {
+        session.stop().map_err(|e| format!("Cannot stop capture: {}", e))?;
+    }
+    Ok(())
+}
+
+// Start a short session if none is running and wait for the first frame
+pub fn capture_single_frame() -> Result<CapturedFrame, String> {
+    let temporary = CONTROL.lock().is_none();
+    if temporary {
+        *LAST_FRAME.lock() = None;
+        start_session(None, Duration::ZERO)?;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let frame = loop {
+        if let Some(frame) = get_last_frame() {
+            break Ok(frame);
+        }
+        if Instant::now() >= deadline {
+            break Err("Capture timeout".to_string());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    if temporary {
+        stop_capture()?;
+    }
+    frame
+}
+
+pub fn get_last_frame() -> Option<CapturedFrame> {
+    LAST_FRAME.lock().as_ref().map(|frame| CapturedFrame {
+        bgra: frame.bgra.clone(),
+        width: frame.width,
+        height: frame.height,
+    })
+}
@@ -19,6 +19,25 @@ type ClientInfo = {
   isSelected: boolean;
 };
 
+// What the admin may do on this machine (Rust SessionPermissions); view-only until granted
+type SessionPermissions = {
+  view: boolean;
+  control_mouse: boolean;
+  control_keyboard: boolean;
+  file_transfer: boolean;
+  power_control: boolean;
+  clipboard: boolean;
+};
+
+const PERMISSION_LABELS: [keyof SessionPermissions, string][] = [
+  ["view", "Xem màn hình"],
+  ["control_mouse", "Điều khiển chuột"],
+  ["control_keyboard", "Điều khiển bàn phím"],
+  ["file_transfer", "Nhận file"],
+  ["power_control", "Khóa / tắt máy"],
+  ["clipboard", "Đồng bộ clipboard"],
+];
+
 function App() {
   const [isLoggedIn, setIsLoggedIn] = useState(false);
   const [role, setRole] = useState<Role | null>(null);
@@ -42,6 +61,12 @@ function App() {
   const [remoteControlClient, setRemoteControlClient] = useState<string | null>(null);
   const [h264Decoders] = useState<Map<string, any>>(new Map());
   const [h264Canvases] = useState<Map<string, HTMLCanvasElement>>(new Map());
+  const [permissions, setPermissions] = useState<SessionPermissions | null>(null);
+  // Client: code shown while an admin is pairing for remote input
+  const [shownPairingCode, setShownPairingCode] = useState<string | null>(null);
+  // Admin: input token per client from a completed pairing, sent with every remote-* event
+  const [inputTokens, setInputTokens] = useState<Map<string, string>>(new Map());
+  const [pairingInput, setPairingInput] = useState("");
 
   // LAN Scan & WOL states
   const [lanHosts, setLanHosts] = useState<{ip: string; hasApp: boolean; online: boolean; mac?: string}[]>([]);
@@ -70,6 +95,42 @@ function App() {
     setDebugLogs((prev) => [...prev.slice(-20), `[${time}] ${msg}`]);
   };
 
+  // Client: grant or revoke permissions for the admin
+  const updatePermission = async (changes: Partial<SessionPermissions>) => {
+    if (!isTauri) return;
+    try {
+      const { invoke } = await import("@tauri-apps/api/core");
+      const current = await invoke<SessionPermissions>("get_session_permissions");
+      const updated = await invoke<SessionPermissions>("set_session_permissions", {
+        permissions: { ...current, ...changes }
+      });
+      setPermissions(updated);
+      Object.entries(changes).forEach(([name, granted]) => {
+        addLog(`Permission ${name}: ${granted ? "granted" : "revoked"}`);
+      });
+    } catch (e: any) {
+      addLog(`Permission error: ${e.message || e}`);
+    }
+  };
+
+  // Client: an admin request needing a permission not granted yet asks the user once
+  const ensurePermission = async (name: keyof SessionPermissions, question: string) => {
+    const { invoke } = await import("@tauri-apps/api/core");
+    const current = await invoke<SessionPermissions>("get_session_permissions");
+    if (current[name]) return true;
+    if (!window.confirm(question)) return false;
+    await updatePermission({ [name]: true });
+    return true;
+  };
+
+  useEffect(() => {
+    if (!isLoggedIn || role !== "client" || !isTauri) return;
+    import("@tauri-apps/api/core")
+      .then(({ invoke }) => invoke<SessionPermissions>("get_session_permissions"))
+      .then(setPermissions)
+      .catch((e) => addLog(`Permission error: ${e.message || e}`));
+  }, [isLoggedIn, role]);
+
   // LAN Scan function
   const scanLan = async () => {
     if (!isTauri) {
@@ -523,15 +584,12 @@ function App() {
       
       // Start UDP frame receiver
       try {
-        const { invoke } = await import("@tauri-apps/api/core");
+        const { invoke, Channel } = await import("@tauri-apps/api/core");
         const { listen } = await import("@tauri-apps/api/event");
         
-        await invoke("start_frame_receiver", { port: 3002 });
-        addLog("UDP frame receiver started on port 3002");
-        
-        // Listen for JPEG frames (fallback)
-        await listen<[string, string]>("udp-frame", (event) => {
-          const [clientIp, dataUrl] = event.payload;
+        // JPEG frames (fallback)
+        const handleUdpFrame = (payload: [string, string]) => {
+          const [clientIp, dataUrl] = payload;
           
           setClients((prev) => {
             const newMap = new Map(prev);
@@ -557,11 +615,11 @@ function App() {
             
             return newMap;
           });
-        });
+        };
 
-        // Listen for H.264 frames
-        await listen<[string, string]>("h264-frame", async (event) => {
-          const [clientIp, base64Data] = event.payload;
+        // H.264 frames
+        const handleH264Frame = async (payload: [string, string]) => {
+          const [clientIp, base64Data] = payload;
           
           try {
             // Check if WebCodecs is available
@@ -650,6 +708,81 @@ function App() {
           } catch (e: any) {
             // Silent fail for individual frames
           }
+        };
+
+        // Frames arrive on a channel; each ack lets the receiver send another,
+        // so a busy UI makes it drop frames instead of queueing them
+        const onFrame = new Channel<{ event: string; payload: [string, string] }>();
+        onFrame.onmessage = async ({ event, payload }) => {
+          try {
+            if (event === "h264-frame") {
+              await handleH264Frame(payload);
+            } else if (event === "udp-frame") {
+              handleUdpFrame(payload);
+            }
+          } finally {
+            invoke("ack_frames").catch(() => {});
+          }
+        };
+        
+        await invoke("start_frame_receiver", { port: 3002, onFrame });
+        addLog("UDP frame receiver started on port 3002");
+
+        // New SPS/PPS (resolution or profile change): drop the decoder so the
+        // next frame builds a fresh one instead of decoding garbage
+        await listen<{ sender: string; hash: string }>("parameter-sets-changed", (event) => {
+          const { sender, hash } = event.payload;
+          const entry = h264Decoders.get(sender);
+          if (entry) {
+            h264Decoders.delete(sender);
+            try {
+              entry.decoder.close();
+            } catch (e) {}
+          }
+          addLog(`Stream from ${sender} changed parameter sets (${hash}), decoder reset`);
+        });
+
+        // Sender stopped on purpose; its last frame is a "session ended" keyframe
+        await listen<{ sender: string }>("stream-ended", (event) => {
+          const { sender } = event.payload;
+          const entry = h264Decoders.get(sender);
+          if (entry) {
+            h264Decoders.delete(sender);
+            h264Canvases.delete(sender);
+            // Let that final frame paint before the decoder goes away
+            entry.decoder.flush().catch(() => {}).finally(() => {
+              try {
+                entry.decoder.close();
+              } catch (e) {}
+            });
+          }
+          addLog(`Stream from ${sender} ended`);
+        });
+
+        // Sender went quiet: drop its decoder and any placeholder tile it created
+        await listen<{ sender: string; idle_secs: number }>("peer-stream-ended", (event) => {
+          const { sender, idle_secs } = event.payload;
+          const entry = h264Decoders.get(sender);
+          if (entry) {
+            try {
+              entry.decoder.close();
+            } catch (e) {}
+            h264Decoders.delete(sender);
+            h264Canvases.delete(sender);
+          }
+
+          setClients((prev) => {
+            const newMap = new Map(prev);
+            newMap.forEach((client, id) => {
+              if (id === `udp-${sender}` || id === `h264-${sender}`) {
+                newMap.delete(id);
+              } else if (client.ip === sender) {
+                newMap.set(id, { ...client, screenData: undefined });
+              }
+            });
+            return newMap;
+          });
+          addLog(`Stream from ${sender} ended (idle ${idle_secs}s)`);
         });
         
         addLog("H.264 decoder ready");
@@ -944,6 +1077,15 @@ function App() {
         addLog(`Screen size from ${clientId}: ${width}x${height}`);
         setRemoteScreenSize({ width, height });
       });
+
+      socket.on("input-pairing-result", ({ clientId, token, error }: { clientId: string; token?: string; error?: string }) => {
+        if (!token) {
+          addLog(`Pairing with ${clientId} failed: ${error}`);
+          return;
+        }
+        setInputTokens((prev) => new Map(prev).set(clientId, token));
+        addLog(`Paired with ${clientId}, remote input enabled`);
+      });
     }
 
     if (role === "client") {
@@ -980,24 +1122,52 @@ function App() {
         }
       });
 
+      // Input pairing: show a code for the user to read out to the admin
+      socket.on("request-input-pairing", async () => {
+        if (!isTauri) return;
+        try {
+          const { invoke } = await import("@tauri-apps/api/core");
+          const pairing = await invoke<{ code: string; expires_in_secs: number }>("start_input_pairing");
+          setShownPairingCode(pairing.code);
+          addLog(`Pairing code shown (valid ${pairing.expires_in_secs}s)`);
+        } catch (e: any) {
+          addLog(`Pairing error: ${e.message || e}`);
+        }
+      });
+
+      // Admin typed the code: a match means the user consented to remote control
+      socket.on("submit-pairing-code", async ({ adminId, code }: { adminId: string; code: string }) => {
+        if (!isTauri) return;
+        try {
+          const { invoke } = await import("@tauri-apps/api/core");
+          const session = await invoke<{ token: string }>("authorize_input_session", { code });
+          await updatePermission({ control_mouse: true, control_keyboard: true });
+          setShownPairingCode(null);
+          socket.emit("input-pairing-result", { adminId, token: session.token });
+        } catch (e: any) {
+          socket.emit("input-pairing-result", { adminId, error: e.message || String(e) });
+        }
+      });
+
       // Remote control handlers (RustDesk style)
-      socket.on("remote-mouse-move", async ({ x, y }: { x: number; y: number }) => {
+      // Each event carries the token from authorize_input_session; without it input is rejected
+      socket.on("remote-mouse-move", async ({ x, y, token }: { x: number; y: number; token?: string }) => {
         if (isTauri) {
           try {
             const { invoke } = await import("@tauri-apps/api/core");
-            await invoke("remote_mouse_move", { x, y });
+            await invoke("remote_mouse_move", { token: token || "", x, y });
           } catch (e: any) {
             // Silent fail for mouse move
           }
         }
       });
 
-      socket.on("remote-mouse-click", async ({ button }: { button: string }) => {
+      socket.on("remote-mouse-click", async ({ button, token }: { button: string; token?: string }) => {
         addLog(`Received remote click: ${button}`);
         if (isTauri) {
           try {
             const { invoke } = await import("@tauri-apps/api/core");
-            await invoke("remote_mouse_click", { button });
+            await invoke("remote_mouse_click", { token: token || "", button });
             addLog(`Click executed: ${button}`);
           } catch (e: any) {
             addLog(`Mouse click error: ${e.message || e}`);
@@ -1005,23 +1175,23 @@ function App() {
         }
       });
 
-      socket.on("remote-mouse-scroll", async ({ deltaX, deltaY }: { deltaX: number; deltaY: number }) => {
+      socket.on("remote-mouse-scroll", async ({ deltaX, deltaY, token }: { deltaX: number; deltaY: number; token?: string }) => {
         if (isTauri) {
           try {
             const { invoke } = await import("@tauri-apps/api/core");
-            await invoke("remote_mouse_scroll", { deltaX, deltaY });
+            await invoke("remote_mouse_scroll", { token: token || "", deltaX, deltaY });
           } catch (e: any) {
             addLog(`Scroll error: ${e.message || e}`);
           }
         }
       });
 
-      socket.on("remote-key-press", async ({ key, code, ctrl, alt, shift, meta }: { key: string; code?: string; ctrl?: boolean; alt?: boolean; shift?: boolean; meta?: boolean }) => {
+      socket.on("remote-key-press", async ({ key, code, ctrl, alt, shift, meta, token }: { key: string; code?: string; ctrl?: boolean; alt?: boolean; shift?: boolean; meta?: boolean; token?: string }) => {
         addLog(`Received remote key: ${key} (code: ${code})`);
         if (isTauri) {
           try {
             const { invoke } = await import("@tauri-apps/api/core");
-            await invoke("remote_key_press", { key, code: code || "", ctrl: ctrl || false, alt: alt || false, shift: shift || false, meta: meta || false });
+            await invoke("remote_key_press", { token: token || "", key, code: code || "", ctrl: ctrl || false, alt: alt || false, shift: shift || false, meta: meta || false });
           } catch (e: any) {
             addLog(`Key press error: ${e.message || e}`);
           }
@@ -1058,6 +1228,9 @@ function App() {
         
         if (isTauri) {
           try {
+            if (!(await ensurePermission("file_transfer", `Quản trị viên muốn gửi file ${metadata.file_name}. Cho phép nhận?`))) {
+              throw new Error("File transfer declined");
+            }
             const { invoke } = await import("@tauri-apps/api/core");
             const { downloadDir } = await import("@tauri-apps/api/path");
             
@@ -1138,6 +1311,9 @@ function App() {
         
         if (isTauri) {
           try {
+            if (!(await ensurePermission("file_transfer", `Quản trị viên muốn gửi file ${request.file_name}. Cho phép nhận?`))) {
+              throw new Error("File transfer declined");
+            }
             const { invoke } = await import("@tauri-apps/api/core");
             const { downloadDir } = await import("@tauri-apps/api/path");
             const { listen } = await import("@tauri-apps/api/event");
@@ -1186,13 +1362,18 @@ function App() {
             );
 
             // Start TCP server
-            const port = await invoke<number>("start_tcp_file_server", {
+            const server = await invoke<{ status: string; port?: number; running?: any }>("start_tcp_file_server", {
               transferId: request.transfer_id,
               fileName: request.file_name,
               fileSize: request.file_size,
               fileHash: request.file_hash,
               saveDir
             });
+            // A server that is already up is only reusable for this same transfer
+            if (server.status === "already_running" && server.running?.transfer_id !== request.transfer_id) {
+              throw new Error(`TCP server busy with ${server.running?.file_name ?? "another transfer"}`);
+            }
+            const port = server.port ?? server.running?.port;
             
             addLog(`[TCP] Server started on port ${port}`);
             
@@ -1277,6 +1458,16 @@ function App() {
     addLog(`Started remote control for ${clientId}`);
     // Request screen size from client
     socket?.emit("request-screen-size", { clientId });
+    // Not paired yet: the client shows a code to type into the remote header
+    if (!inputTokens.has(clientId)) {
+      setPairingInput("");
+      socket?.emit("request-input-pairing", { clientId });
+    }
+  };
+
+  const submitPairingCode = (clientId: string) => {
+    socket?.emit("submit-pairing-code", { clientId, code: pairingInput.trim() });
+    setPairingInput("");
   };
 
   const stopRemoteControl = () => {
@@ -1297,7 +1488,7 @@ function App() {
     const x = (e.clientX - rect.left) / rect.width * remoteScreenSize.width;
     const y = (e.clientY - rect.top) / rect.height * remoteScreenSize.height;
     
-    socket?.emit("remote-mouse-move", { clientId, x, y });
+    socket?.emit("remote-mouse-move", { clientId, x, y, token: inputTokens.get(clientId) });
   };
 
   const handleRemoteClick = (e: React.MouseEvent<HTMLDivElement>, clientId: string) => {
@@ -1313,9 +1504,10 @@ function App() {
     addLog(`Remote click: ${button} at (${Math.round(x)}, ${Math.round(y)}) to ${clientId}`);
     
     // Move then click
-    socket?.emit("remote-mouse-move", { clientId, x, y });
+    const token = inputTokens.get(clientId);
+    socket?.emit("remote-mouse-move", { clientId, x, y, token });
     setTimeout(() => {
-      socket?.emit("remote-mouse-click", { clientId, button });
+      socket?.emit("remote-mouse-click", { clientId, button, token });
     }, 20);
   };
 
@@ -1323,7 +1515,7 @@ function App() {
     e.preventDefault();
     const deltaX = Math.sign(e.deltaX) * -1;
     const deltaY = Math.sign(e.deltaY) * -1;
-    socket?.emit("remote-mouse-scroll", { clientId, deltaX, deltaY });
+    socket?.emit("remote-mouse-scroll", { clientId, deltaX, deltaY, token: inputTokens.get(clientId) });
   };
 
   const handleRemoteKeyDown = (e: React.KeyboardEvent, clientId: string) => {
@@ -1344,7 +1536,8 @@ function App() {
       ctrl: e.ctrlKey, 
       alt: e.altKey, 
       shift: e.shiftKey,
-      meta: e.metaKey
+      meta: e.metaKey,
+      token: inputTokens.get(clientId)
     });
   };
 
@@ -1420,7 +1613,27 @@ function App() {
           <h2>Client Mode</h2>
           <p>Server: {serverIp}:3001</p>
           <p className="client-note">{status}</p>
+          {shownPairingCode && (
+            <div className="pairing-code">
+              Mã cho phép điều khiển: <strong>{shownPairingCode}</strong>
+            </div>
+          )}
         </div>
+        {permissions && (
+          <div className="permission-panel">
+            <h4>Quyền của quản trị viên:</h4>
+            {PERMISSION_LABELS.map(([name, label]) => (
+              <label key={name}>
+                <input
+                  type="checkbox"
+                  checked={permissions[name]}
+                  onChange={(e) => updatePermission({ [name]: e.target.checked })}
+                />
+                {label}
+              </label>
+            ))}
+          </div>
+        )}
         {/* Debug Log Panel */}
         <div className="debug-panel">
           <h4>Debug Log:</h4>
@@ -1451,6 +1664,22 @@ function App() {
       >
         <div className="remote-header">
           <span>🖱️ Điều khiển: {client?.name} ({client?.ip}) - {remoteScreenSize.width}x{remoteScreenSize.height}</span>
+          {!inputTokens.has(remoteControlClient) && (
+            <div className="pairing-prompt">
+              <span>Mã trên máy client:</span>
+              <input
+                value={pairingInput}
+                maxLength={6}
+                onChange={(e) => setPairingInput(e.target.value)}
+                onKeyDown={(e) => {
+                  // Keep typing out of handleRemoteKeyDown
+                  e.stopPropagation();
+                  if (e.key === "Enter") submitPairingCode(remoteControlClient);
+                }}
+              />
+              <button onClick={() => submitPairingCode(remoteControlClient)}>Xác nhận</button>
+            </div>
+          )}
           <button onClick={stopRemoteControl}>✕ Đóng (ESC)</button>
         </div>
         <div 